@@ -1,4 +1,35 @@
+use std::process::Command;
+
 fn main() {
-    prost_build::compile_protos(&["src/proto/message.proto"], &["src/proto"])
+    let mut config = prost_build::Config::new();
+    // Also serialized by the CBOR wire codec
+    if cfg!(feature = "cbor") {
+        config.type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]");
+    }
+    config
+        .compile_protos(&["src/proto/message.proto"], &["src/proto"])
         .expect("Failed to compile proto files");
+
+    #[cfg(feature = "grpc")]
+    tonic_prost_build::configure()
+        .build_client(false)
+        .compile_protos(&["src/proto/control.proto"], &["src/proto"])
+        .expect("Failed to compile the control service");
+
+    // C header of the ffi module, kept out of the source tree
+    #[cfg(feature = "ffi")]
+    cbindgen::generate(std::env::var("CARGO_MANIFEST_DIR").unwrap())
+        .expect("Failed to generate the C header")
+        .write_to_file(std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("dtchat.h"));
+
+    // Source tarballs have no git metadata
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=DTCHAT_GIT_HASH={}", git_hash);
+    println!("cargo:rerun-if-changed=.git/HEAD");
 }
@@ -0,0 +1,199 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, Weak},
+};
+
+use tokio::sync::{broadcast, watch};
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+
+use crate::{
+    delivery::{is_acked, is_given_up, is_sent, outcome, DeliveryError},
+    dtchat::ChatModel,
+    error::ChatError,
+    event::{AppEventObserver, ChatAppEvent, ChatAppInfoEvent, EventFilter, ObserverId},
+    message::{ChatMessage, Content, MessageStatus},
+    net::Endpoint,
+};
+
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+type Trackers = Arc<Mutex<HashMap<String, watch::Sender<Option<ChatMessage>>>>>;
+
+// Observer registered on the wrapped model, forwarding its events to the async world
+struct AsyncBridge {
+    events: broadcast::Sender<ChatAppEvent>,
+    trackers: Trackers,
+}
+
+impl AsyncBridge {
+    fn track(&self, msg: &ChatMessage, terminal: bool) {
+        let mut trackers = self.trackers.lock().unwrap();
+        if let Some(tx) = trackers.get(&msg.uuid) {
+            tx.send_replace(Some(msg.clone()));
+        }
+        if terminal {
+            trackers.remove(&msg.uuid);
+        }
+    }
+}
+
+impl AppEventObserver for AsyncBridge {
+    fn on_event(&mut self, event: ChatAppEvent) {
+        match &event {
+            ChatAppEvent::Message(ChatAppInfoEvent::Sent(msg))
+            | ChatAppEvent::Message(ChatAppInfoEvent::InCustody(msg)) => self.track(msg, false),
+            ChatAppEvent::Message(ChatAppInfoEvent::AckReceived(msg, _))
+            | ChatAppEvent::Message(ChatAppInfoEvent::NackReceived(msg, _))
+            | ChatAppEvent::Message(ChatAppInfoEvent::Refused(msg, _))
+            | ChatAppEvent::Message(ChatAppInfoEvent::Failed(msg))
+            | ChatAppEvent::Message(ChatAppInfoEvent::Expired(msg)) => self.track(msg, true),
+            _ => {}
+        }
+        // Having no subscriber is not an error
+        let _ = self.events.send(event);
+    }
+}
+
+pub struct PendingDelivery {
+    uuid: String,
+    updates: watch::Receiver<Option<ChatMessage>>,
+}
+
+impl PendingDelivery {
+    pub fn uuid(&self) -> &String {
+        &self.uuid
+    }
+
+    pub async fn sent(&mut self) -> Result<ChatMessage, DeliveryError> {
+        self.wait_for(is_sent).await
+    }
+
+    pub async fn acked(&mut self) -> Result<ChatMessage, DeliveryError> {
+        self.wait_for(is_acked).await
+    }
+
+    async fn wait_for(
+        &mut self,
+        done: fn(&MessageStatus) -> bool,
+    ) -> Result<ChatMessage, DeliveryError> {
+        loop {
+            if let Some(res) = outcome(&self.updates.borrow_and_update(), done) {
+                return res;
+            }
+            if self.updates.changed().await.is_err() {
+                return outcome(&self.updates.borrow(), done).unwrap_or(Err(DeliveryError::Closed));
+            }
+        }
+    }
+}
+
+// Runs no periodic work itself, grpc::serve or the application calls ChatModel::tick
+pub struct AsyncChatModel {
+    model: Arc<Mutex<ChatModel>>,
+    events: broadcast::Sender<ChatAppEvent>,
+    trackers: Trackers,
+    // Only held here, the model keeps a weak reference and forgets it once dropped
+    _bridge: Arc<Mutex<dyn AppEventObserver>>,
+    observer: ObserverId,
+}
+
+impl AsyncChatModel {
+    pub fn new(model: Arc<Mutex<ChatModel>>) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        let trackers: Trackers = Arc::new(Mutex::new(HashMap::new()));
+        let bridge: Arc<Mutex<dyn AppEventObserver>> = Arc::new(Mutex::new(AsyncBridge {
+            events: events.clone(),
+            trackers: trackers.clone(),
+        }));
+        let weak: Weak<Mutex<dyn AppEventObserver>> = Arc::downgrade(&bridge);
+        let observer = model
+            .lock()
+            .unwrap()
+            .add_weak_observer(weak, EventFilter::default());
+        Self {
+            model,
+            events,
+            trackers,
+            _bridge: bridge,
+            observer,
+        }
+    }
+
+    // The synchronous model, e.g. to attach it to an engine or query peers and rooms
+    pub fn model(&self) -> Arc<Mutex<ChatModel>> {
+        self.model.clone()
+    }
+
+    // Lagging subscribers silently skip the events they missed
+    pub fn events(&self) -> impl Stream<Item = ChatAppEvent> + Send + 'static {
+        BroadcastStream::new(self.events.subscribe()).filter_map(|res| res.ok())
+    }
+
+    pub fn send_to_peer(
+        &self,
+        content: &Content,
+        room_uuid: &String,
+        peer_uuid: String,
+        endpoint: &Endpoint,
+        try_prediction: bool,
+    ) -> Result<PendingDelivery, ChatError> {
+        // Keep the model locked until tracking starts so no update is missed
+        let mut model = self.model.lock().unwrap();
+        let uuid = model.send_to_peer(content, room_uuid, peer_uuid, endpoint, try_prediction)?;
+        Ok(self.track(&model, uuid))
+    }
+
+    pub fn send_to_room(
+        &self,
+        content: &Content,
+        room_uuid: &String,
+        try_prediction: bool,
+    ) -> Result<Vec<PendingDelivery>, ChatError> {
+        let mut model = self.model.lock().unwrap();
+        let report = model.send_to_room(content, room_uuid, try_prediction)?;
+        Ok(report
+            .room_msg
+            .messages
+            .into_iter()
+            .map(|uuid| self.track(&model, uuid))
+            .collect())
+    }
+
+    pub fn send_to_rooms(
+        &self,
+        content: &Content,
+        room_uuids: &[String],
+        try_prediction: bool,
+    ) -> Result<Vec<PendingDelivery>, ChatError> {
+        let mut model = self.model.lock().unwrap();
+        let report = model.send_to_rooms(content, room_uuids, try_prediction)?;
+        Ok(report
+            .room_msg
+            .messages
+            .into_iter()
+            .map(|uuid| self.track(&model, uuid))
+            .collect())
+    }
+
+    fn track(&self, model: &ChatModel, uuid: String) -> PendingDelivery {
+        let msg_opt = model.get_message(&uuid);
+        let terminal = msg_opt
+            .as_ref()
+            .is_some_and(|msg| is_given_up(&msg.status) || is_acked(&msg.status));
+        let (tx, updates) = watch::channel(msg_opt);
+        if !terminal {
+            self.trackers.lock().unwrap().insert(uuid.clone(), tx);
+        }
+        PendingDelivery { uuid, updates }
+    }
+}
+
+// Never waits for the model: dropped while it is locked, e.g. from one of its observers, the
+// bridge is only forgotten on the next change of the observers
+impl Drop for AsyncChatModel {
+    fn drop(&mut self) {
+        if let Ok(mut model) = self.model.try_lock() {
+            model.remove_observer(self.observer);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use crate::time::DTChatTime;
+
+// Primary block fields of the bundle a message was received in, given by the BP
+// transports able to read them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BundleMetadata {
+    pub source_eid: String,
+    pub creation_time: DTChatTime,
+    pub lifetime_ms: u64,
+}
+
+impl BundleMetadata {
+    // When the bundle is deleted by the nodes still holding it
+    pub fn expires_at(&self) -> Option<DTChatTime> {
+        DTChatTime::from_timestamp_millis(
+            self.creation_time.timestamp_millis() + self.lifetime_ms as i64,
+        )
+    }
+
+    // From the creation of the bundle to its reception, whatever the chat-level timestamps
+    pub fn time_in_network_ms(&self, received: DTChatTime) -> i64 {
+        received.timestamp_millis() - self.creation_time.timestamp_millis()
+    }
+}
+
+// Kinds of BP status reports, as requested in the bundles sent
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BundleStatus {
+    // Taken by a node on the way
+    Received,
+    Forwarded,
+    // Handed to the destination node, the chat acks it once read from there
+    Delivered,
+    // Dropped by a node, with the reason it gave
+    Deleted(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BundleStatusReport {
+    // Given with the data of the send
+    pub token: String,
+    pub status: BundleStatus,
+    // Node the report comes from
+    pub node_eid: String,
+    // As asserted by the node
+    pub time: DTChatTime,
+}
@@ -0,0 +1,65 @@
+use crate::{message::ChatMessage, time::DTChatTime};
+
+#[derive(Clone, Debug)]
+pub struct RoomCatchUp {
+    pub room_uuid: String,
+    pub count: usize,
+    pub first: DTChatTime,
+    pub last: DTChatTime,
+}
+
+// What was said by the other peers while the link was down
+#[derive(Clone, Debug)]
+pub struct CatchUpSummary {
+    pub remote: String,
+    // None when the remote endpoint is of no known peer
+    pub peer_uuid: Option<String>,
+    pub partition_start: DTChatTime,
+    pub partition_end: DTChatTime,
+    pub rooms: Vec<RoomCatchUp>,
+}
+
+impl CatchUpSummary {
+    pub fn new(
+        remote: String,
+        peer_uuid: Option<String>,
+        partition_start: DTChatTime,
+        partition_end: DTChatTime,
+        messages: &[ChatMessage],
+        local_peer_uuid: &str,
+    ) -> Self {
+        let mut rooms: Vec<RoomCatchUp> = Vec::new();
+        let missed = messages
+            .iter()
+            .filter(|msg| msg.sender_uuid != local_peer_uuid && msg.send_time >= partition_start);
+        for msg in missed {
+            match rooms
+                .iter_mut()
+                .find(|room| room.room_uuid == msg.room_uuid)
+            {
+                Some(room) => {
+                    room.count += 1;
+                    room.first = room.first.min(msg.send_time);
+                    room.last = room.last.max(msg.send_time);
+                }
+                None => rooms.push(RoomCatchUp {
+                    room_uuid: msg.room_uuid.clone(),
+                    count: 1,
+                    first: msg.send_time,
+                    last: msg.send_time,
+                }),
+            }
+        }
+        Self {
+            remote,
+            peer_uuid,
+            partition_start,
+            partition_end,
+            rooms,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.rooms.iter().map(|room| room.count).sum()
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::{HashMap, VecDeque};
+
+// Round trips kept per peer, the oldest are dropped first
+const MAX_SAMPLES: usize = 32;
+
+// Offsets are the peer clock minus the local one, in milliseconds
+#[derive(Clone, Debug, Default)]
+struct PeerSkew {
+    // One per ACK round trip, assuming the same delay both ways
+    samples: VecDeque<i64>,
+    // A received message cannot arrive before it was sent, so the offset is at least
+    // the largest send time minus receive time seen
+    lower_bound: Option<i64>,
+}
+
+impl PeerSkew {
+    fn estimate(&self) -> Option<i64> {
+        let median = if self.samples.is_empty() {
+            None
+        } else {
+            let mut sorted: Vec<i64> = self.samples.iter().copied().collect();
+            sorted.sort_unstable();
+            Some(sorted[sorted.len() / 2])
+        };
+        match (median, self.lower_bound) {
+            (Some(median), Some(bound)) => Some(median.max(bound)),
+            (Some(median), None) => Some(median),
+            // Without round trips only a peer ahead of us is noticed
+            (None, Some(bound)) if bound > 0 => Some(bound),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ClockSkewEstimator {
+    peers: HashMap<String, PeerSkew>,
+}
+
+impl ClockSkewEstimator {
+    // Local send and ack reception times, and the peer time of its ack
+    pub fn record_round_trip(&mut self, peer_uuid: &str, sent: i64, acked: i64, ack_received: i64) {
+        if ack_received < sent {
+            return;
+        }
+        let peer = self.peers.entry(peer_uuid.to_string()).or_default();
+        if peer.samples.len() == MAX_SAMPLES {
+            peer.samples.pop_front();
+        }
+        peer.samples.push_back(acked - (sent + ack_received) / 2);
+    }
+
+    // Peer send time and local receive time of a message
+    pub fn record_reception(&mut self, peer_uuid: &str, sent: i64, received: i64) {
+        let peer = self.peers.entry(peer_uuid.to_string()).or_default();
+        let offset = sent - received;
+        peer.lower_bound = Some(match peer.lower_bound {
+            Some(bound) => bound.max(offset),
+            None => offset,
+        });
+    }
+
+    // Positive when the clock of the peer is ahead of the local one
+    pub fn skew_ms(&self, peer_uuid: &str) -> Option<i64> {
+        self.peers.get(peer_uuid)?.estimate()
+    }
+
+    pub fn forget(&mut self, peer_uuid: &str) {
+        self.peers.remove(peer_uuid);
+    }
+}
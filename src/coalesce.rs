@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+
+use crate::{
+    bundle::BundleStatus,
+    event::{
+        ChatAppEvent, ChatAppInfoEvent, DataEvent, ErrorEvent, NetworkErrorEvent, NetworkEvent,
+    },
+};
+
+// Kind and message uuid of the progress events, the only ones merged
+fn progress_key(event: &ChatAppEvent) -> Option<(&'static str, &str)> {
+    match event {
+        ChatAppEvent::Message(ChatAppInfoEvent::Sending(msg)) => Some(("sending", &msg.uuid)),
+        ChatAppEvent::Message(ChatAppInfoEvent::InCustody(msg)) => Some(("custody", &msg.uuid)),
+        ChatAppEvent::Message(ChatAppInfoEvent::BundleStatus(msg, report))
+            if matches!(
+                report.status,
+                BundleStatus::Received | BundleStatus::Forwarded
+            ) =>
+        {
+            Some(("bundle", &msg.uuid))
+        }
+        ChatAppEvent::SocketEngineInfo(NetworkEvent::Data(DataEvent::Sending { token, .. }, _)) => {
+            Some(("engine", token))
+        }
+        _ => None,
+    }
+}
+
+// Message uuid of the other events about a message, which end its held progress
+fn message_uuid(event: &ChatAppEvent) -> Option<&str> {
+    match event {
+        ChatAppEvent::Message(info) => match info {
+            ChatAppInfoEvent::Sent(msg)
+            | ChatAppInfoEvent::BundleStatus(msg, _)
+            | ChatAppInfoEvent::AckReceived(msg, _)
+            | ChatAppInfoEvent::NackReceived(msg, _)
+            | ChatAppInfoEvent::Refused(msg, _)
+            | ChatAppInfoEvent::Failed(msg)
+            | ChatAppInfoEvent::Expired(msg)
+            | ChatAppInfoEvent::Cancelled(msg) => Some(&msg.uuid),
+            _ => None,
+        },
+        ChatAppEvent::SocketEngineInfo(NetworkEvent::Data(DataEvent::Sent { token, .. }, _)) => {
+            Some(token)
+        }
+        ChatAppEvent::SocketEngineError(NetworkErrorEvent::SocketError(
+            ErrorEvent::ConnectionFailed { token, .. } | ErrorEvent::SendFailed { token, .. },
+            _,
+        )) => Some(token),
+        _ => None,
+    }
+}
+
+struct Held {
+    event: ChatAppEvent,
+    // When the last event of its key was delivered
+    delivered_ms: i64,
+}
+
+// Delivers at most one progress event per kind and message within the window, the
+// latest one held meanwhile. Any other event is delivered at once, dropping the progress
+// held for its message
+pub struct Coalescer {
+    window_ms: i64,
+    last_delivered: HashMap<(&'static str, String), i64>,
+    held: HashMap<(&'static str, String), Held>,
+}
+
+impl Coalescer {
+    pub fn new(window_ms: i64) -> Self {
+        Self {
+            window_ms,
+            last_delivered: HashMap::new(),
+            held: HashMap::new(),
+        }
+    }
+
+    // Events to deliver now, in their order
+    pub fn offer(&mut self, event: ChatAppEvent, now_ms: i64) -> Vec<ChatAppEvent> {
+        let Some((kind, uuid)) = progress_key(&event) else {
+            if let Some(uuid) = message_uuid(&event) {
+                self.held.retain(|(_, held_uuid), _| held_uuid != uuid);
+                self.last_delivered
+                    .retain(|(_, delivered_uuid), _| delivered_uuid != uuid);
+            }
+            return vec![event];
+        };
+        let key = (kind, uuid.to_string());
+        match self.last_delivered.get(&key) {
+            Some(&delivered_ms) if now_ms - delivered_ms < self.window_ms => {
+                self.held.insert(
+                    key,
+                    Held {
+                        event,
+                        delivered_ms,
+                    },
+                );
+                Vec::new()
+            }
+            _ => {
+                self.held.remove(&key);
+                self.last_delivered.insert(key, now_ms);
+                vec![event]
+            }
+        }
+    }
+
+    // The held events whose window is over, to be called periodically
+    pub fn flush(&mut self, now_ms: i64) -> Vec<ChatAppEvent> {
+        let due: Vec<(&'static str, String)> = self
+            .held
+            .iter()
+            .filter(|(_, held)| now_ms - held.delivered_ms >= self.window_ms)
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut events = Vec::new();
+        for key in due {
+            if let Some(held) = self.held.remove(&key) {
+                events.push(held.event);
+                self.last_delivered.insert(key, now_ms);
+            }
+        }
+        events
+    }
+}
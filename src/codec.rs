@@ -0,0 +1,69 @@
+use serde::Deserialize;
+
+use crate::proto::ProtoMessage;
+
+// How the messages sent to a peer are serialized, the received ones are decoded with
+// whichever codec reads them
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WireCodec {
+    #[default]
+    Protobuf,
+    // The protobuf messages as CBOR maps keyed by field name, for the DTN chat
+    // implementations without protobuf
+    #[cfg(feature = "cbor")]
+    Cbor,
+}
+
+// Advertised in the WhoAreYou and IAm messages
+pub fn supported_codecs() -> Vec<String> {
+    let codecs = std::iter::once(WireCodec::Protobuf);
+    #[cfg(feature = "cbor")]
+    let codecs = codecs.chain(std::iter::once(WireCodec::Cbor));
+    codecs.map(|codec| codec.name().to_string()).collect()
+}
+
+impl WireCodec {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WireCodec::Protobuf => "protobuf",
+            #[cfg(feature = "cbor")]
+            WireCodec::Cbor => "cbor",
+        }
+    }
+
+    // The codec configured for a peer once it advertised it in the handshake, protobuf
+    // until then and for the peers advertising no codec
+    pub fn negotiate(self, advertised: Option<&[String]>) -> Self {
+        match advertised {
+            Some(codecs) if codecs.iter().any(|codec| codec == self.name()) => self,
+            _ => WireCodec::Protobuf,
+        }
+    }
+
+    pub fn encode(&self, proto_msg: &ProtoMessage) -> Result<Vec<u8>, String> {
+        match self {
+            WireCodec::Protobuf => proto_msg.encode_to_vec().map_err(|err| err.to_string()),
+            #[cfg(feature = "cbor")]
+            WireCodec::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(proto_msg, &mut bytes).map_err(|err| err.to_string())?;
+                Ok(bytes)
+            }
+        }
+    }
+
+    // A frame of another codec than protobuf, None when none of them reads a message
+    // of a known type
+    #[cfg(feature = "cbor")]
+    pub fn decode_other(frame: &[u8]) -> Option<ProtoMessage> {
+        ciborium::from_reader::<ProtoMessage, _>(frame)
+            .ok()
+            .filter(|proto_msg| proto_msg.msg_type.is_some())
+    }
+
+    #[cfg(not(feature = "cbor"))]
+    pub fn decode_other(_frame: &[u8]) -> Option<ProtoMessage> {
+        None
+    }
+}
@@ -0,0 +1,185 @@
+use serde::Deserialize;
+use std::fmt;
+
+use crate::dtchat::{Peer, Room};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum ConflictPolicy {
+    // Refuse to load a configuration with duplicates
+    #[default]
+    Error,
+    // Give a new uuid/name to every duplicate
+    Rename,
+    // Merge the entries sharing a uuid, rename the ones only sharing a name
+    Merge,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictKind {
+    PeerUuid,
+    PeerName,
+    RoomUuid,
+    RoomName,
+}
+
+#[derive(Clone, Debug)]
+pub struct ConfigConflict {
+    pub kind: ConflictKind,
+    pub key: String,
+    pub resolution: Option<String>,
+}
+
+impl fmt::Display for ConfigConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.kind {
+            ConflictKind::PeerUuid => "peer uuid",
+            ConflictKind::PeerName => "peer name",
+            ConflictKind::RoomUuid => "room uuid",
+            ConflictKind::RoomName => "room name",
+        };
+        write!(f, "duplicate {} '{}'", what, self.key)?;
+        if let Some(resolution) = &self.resolution {
+            write!(f, ": {}", resolution)?;
+        }
+        Ok(())
+    }
+}
+
+trait ConfigEntry {
+    fn uuid(&mut self) -> &mut String;
+    fn name(&mut self) -> &mut String;
+    fn merge(&mut self, other: Self);
+}
+
+impl ConfigEntry for Peer {
+    fn uuid(&mut self) -> &mut String {
+        &mut self.uuid
+    }
+    fn name(&mut self) -> &mut String {
+        &mut self.name
+    }
+    fn merge(&mut self, other: Self) {
+        for endpoint in other.endpoints {
+            if !self.endpoints.contains(&endpoint) {
+                self.endpoints.push(endpoint);
+            }
+        }
+    }
+}
+
+impl ConfigEntry for Room {
+    fn uuid(&mut self) -> &mut String {
+        &mut self.uuid
+    }
+    fn name(&mut self) -> &mut String {
+        &mut self.name
+    }
+    fn merge(&mut self, other: Self) {
+        for participant in other.participants {
+            if !self.participants.contains(&participant) {
+                self.participants.push(participant);
+            }
+        }
+    }
+}
+
+// Appends the first free "-n" suffix to the value
+fn free_variant(value: &str, taken: &[String]) -> String {
+    (2..)
+        .map(|n| format!("{value}-{n}"))
+        .find(|candidate| !taken.contains(candidate))
+        .unwrap()
+}
+
+fn resolve<T: ConfigEntry>(
+    entries: Vec<T>,
+    policy: ConflictPolicy,
+    uuid_kind: ConflictKind,
+    name_kind: ConflictKind,
+) -> Result<(Vec<T>, Vec<ConfigConflict>), ConfigConflict> {
+    let mut resolved: Vec<T> = Vec::new();
+    let mut conflicts = Vec::new();
+    let mut uuids: Vec<String> = Vec::new();
+    let mut names: Vec<String> = Vec::new();
+
+    for mut entry in entries {
+        let uuid = entry.uuid().clone();
+        if let Some(pos) = uuids.iter().position(|u| *u == uuid) {
+            let resolution = match policy {
+                ConflictPolicy::Error => {
+                    return Err(ConfigConflict {
+                        kind: uuid_kind,
+                        key: uuid,
+                        resolution: None,
+                    })
+                }
+                ConflictPolicy::Merge => {
+                    resolved[pos].merge(entry);
+                    conflicts.push(ConfigConflict {
+                        kind: uuid_kind,
+                        key: uuid,
+                        resolution: Some("entries merged".to_string()),
+                    });
+                    continue;
+                }
+                ConflictPolicy::Rename => {
+                    let new_uuid = free_variant(&uuid, &uuids);
+                    *entry.uuid() = new_uuid.clone();
+                    format!("renamed to '{new_uuid}'")
+                }
+            };
+            conflicts.push(ConfigConflict {
+                kind: uuid_kind,
+                key: uuid,
+                resolution: Some(resolution),
+            });
+        }
+
+        let name = entry.name().clone();
+        if names.contains(&name) {
+            if policy == ConflictPolicy::Error {
+                return Err(ConfigConflict {
+                    kind: name_kind,
+                    key: name,
+                    resolution: None,
+                });
+            }
+            let new_name = free_variant(&name, &names);
+            *entry.name() = new_name.clone();
+            conflicts.push(ConfigConflict {
+                kind: name_kind,
+                key: name,
+                resolution: Some(format!("renamed to '{new_name}'")),
+            });
+        }
+
+        uuids.push(entry.uuid().clone());
+        names.push(entry.name().clone());
+        resolved.push(entry);
+    }
+    Ok((resolved, conflicts))
+}
+
+pub fn resolve_peers(
+    peers: Vec<Peer>,
+    policy: ConflictPolicy,
+) -> Result<(Vec<Peer>, Vec<ConfigConflict>), ConfigConflict> {
+    resolve(
+        peers,
+        policy,
+        ConflictKind::PeerUuid,
+        ConflictKind::PeerName,
+    )
+}
+
+pub fn resolve_rooms(
+    rooms: Vec<Room>,
+    policy: ConflictPolicy,
+) -> Result<(Vec<Room>, Vec<ConfigConflict>), ConfigConflict> {
+    resolve(
+        rooms,
+        policy,
+        ConflictKind::RoomUuid,
+        ConflictKind::RoomName,
+    )
+}
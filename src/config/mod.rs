@@ -1,25 +1,394 @@
 use crate::{
-    config::yaml_vec::YamlVec, db::ChatDataBase, dtchat::ASabrInitState,
-    prediction::PredictionConfig,
+    config::{
+        conflicts::{resolve_peers, resolve_rooms, ConfigConflict, ConflictPolicy},
+        validation::{validate, Diagnostic, Severity},
+        yaml_vec::YamlVec,
+    },
+    db::{encryption::Cipher, simple_vec::SimpleVecDB, ChatDataBase},
+    delivery::AckTimeoutConfig,
+    dtchat::{ASabrInitState, Peer, Room},
+    link_health::KeepaliveConfig,
+    net::EndpointProto,
+    prediction::{PredictionConfig, PredictionPolicy},
+    rate_limit::RateLimitConfig,
+    reception::{CollisionPolicy, FilePolicy, RoomReception, RoomReceptionConfig},
+    reconnect::ReconnectConfig,
+    send_queue::SendQueueConfig,
+    soak::SoakConfig,
+    time::{default_time_source, is_valid_format, DisplayPrefs, DisplayTimezone},
 };
+#[cfg(feature = "sled")]
+use crate::db::sled_db::SledDB;
+#[cfg(feature = "webhook")]
+use crate::webhook::WebhookConfig;
 use serde::Deserialize;
 use std::{
-    env, fs,
+    collections::HashMap,
+    env, fmt, fs,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
+pub mod conflicts;
+pub mod validation;
 mod yaml_vec;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            "toml" => Some(ConfigFormat::Toml),
+            "json" => Some(ConfigFormat::Json),
+            _ => None,
+        }
+    }
+
+    // Falls back to YAML for unknown or missing extensions
+    pub fn from_path(path: &Path) -> Self {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Self::from_name)
+            .unwrap_or(ConfigFormat::Yaml)
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub enum DbType {
     YamlVec,
 }
 
+// A named local identity, pointing to one of the declared peers
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProfileConfig {
+    pub name: String,
+    pub peer_uuid: String,
+}
+
+// Beyond this delay, the conversation of the room is considered asynchronous
+#[derive(Debug, Clone, Deserialize)]
+pub struct LatencyBudgetConfig {
+    pub room_uuid: String,
+    pub max_latency_ms: i64,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub db_type: DbType,
     pub file_reception_dir: Option<String>,
+    // Rename (default), PerSender or Reject when a received file name is already taken
+    #[serde(default)]
+    pub file_collision_policy: CollisionPolicy,
+    #[serde(default)]
+    pub room_reception: Vec<RoomReceptionConfig>,
     pub cp_path: Option<String>,
+    // Warn this long before the last contact of the plan, one hour by default
+    pub cp_expiry_warning_ms: Option<i64>,
+    #[serde(default)]
+    pub conflict_policy: ConflictPolicy,
+    #[serde(default)]
+    pub profiles: Vec<ProfileConfig>,
+    // BP sends predicted to take longer are also sent over a connected TCP endpoint
+    pub duplicate_send_threshold_ms: Option<i64>,
+    // Disconnections lasting longer are summarized on reconnect
+    pub catch_up_after_ms: Option<i64>,
+    // Protocols tried in turn when a send fails, e.g. [tcp, udp, bp]
+    #[serde(default)]
+    pub failover_order: Vec<String>,
+    // Sent messages not acked in time are reported, and sent again with retry
+    pub ack_timeout: Option<AckTimeoutConfig>,
+    // Predicted messages not acked this long after their predicted arrival are reported late
+    pub late_delivery_margin_ms: Option<i64>,
+    // Idle TCP connections are probed, and closed when the peer stays silent
+    pub keepalive: Option<KeepaliveConfig>,
+    // Endpoints connected to again when their connection closes, the others wait for a send
+    #[serde(default)]
+    pub reconnect: Vec<ReconnectConfig>,
+    // Lifetime given to every sent message, None to never expire
+    pub message_ttl_ms: Option<i64>,
+    // Messages missing from the sequence of a peer for this long are requested again,
+    // None to never request them
+    pub retransmit_gap_after_ms: Option<i64>,
+    // Messages missing from the sequence of a peer are only reported this long after they
+    // were noticed, 5 seconds when unset
+    pub reorder_window_ms: Option<i64>,
+    #[serde(default)]
+    pub latency_budgets: Vec<LatencyBudgetConfig>,
+    pub soak: Option<SoakConfig>,
+    // Accept the texts and acks of the previous prototype, see legacy::PrototypeDecoder
+    #[serde(default)]
+    pub legacy_frames: bool,
+    // BP messages larger than the budget of the next contact wait for a larger one
+    #[serde(default)]
+    pub defer_oversized: bool,
+    // Which sends get a predicted arrival time: PerSend (as each send asks), Always, Never
+    // or BpOnly
+    #[serde(default)]
+    pub prediction_policy: PredictionPolicy,
+    // Messages still being sent to an endpoint before new ones are refused, None for no limit
+    pub send_queue_capacity: Option<usize>,
+    // Messages sent over these protocols wait in a queue of their own, the others go to
+    // the engine right away
+    #[serde(default)]
+    pub send_queues: Vec<SendQueueConfig>,
+    // Events kept in memory for recent_events and add_observer_with_replay, 256 by default
+    pub event_history_capacity: Option<usize>,
+    // Progress events of a message (sending, custody, bundle forwarding) are delivered to the
+    // observers at most once per window, the latest one held meanwhile. The event history and
+    // the event log still get all of them
+    pub coalesce_window_ms: Option<i64>,
+    // Every event is appended to this JSON lines file when set
+    pub event_log_path: Option<String>,
+    // Every engine event given to the model and every local send made to it is appended to
+    // this JSON lines file when set, to be replayed with replay::replay
+    pub replay_log_path: Option<String>,
+    // Past it the replay log is moved to its .1 file, replacing the previous one. 64 MiB
+    // by default
+    pub replay_log_max_bytes: Option<u64>,
+    // Serves the metrics in the Prometheus text format, e.g. "127.0.0.1:9100"
+    pub metrics_address: Option<String>,
+    // REST and server-sent events gateway for web dashboards, e.g. "127.0.0.1:8080"
+    pub http_address: Option<String>,
+    // Exchange room histories with a peer on reconnection to backfill what was missed
+    #[serde(default)]
+    pub history_sync: bool,
+    // Most recent texts of a room listed in a history digest, 256 by default: the older ones
+    // are not synchronized
+    pub history_digest_size: Option<usize>,
+    // Shift the times given by the peers by their estimated clock skew
+    #[serde(default)]
+    pub correct_clock_skew: bool,
+    // "local" (default), "utc" or an offset such as "+02:00"
+    pub display_timezone: Option<String>,
+    // strftime formats, "%Y-%m-%d" and "%H:%M:%S" by default
+    pub date_format: Option<String>,
+    pub time_format: Option<String>,
+    // Drafts are kept in this JSON file across restarts, only in memory when unset
+    pub drafts_path: Option<String>,
+    // Messages being sent are kept in this JSON file, to be sent again or failed on restart
+    pub pending_sends_path: Option<String>,
+    // Blocked peers are kept in this JSON file across restarts, only in memory when unset
+    pub blocked_peers_path: Option<String>,
+    // Seed of the signing key of the local peer, created when missing. No key is
+    // exchanged without it
+    pub key_path: Option<String>,
+    // Keys of the peers recorded on first contact, only in memory when unset
+    pub peer_keys_path: Option<String>,
+    // Last profile each peer sent, only in memory when unset
+    pub peer_profiles_path: Option<String>,
+    // Numbering of the messages sent is kept in this JSON file across restarts, the peers
+    // see it start over when unset
+    pub sequences_path: Option<String>,
+    // Messages with their timelines, room messages, drafts, pending sends and blocked peers
+    // are kept in this sled database instead, drafts_path, pending_sends_path and
+    // blocked_peers_path are then ignored
+    #[cfg(feature = "sled")]
+    pub sled_path: Option<String>,
+    // Name of the environment variable holding the passphrase the drafts, pending sends,
+    // sled values, event journal and replay log are encrypted with. Stored in plain text
+    // when unset, plain ones are refused when set
+    pub encryption_passphrase_env: Option<String>,
+    // Pins are sent to the other participants of the room
+    #[serde(default)]
+    pub propagate_pins: bool,
+    // Inbound messages per sender, beyond which they are dropped
+    pub rate_limit: Option<RateLimitConfig>,
+    // Bytes of text and of file content accepted by send_to_peer, files also on reception
+    pub max_text_size: Option<u64>,
+    pub max_file_size: Option<u64>,
+    // Extensions of the received files, the names are always sanitized
+    #[serde(default)]
+    pub file_policy: FilePolicy,
+    // Files are offered to the peers with the file_offer capability, their data is only
+    // sent to the peers accepting them
+    #[serde(default)]
+    pub offer_files: bool,
+    #[cfg(feature = "webhook")]
+    pub webhook: Option<WebhookConfig>,
+}
+
+pub fn parse_proto(name: &str) -> Option<EndpointProto> {
+    match name.to_lowercase().as_str() {
+        "tcp" => Some(EndpointProto::Tcp),
+        "udp" => Some(EndpointProto::Udp),
+        "bp" => Some(EndpointProto::Bp),
+        _ => None,
+    }
+}
+
+impl Config {
+    // Unknown names are reported by the validation
+    pub fn failover_protocols(&self) -> Vec<EndpointProto> {
+        self.failover_order
+            .iter()
+            .filter_map(|name| parse_proto(name))
+            .collect()
+    }
+
+    // Invalid values are reported by the validation and left to their default
+    pub fn display_prefs(&self) -> DisplayPrefs {
+        let mut prefs = DisplayPrefs::default();
+        if let Some(timezone) = self.display_timezone.as_deref().and_then(DisplayTimezone::parse) {
+            prefs.timezone = timezone;
+        }
+        if let Some(format) = self.date_format.as_ref().filter(|f| is_valid_format(f)) {
+            prefs.date_format = format.clone();
+        }
+        if let Some(format) = self.time_format.as_ref().filter(|f| is_valid_format(f)) {
+            prefs.time_format = format.clone();
+        }
+        prefs
+    }
+}
+
+pub struct LoadedConfig {
+    pub local_peer: Peer,
+    pub peers: Vec<Peer>,
+    pub rooms: Vec<Room>,
+    pub reception_folder: PathBuf,
+    pub room_reception: HashMap<String, RoomReception>,
+    pub latency_budgets: HashMap<String, i64>,
+    pub conflicts: Vec<ConfigConflict>,
+    pub warnings: Vec<Diagnostic>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ConfigDiff {
+    pub added_peers: Vec<String>,
+    pub removed_peers: Vec<String>,
+    pub updated_peers: Vec<String>,
+    pub added_rooms: Vec<String>,
+    pub removed_rooms: Vec<String>,
+    pub updated_rooms: Vec<String>,
+    pub local_peer_updated: bool,
+    pub reception_folder: Option<PathBuf>,
+    pub room_reception_updated: bool,
+}
+
+// Returns the (added, removed, updated) uuids
+fn diff_by_uuid<T: PartialEq>(
+    old: &HashMap<String, T>,
+    new: &HashMap<String, T>,
+) -> (Vec<String>, Vec<String>, Vec<String>) {
+    let mut added = Vec::new();
+    let mut updated = Vec::new();
+    for (uuid, item) in new {
+        match old.get(uuid) {
+            None => added.push(uuid.clone()),
+            Some(previous) if previous != item => updated.push(uuid.clone()),
+            Some(_) => {}
+        }
+    }
+    let removed = old
+        .keys()
+        .filter(|uuid| !new.contains_key(*uuid))
+        .cloned()
+        .collect();
+    (added, removed, updated)
+}
+
+impl ConfigDiff {
+    pub fn new(
+        old_local_peer: &Peer,
+        old_peers: &HashMap<String, Peer>,
+        old_rooms: &HashMap<String, Room>,
+        old_reception_folder: &PathBuf,
+        old_room_reception: &HashMap<String, RoomReception>,
+        new: &LoadedConfig,
+    ) -> Self {
+        let new_peers = new
+            .peers
+            .iter()
+            .map(|p| (p.uuid.clone(), p.clone()))
+            .collect();
+        let new_rooms = new
+            .rooms
+            .iter()
+            .map(|r| (r.uuid.clone(), r.clone()))
+            .collect();
+        let (added_peers, removed_peers, updated_peers) = diff_by_uuid(old_peers, &new_peers);
+        let (added_rooms, removed_rooms, updated_rooms) = diff_by_uuid(old_rooms, &new_rooms);
+
+        Self {
+            added_peers,
+            removed_peers,
+            updated_peers,
+            added_rooms,
+            removed_rooms,
+            updated_rooms,
+            local_peer_updated: *old_local_peer != new.local_peer,
+            reception_folder: if *old_reception_folder != new.reception_folder {
+                Some(new.reception_folder.clone())
+            } else {
+                None
+            },
+            room_reception_updated: *old_room_reception != new.room_reception,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added_peers.is_empty()
+            && self.removed_peers.is_empty()
+            && self.updated_peers.is_empty()
+            && self.added_rooms.is_empty()
+            && self.removed_rooms.is_empty()
+            && self.updated_rooms.is_empty()
+            && !self.local_peer_updated
+            && self.reception_folder.is_none()
+            && !self.room_reception_updated
+    }
+}
+
+impl fmt::Display for ConfigDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no changes");
+        }
+        let mut parts = Vec::new();
+        for (uuids, what) in [
+            (&self.added_peers, "peer(s) added"),
+            (&self.removed_peers, "peer(s) removed"),
+            (&self.updated_peers, "peer(s) updated"),
+            (&self.added_rooms, "room(s) added"),
+            (&self.removed_rooms, "room(s) removed"),
+            (&self.updated_rooms, "room(s) updated"),
+        ] {
+            if !uuids.is_empty() {
+                parts.push(format!("{} {} [{}]", uuids.len(), what, uuids.join(", ")));
+            }
+        }
+        if self.local_peer_updated {
+            // Listeners are only started once, new local endpoints need a restart
+            parts.push("local peer updated (restart to listen on new endpoints)".to_string());
+        }
+        if let Some(folder) = &self.reception_folder {
+            parts.push(format!("reception folder set to {}", folder.to_string_lossy()));
+        }
+        if self.room_reception_updated {
+            parts.push("room reception folders updated".to_string());
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+pub struct AppSetup {
+    pub db: Box<dyn ChatDataBase>,
+    // Of the database, the event journal and the replay log, None without passphrase
+    pub cipher: Option<Arc<Cipher>>,
+    pub a_sabr: ASabrInitState,
+    pub reception_folder: PathBuf,
+    pub room_reception: HashMap<String, RoomReception>,
+    pub latency_budgets: HashMap<String, i64>,
+    pub conflicts: Vec<ConfigConflict>,
+    pub warnings: Vec<Diagnostic>,
+    pub config: Config,
+    pub config_path: String,
 }
 
 pub struct AppConfig {}
@@ -28,64 +397,252 @@ impl AppConfig {
     const DEFAULT_FILE_RECEPTION_DIR: &str = "./";
     const DEFAULT_CONFIG_PATH_VALUE: &str = "default.yaml";
     const DEFAULT_CONFIG_PATH_ENV_VAR: &str = "CONFIG_PATH";
+    const CONFIG_FORMAT_ENV_VAR: &str = "CONFIG_FORMAT";
+    const PEER_UUID_ENV_VAR: &str = "PEER_UUID";
 
-    pub fn new() -> (Box<dyn ChatDataBase>, ASabrInitState, PathBuf) {
-        let config_file = match std::env::var(Self::DEFAULT_CONFIG_PATH_ENV_VAR) {
-            Ok(path) => path,
-            Err(_) => {
-                println!(
-                    "{} is not set, trying with {}",
-                    Self::DEFAULT_CONFIG_PATH_ENV_VAR,
-                    Self::DEFAULT_CONFIG_PATH_VALUE
-                );
-                Self::DEFAULT_CONFIG_PATH_VALUE.to_string()
-            }
-        };
+    fn config_file() -> String {
+        std::env::var(Self::DEFAULT_CONFIG_PATH_ENV_VAR)
+            .unwrap_or_else(|_| Self::DEFAULT_CONFIG_PATH_VALUE.to_string())
+    }
+
+    // Makes the folder absolute and creates it, None if it cannot be created
+    fn prepare_dir(base: &str) -> Option<PathBuf> {
+        let mut path = Path::new(base).to_path_buf();
+        if !path.is_absolute() {
+            path = env::current_dir()
+                .unwrap_or_else(|_| PathBuf::from(base))
+                .join(path);
+        }
+        fs::create_dir_all(&path).ok().map(|_| path)
+    }
+
+    fn reception_folder(conf: &Config) -> PathBuf {
+        // Use config path or default
+        let base = conf
+            .file_reception_dir
+            .as_deref()
+            .unwrap_or(Self::DEFAULT_FILE_RECEPTION_DIR);
+
+        // Fallback to default on failure
+        Self::prepare_dir(base).unwrap_or_else(|| PathBuf::from(Self::DEFAULT_FILE_RECEPTION_DIR))
+    }
+
+    fn room_reception(
+        conf: &Config,
+    ) -> Result<HashMap<String, RoomReception>, Box<dyn std::error::Error>> {
+        let mut rooms = HashMap::new();
+        for room_conf in &conf.room_reception {
+            // Silently falling back to the shared folder would defeat the quota
+            let dir = Self::prepare_dir(&room_conf.dir).ok_or_else(|| {
+                format!(
+                    "Unable to create reception folder '{}' of room '{}'",
+                    room_conf.dir, room_conf.room_uuid
+                )
+            })?;
+            rooms.insert(
+                room_conf.room_uuid.clone(),
+                RoomReception {
+                    dir,
+                    quota_bytes: room_conf.quota_bytes,
+                },
+            );
+        }
+        Ok(rooms)
+    }
+
+    // Without profile, the local peer is given by the PEER_UUID environment variable
+    pub fn new(profile: Option<&str>) -> AppSetup {
+        if std::env::var(Self::DEFAULT_CONFIG_PATH_ENV_VAR).is_err() {
+            tracing::info!(
+                "{} is not set, trying with {}",
+                Self::DEFAULT_CONFIG_PATH_ENV_VAR,
+                Self::DEFAULT_CONFIG_PATH_VALUE
+            );
+        }
+        let config_file = Self::config_file();
 
         let conf: Config = Self::from_file(&config_file).unwrap_or_else(|e| {
             panic!("Failed to load configuration from '{config_file}': {e}");
         });
 
-        let db = match conf.db_type {
-            DbType::YamlVec => YamlVec::new(&config_file),
+        Self::local_peer_uuid(&conf, profile)
+            .and_then(|local_peer_uuid| Self::setup(&config_file, conf, &local_peer_uuid))
+            .unwrap_or_else(|e| {
+                panic!("Failed to load configuration from '{config_file}': {e}");
+            })
+    }
+
+    // Ignores the environment, for embedding several nodes in the same process
+    pub fn for_peer(
+        config_file: &str,
+        local_peer_uuid: &str,
+    ) -> Result<AppSetup, Box<dyn std::error::Error>> {
+        let conf: Config = Self::from_file(config_file)?;
+        Self::setup(config_file, conf, local_peer_uuid)
+    }
+
+    fn cipher(conf: &Config) -> std::io::Result<Option<Arc<Cipher>>> {
+        let Some(var) = &conf.encryption_passphrase_env else {
+            return Ok(None);
         };
+        let passphrase = env::var(var).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("the passphrase is expected in {}", var),
+            )
+        })?;
+        Ok(Some(Arc::new(Cipher::new(passphrase))))
+    }
 
-        let file_reception_path: PathBuf = {
-            // Use config path or default
-            let base = conf
-                .file_reception_dir
-                .as_deref()
-                .unwrap_or(Self::DEFAULT_FILE_RECEPTION_DIR);
-
-            // Make absolute
-            let mut path = Path::new(base).to_path_buf();
-            if !path.is_absolute() {
-                path = env::current_dir()
-                    .unwrap_or_else(|_| PathBuf::from(base))
-                    .join(path);
-            }
+    // A sled database when sled_path is set, the in-memory one with its optional files otherwise
+    fn open_db(
+        conf: &Config,
+        local_peer: Peer,
+        peers: Vec<Peer>,
+        rooms: Vec<Room>,
+        cipher: Option<Arc<Cipher>>,
+    ) -> std::io::Result<Box<dyn ChatDataBase>> {
+        #[cfg(feature = "sled")]
+        if let Some(sled_path) = &conf.sled_path {
+            return Ok(Box::new(SledDB::open(
+                sled_path, local_peer, peers, rooms, cipher,
+            )?));
+        }
+        let mut db = SimpleVecDB::new(Vec::new(), local_peer, peers, rooms);
+        if let Some(cipher) = cipher {
+            db = db.with_cipher(cipher);
+        }
+        if let Some(drafts_path) = &conf.drafts_path {
+            db = db.with_drafts_file(drafts_path)?;
+        }
+        if let Some(pending_sends_path) = &conf.pending_sends_path {
+            db = db.with_pending_sends_file(pending_sends_path)?;
+        }
+        if let Some(blocked_peers_path) = &conf.blocked_peers_path {
+            db = db.with_blocked_peers_file(blocked_peers_path)?;
+        }
+        Ok(Box::new(db))
+    }
 
-            // Ensure directory exists, fallback to default on failure
-            if fs::create_dir_all(&path).is_err() {
-                PathBuf::from(Self::DEFAULT_FILE_RECEPTION_DIR)
-            } else {
-                path
+    fn setup(
+        config_file: &str,
+        conf: Config,
+        local_peer_uuid: &str,
+    ) -> Result<AppSetup, Box<dyn std::error::Error>> {
+        let loaded = Self::load(config_file, &conf, local_peer_uuid)?;
+
+        let a_sabr = match &conf.cp_path {
+            Some(cp_path) => {
+                match PredictionConfig::try_init(
+                    cp_path.clone(),
+                    "VolCgrHybridParenting",
+                    default_time_source(),
+                ) {
+                    Ok(pred_conf) => ASabrInitState::Enabled(pred_conf),
+                    Err(err) => ASabrInitState::Error(err.to_string()),
+                }
             }
+            None => ASabrInitState::Disabled,
         };
 
-        let cp_path_unwrapped = match conf.cp_path {
-            Some(cp) => cp,
-            None => {
-                return (db, ASabrInitState::Disabled, file_reception_path);
-            }
+        let cipher = Self::cipher(&conf)?;
+        let db = Self::open_db(
+            &conf,
+            loaded.local_peer,
+            loaded.peers,
+            loaded.rooms,
+            cipher.clone(),
+        )?;
+
+        Ok(AppSetup {
+            db,
+            cipher,
+            a_sabr,
+            reception_folder: loaded.reception_folder,
+            room_reception: loaded.room_reception,
+            latency_budgets: loaded.latency_budgets,
+            conflicts: loaded.conflicts,
+            warnings: loaded.warnings,
+            config: conf,
+            config_path: config_file.to_string(),
+        })
+    }
+
+    // Re-reads the configuration file, without touching the messages nor the prediction state
+    pub fn reload(profile: Option<&str>) -> Result<LoadedConfig, Box<dyn std::error::Error>> {
+        let config_file = Self::config_file();
+        let conf: Config = Self::from_file(&config_file)?;
+        let local_peer_uuid = Self::local_peer_uuid(&conf, profile)?;
+        Self::load(&config_file, &conf, &local_peer_uuid)
+    }
+
+    fn local_peer_uuid(
+        conf: &Config,
+        profile: Option<&str>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        match profile {
+            Some(name) => conf
+                .profiles
+                .iter()
+                .find(|p| p.name == name)
+                .map(|p| p.peer_uuid.clone())
+                .ok_or_else(|| format!("Unknown profile '{name}'").into()),
+            None => std::env::var(Self::PEER_UUID_ENV_VAR)
+                .map_err(|_| format!("{} must be set", Self::PEER_UUID_ENV_VAR).into()),
+        }
+    }
+
+    fn load(
+        config_file: &str,
+        conf: &Config,
+        local_peer_uuid: &str,
+    ) -> Result<LoadedConfig, Box<dyn std::error::Error>> {
+        let (all_peers, rooms) = match conf.db_type {
+            DbType::YamlVec => YamlVec::load(config_file)?,
         };
 
-        let pred_res = PredictionConfig::try_init(cp_path_unwrapped,"VolCgrHybridParenting");
-        let pred_opt = match pred_res {
-            Ok(pred_conf) => ASabrInitState::Enabled(pred_conf),
-            Err(err) => ASabrInitState::Error(err.to_string()),
+        let (errors, warnings): (Vec<Diagnostic>, Vec<Diagnostic>) =
+            validate(&all_peers, &rooms, local_peer_uuid, conf)
+                .into_iter()
+                .partition(|diagnostic| diagnostic.severity == Severity::Error);
+        if !errors.is_empty() {
+            let report: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+            return Err(format!("Invalid configuration:\n{}", report.join("\n")).into());
+        }
+
+        let (all_peers, mut conflicts) = resolve_peers(all_peers, conf.conflict_policy)
+            .map_err(|conflict| format!("Configuration conflict: {conflict}"))?;
+        let (rooms, room_conflicts) = resolve_rooms(rooms, conf.conflict_policy)
+            .map_err(|conflict| format!("Configuration conflict: {conflict}"))?;
+        conflicts.extend(room_conflicts);
+
+        let mut local_peer_opt = None;
+        let mut peers: Vec<Peer> = Vec::new();
+        for p in all_peers {
+            if p.uuid == local_peer_uuid {
+                local_peer_opt = Some(p)
+            } else {
+                peers.push(p);
+            }
+        }
+        let Some(local_peer) = local_peer_opt else {
+            return Err(format!("Failed identify localpeer with uuid '{local_peer_uuid}'").into());
         };
-        (db, pred_opt, file_reception_path)
+
+        Ok(LoadedConfig {
+            local_peer,
+            peers,
+            rooms,
+            reception_folder: Self::reception_folder(conf),
+            room_reception: Self::room_reception(conf)?,
+            latency_budgets: conf
+                .latency_budgets
+                .iter()
+                .map(|budget| (budget.room_uuid.clone(), budget.max_latency_ms))
+                .collect(),
+            conflicts,
+            warnings,
+        })
     }
 
     pub fn from_file<T, P>(path: P) -> Result<T, Box<dyn std::error::Error>>
@@ -93,8 +650,17 @@ impl AppConfig {
         T: for<'de> Deserialize<'de>,
         P: AsRef<std::path::Path>,
     {
+        let format = match std::env::var(Self::CONFIG_FORMAT_ENV_VAR) {
+            Ok(value) => ConfigFormat::from_name(&value)
+                .ok_or_else(|| format!("Unknown configuration format '{value}'"))?,
+            Err(_) => ConfigFormat::from_path(path.as_ref()),
+        };
         let content = fs::read_to_string(path)?;
-        let config: T = serde_yaml::from_str(&content)?;
+        let config: T = match format {
+            ConfigFormat::Yaml => serde_yaml::from_str(&content)?,
+            ConfigFormat::Toml => toml::from_str(&content)?,
+            ConfigFormat::Json => serde_json::from_str(&content)?,
+        };
         Ok(config)
     }
 }
@@ -0,0 +1,268 @@
+use std::{fmt, path::Path};
+
+use crate::{
+    config::{conflicts::ConflictPolicy, parse_proto, Config},
+    dtchat::{Peer, Room},
+    net::EndpointProto,
+    send_queue::FlushTrigger,
+    time::{is_valid_format, DisplayTimezone},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub hint: String,
+}
+
+impl Diagnostic {
+    fn error(message: String, hint: &str) -> Self {
+        Self {
+            severity: Severity::Error,
+            message,
+            hint: hint.to_string(),
+        }
+    }
+
+    pub(crate) fn warning(message: String, hint: &str) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message,
+            hint: hint.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}: {} ({})", level, self.message, self.hint)
+    }
+}
+
+fn check_duplicates(peers: &[Peer], rooms: &[Room], diagnostics: &mut Vec<Diagnostic>) {
+    for (i, peer) in peers.iter().enumerate() {
+        if peers[..i].iter().any(|p| p.uuid == peer.uuid) {
+            diagnostics.push(Diagnostic::error(
+                format!("peer uuid '{}' is declared more than once", peer.uuid),
+                "give each peer a unique uuid or set conflict_policy to Rename or Merge",
+            ));
+        }
+    }
+    for (i, room) in rooms.iter().enumerate() {
+        if rooms[..i].iter().any(|r| r.uuid == room.uuid) {
+            diagnostics.push(Diagnostic::error(
+                format!("room uuid '{}' is declared more than once", room.uuid),
+                "give each room a unique uuid or set conflict_policy to Rename or Merge",
+            ));
+        }
+    }
+}
+
+fn check_rooms(peers: &[Peer], rooms: &[Room], diagnostics: &mut Vec<Diagnostic>) {
+    for room in rooms {
+        for (peer_uuid, endpoint) in &room.participants {
+            match peers.iter().find(|p| p.uuid == *peer_uuid) {
+                None => diagnostics.push(Diagnostic::error(
+                    format!(
+                        "room '{}' references unknown peer '{}'",
+                        room.name, peer_uuid
+                    ),
+                    "declare the peer in peer_list or remove it from the participants",
+                )),
+                Some(peer) if !peer.endpoints.contains(endpoint) => {
+                    diagnostics.push(Diagnostic::warning(
+                        format!(
+                            "room '{}' reaches peer '{}' on {} which is not one of its endpoints",
+                            room.name,
+                            peer_uuid,
+                            endpoint.to_string()
+                        ),
+                        "add the endpoint to the peer or fix the participant endpoint",
+                    ))
+                }
+                Some(_) => {}
+            }
+        }
+    }
+}
+
+fn check_endpoints(peers: &[Peer], diagnostics: &mut Vec<Diagnostic>) {
+    for (i, peer) in peers.iter().enumerate() {
+        for endpoint in &peer.endpoints {
+            for other in peers[..i].iter().filter(|p| p.uuid != peer.uuid) {
+                if other.endpoints.contains(endpoint) {
+                    diagnostics.push(Diagnostic::error(
+                        format!(
+                            "peers '{}' and '{}' both use {}",
+                            other.uuid,
+                            peer.uuid,
+                            endpoint.to_string()
+                        ),
+                        "only one peer can listen on a given address and port",
+                    ));
+                }
+            }
+        }
+    }
+}
+
+fn check_preferences(peers: &[Peer], diagnostics: &mut Vec<Diagnostic>) {
+    for peer in peers {
+        for preference in &peer.preferences {
+            if !peer.endpoints.contains(&preference.endpoint) {
+                diagnostics.push(Diagnostic::error(
+                    format!(
+                        "peer '{}' prefers {} which is not one of its endpoints",
+                        peer.uuid,
+                        preference.endpoint.to_string()
+                    ),
+                    "add the endpoint to the peer or remove it from its preferences",
+                ));
+            }
+        }
+    }
+}
+
+fn check_prediction(
+    peers: &[Peer],
+    local_peer_uuid: &str,
+    cp_path: &str,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    if !Path::new(cp_path).exists() {
+        diagnostics.push(Diagnostic::warning(
+            format!("contact plan '{}' does not exist", cp_path),
+            "fix cp_path or remove it to disable prediction",
+        ));
+    }
+    for peer in peers {
+        if peer
+            .endpoints
+            .iter()
+            .any(|ep| ep.proto == EndpointProto::Bp)
+        {
+            continue;
+        }
+        if peer.uuid == local_peer_uuid {
+            diagnostics.push(Diagnostic::warning(
+                format!("local peer '{}' has no BP endpoint", peer.uuid),
+                "add a bp endpoint to the local peer, no prediction can be made without it",
+            ));
+        } else {
+            diagnostics.push(Diagnostic::warning(
+                format!("peer '{}' has no BP endpoint", peer.uuid),
+                "add a bp endpoint to the peer to get predictions for it",
+            ));
+        }
+    }
+}
+
+fn check_failover(conf: &Config, diagnostics: &mut Vec<Diagnostic>) {
+    for name in &conf.failover_order {
+        if parse_proto(name).is_none() {
+            diagnostics.push(Diagnostic::error(
+                format!("unknown protocol '{}' in failover_order", name),
+                "use tcp, udp or bp",
+            ));
+        }
+    }
+}
+
+fn check_send_queues(conf: &Config, diagnostics: &mut Vec<Diagnostic>) {
+    let mut seen = Vec::new();
+    for queue in &conf.send_queues {
+        let Some(proto) = parse_proto(&queue.proto) else {
+            diagnostics.push(Diagnostic::error(
+                format!("unknown protocol '{}' in send_queues", queue.proto),
+                "use tcp, udp or bp",
+            ));
+            continue;
+        };
+        if seen.contains(&proto) {
+            diagnostics.push(Diagnostic::warning(
+                format!(
+                    "several send queues for {}, the first one is used",
+                    queue.proto
+                ),
+                "keep a single queue per protocol",
+            ));
+        } else if queue.flush == FlushTrigger::ContactStart && proto != EndpointProto::Bp {
+            diagnostics.push(Diagnostic::warning(
+                format!("{} queue flushed at contact start", queue.proto),
+                "only BP peers have predicted contacts, their messages would never be sent",
+            ));
+        } else if queue.flush == FlushTrigger::ContactStart && conf.cp_path.is_none() {
+            diagnostics.push(Diagnostic::warning(
+                format!(
+                    "{} queue flushed at contact start without contact plan",
+                    queue.proto
+                ),
+                "set cp_path, its messages are sent right away until then",
+            ));
+        }
+        if queue.max_in_flight == Some(0) {
+            diagnostics.push(Diagnostic::error(
+                format!("max_in_flight of the {} queue is 0", queue.proto),
+                "allow at least one message in flight",
+            ));
+        }
+        seen.push(proto);
+    }
+}
+
+fn check_display(conf: &Config, diagnostics: &mut Vec<Diagnostic>) {
+    if let Some(timezone) = &conf.display_timezone {
+        if DisplayTimezone::parse(timezone).is_none() {
+            diagnostics.push(Diagnostic::warning(
+                format!("unknown display_timezone '{}', local time is used", timezone),
+                "use local, utc or an offset such as +02:00",
+            ));
+        }
+    }
+    let formats = [
+        ("date_format", &conf.date_format),
+        ("time_format", &conf.time_format),
+    ];
+    for (key, format) in formats {
+        if let Some(format) = format.as_ref().filter(|f| !is_valid_format(f)) {
+            diagnostics.push(Diagnostic::warning(
+                format!("invalid {} '{}', the default is used", key, format),
+                "use strftime specifiers such as %H:%M",
+            ));
+        }
+    }
+}
+
+// Runs on the entries as declared, before any conflict resolution
+pub fn validate(
+    peers: &[Peer],
+    rooms: &[Room],
+    local_peer_uuid: &str,
+    conf: &Config,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    // Other policies resolve duplicates on their own
+    if conf.conflict_policy == ConflictPolicy::Error {
+        check_duplicates(peers, rooms, &mut diagnostics);
+    }
+    check_rooms(peers, rooms, &mut diagnostics);
+    check_endpoints(peers, &mut diagnostics);
+    check_preferences(peers, &mut diagnostics);
+    check_failover(conf, &mut diagnostics);
+    check_send_queues(conf, &mut diagnostics);
+    check_display(conf, &mut diagnostics);
+    if let Some(cp_path) = &conf.cp_path {
+        check_prediction(peers, local_peer_uuid, cp_path, &mut diagnostics);
+    }
+    diagnostics
+}
@@ -1,14 +1,14 @@
 use crate::{
+    codec::WireCodec,
     config::AppConfig,
-    db::{simple_vec::SimpleVecDB, ChatDataBase},
-    dtchat::{Peer, Room},
+    dtchat::{EndpointPreference, Peer, Room, RoomTransport, TrustLevel},
+    net::Endpoint,
 };
 use serde::{
     de::{self, Visitor},
     Deserialize, Deserializer,
 };
-use socket_engine::endpoint::Endpoint;
-use std::fmt;
+use std::{error::Error, fmt};
 
 #[derive(Clone, Debug)]
 pub struct EndpointWrapper(pub Endpoint);
@@ -47,12 +47,41 @@ impl From<EndpointWrapper> for Endpoint {
     }
 }
 
+#[derive(Clone, Debug, Deserialize)]
+pub struct RawPreference {
+    pub endpoint: EndpointWrapper,
+    #[serde(default)]
+    pub weight: u32,
+    #[serde(default)]
+    pub when_reachable: bool,
+}
+
+impl From<RawPreference> for EndpointPreference {
+    fn from(raw: RawPreference) -> Self {
+        EndpointPreference {
+            endpoint: raw.endpoint.into(),
+            weight: raw.weight,
+            when_reachable: raw.when_reachable,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct RawPeer {
     pub uuid: String,
     pub name: String,
     pub endpoints: Vec<EndpointWrapper>,
     pub color: String,
+    #[serde(default)]
+    pub trust: TrustLevel,
+    // protobuf (default) or cbor, for the messages sent to the peer
+    #[serde(default)]
+    pub codec: WireCodec,
+    // Endpoints tried in turn by send_to_preferred, highest weight first
+    #[serde(default)]
+    pub preferences: Vec<RawPreference>,
+    // Sent with the name and color of the local peer in its profile
+    pub avatar_hash: Option<String>,
 }
 
 impl From<RawPeer> for Peer {
@@ -62,6 +91,10 @@ impl From<RawPeer> for Peer {
             name: raw.name,
             color: raw.color,
             endpoints: raw.endpoints.into_iter().map(|e| e.into()).collect(),
+            trust: raw.trust,
+            codec: raw.codec,
+            preferences: raw.preferences.into_iter().map(|p| p.into()).collect(),
+            avatar_hash: raw.avatar_hash,
         }
     }
 }
@@ -77,6 +110,11 @@ pub struct RawRoom {
     pub uuid: String,
     pub name: String,
     pub participants: Vec<Registration>,
+    // BP endpoint delivering to every participant, e.g. a group EID
+    pub bundle_endpoint: Option<EndpointWrapper>,
+    // registered (default), bp-only, prefer-tcp or prefer-bp
+    #[serde(default)]
+    pub transport: RoomTransport,
 }
 
 #[derive(Debug, Deserialize)]
@@ -86,34 +124,12 @@ pub struct YamlVec {
 }
 
 impl YamlVec {
-    pub fn new(config_file: &str) -> Box<dyn ChatDataBase> {
-        const PEER_ENV_VAR: &str = "PEER_UUID";
+    // Returns every declared peer, local one included, and room as is
+    pub fn load(config_file: &str) -> Result<(Vec<Peer>, Vec<Room>), Box<dyn Error>> {
+        let conf: YamlVec = AppConfig::from_file(&config_file)?;
 
-        let local_peer_uuid = match std::env::var(PEER_ENV_VAR) {
-            Ok(uuid) => uuid,
-            Err(_) => {
-                panic!("{} must be set with the YamlVec Method", PEER_ENV_VAR);
-            }
-        };
-
-        let conf: YamlVec = AppConfig::from_file(&config_file).unwrap_or_else(|e| {
-            panic!("Failed to load configuration from '{config_file}': {e}");
-        });
-
-        let mut local_peer_opt = None;
-
-        let mut peers: Vec<Peer> = Vec::new();
-        for p in conf.peer_list {
-            if p.uuid == local_peer_uuid {
-                local_peer_opt = Some(p.clone())
-            } else {
-                peers.push(Peer::from(p));
-            }
-        }
+        let peers = conf.peer_list.into_iter().map(Peer::from).collect();
 
-        let Some(local_peer) = local_peer_opt else {
-            panic!("Failed identify localpeer with uuid '{local_peer_uuid}'")
-        };
         let mut rooms: Vec<Room> = Vec::new();
         for raw_room in conf.room_list {
             let mut registrations: Vec<(String, Endpoint)> = Vec::new();
@@ -124,14 +140,11 @@ impl YamlVec {
                 uuid: raw_room.uuid,
                 name: raw_room.name,
                 participants: registrations,
+                bundle_endpoint: raw_room.bundle_endpoint.map(Endpoint::from),
+                transport: raw_room.transport,
             })
         }
 
-        Box::new(SimpleVecDB::new(
-            Vec::new(),
-            Peer::from(local_peer),
-            peers,
-            rooms,
-        ))
+        Ok((peers, rooms))
     }
 }
@@ -0,0 +1,129 @@
+use std::{collections::HashMap, io, sync::Mutex};
+
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Key, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use pbkdf2::pbkdf2_hmac;
+use rand_core::{OsRng, RngCore};
+use sha2::Sha256;
+
+// Starts every encrypted file and value, the plain ones are JSON
+const MAGIC: &[u8] = b"DTCHATENC1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const PBKDF2_ROUNDS: u32 = 600_000;
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Key<Aes256Gcm> {
+    let mut key = [0u8; 32];
+    pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, PBKDF2_ROUNDS, &mut key);
+    *Key::<Aes256Gcm>::from_slice(&key)
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+// AES-256-GCM with keys derived from a passphrase by PBKDF2-SHA256. Every file or value
+// carries the salt of its key and its nonce: <magic> <salt> <nonce> <ciphertext>
+pub struct Cipher {
+    passphrase: String,
+    // Of the values written by this run
+    salt: [u8; SALT_LEN],
+    // Per salt, the derivation being slow on purpose
+    keys: Mutex<HashMap<[u8; SALT_LEN], Key<Aes256Gcm>>>,
+}
+
+impl Cipher {
+    pub fn new(passphrase: String) -> Self {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let key = derive_key(&passphrase, &salt);
+        Self {
+            passphrase,
+            salt,
+            keys: Mutex::new(HashMap::from([(salt, key)])),
+        }
+    }
+
+    fn key(&self, salt: [u8; SALT_LEN]) -> Key<Aes256Gcm> {
+        *self
+            .keys
+            .lock()
+            .unwrap()
+            .entry(salt)
+            .or_insert_with(|| derive_key(&self.passphrase, &salt))
+    }
+
+    pub fn encrypt(&self, plain: &[u8]) -> io::Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let ciphertext = Aes256Gcm::new(&self.key(self.salt))
+            .encrypt(Nonce::from_slice(&nonce), plain)
+            .map_err(|_| invalid("encryption failed"))?;
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&self.salt);
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&ciphertext);
+        Ok(data)
+    }
+
+    // Fails on a wrong passphrase as on altered data
+    pub fn decrypt(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let header = data
+            .strip_prefix(MAGIC)
+            .filter(|rest| rest.len() >= SALT_LEN + NONCE_LEN)
+            .ok_or_else(|| invalid("not encrypted"))?;
+        let (salt, rest) = header.split_at(SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let salt: [u8; SALT_LEN] = salt.try_into().unwrap();
+        Aes256Gcm::new(&self.key(salt))
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| invalid("wrong passphrase or corrupted data"))
+    }
+}
+
+// With a cipher, plain data is refused: whoever can write the files could otherwise have
+// their content taken as is
+pub fn open(cipher: Option<&Cipher>, data: &[u8]) -> io::Result<Vec<u8>> {
+    match cipher {
+        Some(cipher) if is_encrypted(data) => cipher.decrypt(data),
+        Some(_) => Err(invalid(
+            "not encrypted, plain data is refused once a passphrase is given",
+        )),
+        None if is_encrypted(data) => Err(invalid(
+            "encrypted, the passphrase must be given with encryption_passphrase_env",
+        )),
+        None => Ok(data.to_vec()),
+    }
+}
+
+pub fn seal(cipher: Option<&Cipher>, data: Vec<u8>) -> io::Result<Vec<u8>> {
+    match cipher {
+        Some(cipher) => cipher.encrypt(&data),
+        None => Ok(data),
+    }
+}
+
+// A line of an append-only log, encrypted on its own and written in base64 with a cipher
+pub fn seal_line(cipher: Option<&Cipher>, line: String) -> io::Result<String> {
+    match cipher {
+        Some(cipher) => Ok(STANDARD.encode(cipher.encrypt(line.as_bytes())?)),
+        None => Ok(line),
+    }
+}
+
+pub fn open_line(cipher: Option<&Cipher>, line: &str) -> io::Result<String> {
+    let data = match cipher {
+        Some(_) => STANDARD.decode(line.trim()).map_err(|_| {
+            invalid("not encrypted, plain lines are refused once a passphrase is given")
+        })?,
+        None => line.as_bytes().to_vec(),
+    };
+    String::from_utf8(open(cipher, &data)?).map_err(|_| invalid("not UTF-8"))
+}
@@ -0,0 +1,98 @@
+use std::{io, path::Path};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    db::encryption::{self, Cipher},
+    files,
+};
+
+// Version of the files written by the persistent databases, to be increased with a new
+// step in MIGRATIONS whenever the layout of one of their stores changes
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Store {
+    Drafts,
+    PendingSends,
+    PeerKeys,
+    BlockedPeers,
+    Sequences,
+    PeerProfiles,
+}
+
+// Turns the data of a store from one version into the next one
+type Migration = fn(Store, Value) -> io::Result<Value>;
+
+// MIGRATIONS[n] upgrades version n to version n + 1
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] = [
+    // Version 0 is the bare data written before the stores were versioned,
+    // version 1 only wraps it with its version
+    |_, data| Ok(data),
+];
+
+fn invalid(store: Store, reason: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{:?} store: {}", store, reason),
+    )
+}
+
+// Version and data of a stored file, unversioned files being version 0
+fn split_version(value: Value) -> (u32, Value) {
+    match value {
+        Value::Object(mut fields) if fields.contains_key("schema_version") => {
+            let version = fields["schema_version"].as_u64().unwrap_or(u64::MAX);
+            let data = fields.remove("data").unwrap_or(Value::Null);
+            (u32::try_from(version).unwrap_or(u32::MAX), data)
+        }
+        data => (0, data),
+    }
+}
+
+// None when there is no file yet. Files of older versions are migrated and written back,
+// those of newer versions are refused rather than misread. With a cipher, plain files are
+// refused
+pub fn read_store<T: Serialize + DeserializeOwned>(
+    store: Store,
+    path: &Path,
+    cipher: Option<&Cipher>,
+) -> io::Result<Option<T>> {
+    let content = match files::read(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let content =
+        encryption::open(cipher, &content).map_err(|err| invalid(store, err.to_string()))?;
+    let (version, mut data) = split_version(serde_json::from_slice(&content)?);
+    if version > SCHEMA_VERSION {
+        return Err(invalid(
+            store,
+            format!(
+                "schema version {} is newer than the supported {}",
+                version, SCHEMA_VERSION
+            ),
+        ));
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        data = migration(store, data)?;
+    }
+    let data: T = serde_json::from_value(data).map_err(|err| invalid(store, err.to_string()))?;
+    if version < SCHEMA_VERSION {
+        write_store(path, &data, cipher)?;
+    }
+    Ok(Some(data))
+}
+
+pub fn write_store<T: Serialize>(path: &Path, data: &T, cipher: Option<&Cipher>) -> io::Result<()> {
+    let content = json!({
+        "schema_version": SCHEMA_VERSION,
+        "data": data,
+    });
+    files::write(
+        path,
+        &encryption::seal(cipher, serde_json::to_vec_pretty(&content)?)?,
+    )
+}
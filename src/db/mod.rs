@@ -1,26 +1,144 @@
-use std::collections::HashMap;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    io,
+};
+
+use serde::{Deserialize, Serialize};
 
 use crate::{
+    db::simple_vec::Snapshot,
+    delivery::{DeliveryStage, TimelineEntry},
     dtchat::{Peer, Room},
-    message::ChatMessage,
+    export::ExportedMessage,
+    message::{ChatMessage, RoomMessage},
+    stats::Statistics,
     time::DTChatTime,
 };
+pub mod encryption;
+pub mod migration;
 pub mod simple_vec;
+#[cfg(feature = "sled")]
+pub mod sled_db;
 
 pub enum MarkIntent {
     Acked(DTChatTime),
     Sent(DTChatTime),
+    InCustody,
+    DeliveredToNode,
     Failed,
+    Expired,
+    Cancelled,
+    Refused,
+    // Seen by the local user, the status is left untouched
+    ReadLocally,
+    Pinned(bool),
+}
+
+impl MarkIntent {
+    // None for the intents leaving the status untouched. The stages without a time of their
+    // own are at now
+    pub fn stage(&self, now: DTChatTime) -> Option<(DeliveryStage, DTChatTime)> {
+        let stage = match self {
+            MarkIntent::Acked(time) => return Some((DeliveryStage::Acked, *time)),
+            MarkIntent::Sent(time) => return Some((DeliveryStage::Sent, *time)),
+            MarkIntent::InCustody => DeliveryStage::InCustody,
+            MarkIntent::DeliveredToNode => DeliveryStage::DeliveredToNode,
+            MarkIntent::Failed => DeliveryStage::Failed,
+            MarkIntent::Expired => DeliveryStage::Expired,
+            MarkIntent::Cancelled => DeliveryStage::Cancelled,
+            MarkIntent::Refused => DeliveryStage::Refused,
+            MarkIntent::ReadLocally | MarkIntent::Pinned(_) => return None,
+        };
+        Some((stage, now))
+    }
+}
+
+// A local message handed to the engine and not yet reported sent, failed nor given up
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingSend {
+    pub message: ExportedMessage,
+    pub peer_uuid: String,
+    pub endpoint: String,
+    // Of the room bundle the message is a replica of, the uuid it goes by on the wire
+    #[serde(default)]
+    pub bundle_uuid: Option<String>,
+}
+
+// A message of a type this node does not know, kept as received for a later version to
+// read
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QuarantinedMessage {
+    pub uuid: String,
+    pub sender_uuid: String,
+    pub room_uuid: String,
+    // Version of the sender, when it answered a node info query
+    pub sender_version: Option<String>,
+    pub received_at_ms: i64,
+    pub data: Vec<u8>,
 }
 
 pub trait ChatDataBase: Send + Sync {
+    // Version of the persisted stores, see migration::SCHEMA_VERSION
+    fn schema_version(&self) -> u32;
+    // Checkpoint of the conversation state, None for the databases that cannot roll back
+    fn snapshot(&self) -> Option<Snapshot>;
+    fn restore(&mut self, snapshot: Snapshot) -> io::Result<()>;
     fn get_rooms(&self) -> &HashMap<String, Room>;
+    fn set_rooms(&mut self, rooms: Vec<Room>);
     // Peers
     fn get_other_peers(&self) -> &HashMap<String, Peer>;
     fn get_localpeer(&self) -> &Peer;
+    fn set_peers(&mut self, localpeer: Peer, peers: Vec<Peer>);
     // Messages
     fn get_last_messages(&self, count: usize) -> &[ChatMessage];
+    // Oldest first, like get_last_messages
+    fn get_last_messages_for_room(&self, room_uuid: &String, count: usize) -> Vec<ChatMessage>;
     fn get_all_messages(&self) -> &Vec<ChatMessage>;
+    // Oldest first, for the scans that must not clone the history: lent by the databases
+    // holding it in memory, read one message at a time by the persistent ones
+    fn iter_messages(&self) -> Box<dyn Iterator<Item = Cow<'_, ChatMessage>> + '_>;
+    fn get_message(&self, uuid: &String) -> Option<ChatMessage>;
+    // False when a message with the same uuid is stored already, it is left as it is
     fn add_message(&mut self, msg: ChatMessage) -> bool;
-    fn mark_as(&mut self, uuid: &String, intent: MarkIntent) -> Option<ChatMessage>;
+    // Replaces the message with the same uuid, false when there is none
+    fn replace_message(&mut self, msg: ChatMessage) -> bool;
+    // Along with their timelines, pending sends and replica links, returns how many were
+    // stored
+    fn remove_messages(&mut self, uuids: &HashSet<String>) -> io::Result<usize>;
+    // Status intents are also added to the timeline of the message and end its pending send,
+    // at now unless they carry their time
+    fn mark_as(&mut self, uuid: &String, intent: MarkIntent, now: DTChatTime)
+        -> Option<ChatMessage>;
+    // Stages of the delivery of a message ordered by time, adding and marking a message
+    // record theirs
+    fn add_timeline_entry(&mut self, uuid: &String, entry: TimelineEntry);
+    fn get_timeline(&self, uuid: &String) -> &[TimelineEntry];
+    // Pending sends, kept across restarts when the database persists them
+    fn add_pending_send(&mut self, pending: PendingSend) -> io::Result<()>;
+    fn remove_pending_send(&mut self, uuid: &String) -> io::Result<()>;
+    fn get_pending_sends(&self) -> &[PendingSend];
+    // Writes the changes held back, called from the tick so that a burst of sends is one
+    // write rather than one per send. Also reports the writes that failed since the last
+    // call in the methods returning no error
+    fn flush(&mut self) -> io::Result<()>;
+    // Room messages, linking a message sent to a room to its per-peer replicas
+    fn add_room_message(&mut self, room_msg: RoomMessage);
+    fn get_room_message(&self, uuid: &String) -> Option<&RoomMessage>;
+    fn get_room_message_for_replica(&self, replica_uuid: &String) -> Option<&RoomMessage>;
+    // Unfinished messages per room uuid, saving an empty text removes the draft
+    fn save_draft(&mut self, room_uuid: &String, text: String) -> io::Result<()>;
+    fn get_draft(&self, room_uuid: &String) -> Option<&String>;
+    // Peer uuids blocked or muted by the local user
+    fn set_blocked(&mut self, peer_uuid: &String, blocked: bool) -> io::Result<()>;
+    fn get_blocked_peers(&self) -> &HashSet<String>;
+    fn set_muted(&mut self, peer_uuid: &String, muted: bool);
+    fn get_muted_peers(&self) -> &HashSet<String>;
+    // Messages of unknown types, oldest first
+    fn quarantine(&mut self, msg: QuarantinedMessage) -> io::Result<()>;
+    // False when no message of this uuid is quarantined
+    fn remove_quarantined(&mut self, uuid: &str) -> io::Result<bool>;
+    fn get_quarantined(&self) -> &[QuarantinedMessage];
+    // Statistics
+    fn get_statistics(&self) -> Statistics;
 }
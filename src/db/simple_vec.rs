@@ -1,16 +1,67 @@
-use std::collections::HashMap;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use crate::{
-    db::{ChatDataBase, MarkIntent},
+    db::{
+        encryption::Cipher,
+        migration::{self, read_store, write_store, Store},
+        ChatDataBase, MarkIntent, PendingSend, QuarantinedMessage,
+    },
+    delivery::{DeliveryStage, TimelineEntry},
     dtchat::{Peer, Room},
-    message::{ChatMessage, MessageStatus},
+    message::{ChatMessage, MessageStatus, RoomMessage},
+    stats::Statistics,
+    time::DTChatTime,
 };
 
+// Stages kept per message, the first one and the last ones. A message retried over and over
+// would otherwise grow its timeline without end
+const MAX_TIMELINE_ENTRIES: usize = 64;
+// Timelines of messages not stored, e.g. sends that failed before the message was added,
+// dropped once there are more
+const MAX_ORPHAN_TIMELINES: usize = 256;
+
+// Conversation state of a SimpleVecDB, the peers and rooms come from the configuration
+// and are not part of it
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    messages: Vec<ChatMessage>,
+    room_messages: Vec<RoomMessage>,
+    drafts: HashMap<String, String>,
+    blocked_peers: HashSet<String>,
+    muted_peers: HashSet<String>,
+    timelines: HashMap<String, Vec<TimelineEntry>>,
+    pending_sends: Vec<PendingSend>,
+}
+
 pub struct SimpleVecDB {
     messages: Vec<ChatMessage>,
     localpeer: Peer,
     peers: HashMap<String, Peer>,
     rooms: HashMap<String, Room>,
+    room_messages: Vec<RoomMessage>,
+    drafts: HashMap<String, String>,
+    // Drafts are only kept in memory without it
+    drafts_path: Option<PathBuf>,
+    blocked_peers: HashSet<String>,
+    muted_peers: HashSet<String>,
+    // Per message uuid
+    timelines: HashMap<String, Vec<TimelineEntry>>,
+    pending_sends: Vec<PendingSend>,
+    // Pending sends are only kept in memory without it
+    pending_sends_path: Option<PathBuf>,
+    // Changed since last written by flush
+    pending_sends_dirty: bool,
+    // Blocked peers are only kept in memory without it
+    blocked_peers_path: Option<PathBuf>,
+    quarantined: Vec<QuarantinedMessage>,
+    // Of the drafts and pending sends files, written in plain JSON without it
+    cipher: Option<Arc<Cipher>>,
 }
 
 impl SimpleVecDB {
@@ -20,31 +71,138 @@ impl SimpleVecDB {
         peers: Vec<Peer>,
         rooms: Vec<Room>,
     ) -> Self {
-        let mut peer_map = HashMap::new();
-        let mut room_map = HashMap::new();
+        let mut db = Self {
+            messages,
+            localpeer: localpeer.clone(),
+            peers: HashMap::new(),
+            rooms: HashMap::new(),
+            room_messages: Vec::new(),
+            drafts: HashMap::new(),
+            drafts_path: None,
+            blocked_peers: HashSet::new(),
+            muted_peers: HashSet::new(),
+            timelines: HashMap::new(),
+            pending_sends: Vec::new(),
+            pending_sends_path: None,
+            pending_sends_dirty: false,
+            blocked_peers_path: None,
+            quarantined: Vec::new(),
+            cipher: None,
+        };
+        db.set_peers(localpeer, peers);
+        db.set_rooms(rooms);
+        db
+    }
 
-        peers.iter().for_each(|p| {
-            peer_map.insert(p.uuid.clone(), p.clone());
-        });
+    // To be given before the files
+    pub fn with_cipher(mut self, cipher: Arc<Cipher>) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
 
-        rooms.iter().for_each(|r| {
-            room_map.insert(r.uuid.clone(), r.clone());
-        });
+    // Drafts are restored from the file, if any, and written back on every change
+    pub fn with_drafts_file(mut self, path: &str) -> io::Result<Self> {
+        if let Some(drafts) = read_store(Store::Drafts, Path::new(path), self.cipher.as_deref())? {
+            self.drafts = drafts;
+        }
+        self.drafts_path = Some(PathBuf::from(path));
+        Ok(self)
+    }
 
-        Self {
-            messages,
-            localpeer,
-            peers: peer_map,
-            rooms: room_map,
+    // Pending sends are restored from the file, if any, and written back by flush
+    pub fn with_pending_sends_file(mut self, path: &str) -> io::Result<Self> {
+        if let Some(pending_sends) =
+            read_store(Store::PendingSends, Path::new(path), self.cipher.as_deref())?
+        {
+            self.pending_sends = pending_sends;
+        }
+        self.pending_sends_path = Some(PathBuf::from(path));
+        Ok(self)
+    }
+
+    // Blocked peers are restored from the file, if any, and written back on every change
+    pub fn with_blocked_peers_file(mut self, path: &str) -> io::Result<Self> {
+        if let Some(blocked_peers) =
+            read_store(Store::BlockedPeers, Path::new(path), self.cipher.as_deref())?
+        {
+            self.blocked_peers = blocked_peers;
+        }
+        self.blocked_peers_path = Some(PathBuf::from(path));
+        Ok(self)
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            messages: self.messages.clone(),
+            room_messages: self.room_messages.clone(),
+            drafts: self.drafts.clone(),
+            blocked_peers: self.blocked_peers.clone(),
+            muted_peers: self.muted_peers.clone(),
+            timelines: self.timelines.clone(),
+            pending_sends: self.pending_sends.clone(),
+        }
+    }
+
+    // The drafts, blocked peers and pending sends files, if any, are written back with the
+    // restored state
+    pub fn restore(&mut self, snapshot: Snapshot) -> io::Result<()> {
+        self.messages = snapshot.messages;
+        self.room_messages = snapshot.room_messages;
+        self.drafts = snapshot.drafts;
+        self.blocked_peers = snapshot.blocked_peers;
+        self.muted_peers = snapshot.muted_peers;
+        self.timelines = snapshot.timelines;
+        self.pending_sends = snapshot.pending_sends;
+        self.write_drafts()?;
+        self.write_blocked_peers()?;
+        self.write_pending_sends()
+    }
+
+    fn write_drafts(&self) -> io::Result<()> {
+        match &self.drafts_path {
+            Some(path) => write_store(path, &self.drafts, self.cipher.as_deref()),
+            None => Ok(()),
+        }
+    }
+
+    fn write_blocked_peers(&self) -> io::Result<()> {
+        match &self.blocked_peers_path {
+            Some(path) => write_store(path, &self.blocked_peers, self.cipher.as_deref()),
+            None => Ok(()),
+        }
+    }
+
+    fn write_pending_sends(&self) -> io::Result<()> {
+        match &self.pending_sends_path {
+            Some(path) => write_store(path, &self.pending_sends, self.cipher.as_deref()),
+            None => Ok(()),
         }
     }
 }
 
 impl ChatDataBase for SimpleVecDB {
+    fn schema_version(&self) -> u32 {
+        migration::SCHEMA_VERSION
+    }
+
+    fn snapshot(&self) -> Option<Snapshot> {
+        Some(SimpleVecDB::snapshot(self))
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) -> io::Result<()> {
+        SimpleVecDB::restore(self, snapshot)
+    }
+
     // Peers
     fn get_rooms(&self) -> &HashMap<String, Room> {
         return &self.rooms;
     }
+    fn set_rooms(&mut self, rooms: Vec<Room>) {
+        self.rooms.clear();
+        rooms.iter().for_each(|r| {
+            self.rooms.insert(r.uuid.clone(), r.clone());
+        });
+    }
 
     // Peers
     fn get_other_peers(&self) -> &HashMap<String, Peer> {
@@ -53,6 +211,13 @@ impl ChatDataBase for SimpleVecDB {
     fn get_localpeer(&self) -> &Peer {
         return &self.localpeer;
     }
+    fn set_peers(&mut self, localpeer: Peer, peers: Vec<Peer>) {
+        self.localpeer = localpeer;
+        self.peers.clear();
+        peers.iter().for_each(|p| {
+            self.peers.insert(p.uuid.clone(), p.clone());
+        });
+    }
 
     // Messages
     fn get_last_messages(&self, count: usize) -> &[ChatMessage] {
@@ -61,16 +226,102 @@ impl ChatDataBase for SimpleVecDB {
         &self.messages[start..]
     }
 
+    // Scans back from the most recent message, stopping once count are found
+    fn get_last_messages_for_room(&self, room_uuid: &String, count: usize) -> Vec<ChatMessage> {
+        let mut messages: Vec<ChatMessage> = self
+            .messages
+            .iter()
+            .rev()
+            .filter(|msg| msg.in_room(room_uuid))
+            .take(count)
+            .cloned()
+            .collect();
+        messages.reverse();
+        messages
+    }
+
     fn add_message(&mut self, msg: ChatMessage) -> bool {
+        if self.messages.iter().any(|message| message.uuid == msg.uuid) {
+            return false;
+        }
+        let entry = if msg.sender_uuid == self.localpeer.uuid {
+            TimelineEntry {
+                stage: DeliveryStage::Created,
+                time: msg.send_time,
+            }
+        } else {
+            TimelineEntry {
+                stage: DeliveryStage::Received,
+                time: msg.receive_time.unwrap_or(msg.send_time),
+            }
+        };
+        self.add_timeline_entry(&msg.uuid, entry);
         self.messages.push(msg);
         true
     }
 
+    fn replace_message(&mut self, msg: ChatMessage) -> bool {
+        match self.messages.iter_mut().find(|message| message.uuid == msg.uuid) {
+            Some(message) => {
+                *message = msg;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove_messages(&mut self, uuids: &HashSet<String>) -> io::Result<usize> {
+        let count = self.messages.len();
+        self.messages
+            .retain(|message| !uuids.contains(&message.uuid));
+        self.timelines.retain(|uuid, _| !uuids.contains(uuid));
+        for room_msg in &mut self.room_messages {
+            let (messages, peers) = room_msg
+                .messages
+                .iter()
+                .zip(&room_msg.peers)
+                .filter(|(replica, _)| !uuids.contains(*replica))
+                .map(|(replica, peer)| (replica.clone(), peer.clone()))
+                .unzip();
+            room_msg.messages = messages;
+            room_msg.peers = peers;
+        }
+        self.room_messages
+            .retain(|room_msg| !room_msg.messages.is_empty());
+        let pending_count = self.pending_sends.len();
+        self.pending_sends
+            .retain(|pending| !uuids.contains(&pending.message.uuid));
+        if self.pending_sends.len() != pending_count {
+            self.pending_sends_dirty = true;
+        }
+        Ok(count - self.messages.len())
+    }
+
     fn get_all_messages(&self) -> &Vec<ChatMessage> {
         &self.messages
     }
 
-    fn mark_as(&mut self, uuid: &String, intent: super::MarkIntent) -> Option<ChatMessage> {
+    fn iter_messages(&self) -> Box<dyn Iterator<Item = Cow<'_, ChatMessage>> + '_> {
+        Box::new(self.messages.iter().map(Cow::Borrowed))
+    }
+
+    fn get_message(&self, uuid: &String) -> Option<ChatMessage> {
+        self.messages.iter().find(|msg| msg.uuid == *uuid).cloned()
+    }
+
+    fn mark_as(
+        &mut self,
+        uuid: &String,
+        intent: super::MarkIntent,
+        now: DTChatTime,
+    ) -> Option<ChatMessage> {
+        if let Some((stage, time)) = intent.stage(now) {
+            if self.messages.iter().any(|message| message.uuid == *uuid) {
+                self.add_timeline_entry(uuid, TimelineEntry { stage, time });
+            }
+            // A failed write keeps the entry, it is reconciled again on the next start
+            let _ = self.remove_pending_send(uuid);
+        }
         for message in &mut self.messages {
             if message.uuid == *uuid {
                 match intent {
@@ -81,17 +332,175 @@ impl ChatDataBase for SimpleVecDB {
                         return Some(message.clone());
                     }
                     MarkIntent::Sent(date_time) => {
-                        message.send_completed = Some(date_time);
+                        // The first completion is kept when the message is sent again
+                        message.send_completed.get_or_insert(date_time);
                         message.status = MessageStatus::Sent;
                         return Some(message.clone());
                     }
+                    MarkIntent::InCustody => {
+                        message.status = MessageStatus::InCustody;
+                        return Some(message.clone());
+                    }
+                    MarkIntent::DeliveredToNode => {
+                        message.status = MessageStatus::DeliveredToNode;
+                        return Some(message.clone());
+                    }
                     MarkIntent::Failed => {
                         message.status = MessageStatus::Failed;
                         return Some(message.clone());
                     }
+                    MarkIntent::Expired => {
+                        message.status = MessageStatus::Expired;
+                        return Some(message.clone());
+                    }
+                    MarkIntent::Cancelled => {
+                        message.status = MessageStatus::Cancelled;
+                        return Some(message.clone());
+                    }
+                    MarkIntent::Refused => {
+                        message.status = MessageStatus::Refused;
+                        return Some(message.clone());
+                    }
+                    MarkIntent::ReadLocally => {
+                        message.read_locally = true;
+                        return Some(message.clone());
+                    }
+                    MarkIntent::Pinned(pinned) => {
+                        message.pinned = pinned;
+                        return Some(message.clone());
+                    }
                 }
             }
         }
         None
     }
+
+    fn add_timeline_entry(&mut self, uuid: &String, entry: TimelineEntry) {
+        if !self.timelines.contains_key(uuid)
+            && self.timelines.len() >= self.messages.len() + MAX_ORPHAN_TIMELINES
+        {
+            let stored: HashSet<&String> = self.messages.iter().map(|msg| &msg.uuid).collect();
+            self.timelines.retain(|uuid, _| stored.contains(uuid));
+        }
+        let timeline = self.timelines.entry(uuid.clone()).or_default();
+        // Entries may be given out of order, a send time being known before the message is added
+        let pos = timeline.partition_point(|known| known.time <= entry.time);
+        timeline.insert(pos, entry);
+        if timeline.len() > MAX_TIMELINE_ENTRIES {
+            timeline.remove(1);
+        }
+    }
+
+    fn get_timeline(&self, uuid: &String) -> &[TimelineEntry] {
+        self.timelines
+            .get(uuid)
+            .map_or(&[], |timeline| timeline.as_slice())
+    }
+
+    // Pending sends
+    fn add_pending_send(&mut self, pending: PendingSend) -> io::Result<()> {
+        self.pending_sends
+            .retain(|known| known.message.uuid != pending.message.uuid);
+        self.pending_sends.push(pending);
+        self.pending_sends_dirty = true;
+        Ok(())
+    }
+
+    fn remove_pending_send(&mut self, uuid: &String) -> io::Result<()> {
+        let count = self.pending_sends.len();
+        self.pending_sends
+            .retain(|pending| pending.message.uuid != *uuid);
+        if self.pending_sends.len() != count {
+            self.pending_sends_dirty = true;
+        }
+        Ok(())
+    }
+
+    fn get_pending_sends(&self) -> &[PendingSend] {
+        &self.pending_sends
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.pending_sends_dirty {
+            return Ok(());
+        }
+        self.write_pending_sends()?;
+        self.pending_sends_dirty = false;
+        Ok(())
+    }
+
+    // Room messages
+    fn add_room_message(&mut self, room_msg: RoomMessage) {
+        self.room_messages.push(room_msg);
+    }
+
+    fn get_room_message(&self, uuid: &String) -> Option<&RoomMessage> {
+        self.room_messages.iter().find(|room_msg| room_msg.uuid == *uuid)
+    }
+
+    fn get_room_message_for_replica(&self, replica_uuid: &String) -> Option<&RoomMessage> {
+        self.room_messages
+            .iter()
+            .find(|room_msg| room_msg.messages.contains(replica_uuid))
+    }
+
+    // Drafts
+    fn save_draft(&mut self, room_uuid: &String, text: String) -> io::Result<()> {
+        if text.is_empty() {
+            self.drafts.remove(room_uuid);
+        } else {
+            self.drafts.insert(room_uuid.clone(), text);
+        }
+        self.write_drafts()
+    }
+
+    fn get_draft(&self, room_uuid: &String) -> Option<&String> {
+        self.drafts.get(room_uuid)
+    }
+
+    // Blocked and muted peers
+    fn set_blocked(&mut self, peer_uuid: &String, blocked: bool) -> io::Result<()> {
+        if blocked {
+            self.blocked_peers.insert(peer_uuid.clone());
+        } else {
+            self.blocked_peers.remove(peer_uuid);
+        }
+        self.write_blocked_peers()
+    }
+
+    fn get_blocked_peers(&self) -> &HashSet<String> {
+        &self.blocked_peers
+    }
+
+    fn set_muted(&mut self, peer_uuid: &String, muted: bool) {
+        if muted {
+            self.muted_peers.insert(peer_uuid.clone());
+        } else {
+            self.muted_peers.remove(peer_uuid);
+        }
+    }
+
+    fn get_muted_peers(&self) -> &HashSet<String> {
+        &self.muted_peers
+    }
+
+    // Quarantine, only kept in memory
+    fn quarantine(&mut self, msg: QuarantinedMessage) -> io::Result<()> {
+        self.quarantined.push(msg);
+        Ok(())
+    }
+
+    fn remove_quarantined(&mut self, uuid: &str) -> io::Result<bool> {
+        let count = self.quarantined.len();
+        self.quarantined.retain(|msg| msg.uuid != uuid);
+        Ok(self.quarantined.len() != count)
+    }
+
+    fn get_quarantined(&self) -> &[QuarantinedMessage] {
+        &self.quarantined
+    }
+
+    fn get_statistics(&self) -> Statistics {
+        Statistics::new(self.messages.iter(), &self.localpeer.uuid, &self.peers)
+    }
 }
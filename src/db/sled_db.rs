@@ -0,0 +1,664 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    io,
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+use sled::{IVec, Tree};
+
+use crate::{
+    bundle::BundleMetadata,
+    db::{
+        encryption::{self, Cipher},
+        migration::SCHEMA_VERSION,
+        simple_vec::{SimpleVecDB, Snapshot},
+        ChatDataBase, MarkIntent, PendingSend, QuarantinedMessage,
+    },
+    delivery::{DeliveryStage, TimelineEntry},
+    dtchat::{Peer, Room},
+    export::ExportedMessage,
+    file_info::FileInfo,
+    message::{ChatMessage, RoomMessage},
+    net::Endpoint,
+    prediction::PredictionSkip,
+    stats::Statistics,
+    time::DTChatTime,
+};
+
+const SCHEMA_KEY: &[u8] = b"schema_version";
+
+// Sorts as the times, negative ones included
+fn millis_key(millis: i64) -> [u8; 8] {
+    ((millis as u64) ^ (1 << 63)).to_be_bytes()
+}
+
+fn time_key(time: DTChatTime) -> [u8; 8] {
+    millis_key(time.timestamp_millis())
+}
+
+// <room uuid> 0 <send time> <uuid>, the room uuids never contain a NUL byte
+fn room_key(room_uuid: &str, time: Option<DTChatTime>) -> Vec<u8> {
+    let mut key = room_uuid.as_bytes().to_vec();
+    key.push(0);
+    if let Some(time) = time {
+        key.extend_from_slice(&time_key(time));
+    }
+    key
+}
+
+// One room key per room the message is shown in
+fn index_keys(msg: &ChatMessage) -> (Vec<Vec<u8>>, Vec<u8>) {
+    let by_room = std::iter::once(&msg.room_uuid)
+        .chain(&msg.also_rooms)
+        .map(|room_uuid| {
+            let mut key = room_key(room_uuid, Some(msg.send_time));
+            key.extend_from_slice(msg.uuid.as_bytes());
+            key
+        })
+        .collect();
+    let mut by_time = time_key(msg.send_time).to_vec();
+    by_time.extend_from_slice(msg.uuid.as_bytes());
+    (by_room, by_time)
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredBundle {
+    source_eid: String,
+    creation_time_ms: i64,
+    lifetime_ms: u64,
+}
+
+// A whole message, its export along with what the export leaves out. Values written before
+// these fields were kept read as defaults
+#[derive(Serialize, Deserialize)]
+struct StoredMessage {
+    #[serde(flatten)]
+    exported: ExportedMessage,
+    #[serde(default)]
+    received_from: Option<String>,
+    #[serde(default)]
+    bundle: Option<StoredBundle>,
+    #[serde(default)]
+    vector_clock: HashMap<String, u64>,
+    #[serde(default)]
+    lamport_time: u64,
+    // None in older values, read as read like imported messages
+    #[serde(default)]
+    read_locally: Option<bool>,
+    #[serde(default)]
+    pinned: bool,
+    #[serde(default)]
+    muted: bool,
+    #[serde(default)]
+    stored_path: Option<String>,
+    #[serde(default)]
+    prediction_skipped: Option<PredictionSkip>,
+    #[serde(default)]
+    file_info: Option<FileInfo>,
+    #[serde(default)]
+    sequence: u64,
+}
+
+impl From<&ChatMessage> for StoredMessage {
+    fn from(msg: &ChatMessage) -> Self {
+        Self {
+            exported: ExportedMessage::from(msg),
+            received_from: msg.received_from.as_ref().map(|from| from.to_string()),
+            bundle: msg.bundle.as_ref().map(|bundle| StoredBundle {
+                source_eid: bundle.source_eid.clone(),
+                creation_time_ms: bundle.creation_time.timestamp_millis(),
+                lifetime_ms: bundle.lifetime_ms,
+            }),
+            vector_clock: msg.vector_clock.clone(),
+            lamport_time: msg.lamport_time,
+            read_locally: Some(msg.read_locally),
+            pinned: msg.pinned,
+            muted: msg.muted,
+            stored_path: msg.stored_path.clone(),
+            prediction_skipped: msg.prediction_skipped,
+            file_info: msg.file_info.clone(),
+            sequence: msg.sequence,
+        }
+    }
+}
+
+impl StoredMessage {
+    // None when an endpoint or a time cannot be parsed back
+    fn into_message(self) -> Option<ChatMessage> {
+        let mut msg = self.exported.into_message()?;
+        msg.received_from = match self.received_from {
+            Some(from) => Some(Endpoint::from_str(&from).ok()?),
+            None => None,
+        };
+        msg.bundle = match self.bundle {
+            Some(bundle) => Some(BundleMetadata {
+                source_eid: bundle.source_eid,
+                creation_time: DTChatTime::from_timestamp_millis(bundle.creation_time_ms)?,
+                lifetime_ms: bundle.lifetime_ms,
+            }),
+            None => None,
+        };
+        msg.vector_clock = self.vector_clock;
+        msg.lamport_time = self.lamport_time;
+        msg.read_locally = self.read_locally.unwrap_or(msg.read_locally);
+        msg.pinned = self.pinned;
+        msg.muted = self.muted;
+        msg.stored_path = self.stored_path;
+        msg.prediction_skipped = self.prediction_skipped;
+        msg.file_info = self.file_info;
+        msg.sequence = self.sequence;
+        Some(msg)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredEntry {
+    stage: DeliveryStage,
+    time_ms: i64,
+}
+
+fn decode_message(value: &[u8], cipher: Option<&Cipher>) -> io::Result<ChatMessage> {
+    let stored: StoredMessage = serde_json::from_slice(&encryption::open(cipher, value)?)?;
+    let uuid = stored.exported.uuid.clone();
+    stored.into_message().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("stored message {} cannot be read back", uuid),
+        )
+    })
+}
+
+// Messages of an index, in its order
+fn load_messages(
+    messages: &Tree,
+    index: impl Iterator<Item = sled::Result<(IVec, IVec)>>,
+    cipher: Option<&Cipher>,
+) -> io::Result<Vec<ChatMessage>> {
+    let mut loaded = Vec::new();
+    for entry in index {
+        let (_, uuid) = entry?;
+        if let Some(value) = messages.get(&uuid)? {
+            loaded.push(decode_message(&value, cipher)?);
+        }
+    }
+    Ok(loaded)
+}
+
+fn check_schema_version(meta: &Tree) -> io::Result<()> {
+    let Some(stored) = meta.get(SCHEMA_KEY)? else {
+        meta.insert(SCHEMA_KEY, SCHEMA_VERSION.to_be_bytes().to_vec())?;
+        return Ok(());
+    };
+    let version = <[u8; 4]>::try_from(stored.as_ref())
+        .map(u32::from_be_bytes)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "unreadable schema version"))?;
+    // No earlier sled schema to migrate from yet
+    if version > SCHEMA_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "schema version {} is newer than the supported {}",
+                version, SCHEMA_VERSION
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// Messages keyed by uuid with indices by room and by send time, their timelines, room
+// messages, drafts, pending sends, blocked peers and quarantined messages in an embedded
+// sled database. Everything is also kept in memory to be lent by the ChatDataBase getters,
+// but for iter_messages and get_message which read the trees.
+// With a cipher the stored values are encrypted, not the keys: room uuids, send times,
+// message uuids and blocked peer uuids remain readable
+pub struct SledDB {
+    cache: SimpleVecDB,
+    messages: Tree,
+    by_room: Tree,
+    by_time: Tree,
+    // Per message uuid
+    timelines: Tree,
+    room_messages: Tree,
+    drafts: Tree,
+    pending_sends: Tree,
+    // Keys only
+    blocked_peers: Tree,
+    quarantine: Tree,
+    cipher: Option<Arc<Cipher>>,
+    // Of the writes the ChatDataBase methods cannot return, reported by flush
+    failed_writes: usize,
+    last_write_error: Option<io::Error>,
+}
+
+impl SledDB {
+    // Creates the database if there is none at path, its messages are loaded by send time
+    pub fn open(
+        path: &str,
+        localpeer: Peer,
+        peers: Vec<Peer>,
+        rooms: Vec<Room>,
+        cipher: Option<Arc<Cipher>>,
+    ) -> io::Result<Self> {
+        let db = sled::open(path)?;
+        check_schema_version(&db.open_tree("meta")?)?;
+        let messages = db.open_tree("messages")?;
+        let by_room = db.open_tree("messages_by_room")?;
+        let by_time = db.open_tree("messages_by_time")?;
+        let timelines = db.open_tree("timelines")?;
+        let room_messages = db.open_tree("room_messages")?;
+        let drafts = db.open_tree("drafts")?;
+        let pending_sends = db.open_tree("pending_sends")?;
+        let blocked_peers = db.open_tree("blocked_peers")?;
+        let quarantine = db.open_tree("quarantine")?;
+
+        let key = cipher.as_deref();
+        let loaded = load_messages(&messages, by_time.iter(), key)?;
+        let mut cache = SimpleVecDB::new(loaded, localpeer, peers, rooms);
+        for entry in timelines.iter() {
+            let (uuid, value) = entry?;
+            let uuid = String::from_utf8_lossy(&uuid).to_string();
+            // Left by messages that were never stored
+            if !messages.contains_key(uuid.as_bytes())? {
+                timelines.remove(uuid.as_bytes())?;
+                continue;
+            }
+            let entries: Vec<StoredEntry> =
+                serde_json::from_slice(&encryption::open(key, &value)?)?;
+            for entry in entries {
+                if let Some(time) = DTChatTime::from_timestamp_millis(entry.time_ms) {
+                    let stage = entry.stage;
+                    cache.add_timeline_entry(&uuid, TimelineEntry { stage, time });
+                }
+            }
+        }
+        for entry in room_messages.iter() {
+            let (_, value) = entry?;
+            cache.add_room_message(serde_json::from_slice(&encryption::open(key, &value)?)?);
+        }
+        for entry in drafts.iter() {
+            let (room_uuid, text) = entry?;
+            let room_uuid = String::from_utf8_lossy(&room_uuid).to_string();
+            let text = encryption::open(key, &text)?;
+            cache.save_draft(&room_uuid, String::from_utf8_lossy(&text).to_string())?;
+        }
+        for entry in pending_sends.iter() {
+            let (_, value) = entry?;
+            cache.add_pending_send(serde_json::from_slice(&encryption::open(key, &value)?)?)?;
+        }
+        for entry in blocked_peers.iter() {
+            let (peer_uuid, _) = entry?;
+            cache.set_blocked(&String::from_utf8_lossy(&peer_uuid).to_string(), true)?;
+        }
+        // Keyed by reception time, loaded in that order
+        for entry in quarantine.iter() {
+            let (_, value) = entry?;
+            cache.quarantine(serde_json::from_slice(&encryption::open(key, &value)?)?)?;
+        }
+        Ok(Self {
+            cache,
+            messages,
+            by_room,
+            by_time,
+            timelines,
+            room_messages,
+            drafts,
+            pending_sends,
+            blocked_peers,
+            quarantine,
+            cipher,
+            failed_writes: 0,
+            last_write_error: None,
+        })
+    }
+
+    // Kept for flush to report
+    fn record_write(&mut self, result: io::Result<()>) {
+        if let Err(err) = result {
+            self.failed_writes += 1;
+            self.last_write_error = Some(err);
+        }
+    }
+
+    fn seal(&self, value: Vec<u8>) -> io::Result<Vec<u8>> {
+        encryption::seal(self.cipher.as_deref(), value)
+    }
+
+    fn store_timeline(&self, uuid: &String) -> io::Result<()> {
+        let entries: Vec<StoredEntry> = self
+            .cache
+            .get_timeline(uuid)
+            .iter()
+            .map(|entry| StoredEntry {
+                stage: entry.stage.clone(),
+                time_ms: entry.time.timestamp_millis(),
+            })
+            .collect();
+        let value = self.seal(serde_json::to_vec(&entries)?)?;
+        self.timelines.insert(uuid.as_bytes(), value)?;
+        Ok(())
+    }
+
+    fn store_room_message(&self, room_msg: &RoomMessage) -> io::Result<()> {
+        let value = self.seal(serde_json::to_vec(room_msg)?)?;
+        self.room_messages.insert(room_msg.uuid.as_bytes(), value)?;
+        Ok(())
+    }
+
+    fn store_message(&self, msg: &ChatMessage) -> io::Result<()> {
+        let value = self.seal(serde_json::to_vec(&StoredMessage::from(msg))?)?;
+        if let Some(previous) = self.messages.insert(msg.uuid.as_bytes(), value)? {
+            let (by_room, by_time) =
+                index_keys(&decode_message(&previous, self.cipher.as_deref())?);
+            for key in by_room {
+                self.by_room.remove(key)?;
+            }
+            self.by_time.remove(by_time)?;
+        }
+        let (by_room, by_time) = index_keys(msg);
+        for key in by_room {
+            self.by_room.insert(key, msg.uuid.as_bytes())?;
+        }
+        self.by_time.insert(by_time, msg.uuid.as_bytes())?;
+        Ok(())
+    }
+
+    // Read from the room index, oldest first
+    pub fn room_messages_since(
+        &self,
+        room_uuid: &str,
+        since: DTChatTime,
+    ) -> io::Result<Vec<ChatMessage>> {
+        let start = room_key(room_uuid, Some(since));
+        let mut end = room_key(room_uuid, None);
+        // Past every key of the room
+        *end.last_mut().unwrap() = 1;
+        load_messages(
+            &self.messages,
+            self.by_room.range(start..end),
+            self.cipher.as_deref(),
+        )
+    }
+
+    // Sent from start included to end excluded, read from the time index, oldest first
+    pub fn messages_between(
+        &self,
+        start: DTChatTime,
+        end: DTChatTime,
+    ) -> io::Result<Vec<ChatMessage>> {
+        let range = time_key(start).to_vec()..time_key(end).to_vec();
+        load_messages(
+            &self.messages,
+            self.by_time.range(range),
+            self.cipher.as_deref(),
+        )
+    }
+}
+
+// Write failures leave the message in memory, it is missing from the next start only. Those
+// the methods cannot return are reported by flush
+impl ChatDataBase for SledDB {
+    fn schema_version(&self) -> u32 {
+        SCHEMA_VERSION
+    }
+
+    // Rolling back would have to rewrite the whole store
+    fn snapshot(&self) -> Option<Snapshot> {
+        None
+    }
+
+    fn restore(&mut self, _snapshot: Snapshot) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "sled databases cannot be restored from a snapshot",
+        ))
+    }
+
+    // Rooms and peers come from the configuration
+    fn get_rooms(&self) -> &HashMap<String, Room> {
+        self.cache.get_rooms()
+    }
+    fn set_rooms(&mut self, rooms: Vec<Room>) {
+        self.cache.set_rooms(rooms);
+    }
+
+    fn get_other_peers(&self) -> &HashMap<String, Peer> {
+        self.cache.get_other_peers()
+    }
+    fn get_localpeer(&self) -> &Peer {
+        self.cache.get_localpeer()
+    }
+    fn set_peers(&mut self, localpeer: Peer, peers: Vec<Peer>) {
+        self.cache.set_peers(localpeer, peers);
+    }
+
+    // Messages
+    fn get_last_messages(&self, count: usize) -> &[ChatMessage] {
+        self.cache.get_last_messages(count)
+    }
+
+    fn get_last_messages_for_room(&self, room_uuid: &String, count: usize) -> Vec<ChatMessage> {
+        self.cache.get_last_messages_for_room(room_uuid, count)
+    }
+
+    fn get_all_messages(&self) -> &Vec<ChatMessage> {
+        self.cache.get_all_messages()
+    }
+
+    // Walks the time index, each message is read from the tree when the scan reaches it.
+    // open already read every stored one back, those failing since are skipped
+    fn iter_messages(&self) -> Box<dyn Iterator<Item = Cow<'_, ChatMessage>> + '_> {
+        let cipher = self.cipher.as_deref();
+        Box::new(self.by_time.iter().filter_map(move |entry| {
+            let (_, uuid) = entry.ok()?;
+            let value = self.messages.get(uuid).ok()??;
+            decode_message(&value, cipher).ok().map(Cow::Owned)
+        }))
+    }
+
+    fn get_message(&self, uuid: &String) -> Option<ChatMessage> {
+        let value = self.messages.get(uuid.as_bytes()).ok()??;
+        decode_message(&value, self.cipher.as_deref()).ok()
+    }
+
+    fn add_message(&mut self, msg: ChatMessage) -> bool {
+        if self.cache.get_message(&msg.uuid).is_some() {
+            return false;
+        }
+        let stored = self.store_message(&msg);
+        self.record_write(stored);
+        let uuid = msg.uuid.clone();
+        let added = self.cache.add_message(msg);
+        let stored = self.store_timeline(&uuid);
+        self.record_write(stored);
+        added
+    }
+
+    fn replace_message(&mut self, msg: ChatMessage) -> bool {
+        if !self.cache.replace_message(msg.clone()) {
+            return false;
+        }
+        let stored = self.store_message(&msg);
+        self.record_write(stored);
+        true
+    }
+
+    fn remove_messages(&mut self, uuids: &HashSet<String>) -> io::Result<usize> {
+        for uuid in uuids {
+            if let Some(previous) = self.messages.remove(uuid.as_bytes())? {
+                let (by_room, by_time) =
+                    index_keys(&decode_message(&previous, self.cipher.as_deref())?);
+                for key in by_room {
+                    self.by_room.remove(key)?;
+                }
+                self.by_time.remove(by_time)?;
+            }
+            self.timelines.remove(uuid.as_bytes())?;
+            self.pending_sends.remove(uuid.as_bytes())?;
+        }
+        let removed = self.cache.remove_messages(uuids)?;
+        // The replicas removed are taken off their room messages, emptied ones are dropped
+        for entry in self.room_messages.iter() {
+            let (key, _) = entry?;
+            let uuid = String::from_utf8_lossy(&key).to_string();
+            match self.cache.get_room_message(&uuid) {
+                Some(room_msg) => self.store_room_message(room_msg)?,
+                None => {
+                    self.room_messages.remove(key)?;
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    fn mark_as(
+        &mut self,
+        uuid: &String,
+        intent: MarkIntent,
+        now: DTChatTime,
+    ) -> Option<ChatMessage> {
+        let ends_pending = intent.stage(now).is_some();
+        let marked = self.cache.mark_as(uuid, intent, now);
+        if let Some(msg) = &marked {
+            let stored = self.store_message(msg);
+            self.record_write(stored);
+        }
+        if ends_pending {
+            let removed = self.pending_sends.remove(uuid.as_bytes());
+            self.record_write(removed.map(|_| ()).map_err(io::Error::from));
+            let stored = self.store_timeline(uuid);
+            self.record_write(stored);
+        }
+        marked
+    }
+
+    // Timelines of messages not stored yet are written along with their message
+    fn add_timeline_entry(&mut self, uuid: &String, entry: TimelineEntry) {
+        self.cache.add_timeline_entry(uuid, entry);
+        let stored = match self.messages.contains_key(uuid.as_bytes()) {
+            Ok(true) => self.store_timeline(uuid),
+            Ok(false) => Ok(()),
+            Err(err) => Err(err.into()),
+        };
+        self.record_write(stored);
+    }
+
+    fn get_timeline(&self, uuid: &String) -> &[TimelineEntry] {
+        self.cache.get_timeline(uuid)
+    }
+
+    // Pending sends
+    fn add_pending_send(&mut self, pending: PendingSend) -> io::Result<()> {
+        let value = self.seal(serde_json::to_vec(&pending)?)?;
+        self.pending_sends
+            .insert(pending.message.uuid.as_bytes(), value)?;
+        self.cache.add_pending_send(pending)
+    }
+
+    fn remove_pending_send(&mut self, uuid: &String) -> io::Result<()> {
+        self.pending_sends.remove(uuid.as_bytes())?;
+        self.cache.remove_pending_send(uuid)
+    }
+
+    fn get_pending_sends(&self) -> &[PendingSend] {
+        self.cache.get_pending_sends()
+    }
+
+    // Every change is already a write of its own key, the failed ones are reported
+    fn flush(&mut self) -> io::Result<()> {
+        let Some(err) = self.last_write_error.take() else {
+            return Ok(());
+        };
+        let failed = std::mem::take(&mut self.failed_writes);
+        Err(io::Error::new(
+            err.kind(),
+            format!("{} writes failed, the last one: {}", failed, err),
+        ))
+    }
+
+    // Room messages
+    fn add_room_message(&mut self, room_msg: RoomMessage) {
+        let stored = self.store_room_message(&room_msg);
+        self.record_write(stored);
+        self.cache.add_room_message(room_msg);
+    }
+
+    fn get_room_message(&self, uuid: &String) -> Option<&RoomMessage> {
+        self.cache.get_room_message(uuid)
+    }
+
+    fn get_room_message_for_replica(&self, replica_uuid: &String) -> Option<&RoomMessage> {
+        self.cache.get_room_message_for_replica(replica_uuid)
+    }
+
+    // Drafts
+    fn save_draft(&mut self, room_uuid: &String, text: String) -> io::Result<()> {
+        if text.is_empty() {
+            self.drafts.remove(room_uuid.as_bytes())?;
+        } else {
+            let value = self.seal(text.as_bytes().to_vec())?;
+            self.drafts.insert(room_uuid.as_bytes(), value)?;
+        }
+        self.cache.save_draft(room_uuid, text)
+    }
+
+    fn get_draft(&self, room_uuid: &String) -> Option<&String> {
+        self.cache.get_draft(room_uuid)
+    }
+
+    // Blocked and muted peers
+    fn set_blocked(&mut self, peer_uuid: &String, blocked: bool) -> io::Result<()> {
+        if blocked {
+            self.blocked_peers
+                .insert(peer_uuid.as_bytes(), Vec::new())?;
+        } else {
+            self.blocked_peers.remove(peer_uuid.as_bytes())?;
+        }
+        self.cache.set_blocked(peer_uuid, blocked)
+    }
+
+    fn get_blocked_peers(&self) -> &HashSet<String> {
+        self.cache.get_blocked_peers()
+    }
+
+    fn set_muted(&mut self, peer_uuid: &String, muted: bool) {
+        self.cache.set_muted(peer_uuid, muted);
+    }
+
+    fn get_muted_peers(&self) -> &HashSet<String> {
+        self.cache.get_muted_peers()
+    }
+
+    // Quarantine
+    fn quarantine(&mut self, msg: QuarantinedMessage) -> io::Result<()> {
+        let mut key = millis_key(msg.received_at_ms).to_vec();
+        key.extend_from_slice(msg.uuid.as_bytes());
+        let value = self.seal(serde_json::to_vec(&msg)?)?;
+        self.quarantine.insert(key, value)?;
+        self.cache.quarantine(msg)
+    }
+
+    fn remove_quarantined(&mut self, uuid: &str) -> io::Result<bool> {
+        let Some(msg) = self
+            .cache
+            .get_quarantined()
+            .iter()
+            .find(|msg| msg.uuid == uuid)
+        else {
+            return Ok(false);
+        };
+        let mut key = millis_key(msg.received_at_ms).to_vec();
+        key.extend_from_slice(msg.uuid.as_bytes());
+        self.quarantine.remove(key)?;
+        self.cache.remove_quarantined(uuid)
+    }
+
+    fn get_quarantined(&self) -> &[QuarantinedMessage] {
+        self.cache.get_quarantined()
+    }
+
+    fn get_statistics(&self) -> Statistics {
+        self.cache.get_statistics()
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Condvar, Mutex, Weak},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    event::{AppEventObserver, ChatAppEvent, ChatAppInfoEvent},
+    message::{ChatMessage, MessageStatus},
+    net::EndpointProto,
+    time::DTChatTime,
+};
+
+#[derive(Clone, Debug)]
+pub enum DeliveryError {
+    Failed(ChatMessage),
+    TimedOut,
+    Closed,
+}
+
+impl fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeliveryError::Failed(msg) => write!(f, "delivery of message {} failed", msg.uuid),
+            DeliveryError::TimedOut => write!(f, "timed out while waiting for delivery"),
+            DeliveryError::Closed => write!(f, "delivery tracking was interrupted"),
+        }
+    }
+}
+
+impl std::error::Error for DeliveryError {}
+
+// Delay after which a sent message not yet acked is reported overdue, per protocol
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct AckTimeoutConfig {
+    pub tcp_ms: Option<i64>,
+    pub udp_ms: Option<i64>,
+    pub bp_ms: Option<i64>,
+    // Added to the predicted arrival time, used instead of the protocol delay when predicted
+    pub prediction_margin_ms: Option<i64>,
+    // Overdue messages are sent again over the same endpoint
+    #[serde(default)]
+    pub retry: bool,
+}
+
+impl AckTimeoutConfig {
+    // In milliseconds since the epoch, None when the protocol of the message has no delay
+    pub fn deadline(&self, msg: &ChatMessage) -> Option<i64> {
+        if let (Some(margin_ms), Some(predicted)) =
+            (self.prediction_margin_ms, msg.predicted_arrival_time)
+        {
+            return Some(predicted.timestamp_millis() + margin_ms);
+        }
+        let timeout_ms = match msg.source_endpoint.proto {
+            EndpointProto::Tcp => self.tcp_ms,
+            EndpointProto::Udp => self.udp_ms,
+            EndpointProto::Bp => self.bp_ms,
+        }?;
+        let sent = msg.send_completed.unwrap_or(msg.send_time);
+        Some(sent.timestamp_millis() + timeout_ms)
+    }
+}
+
+// A step of the delivery of a message, as listed by get_message_timeline
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeliveryStage {
+    Created,
+    // Waiting for a contact able to carry it
+    Queued,
+    // Handed to the transport, with the endpoint
+    Sending(String),
+    Sent,
+    InCustody,
+    // Forwarded by the BP node, as it reported
+    Forwarded(String),
+    DeliveredToNode,
+    Acked,
+    Failed,
+    Expired,
+    Cancelled,
+    Refused,
+    // Sent again over the endpoint, by failover, duplication or on request of the peer
+    Retried(String),
+    Received,
+}
+
+#[derive(Clone, Debug)]
+pub struct TimelineEntry {
+    pub stage: DeliveryStage,
+    pub time: DTChatTime,
+}
+
+impl TimelineEntry {
+    pub fn at(stage: DeliveryStage, time: DTChatTime) -> Self {
+        Self { stage, time }
+    }
+}
+
+// A message is considered sent once the engine handed it over, acked messages included
+pub fn is_sent(status: &MessageStatus) -> bool {
+    matches!(
+        status,
+        MessageStatus::Sent
+            | MessageStatus::InCustody
+            | MessageStatus::DeliveredToNode
+            | MessageStatus::ReceivedByPeer
+    )
+}
+
+pub fn is_acked(status: &MessageStatus) -> bool {
+    *status == MessageStatus::ReceivedByPeer
+}
+
+// No further delivery attempt will be made
+pub fn is_given_up(status: &MessageStatus) -> bool {
+    matches!(
+        status,
+        MessageStatus::Failed
+            | MessageStatus::Expired
+            | MessageStatus::Cancelled
+            | MessageStatus::Refused
+    )
+}
+
+pub(crate) fn outcome(
+    msg_opt: &Option<ChatMessage>,
+    done: fn(&MessageStatus) -> bool,
+) -> Option<Result<ChatMessage, DeliveryError>> {
+    match msg_opt {
+        Some(msg) if is_given_up(&msg.status) => Some(Err(DeliveryError::Failed(msg.clone()))),
+        Some(msg) if done(&msg.status) => Some(Ok(msg.clone())),
+        _ => None,
+    }
+}
+
+type DeliveryState = (Mutex<Option<ChatMessage>>, Condvar);
+
+pub struct DeliveryHandle {
+    uuid: String,
+    state: Arc<DeliveryState>,
+}
+
+impl DeliveryHandle {
+    pub fn uuid(&self) -> &String {
+        &self.uuid
+    }
+
+    pub fn wait_sent(&self, timeout: Duration) -> Result<ChatMessage, DeliveryError> {
+        self.wait_for(is_sent, timeout)
+    }
+
+    pub fn wait_acked(&self, timeout: Duration) -> Result<ChatMessage, DeliveryError> {
+        self.wait_for(is_acked, timeout)
+    }
+
+    // The model must not be locked by the caller while waiting, or no update can come in
+    fn wait_for(
+        &self,
+        done: fn(&MessageStatus) -> bool,
+        timeout: Duration,
+    ) -> Result<ChatMessage, DeliveryError> {
+        let (lock, cvar) = &*self.state;
+        let guard = lock.lock().unwrap();
+        let (guard, _) = cvar
+            .wait_timeout_while(guard, timeout, |msg_opt| outcome(msg_opt, done).is_none())
+            .unwrap();
+        outcome(&guard, done).unwrap_or(Err(DeliveryError::TimedOut))
+    }
+}
+
+// Observer registered by the model itself, updating the handles given by send_and_track
+#[derive(Default)]
+pub struct DeliveryTracker {
+    tracked: HashMap<String, Weak<DeliveryState>>,
+}
+
+impl DeliveryTracker {
+    pub fn track(&mut self, uuid: String, current: Option<ChatMessage>) -> DeliveryHandle {
+        // Forget about the handles that were dropped
+        self.tracked.retain(|_, state| state.strong_count() > 0);
+
+        let state = Arc::new((Mutex::new(current), Condvar::new()));
+        self.tracked.insert(uuid.clone(), Arc::downgrade(&state));
+        DeliveryHandle { uuid, state }
+    }
+
+    fn update(&mut self, msg: &ChatMessage, terminal: bool) {
+        if let Some(state) = self.tracked.get(&msg.uuid).and_then(|weak| weak.upgrade()) {
+            let (lock, cvar) = &*state;
+            *lock.lock().unwrap() = Some(msg.clone());
+            cvar.notify_all();
+        }
+        if terminal {
+            self.tracked.remove(&msg.uuid);
+        }
+    }
+}
+
+impl AppEventObserver for DeliveryTracker {
+    fn on_event(&mut self, event: ChatAppEvent) {
+        match &event {
+            ChatAppEvent::Message(ChatAppInfoEvent::Sent(msg))
+            | ChatAppEvent::Message(ChatAppInfoEvent::InCustody(msg)) => self.update(msg, false),
+            ChatAppEvent::Message(ChatAppInfoEvent::AckReceived(msg, _))
+            | ChatAppEvent::Message(ChatAppInfoEvent::NackReceived(msg, _))
+            | ChatAppEvent::Message(ChatAppInfoEvent::Refused(msg, _))
+            | ChatAppEvent::Message(ChatAppInfoEvent::Failed(msg))
+            | ChatAppEvent::Message(ChatAppInfoEvent::Expired(msg)) => self.update(msg, true),
+            _ => {}
+        }
+    }
+}
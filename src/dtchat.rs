@@ -1,85 +1,458 @@
 use std::{
-    collections::HashMap/* , fmt::format*/, fs, path::{Path, PathBuf}, sync::{Arc, Mutex}
+    borrow::Cow,
+    collections::{HashMap, HashSet, VecDeque}/* , fmt::format*/, fs, path::PathBuf,
+    sync::{Arc, Mutex, Weak}
 };
 
-use socket_engine::{
-    endpoint::{Endpoint, EndpointProto},
-    engine::Engine,
-    event::{ConnectionEvent, DataEvent, EngineObserver, ErrorEvent, SocketEngineEvent},
-};
+use serde::Deserialize;
+#[cfg(feature = "native")]
+use socket_engine::engine::Engine;
 use uuid::Uuid;
 
 use crate::{
-    config::AppConfig,
-    db::{ChatDataBase, MarkIntent},
+    bundle::{BundleMetadata, BundleStatus, BundleStatusReport},
+    catch_up::CatchUpSummary,
+    clock_skew::ClockSkewEstimator,
+    coalesce::Coalescer,
+    codec::{supported_codecs, WireCodec},
+    config::{
+        conflicts::ConfigConflict, validation::Diagnostic, AppConfig, AppSetup, ConfigDiff,
+        LoadedConfig,
+    },
+    db::{simple_vec::Snapshot, ChatDataBase, MarkIntent, PendingSend, QuarantinedMessage},
+    delivery::{
+        is_given_up, AckTimeoutConfig, DeliveryHandle, DeliveryStage, DeliveryTracker,
+        TimelineEntry,
+    },
+    error::ChatError,
     event::{
-        AppEventObserver, ChatAppErrorEvent, ChatAppEvent, ChatAppInfoEvent, NetworkErrorEvent,
-        NetworkEvent,
+        AppEventObserver, ChatAppErrorEvent, ChatAppEvent, ChatAppInfoEvent, EventFilter,
+        NetworkErrorEvent, NetworkEvent, ObserverId,
+    },
+    export::{export_messages, import_messages, ExportFormat, ExportedMessage, ImportSummary},
+    extension::{BlobHandler, ExtensionRegistry},
+    file_info::{mime_type_of, valid_thumbnail, AudioInfo, FileInfo},
+    files,
+    journal::EventJournal,
+    keys::{fingerprint, new_challenge, verify_challenge, KeyCheck, KeyStore, LocalKey, PeerKey},
+    latency::{LatencyTracker, PeerLatencyStats},
+    legacy::{LegacyDecoder, PrototypeDecoder},
+    link_health::{KeepaliveConfig, LinkHealth, LinkStatus},
+    message::{
+        mention_names, same_node, ChatMessage, Content, Location, MessageStatus, RoomMessage,
+        RoomMessageStatus, RoomSendReport, SortStrategy,
+    },
+    metrics::{Metrics, MetricsSnapshot},
+    net::{
+        ConnectionEvent, DataEvent, Endpoint, EndpointProto, EngineObserver, ErrorEvent,
+        SocketEngineEvent,
+    },
+    node_info::{enabled_features, NodeInfo, PeerNodeInfo, CAPABILITIES, GIT_HASH, VERSION},
+    prediction::{
+        ContactBudget, NextContact, PredictionConfig, PredictionHealth, PredictionPolicy,
+        PredictionSkip,
+    },
+    profile::{
+        same_name, sanitize_profile_color, sanitize_profile_name, PeerProfile, ProfileStore,
+    },
+    proto::{
+        proto_message::MsgType, FileMessage, FileOfferMessage, IAmMessage, LocationMessage,
+        ProfileMessage, ProtoMessage, SelectiveNackMessage,
     },
-    message::{ChatMessage, Content, RoomMessage, SortStrategy},
-    prediction::PredictionConfig,
-    proto::{proto_message::MsgType, ProtoMessage},
-    time::DTChatTime,
+    rate_limit::RateLimiter,
+    reception::{
+        file_hash, sanitize_file_name, stored_file_path, CollisionPolicy, FileOffer, FilePolicy,
+        RoomReception,
+    },
+    reconnect::Reconnector,
+    replay::{RecordedEvent, ReplayRecorder},
+    retransmit::{SentSequences, SequenceTracker, DEFAULT_REORDER_WINDOW_MS},
+    scheduler::ScheduledSend,
+    send_queue::{is_bulk, QueuedSend, SendQueues},
+    soak::SoakConfig,
+    stats::{ConversationStats, Statistics},
+    time::{default_time_source, DTChatTime, DisplayPrefs, TimeSource},
+    transport::Transport,
 };
+#[cfg(feature = "webhook")]
+use crate::webhook::WebhookDispatcher;
+
+// Room of the messages sent with broadcast, it is not configured anywhere
+pub const BROADCAST_ROOM_UUID: &str = "broadcast";
 
 pub fn generate_uuid() -> String {
     Uuid::new_v4().to_string()
 }
 
+fn config_reports(
+    conflicts: Vec<ConfigConflict>,
+    warnings: Vec<Diagnostic>,
+) -> Vec<ChatAppInfoEvent> {
+    let mut reports: Vec<ChatAppInfoEvent> = warnings
+        .into_iter()
+        .map(ChatAppInfoEvent::ConfigWarning)
+        .collect();
+    reports.extend(conflicts.into_iter().map(ChatAppInfoEvent::ConfigConflictResolved));
+    reports
+}
+
+// Takes the metrics alone, the engine is often borrowed from the model at that point
+fn record_bytes_sent(metrics: &Mutex<Metrics>, proto: &EndpointProto, bytes: usize) {
+    metrics.lock().unwrap().record_bytes_sent(proto, bytes);
+}
+
+// Older peers send no metadata, the size is the one of the data actually received
+fn received_file_info(file_name: &str, file_part: &FileMessage) -> FileInfo {
+    let mime_type = if file_part.mime_type.is_empty() {
+        mime_type_of(file_name).to_string()
+    } else {
+        file_part.mime_type.clone()
+    };
+    FileInfo {
+        mime_type,
+        size: file_part.data.len() as u64,
+        thumbnail: valid_thumbnail(&file_part.thumbnail).then(|| file_part.thumbnail.clone()),
+    }
+}
+
+// A missing position time is the one of the message
+fn location_from_proto(
+    proto_msg: &ProtoMessage,
+    location_part: &LocationMessage,
+) -> Result<Location, String> {
+    let timestamp = match location_part.timestamp {
+        0 => proto_msg.timestamp,
+        timestamp => timestamp,
+    };
+    let location = Location {
+        latitude: location_part.latitude,
+        longitude: location_part.longitude,
+        altitude: location_part.altitude,
+        accuracy: location_part.accuracy,
+        timestamp: DTChatTime::from_timestamp_millis(timestamp)
+            .ok_or_else(|| format!("invalid timestamp {}", timestamp))?,
+    };
+    location.check()?;
+    Ok(location)
+}
+
+// What is accepted from a peer, acks and nacks are always accepted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum TrustLevel {
+    #[default]
+    Full,
+    // Texts and locations only, no files
+    Limited,
+    // Nothing but acks and nacks
+    Untrusted,
+}
+
+impl TrustLevel {
+    pub fn allows(&self, msg_type: &MsgType) -> bool {
+        match (self, msg_type) {
+            (_, MsgType::Ack(_)) | (_, MsgType::Nack(_)) | (_, MsgType::SelectiveNack(_)) => true,
+            (_, MsgType::WhoAreYou(_)) | (_, MsgType::IAm(_)) | (_, MsgType::Keepalive(_)) => true,
+            // Checked against the recorded key
+            (_, MsgType::KeyRotation(_)) => true,
+            (TrustLevel::Limited, MsgType::HistoryDigest(_))
+            | (TrustLevel::Limited, MsgType::HistoryRequest(_))
+            | (TrustLevel::Limited, MsgType::HistoryBackfill(_))
+            | (TrustLevel::Limited, MsgType::Pin(_))
+            | (TrustLevel::Limited, MsgType::FileRequest(_)) => true,
+            (TrustLevel::Full, _) => true,
+            (TrustLevel::Limited, MsgType::Text(_))
+            | (TrustLevel::Limited, MsgType::Location(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+// An endpoint of a peer in its policy, higher weights are tried first
+#[derive(Clone, Debug, PartialEq)]
+pub struct EndpointPreference {
+    pub endpoint: Endpoint,
+    pub weight: u32,
+    // Skipped while the link to the endpoint is degraded
+    pub when_reachable: bool,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Peer {
     pub uuid: String,
     pub name: String,
     pub endpoints: Vec<Endpoint>,
     pub color: String,
+    pub trust: TrustLevel,
+    pub codec: WireCodec,
+    // Used by send_to_preferred, the endpoints in their order when empty
+    pub preferences: Vec<EndpointPreference>,
+    // As given by the peer in its profile, the image itself is not exchanged
+    pub avatar_hash: Option<String>,
 }
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Room {
     pub uuid: String,
     pub name: String,
     pub participants: Vec<(String, Endpoint)>,
+    // Room sends reaching every participant over BP go there in a single bundle
+    pub bundle_endpoint: Option<Endpoint>,
+    pub transport: RoomTransport,
+}
+
+// Endpoints send_to_room uses for the participants of a room
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RoomTransport {
+    // Those the participants registered in the room with
+    #[default]
+    Registered,
+    // Participants without a BP endpoint are left out, for latency tolerant rooms
+    BpOnly,
+    // Unless the participant has none or its link is degraded, the registered one then
+    PreferTcp,
+    PreferBp,
 }
 
 #[derive(PartialEq, Eq)]
 enum MessageType {
     Ack,
+    Nack,
+    Duplicate,
+    // WhoAreYou, IAm and history sync exchanges
+    Control,
     Text,
 }
 
+// What purge_peer deleted
+#[derive(Clone, Debug, Default)]
+pub struct PurgeSummary {
+    pub peer_uuid: String,
+    // Sent by the peer or by the local peer to one of its endpoints
+    pub messages: usize,
+    // Received files written by the messages
+    pub files: usize,
+    // Of the rooms the peer is the only other participant of
+    pub drafts: usize,
+    // Cancelled while being sent, or waiting for their send time
+    pub cancelled_sends: usize,
+    // Offers of the peer, waiting or accepted
+    pub file_offers: usize,
+    // Messages of unknown types it sent
+    pub quarantined: usize,
+    // Of the event journal and the replay log
+    pub log_lines: usize,
+    pub key_removed: bool,
+}
+
+// Weak observers are skipped once dropped, and forgotten when the observers change
+enum ObserverRef {
+    Strong(Arc<Mutex<dyn AppEventObserver>>),
+    Weak(Weak<Mutex<dyn AppEventObserver>>),
+}
+
+impl ObserverRef {
+    fn get(&self) -> Option<Arc<Mutex<dyn AppEventObserver>>> {
+        match self {
+            ObserverRef::Strong(obs) => Some(obs.clone()),
+            ObserverRef::Weak(obs) => obs.upgrade(),
+        }
+    }
+}
+
+// A BP message held until a contact can carry it
+struct DeferredSend {
+    message_uuid: String,
+    peer_uuid: String,
+    local_endpoint: Option<Endpoint>,
+    endpoint: Endpoint,
+    bytes: Vec<u8>,
+}
+
 pub enum ASabrInitState {
     Enabled(PredictionConfig),
     Error(String),
     Disabled,
 }
 
+const DEFAULT_CP_EXPIRY_WARNING_MS: i64 = 3_600_000;
+const DEFAULT_EVENT_HISTORY_CAPACITY: usize = 256;
+const DEFAULT_REPLAY_LOG_MAX_BYTES: u64 = 64 * 1024 * 1024;
+const DEFAULT_HISTORY_DIGEST_SIZE: usize = 256;
+// Received offers waiting for the user, in all and per sender, the next ones are refused
+const MAX_FILE_OFFERS: usize = 256;
+const MAX_FILE_OFFERS_PER_PEER: usize = 32;
+// Accepted offers waiting for their data, the oldest is forgotten past it
+const MAX_ACCEPTED_OFFERS: usize = 256;
+// Quarantined messages in all, the next ones are not kept, and per sender, whose oldest
+// makes room
+const MAX_QUARANTINED: usize = 1024;
+const MAX_QUARANTINED_PER_PEER: usize = 64;
+// WhoAreYou challenges waiting for their IAm, the oldest is forgotten past it
+const MAX_KEY_CHALLENGES: usize = 256;
+
 pub struct ChatModel {
     pub sort_strategy: SortStrategy,
 
-    observers: Vec<Arc<Mutex<dyn AppEventObserver>>>,
-    network_engine: Option<Engine>,
+    observers: Vec<(ObserverId, ObserverRef, EventFilter)>,
+    next_observer_id: u64,
+    delivery_tracker: Arc<Mutex<DeliveryTracker>>,
+    metrics: Arc<Mutex<Metrics>>,
+    metrics_address: Option<String>,
+    http_address: Option<String>,
+    network_engine: Option<Box<dyn Transport>>,
     pending_send_list: Vec<(MessageType, String, Option<String>)>, // msg_type, uuid, original_msg_id pour ACK
     db: Box<dyn ChatDataBase>,
     a_sabr: ASabrInitState,
+    prediction_policy: PredictionPolicy,
     reception_folder: PathBuf,
+    room_reception: HashMap<String, RoomReception>,
+    // Maximum delivery latency per room uuid, in milliseconds
+    latency_budgets: HashMap<String, i64>,
+    // Reported once observers are attached, on start
+    config_reports: Vec<ChatAppInfoEvent>,
+    // Profile in use, None when the local peer comes from the environment
+    identity: Option<String>,
+    connected_endpoints: HashSet<String>,
+    link_health: LinkHealth,
+    keepalive: Option<KeepaliveConfig>,
+    // Last reception per connected endpoint, and last keepalive sent to it
+    last_heard: HashMap<String, i64>,
+    keepalive_sent: HashMap<String, i64>,
+    reconnector: Reconnector,
+    disconnected_since: HashMap<String, DTChatTime>,
+    catch_up_after_ms: Option<i64>,
+    duplicate_send_threshold_ms: Option<i64>,
+    message_ttl_ms: Option<i64>,
+    ack_timeout: Option<AckTimeoutConfig>,
+    // Uuids of the messages already reported overdue
+    ack_overdue: HashSet<String>,
+    late_delivery_margin_ms: Option<i64>,
+    // Uuids of the messages already reported late
+    late_deliveries: HashSet<String>,
+    failover_order: Vec<EndpointProto>,
+    soak: Option<SoakConfig>,
+    // Peer uuid and protocols already tried, per message uuid
+    failover_attempts: HashMap<String, (String, Vec<EndpointProto>)>,
+    // Endpoints left to try per message sent with send_to_preferred, before the failover
+    endpoint_fallbacks: HashMap<String, Vec<Endpoint>>,
+    legacy_frames: bool,
+    history_sync: bool,
+    history_digest_size: usize,
+    legacy_decoder: Box<dyn LegacyDecoder>,
+    extensions: ExtensionRegistry,
+    replay_recorder: Option<ReplayRecorder>,
+    // Given every event along with event_history, before coalescing
+    journal: Option<Mutex<EventJournal>>,
+    defer_oversized: bool,
+    deferred_sends: Vec<DeferredSend>,
+    scheduled_sends: Vec<ScheduledSend>,
+    send_queue_capacity: Option<usize>,
+    send_queues: SendQueues,
+    // Oldest first, notify_observers only borrows the model
+    event_history: Mutex<VecDeque<ChatAppEvent>>,
+    event_history_capacity: usize,
+    // Merges the progress events when coalesce_window_ms is set
+    coalescer: Option<Mutex<Coalescer>>,
+    // Vector clock per room uuid, merged from every stored message
+    room_clocks: HashMap<String, HashMap<String, u64>>,
+    // Lamport clock per room uuid
+    room_lamport_times: HashMap<String, u64>,
+    clock_skew: ClockSkewEstimator,
+    latency: LatencyTracker,
+    correct_clock_skew: bool,
+    display_prefs: DisplayPrefs,
+    time_source: Arc<dyn TimeSource>,
+    propagate_pins: bool,
+    rate_limiter: Option<RateLimiter>,
+    max_text_size: Option<u64>,
+    max_file_size: Option<u64>,
+    file_policy: FilePolicy,
+    file_collision_policy: CollisionPolicy,
+    offer_files: bool,
+    retransmit_gap_after_ms: Option<i64>,
+    reorder_window_ms: i64,
+    sent_sequences: SentSequences,
+    // Per sender peer uuid and room uuid
+    received_sequences: HashMap<(String, String), SequenceTracker>,
+    // Received files whose data did not match the offer, requested once again
+    corrupted_files: HashSet<String>,
+    // Received offers with the message announcing them, per message uuid
+    file_offers: HashMap<String, (FileOffer, ProtoMessage)>,
+    // Checked against the data once it arrives
+    accepted_offers: HashMap<String, FileOffer>,
+    // Per peer uuid, as given in its answers to query_peer_info
+    peer_versions: HashMap<String, String>,
+    peer_capabilities: HashMap<String, Vec<String>>,
+    // Peers the local profile was sent to since the start
+    profiles_sent: HashSet<String>,
+    peer_profiles: ProfileStore,
+    local_key: Option<LocalKey>,
+    key_store: KeyStore,
+    // Request uuid, target endpoint and challenge of the WhoAreYou sent
+    key_challenges: VecDeque<(String, String, Vec<u8>)>,
+    // Peers whose key was checked against a challenge since the start
+    keys_verified: HashSet<String>,
+    // Peers presenting another key than the recorded one, untrusted until trust_peer_key
+    key_mismatches: HashSet<String>,
+    // Per peer uuid, as advertised in its WhoAreYou and IAm messages
+    peer_codecs: HashMap<String, Vec<String>>,
+    // Of the BP data being treated, given to the message it holds
+    received_bundle: Option<BundleMetadata>,
+    cp_expiry_warning_ms: i64,
+    // Reported once per loaded plan
+    cp_expiry_reported: bool,
+    config_path: String,
+    db_type: String,
+    // Endpoints given to start_on
+    listening: Vec<Endpoint>,
 }
 
 impl EngineObserver for ChatModel {
     fn on_engine_event(&mut self, event: SocketEngineEvent) {
+        let now = self.now();
+        if let Some(recorder) = &mut self.replay_recorder {
+            recorder.record(&event, now);
+        }
         match event {
             SocketEngineEvent::Data(data_event) => match data_event {
                 DataEvent::Received { data, from } => {
+                    self.record_link(&from, None);
+                    self.last_heard
+                        .insert(from.to_string(), self.now().timestamp_millis());
                     self.notify_observers(ChatAppEvent::SocketEngineInfo(NetworkEvent::Data(
                         DataEvent::Received {
                             data: data.clone(),
-                            from,
+                            from: from.clone(),
                         },
+                        None,
                     )));
 
-                    let decode_res = ProtoMessage::decode_from_vec(data);
+                    self.received_bundle = match from.proto {
+                        EndpointProto::Bp => self
+                            .network_engine
+                            .as_mut()
+                            .and_then(|engine| engine.take_bundle_metadata(&from)),
+                        _ => None,
+                    };
+                    // Every message gives its source endpoint since the prototype
+                    let decode_res = match ProtoMessage::decode_from_slice(&data) {
+                        Ok(proto_msg)
+                            if proto_msg.msg_type.is_some()
+                                && !proto_msg.source_endpoint.is_empty() =>
+                        {
+                            Ok(proto_msg)
+                        }
+                        other => self
+                            .may_use_other_codec(&from)
+                            .then(|| WireCodec::decode_other(&data))
+                            .flatten()
+                            .or_else(|| self.decode_legacy(&data, &from))
+                            .map_or(other, Ok),
+                    };
 
                     match decode_res {
                         Ok(proto_msg) => {
-                            self.treat_proto_message(proto_msg);
+                            self.treat_received(proto_msg, Some(from), Some(&data));
                         }
                         Err(decode_err) => {
                             self.notify_observers(ChatAppEvent::Error(
@@ -90,25 +463,31 @@ impl EngineObserver for ChatModel {
                             ));
                         }
                     };
+                    self.received_bundle = None;
                 }
                 DataEvent::Sent {
                     token,
                     to,
                     bytes_sent,
                 } => {
+                    self.record_link(&to, None);
+                    let message_uuid = self.lookup_message_by_token(&token);
                     self.notify_observers(ChatAppEvent::SocketEngineInfo(NetworkEvent::Data(
                         DataEvent::Sent {
                             token: token.clone(),
                             to,
                             bytes_sent,
                         },
+                        message_uuid,
                     )));
 
                     self.mark_as_sent(&token);
                 }
                 DataEvent::Sending { token, to, bytes } => {
+                    let message_uuid = self.lookup_message_by_token(&token);
                     self.notify_observers(ChatAppEvent::SocketEngineInfo(NetworkEvent::Data(
                         DataEvent::Sending { token, to, bytes },
+                        message_uuid,
                     )));
                 }
             },
@@ -119,6 +498,17 @@ impl EngineObserver for ChatModel {
                     ));
                 }
                 ConnectionEvent::Established { remote } => {
+                    self.connected_endpoints.insert(remote.to_string());
+                    let link = self.link_key(&remote);
+                    self.link_health.set_connected(&link, true);
+                    self.reconnector.on_established(&link);
+                    self.last_heard
+                        .insert(remote.to_string(), self.now().timestamp_millis());
+                    self.record_link(&remote, None);
+                    self.catch_up(&remote);
+                    if self.history_sync {
+                        self.sync_history_with_endpoint(&remote);
+                    }
                     self.notify_observers(ChatAppEvent::SocketEngineInfo(
                         NetworkEvent::Connection(ConnectionEvent::Established {
                             remote: remote.clone(),
@@ -126,6 +516,17 @@ impl EngineObserver for ChatModel {
                     ));
                 }
                 ConnectionEvent::Closed { remote } => {
+                    if let Some(remote_ep) = &remote {
+                        self.connected_endpoints.remove(&remote_ep.to_string());
+                        self.last_heard.remove(&remote_ep.to_string());
+                        self.keepalive_sent.remove(&remote_ep.to_string());
+                        let link = self.link_key(remote_ep);
+                        self.link_health.set_connected(&link, false);
+                        let key = self.catch_up_key(remote_ep);
+                        self.disconnected_since.insert(key, self.now());
+                        self.reconnector
+                            .on_closed(&link, self.now().timestamp_millis());
+                    }
                     self.notify_observers(ChatAppEvent::SocketEngineInfo(
                         NetworkEvent::Connection(ConnectionEvent::Closed {
                             remote: remote.clone(),
@@ -135,34 +536,38 @@ impl EngineObserver for ChatModel {
             },
             SocketEngineEvent::Error(error_event) => match &error_event {
                 ErrorEvent::ConnectionFailed {
-                    endpoint: _,
-                    reason: _,
+                    endpoint,
+                    reason,
                     token,
                 } => {
+                    self.record_link(endpoint, Some(reason.to_string()));
+                    let message_uuid = self.lookup_message_by_token(token);
                     self.notify_observers(ChatAppEvent::SocketEngineError(
-                        NetworkErrorEvent::SocketError(error_event.clone()),
+                        NetworkErrorEvent::SocketError(error_event.clone(), message_uuid),
                     ));
 
                     self.mark_pending_message_as_failed(token);
                 }
                 ErrorEvent::SendFailed {
-                    endpoint: _,
-                    reason: _,
+                    endpoint,
+                    reason,
                     token,
                 } => {
+                    self.record_link(endpoint, Some(reason.to_string()));
+                    let message_uuid = self.lookup_message_by_token(token);
                     self.notify_observers(ChatAppEvent::SocketEngineError(
-                        NetworkErrorEvent::SocketError(error_event.clone()),
+                        NetworkErrorEvent::SocketError(error_event.clone(), message_uuid),
                     ));
                     self.mark_pending_message_as_failed(token);
                 }
                 ErrorEvent::ReceiveFailed { .. } => {
                     self.notify_observers(ChatAppEvent::SocketEngineError(
-                        NetworkErrorEvent::SocketError(error_event.clone()),
+                        NetworkErrorEvent::SocketError(error_event.clone(), None),
                     ));
                 }
                 ErrorEvent::SocketError { .. } => {
                     self.notify_observers(ChatAppEvent::SocketEngineError(
-                        NetworkErrorEvent::SocketError(error_event.clone()),
+                        NetworkErrorEvent::SocketError(error_event.clone(), None),
                     ));
                 }
             },
@@ -170,29 +575,259 @@ impl EngineObserver for ChatModel {
     }
 }
 
+// What the tick held back is written on the way out, nobody is left to report a failure to
+impl Drop for ChatModel {
+    fn drop(&mut self) {
+        let _ = self.sent_sequences.flush();
+        let _ = self.db.flush();
+    }
+}
+
 impl ChatModel {
     pub fn new() -> Self {
-        let (db, pred, reception_folder) = AppConfig::new();
-        Self {
+        Self::from_setup(AppConfig::new(None), None)
+    }
+
+    pub fn new_with_identity(profile_name: &str) -> Self {
+        Self::from_setup(
+            AppConfig::new(Some(profile_name)),
+            Some(profile_name.to_string()),
+        )
+    }
+
+    // Configuration reloads still go through the environment
+    pub fn for_peer(
+        config_file: &str,
+        local_peer_uuid: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self::from_setup(AppConfig::for_peer(config_file, local_peer_uuid)?, None))
+    }
+
+    fn from_setup(setup: AppSetup, identity: Option<String>) -> Self {
+        let mut model = Self {
             // TODO: have an SQL(ite) db.rs
             sort_strategy: SortStrategy::Standard,
             observers: Vec::new(),
+            next_observer_id: 0,
+            delivery_tracker: Arc::new(Mutex::new(DeliveryTracker::default())),
+            metrics: Arc::new(Mutex::new(Metrics::default())),
+            metrics_address: setup.config.metrics_address.clone(),
+            http_address: setup.config.http_address.clone(),
             network_engine: None,
             pending_send_list: Vec::new(),
-            db,
-            a_sabr: pred,
-            reception_folder,
+            db: setup.db,
+            a_sabr: setup.a_sabr,
+            prediction_policy: setup.config.prediction_policy,
+            reception_folder: setup.reception_folder,
+            room_reception: setup.room_reception,
+            latency_budgets: setup.latency_budgets,
+            config_reports: config_reports(setup.conflicts, setup.warnings),
+            identity,
+            connected_endpoints: HashSet::new(),
+            link_health: LinkHealth::default(),
+            keepalive: setup.config.keepalive.clone(),
+            last_heard: HashMap::new(),
+            keepalive_sent: HashMap::new(),
+            reconnector: Reconnector::new(setup.config.reconnect.clone()),
+            disconnected_since: HashMap::new(),
+            catch_up_after_ms: setup.config.catch_up_after_ms,
+            duplicate_send_threshold_ms: setup.config.duplicate_send_threshold_ms,
+            message_ttl_ms: setup.config.message_ttl_ms,
+            ack_timeout: setup.config.ack_timeout.clone(),
+            ack_overdue: HashSet::new(),
+            late_delivery_margin_ms: setup.config.late_delivery_margin_ms,
+            late_deliveries: HashSet::new(),
+            failover_order: setup.config.failover_protocols(),
+            soak: setup.config.soak.clone(),
+            legacy_frames: setup.config.legacy_frames,
+            history_sync: setup.config.history_sync,
+            history_digest_size: setup
+                .config
+                .history_digest_size
+                .unwrap_or(DEFAULT_HISTORY_DIGEST_SIZE),
+            legacy_decoder: Box::new(PrototypeDecoder),
+            extensions: ExtensionRegistry::default(),
+            replay_recorder: None,
+            journal: None,
+            defer_oversized: setup.config.defer_oversized,
+            deferred_sends: Vec::new(),
+            scheduled_sends: Vec::new(),
+            send_queue_capacity: setup.config.send_queue_capacity,
+            send_queues: SendQueues::new(&setup.config.send_queues),
+            event_history: Mutex::new(VecDeque::new()),
+            event_history_capacity: setup
+                .config
+                .event_history_capacity
+                .unwrap_or(DEFAULT_EVENT_HISTORY_CAPACITY),
+            coalescer: setup
+                .config
+                .coalesce_window_ms
+                .map(|window_ms| Mutex::new(Coalescer::new(window_ms))),
+            room_clocks: HashMap::new(),
+            room_lamport_times: HashMap::new(),
+            clock_skew: ClockSkewEstimator::default(),
+            latency: LatencyTracker::default(),
+            correct_clock_skew: setup.config.correct_clock_skew,
+            display_prefs: setup.config.display_prefs(),
+            time_source: default_time_source(),
+            propagate_pins: setup.config.propagate_pins,
+            rate_limiter: setup.config.rate_limit.clone().map(RateLimiter::new),
+            max_text_size: setup.config.max_text_size,
+            max_file_size: setup.config.max_file_size,
+            file_policy: setup.config.file_policy.clone(),
+            file_collision_policy: setup.config.file_collision_policy,
+            offer_files: setup.config.offer_files,
+            retransmit_gap_after_ms: setup.config.retransmit_gap_after_ms,
+            reorder_window_ms: setup
+                .config
+                .reorder_window_ms
+                .unwrap_or(DEFAULT_REORDER_WINDOW_MS),
+            sent_sequences: SentSequences::default(),
+            received_sequences: HashMap::new(),
+            corrupted_files: HashSet::new(),
+            file_offers: HashMap::new(),
+            accepted_offers: HashMap::new(),
+            peer_versions: HashMap::new(),
+            peer_capabilities: HashMap::new(),
+            profiles_sent: HashSet::new(),
+            peer_profiles: ProfileStore::default(),
+            local_key: None,
+            key_store: KeyStore::default(),
+            key_challenges: VecDeque::new(),
+            keys_verified: HashSet::new(),
+            key_mismatches: HashSet::new(),
+            peer_codecs: HashMap::new(),
+            received_bundle: None,
+            cp_expiry_warning_ms: setup
+                .config
+                .cp_expiry_warning_ms
+                .unwrap_or(DEFAULT_CP_EXPIRY_WARNING_MS),
+            cp_expiry_reported: false,
+            config_path: setup.config_path,
+            db_type: format!("{:?}", setup.config.db_type),
+            listening: Vec::new(),
+            failover_attempts: HashMap::new(),
+            endpoint_fallbacks: HashMap::new(),
+        };
+        model.add_observer(model.delivery_tracker.clone());
+        model.add_observer(model.metrics.clone());
+        if let Some(event_log_path) = &setup.config.event_log_path {
+            match EventJournal::open(event_log_path, setup.cipher.clone()) {
+                Ok(journal) => {
+                    model.journal = Some(Mutex::new(journal));
+                }
+                Err(err) => model.config_reports.push(ChatAppInfoEvent::ConfigWarning(
+                    Diagnostic::warning(
+                        format!("cannot open event log {}: {}", event_log_path, err),
+                        "check event_log_path, events are not journaled",
+                    ),
+                )),
+            }
+        }
+        if let Some(replay_log_path) = &setup.config.replay_log_path {
+            let max_bytes = setup
+                .config
+                .replay_log_max_bytes
+                .unwrap_or(DEFAULT_REPLAY_LOG_MAX_BYTES);
+            match ReplayRecorder::open(replay_log_path, setup.cipher.clone(), max_bytes) {
+                Ok(recorder) => model.replay_recorder = Some(recorder),
+                Err(err) => {
+                    model
+                        .config_reports
+                        .push(ChatAppInfoEvent::ConfigWarning(Diagnostic::warning(
+                            format!("cannot open replay log {}: {}", replay_log_path, err),
+                            "check replay_log_path, engine events are not recorded",
+                        )))
+                }
+            }
         }
+        if let Some(key_path) = &setup.config.key_path {
+            match LocalKey::open(key_path) {
+                Ok(local_key) => model.local_key = Some(local_key),
+                Err(err) => {
+                    model
+                        .config_reports
+                        .push(ChatAppInfoEvent::ConfigWarning(Diagnostic::warning(
+                            format!("cannot open key {}: {}", key_path, err),
+                            "check key_path, no key is exchanged with the peers",
+                        )))
+                }
+            }
+        }
+        if let Some(sequences_path) = &setup.config.sequences_path {
+            match SentSequences::open(sequences_path) {
+                Ok(sent_sequences) => model.sent_sequences = sent_sequences,
+                Err(err) => {
+                    model
+                        .config_reports
+                        .push(ChatAppInfoEvent::ConfigWarning(Diagnostic::warning(
+                            format!("cannot open sequences {}: {}", sequences_path, err),
+                            "check sequences_path, the numbering restarts with the process",
+                        )))
+                }
+            }
+        }
+        if let Some(peer_keys_path) = &setup.config.peer_keys_path {
+            match KeyStore::open(peer_keys_path) {
+                Ok(key_store) => model.key_store = key_store,
+                Err(err) => {
+                    model
+                        .config_reports
+                        .push(ChatAppInfoEvent::ConfigWarning(Diagnostic::warning(
+                            format!("cannot open peer keys {}: {}", peer_keys_path, err),
+                            "check peer_keys_path, the keys are only kept in memory",
+                        )))
+                }
+            }
+        }
+        if let Some(peer_profiles_path) = &setup.config.peer_profiles_path {
+            match ProfileStore::open(peer_profiles_path) {
+                Ok(peer_profiles) => model.peer_profiles = peer_profiles,
+                Err(err) => {
+                    model
+                        .config_reports
+                        .push(ChatAppInfoEvent::ConfigWarning(Diagnostic::warning(
+                            format!("cannot open peer profiles {}: {}", peer_profiles_path, err),
+                            "check peer_profiles_path, the profiles are only kept in memory",
+                        )))
+                }
+            }
+        }
+        #[cfg(feature = "webhook")]
+        if let Some(webhook_config) = setup.config.webhook {
+            model.add_observer(Arc::new(Mutex::new(WebhookDispatcher::new(webhook_config))));
+        }
+        model
     }
 
+    #[cfg(feature = "native")]
     pub fn start(&mut self, engine: Engine) {
-        self.network_engine = Some(engine);
-        let endpoints = &self.db.get_localpeer().endpoints;
+        let endpoints = self.db.get_localpeer().endpoints.clone();
+        self.start_on(engine, endpoints);
+    }
+
+    // Listens on the given endpoints instead of the ones of the local peer
+    #[cfg(feature = "native")]
+    pub fn start_on(&mut self, engine: Engine, endpoints: Vec<Endpoint>) {
+        self.start_with_transport(Box::new(engine), endpoints);
+    }
+
+    // The transport must feed its events back to on_engine_event
+    pub fn start_with_transport(
+        &mut self,
+        transport: Box<dyn Transport>,
+        endpoints: Vec<Endpoint>,
+    ) {
+        self.network_engine = Some(transport);
+        self.listening = endpoints.clone();
         if let Some(eng) = &mut self.network_engine {
             for endpoint in endpoints {
-                eng.start_listener_async(endpoint.clone());
+                eng.start_listener(endpoint);
             }
         }
+        self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::Started(
+            self.get_node_info(),
+        )));
         let message = match &self.a_sabr {
             ASabrInitState::Enabled(_) => "A-SABR prediction enabled".to_string(),
             ASabrInitState::Error(err) => {
@@ -207,7 +842,23 @@ impl ChatModel {
             "Received files will be stored in folder {}",
             self.reception_folder.to_string_lossy().into_owned()
         )));
+        for report in std::mem::take(&mut self.config_reports) {
+            self.notify_observers(ChatAppEvent::Message(report));
+        }
+        self.reconcile_pending_sends();
     }
+    pub fn get_node_info(&self) -> NodeInfo {
+        NodeInfo {
+            version: VERSION.to_string(),
+            git_hash: GIT_HASH.to_string(),
+            features: enabled_features().into_iter().map(String::from).collect(),
+            config_path: self.config_path.clone(),
+            db_type: self.db_type.clone(),
+            peer_uuid: self.db.get_localpeer().uuid.clone(),
+            endpoints: self.listening.clone(),
+        }
+    }
+
     pub fn is_pbat_enabled(&self) -> bool {
         if let ASabrInitState::Enabled(_) = self.a_sabr {
             return true;
@@ -215,12 +866,35 @@ impl ChatModel {
         false
     }
 
+    pub fn metrics_snapshot(&self) -> MetricsSnapshot {
+        self.metrics.lock().unwrap().snapshot()
+    }
+
+    pub fn metrics_address(&self) -> Option<String> {
+        self.metrics_address.clone()
+    }
+
+    pub fn http_address(&self) -> Option<String> {
+        self.http_address.clone()
+    }
+
+    // Sends go on without prediction for the pairs the router failed for
+    pub fn prediction_health(&self) -> PredictionHealth {
+        match &self.a_sabr {
+            ASabrInitState::Enabled(a_sabr) => a_sabr.health(),
+            ASabrInitState::Error(error) => PredictionHealth::Error(error.clone()),
+            ASabrInitState::Disabled => PredictionHealth::Disabled,
+        }
+    }
+
     pub fn update(&mut self, path:String, algo: &str){
-        match PredictionConfig::try_init(path.clone(), algo){
+        match PredictionConfig::try_init(path.clone(), algo, self.time_source.clone()) {
             Ok(update_config) => {
                 let nodes = update_config.nodes_length;
                 let contacts = update_config.contacts_length;
                 self.a_sabr = ASabrInitState::Enabled(update_config);
+                // The new plan gets its own expiry warning
+                self.cp_expiry_reported = false;
                 self.notify_observers(ChatAppEvent::Info(format!("Update done with : {algo} and  {path}")));
                 self.notify_observers(ChatAppEvent::Info(format!("{nodes} nodes and {contacts} contacts ")));
             }
@@ -231,262 +905,4068 @@ impl ChatModel {
         }
     }
 
-    fn treat_file_and_text(&mut self, msg_opt: Option<ChatMessage>, proto_msg: &ProtoMessage) {
-        if let Some(msg) = msg_opt {
-            self.add_message(msg.clone());
-
-            match Endpoint::from_str(proto_msg.source_endpoint.as_str()) {
-                Ok(endpoint) => self.send_ack_to_peer(&msg, endpoint),
-                Err(_err) => {
-                    self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::ProtocolDecode(
-                        "Received proto message source endpoint cannot be parsed".to_string(),
-                    )));
-                }
+    pub fn reload_config(&mut self) {
+        match AppConfig::reload(self.identity.as_deref()) {
+            Ok(loaded) => {
+                let diff = self.apply_config(loaded);
+                self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::ConfigReloaded(
+                    diff,
+                )));
+            }
+            Err(err) => {
+                self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::InternalError(
+                    format!("Unable to reload configuration: {}", err),
+                )));
             }
         }
     }
 
-    pub fn treat_proto_message(&mut self, proto_msg: ProtoMessage) {
-        match &proto_msg.msg_type {
-            Some(MsgType::Text(text_part)) => {
-                let chat_msg =
-                    ChatMessage::new_received(&proto_msg, Content::Text(text_part.text.clone()));
-                self.treat_file_and_text(chat_msg, &proto_msg)
-            }
+    pub fn get_identity(&self) -> Option<String> {
+        self.identity.clone()
+    }
 
-            Some(MsgType::File(file_part)) => {
-                let chat_msg =
-                    ChatMessage::new_received(&proto_msg, Content::File(file_part.name.clone()));
-                let full_path = Path::new(&self.reception_folder).join(file_part.name.clone());
-                match fs::write(full_path, file_part.data.clone()) {
-                    Ok(_) => {
-                        self.notify_observers(ChatAppEvent::Info(format!(
-                            "File stored: {}",
-                            file_part.name
-                        )));
-                    }
-                    Err(err) => {
-                        self.notify_observers(ChatAppEvent::Error(
-                            ChatAppErrorEvent::InternalError(format!(
-                                "Unable to save received file: {}",
-                                err
-                            )),
-                        ));
+    // Listeners of the previous identity cannot be stopped and keep running
+    pub fn switch_identity(&mut self, profile_name: &str) {
+        match AppConfig::reload(Some(profile_name)) {
+            Ok(loaded) => {
+                self.identity = Some(profile_name.to_string());
+                self.apply_config(loaded);
+                let local_peer = self.db.get_localpeer().clone();
+                if let Some(engine) = &mut self.network_engine {
+                    for endpoint in &local_peer.endpoints {
+                        engine.start_listener(endpoint.clone());
                     }
                 }
-
-                self.treat_file_and_text(chat_msg, &proto_msg)
+                self.notify_observers(ChatAppEvent::Message(
+                    ChatAppInfoEvent::IdentitySwitched(local_peer),
+                ));
+            }
+            Err(err) => {
+                self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::InternalError(
+                    format!("Unable to switch to identity '{}': {}", profile_name, err),
+                )));
             }
+        }
+    }
 
-            Some(MsgType::Ack(ack)) => {
-                self.mark_as_acked(&ack.message_uuid, proto_msg.timestamp);
+    fn apply_config(&mut self, loaded: LoadedConfig) -> ConfigDiff {
+        let diff = ConfigDiff::new(
+            self.db.get_localpeer(),
+            self.db.get_other_peers(),
+            self.db.get_rooms(),
+            &self.reception_folder,
+            &self.room_reception,
+            &loaded,
+        );
+        self.db.set_peers(loaded.local_peer, loaded.peers);
+        self.db.set_rooms(loaded.rooms);
+        self.reception_folder = loaded.reception_folder;
+        self.room_reception = loaded.room_reception;
+        self.latency_budgets = loaded.latency_budgets;
+        for report in config_reports(loaded.conflicts, loaded.warnings) {
+            self.notify_observers(ChatAppEvent::Message(report));
+        }
+        diff
+    }
+
+    // True when the message was stored, a copy of a stored message is only acked again
+    fn treat_file_and_text(
+        &mut self,
+        msg_opt: Option<ChatMessage>,
+        proto_msg: &ProtoMessage,
+        from: Option<Endpoint>,
+    ) -> bool {
+        let mut stored = false;
+        if let Some(mut msg) = msg_opt {
+            msg.received_from = from;
+            msg.bundle = self.received_bundle.take();
+            msg.muted = self.db.get_muted_peers().contains(&msg.sender_uuid);
+            if let Some(receive_time) = msg.receive_time {
+                self.clock_skew.record_reception(
+                    &msg.sender_uuid,
+                    msg.send_time.timestamp_millis(),
+                    receive_time.timestamp_millis(),
+                );
+            }
+            let send_time =
+                self.to_local_millis(&msg.sender_uuid, msg.send_time.timestamp_millis());
+            if let Some(send_time) = DTChatTime::from_timestamp_millis(send_time) {
+                msg.send_time = send_time;
+                msg.send_completed = Some(send_time);
             }
+            self.record_sequence(&msg, proto_msg);
+            if msg.has_source_mismatch() {
+                self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::SourceMismatch(
+                    msg.clone(),
+                )));
+            }
+            stored = self.add_message(msg.clone());
 
-            None => self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::ProtocolDecode(
-                "Received proto message with unknown type".to_string(),
-            ))),
+            match Endpoint::from_str(proto_msg.source_endpoint.as_str()) {
+                Ok(endpoint) => self.send_ack_to_peer(&msg, endpoint),
+                Err(_err) => {
+                    self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::ProtocolDecode(
+                        "Received proto message source endpoint cannot be parsed".to_string(),
+                    )));
+                }
+            }
         }
+        stored
     }
 
-    pub fn add_observer(&mut self, obs: Arc<Mutex<dyn AppEventObserver>>) {
-        self.observers.push(obs);
+    fn next_sequence(&mut self, peer_uuid: &String, msg: &ChatMessage) -> u64 {
+        let now_ms = self.now().timestamp_millis();
+        self.sent_sequences
+            .next(peer_uuid, &msg.room_uuid, &msg.uuid, now_ms)
     }
 
-    pub fn notify_observers(&self, event: ChatAppEvent) {
-        for obs in &self.observers {
-            obs.lock().unwrap().on_event(event.clone());
+    // Written from the tick, a numbering lost in a crash is seen by the peers as a restart
+    fn flush_sequences(&mut self) {
+        if let Err(err) = self.sent_sequences.flush() {
+            self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::InternalError(
+                format!("Unable to write the sequence numbers: {}", err),
+            )));
         }
     }
 
-    pub fn get_other_peers_for_room(&self, room_uuid: &String) -> Option<Vec<(String, Endpoint)>> {
-        let rooms = self.db.get_rooms();
-        for (uuid, room) in rooms {
-            if *uuid != *room_uuid {
-                continue;
-            }
-            let mut is_allowed = false;
-            let mut participations: Vec<(String, Endpoint)> = Vec::new();
-            for reg in &room.participants {
-                if reg.0 == self.db.get_localpeer().uuid {
-                    is_allowed = true;
-                } else {
-                    participations.push(reg.clone());
-                }
-            }
-            if is_allowed {
-                return Some(participations);
-            }
+    fn flush_db(&mut self) {
+        if let Err(err) = self.db.flush() {
+            self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::InternalError(
+                format!("Failed to write the database: {}", err),
+            )));
         }
-        None
     }
 
-    pub fn send_to_room(
-        &mut self,
-        content: &Content,
-        room_uuid: &String,
-        try_prediction: bool,
-    ) -> Option<RoomMessage> {
-        let participants_opt = self.get_other_peers_for_room(room_uuid);
-        if let Some(participants) = participants_opt {
-            let mut room_msg = RoomMessage {
-                uuid: generate_uuid(),
-                room_uuid: room_uuid.clone(),
-                messages: Vec::new(),
-            };
-            if participants.len() == 0 {
-                return None;
+    fn record_sequence(&mut self, msg: &ChatMessage, proto_msg: &ProtoMessage) {
+        if msg.sequence == 0 {
+            return;
+        }
+        let Ok(endpoint) = Endpoint::from_str(&proto_msg.source_endpoint) else {
+            return;
+        };
+        let key = (msg.sender_uuid.clone(), msg.room_uuid.clone());
+        let now_ms = self.now().timestamp_millis();
+        match self.received_sequences.get_mut(&key) {
+            Some(tracker) => tracker.record(msg.sequence, endpoint, now_ms),
+            None => {
+                let tracker = SequenceTracker::new(msg.sequence, endpoint);
+                self.received_sequences.insert(key, tracker);
             }
+        }
+    }
 
-            for (peer_uuid, endpoint) in participants {
-                room_msg.messages.push(self.send_to_peer(
-                    content,
-                    &room_uuid,
-                    peer_uuid,
-                    &endpoint,
-                    try_prediction,
-                ));
+    // Gaps still open after the reorder window are reported, then requested again with a
+    // selective nack when retransmission is configured, or through history sync
+    fn report_gaps(&mut self, now: DTChatTime) {
+        let now_ms = now.timestamp_millis();
+        let window_ms = self.reorder_window_ms;
+        let gaps: Vec<(String, String, Vec<u64>)> = self
+            .received_sequences
+            .iter_mut()
+            .map(|((sender_uuid, room_uuid), tracker)| {
+                let overdue = tracker.overdue(window_ms, now_ms);
+                (sender_uuid.clone(), room_uuid.clone(), overdue)
+            })
+            .filter(|(_, _, sequences)| !sequences.is_empty())
+            .collect();
+        for (sender_uuid, room_uuid, missing) in gaps {
+            self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::PossibleLoss(
+                sender_uuid.clone(),
+                room_uuid,
+                missing,
+            )));
+            if self.retransmit_gap_after_ms.is_none() && self.history_sync {
+                self.sync_history_with(&sender_uuid);
             }
-            return Some(room_msg);
         }
-        None
     }
 
-    pub fn send_to_peer(
+    // Asks the peers for the messages reported missing from their sequence for
+    // retransmit_gap_after_ms
+    fn request_missing(&mut self, now: DTChatTime) {
+        let Some(after_ms) = self.retransmit_gap_after_ms else {
+            return;
+        };
+        let now_ms = now.timestamp_millis();
+        let requests: Vec<(Endpoint, String, Vec<u64>)> = self
+            .received_sequences
+            .iter_mut()
+            .map(|((_, room_uuid), tracker)| {
+                let due = tracker.due(after_ms, now_ms);
+                (tracker.endpoint.clone(), room_uuid.clone(), due)
+            })
+            .filter(|(_, _, sequences)| !sequences.is_empty())
+            .collect();
+        for (endpoint, room_uuid, sequences) in requests {
+            self.send_selective_nack(endpoint, room_uuid, sequences, Vec::new());
+        }
+    }
+
+    fn send_selective_nack(
         &mut self,
-        content: &Content,
-        room_uuid: &String,
-        peer_uuid: String,
-        endpoint: &Endpoint,
-        try_prediction: bool,
-    ) -> String {
-        let mut chatmsg = ChatMessage::new_to_send(
-            &self.db.get_localpeer().uuid,
+        endpoint: Endpoint,
+        room_uuid: String,
+        sequences: Vec<u64>,
+        message_uuids: Vec<String>,
+    ) {
+        let local_endpoint = self.find_local_endpoint_for_protocol(endpoint.proto.clone());
+        let proto_msg = ProtoMessage::new_selective_nack(
+            self.db.get_localpeer().uuid.clone(),
+            local_endpoint.clone(),
+            self.now().timestamp_millis(),
             room_uuid,
-            content.clone(),
-            endpoint.clone(),
+            sequences,
+            message_uuids,
         );
-        let sending_uuid = chatmsg.uuid.clone();
+        self.send_control_message(proto_msg, local_endpoint, endpoint);
+    }
 
+    // Only the messages sent to the requester are resent, missing ones as they were first
+    // sent and corrupted ones with their data
+    fn on_selective_nack(&mut self, proto_msg: &ProtoMessage, nack: &SelectiveNackMessage) {
+        let Ok(endpoint) = Endpoint::from_str(&proto_msg.source_endpoint) else {
+            return;
+        };
+        let requester = &proto_msg.sender_uuid;
+        let room_uuid = &proto_msg.room_uuid;
+        let sent = |sequence: u64| self.sent_sequences.sent(requester, room_uuid, sequence);
+        let as_offer = self.offers_files_to(requester);
+        let missing = nack
+            .sequences
+            .iter()
+            .filter_map(|sequence| Some((sent(*sequence)?.clone(), as_offer)));
+        let corrupted = nack.message_uuids.iter().filter_map(|uuid| {
+            let sequence = self.get_message(uuid)?.sequence;
+            (sent(sequence) == Some(uuid)).then(|| (uuid.clone(), false))
+        });
+        let resends: Vec<(String, bool)> = missing.chain(corrupted).collect();
         let local_endpoint = self.find_local_endpoint_for_protocol(endpoint.proto.clone());
-
-        self.pending_send_list
-            .push((MessageType::Text, sending_uuid.clone(), None));
-
-        let mut size_serialized = None;
-
-        if let Some(engine) = &mut self.network_engine {
-            match ProtoMessage::new_text(&chatmsg, local_endpoint.clone()) {
-                Ok(create_proto) => match create_proto.encode_to_vec() {
-                    Ok(bytes) => {
-                        size_serialized = Some(bytes.len());
-                        engine.send_async(local_endpoint, endpoint.clone(), bytes, sending_uuid);
-                    }
-                    Err(err) => {
-                        self.notify_observers(ChatAppEvent::Error(
-                            ChatAppErrorEvent::ProtocolEncode(format!(
-                                "Failed to encode message: {}",
-                                err
-                            )),
-                        ));
-                    }
-                },
-                Err(err) => self.notify_observers(ChatAppEvent::Error(
-                    ChatAppErrorEvent::InternalError(format!("Failed to encode message: {}", err)),
-                )),
+        for (uuid, as_offer) in resends {
+            let Some(msg) = self.get_message(&uuid) else {
+                continue;
+            };
+            if msg.status == MessageStatus::ReceivedByPeer || is_given_up(&msg.status) {
+                continue;
             }
-        }
-        if try_prediction {
-            let bp_local_endpoint_opt = self.find_local_endpoint_for_protocol(EndpointProto::Bp);
-            let bp_peer_endpoint_opt =
-                self.find_peer_endpoint_for_protocol(peer_uuid, EndpointProto::Bp);
-
-            if let (Some(src_eid), Some(dest_eid)) = (bp_local_endpoint_opt, bp_peer_endpoint_opt) {
-                // In theory we should add transport overhead..
-                if let (Some(size_sent), ASabrInitState::Enabled(a_sabr)) =
-                    (size_serialized, &mut self.a_sabr)
-                {
-                    if let Ok(arrival_time) = a_sabr.predict(
-                        src_eid.endpoint.as_str(),
-                        dest_eid.endpoint.as_str(),
-                        size_sent as f64,
-                    ) {
-                        chatmsg.predicted_arrival_time = Some(arrival_time);
-                    }
+            let wire_msg = self.wire_message(&msg);
+            let resent = if as_offer {
+                ProtoMessage::new_file_offer(&wire_msg, local_endpoint.clone())
+            } else {
+                ProtoMessage::new_text(&wire_msg, local_endpoint.clone())
+            };
+            match resent {
+                Ok(resent) => {
+                    let stage = DeliveryStage::Retried(endpoint.to_string());
+                    let now = self.now();
+                    self.db
+                        .add_timeline_entry(&uuid, TimelineEntry::at(stage, now));
+                    self.send_control_message(resent, local_endpoint.clone(), endpoint.clone())
+                }
+                Err(err) => {
+                    self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::InternalError(
+                        format!("Unable to resend message {}: {}", uuid, err),
+                    )))
                 }
             }
         }
-        self.add_message(chatmsg.clone());
-        return chatmsg.uuid;
     }
 
-    pub fn send_ack_to_peer(&mut self, for_msg: &ChatMessage, target_endpoint: Endpoint) {
-        let local_endpoint = self.find_local_endpoint_for_protocol(target_endpoint.proto.clone());
-
-        let proto_msg = ProtoMessage::new_ack(
-            for_msg,
-            self.db.get_localpeer().uuid.clone(),
+    // Ignores the acks of messages already acked, their reception time says nothing
+    // Returns the round trip from the last time the engine reported the message sent, None
+    // for an unknown or already acked message
+    fn record_ack_round_trip(&mut self, ack: &ProtoMessage, message_uuid: &String) -> Option<i64> {
+        let msg = self.get_message(message_uuid)?;
+        if msg.status == MessageStatus::ReceivedByPeer {
+            return None;
+        }
+        let now_ms = self.now().timestamp_millis();
+        let timeline = self.db.get_timeline(message_uuid);
+        // Queuing and connection time are not part of the round trip
+        let sent_ms = timeline
+            .iter()
+            .rev()
+            .find(|entry| entry.stage == DeliveryStage::Sent)
+            .map(|entry| entry.time)
+            .or(msg.send_completed)
+            .unwrap_or(msg.send_time)
+            .timestamp_millis();
+        // Which copy the ack answers is unknown once the message was sent again, such round
+        // trips are not sampled
+        let resent = timeline
+            .iter()
+            .any(|entry| matches!(entry.stage, DeliveryStage::Retried(_)));
+        let rtt_ms = now_ms - sent_ms;
+        if !resent {
+            self.clock_skew
+                .record_round_trip(&ack.sender_uuid, sent_ms, ack.timestamp, now_ms);
+            self.latency
+                .record(&ack.sender_uuid, &msg.source_endpoint.proto, rtt_ms);
+        }
+        Some(rtt_ms)
+    }
+
+    // A time given by the peer, in the local clock when the skew is corrected
+    fn to_local_millis(&self, peer_uuid: &str, timestamp: i64) -> i64 {
+        if !self.correct_clock_skew {
+            return timestamp;
+        }
+        timestamp - self.clock_skew.skew_ms(peer_uuid).unwrap_or(0)
+    }
+
+    // An empty text discards the draft of the room
+    pub fn save_draft(&mut self, room_uuid: &String, text: String) {
+        if let Err(err) = self.db.save_draft(room_uuid, text) {
+            self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::InternalError(
+                format!("Unable to save the draft of room {}: {}", room_uuid, err),
+            )));
+        }
+    }
+
+    pub fn get_draft(&self, room_uuid: &String) -> Option<String> {
+        self.db.get_draft(room_uuid).cloned()
+    }
+
+    // Timezone and formats the frontends should show times with
+    pub fn display_prefs(&self) -> DisplayPrefs {
+        self.display_prefs.clone()
+    }
+
+    // The time the model goes by, of its own source
+    pub fn now(&self) -> DTChatTime {
+        DTChatTime::now_from(self.time_source.as_ref())
+    }
+
+    // Replaces the clock of this model and of its contact plan only, returns the one
+    // replaced. A model starts with the time source of the process
+    pub fn set_time_source(&mut self, source: Arc<dyn TimeSource>) -> Arc<dyn TimeSource> {
+        if let ASabrInitState::Enabled(a_sabr) = &mut self.a_sabr {
+            a_sabr.set_time_source(source.clone());
+        }
+        std::mem::replace(&mut self.time_source, source)
+    }
+
+    // Milliseconds the clock of the peer is ahead of the local one, None until estimated
+    pub fn get_peer_clock_skew(&self, peer_uuid: &str) -> Option<i64> {
+        self.clock_skew.skew_ms(peer_uuid)
+    }
+
+    fn refuse_file(
+        &mut self,
+        msg_opt: Option<ChatMessage>,
+        proto_msg: &ProtoMessage,
+        reason: String,
+    ) {
+        let Some(msg) = msg_opt else {
+            return;
+        };
+        self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::InternalError(format!(
+            "Received file {} refused: {}",
+            msg.content_as_string(),
+            reason
+        ))));
+        match Endpoint::from_str(proto_msg.source_endpoint.as_str()) {
+            Ok(endpoint) => self.send_refusal_to_peer(&msg, endpoint, reason),
+            Err(_err) => {
+                self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::ProtocolDecode(
+                    "Received proto message source endpoint cannot be parsed".to_string(),
+                )));
+            }
+        }
+    }
+
+    // The peer is nacked with the reason when its endpoint can be read
+    fn reject_file(&mut self, proto_msg: &ProtoMessage, name: String, reason: String) {
+        self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::FileRejected(
+            name.clone(),
+            reason.clone(),
+        )));
+        let msg_opt = ChatMessage::new_received(proto_msg, Content::File(name), self.now());
+        if let (Some(msg), Ok(endpoint)) =
+            (msg_opt, Endpoint::from_str(&proto_msg.source_endpoint))
+        {
+            self.send_nack_to_peer(&msg, endpoint, reason);
+        }
+    }
+
+    // False until the peer answered a who-are-you listing the capability
+    fn peer_has_capability(&self, peer_uuid: &str, capability: &str) -> bool {
+        self.peer_capabilities
+            .get(peer_uuid)
+            .is_some_and(|capabilities| capabilities.iter().any(|cap| cap == capability))
+    }
+
+    // The peers not known to understand offers get the whole file
+    fn offers_files_to(&self, peer_uuid: &str) -> bool {
+        self.offer_files && self.peer_has_capability(peer_uuid, "file_offer")
+    }
+
+    // Checked as the file itself would be, so that nothing bound to be refused is requested
+    fn on_file_offer(&mut self, proto_msg: &ProtoMessage, offer: &FileOfferMessage) {
+        let name = match sanitize_file_name(&offer.name) {
+            Some(name) => self.file_policy.check(&name).map(|_| name),
+            None => Err("no usable file name".to_string()),
+        };
+        let name = match name {
+            Ok(name) => name,
+            Err(reason) => {
+                self.reject_file(proto_msg, offer.name.clone(), reason);
+                return;
+            }
+        };
+        if let Some(limit) = self.max_file_size.filter(|limit| offer.size > *limit) {
+            self.notify_observers(ChatAppEvent::Error(
+                ChatAppErrorEvent::ReceivedFileTooLarge(offer.name.clone(), offer.size, limit),
+            ));
+            if let (Some(msg), Ok(endpoint)) = (
+                ChatMessage::new_received(proto_msg, Content::File(name), self.now()),
+                Endpoint::from_str(&proto_msg.source_endpoint),
+            ) {
+                self.send_nack_to_peer(&msg, endpoint, format!("over {} bytes", limit));
+            }
+            return;
+        }
+        let Ok(source_endpoint) = Endpoint::from_str(&proto_msg.source_endpoint) else {
+            self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::ProtocolDecode(
+                "Received proto message source endpoint cannot be parsed".to_string(),
+            )));
+            return;
+        };
+        let from_sender = self
+            .file_offers
+            .values()
+            .filter(|(offer, _)| offer.sender_uuid == proto_msg.sender_uuid)
+            .count();
+        if self.file_offers.len() >= MAX_FILE_OFFERS || from_sender >= MAX_FILE_OFFERS_PER_PEER {
+            let reason = "too many pending file offers".to_string();
+            self.reject_file(proto_msg, offer.name.clone(), reason);
+            return;
+        }
+        let offer = FileOffer {
+            uuid: proto_msg.uuid.clone(),
+            sender_uuid: proto_msg.sender_uuid.clone(),
+            room_uuid: proto_msg.room_uuid.clone(),
+            name,
+            size: offer.size,
+            hash: offer.hash.clone(),
+            source_endpoint,
+            offered_at: self.now(),
+        };
+        self.file_offers
+            .insert(offer.uuid.clone(), (offer.clone(), proto_msg.clone()));
+        self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::FileOffered(offer)));
+    }
+
+    // Only the participants of the room of the offered file get it
+    fn on_file_request(&mut self, proto_msg: &ProtoMessage, message_uuid: &String) {
+        let Some(endpoint) = self.room_peer_endpoint(proto_msg) else {
+            return;
+        };
+        let local_peer_uuid = self.db.get_localpeer().uuid.clone();
+        let Some(msg) = self.get_message(message_uuid).filter(|msg| {
+            msg.sender_uuid == local_peer_uuid
+                && msg.in_room(&proto_msg.room_uuid)
+                && msg.status != MessageStatus::Cancelled
+                && msg.content.file_path().is_some()
+        }) else {
+            self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::MessageNotFound(
+                format!(
+                    "File {} requested by peer {} was not offered",
+                    message_uuid, proto_msg.sender_uuid
+                ),
+            )));
+            return;
+        };
+        let local_endpoint = self.find_local_endpoint_for_protocol(endpoint.proto.clone());
+        match ProtoMessage::new_text(&msg, local_endpoint.clone()) {
+            Ok(file_msg) => {
+                let stage = DeliveryStage::Sending(endpoint.to_string());
+                let now = self.now();
+                self.db
+                    .add_timeline_entry(&msg.uuid, TimelineEntry::at(stage, now));
+                self.send_control_message(file_msg, local_endpoint, endpoint)
+            }
+            Err(err) => self.notify_observers(ChatAppEvent::Error(
+                ChatAppErrorEvent::InternalError(format!(
+                    "Unable to read the requested file {}: {}",
+                    msg.content_as_string(),
+                    err
+                )),
+            )),
+        }
+    }
+
+    // Asks the sender for the data of the offered file
+    pub fn accept_file(&mut self, offer_uuid: &String) -> Result<(), ChatAppErrorEvent> {
+        let (offer, _) = self.take_file_offer(offer_uuid)?;
+        let endpoint = offer.source_endpoint.clone();
+        let local_endpoint = self.find_local_endpoint_for_protocol(endpoint.proto.clone());
+        let request = ProtoMessage::new_file_request(
+            self.db.get_localpeer().uuid.clone(),
+            local_endpoint.clone(),
+            self.now().timestamp_millis(),
+            &offer,
+        );
+        self.send_control_message(request, local_endpoint, endpoint);
+        if self.accepted_offers.len() >= MAX_ACCEPTED_OFFERS {
+            let oldest = self
+                .accepted_offers
+                .values()
+                .min_by_key(|offer| offer.offered_at.timestamp_millis())
+                .map(|offer| offer.uuid.clone());
+            if let Some(oldest) = oldest {
+                self.accepted_offers.remove(&oldest);
+                self.corrupted_files.remove(&oldest);
+            }
+        }
+        self.accepted_offers.insert(offer.uuid.clone(), offer);
+        Ok(())
+    }
+
+    // The sender is nacked, its message fails
+    pub fn decline_file(&mut self, offer_uuid: &String) -> Result<(), ChatAppErrorEvent> {
+        let (offer, proto_msg) = self.take_file_offer(offer_uuid)?;
+        if let Some(msg) =
+            ChatMessage::new_received(&proto_msg, Content::File(offer.name), self.now())
+        {
+            self.send_nack_to_peer(&msg, offer.source_endpoint, "file declined".to_string());
+        }
+        Ok(())
+    }
+
+    fn take_file_offer(
+        &mut self,
+        offer_uuid: &String,
+    ) -> Result<(FileOffer, ProtoMessage), ChatAppErrorEvent> {
+        self.file_offers.remove(offer_uuid).ok_or_else(|| {
+            ChatAppErrorEvent::MessageNotFound(format!("No pending file offer {}", offer_uuid))
+        })
+    }
+
+    // Offers neither accepted nor declined yet, oldest first
+    pub fn get_file_offers(&self) -> Vec<FileOffer> {
+        let mut offers: Vec<FileOffer> = self
+            .file_offers
+            .values()
+            .map(|(offer, _)| offer.clone())
+            .collect();
+        offers.sort_by_key(|offer| offer.offered_at.timestamp_millis());
+        offers
+    }
+
+    // Peers missing from the configuration get the least trust, the local uuid included:
+    // only the texts sent to ourselves are taken, from a local endpoint. So do the peers
+    // whose key changed
+    fn trust_of(&self, peer_uuid: &String) -> TrustLevel {
+        if self.key_mismatches.contains(peer_uuid) {
+            return TrustLevel::Untrusted;
+        }
+        self.db
+            .get_other_peers()
+            .get(peer_uuid)
+            .map_or(TrustLevel::Untrusted, |peer| peer.trust)
+    }
+
+    // Both the claimed sender and the transport endpoint are limited, the uuid being easy to forge
+    fn within_rate_limit(&mut self, proto_msg: &ProtoMessage, from: Option<&Endpoint>) -> bool {
+        let now_ms = self.now().timestamp_millis();
+        let Some(limiter) = &mut self.rate_limiter else {
+            return true;
+        };
+        if !limiter.allow(&proto_msg.sender_uuid, now_ms) {
+            return false;
+        }
+        match from {
+            Some(from) => limiter.allow(&from.to_string(), now_ms),
+            None => true,
+        }
+    }
+
+    pub fn treat_proto_message(&mut self, proto_msg: ProtoMessage) {
+        self.treat_proto_message_from(proto_msg, None);
+    }
+
+    // from is the transport level sender, when known
+    pub fn treat_proto_message_from(&mut self, proto_msg: ProtoMessage, from: Option<Endpoint>) {
+        self.treat_received(proto_msg, from, None);
+    }
+
+    // frame is the data the message was decoded from, quarantined when its type is unknown
+    fn treat_received(
+        &mut self,
+        mut proto_msg: ProtoMessage,
+        from: Option<Endpoint>,
+        frame: Option<&[u8]>,
+    ) {
+        let _span = tracing::info_span!(
+            "receive",
+            message_uuid = %proto_msg.uuid,
+            sender_uuid = %proto_msg.sender_uuid
+        )
+        .entered();
+        if !self.within_rate_limit(&proto_msg, from.as_ref()) {
+            self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::RateLimited(
+                format!(
+                    "message {} from peer {} dropped",
+                    proto_msg.uuid, proto_msg.sender_uuid
+                ),
+            )));
+            return;
+        }
+        // Messages sent to ourselves are only acked, the sent copy is already stored. The
+        // local uuid is easy to claim: only what comes from a local endpoint is acked
+        if proto_msg.sender_uuid == self.db.get_localpeer().uuid {
+            if let Some(MsgType::Text(text_part)) = &proto_msg.msg_type {
+                let local_source = Endpoint::from_str(&proto_msg.source_endpoint)
+                    .ok()
+                    .filter(|source| self.db.get_localpeer().endpoints.contains(source))
+                    .filter(|source| from.as_ref().map_or(true, |from| same_node(from, source)));
+                let Some(endpoint) = local_source else {
+                    self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::TrustViolation(
+                        format!(
+                            "message {} claims the local uuid from {}",
+                            proto_msg.uuid, proto_msg.source_endpoint
+                        ),
+                    )));
+                    return;
+                };
+                let msg_opt = ChatMessage::new_received(
+                    &proto_msg,
+                    Content::Text(text_part.text.clone()),
+                    self.now(),
+                );
+                if let Some(msg) = msg_opt {
+                    self.send_ack_to_peer(&msg, endpoint);
+                }
+                return;
+            }
+        }
+        if let Some(msg_type) = &proto_msg.msg_type {
+            if !self.trust_of(&proto_msg.sender_uuid).allows(msg_type) {
+                self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::TrustViolation(
+                    format!(
+                        "message {} from peer {} refused",
+                        proto_msg.uuid, proto_msg.sender_uuid
+                    ),
+                )));
+                return;
+            }
+        }
+        // The other rooms a message is shown in come from the sender, which must be one of
+        // their participants
+        if !proto_msg.also_rooms.is_empty() {
+            let sender_uuid = proto_msg.sender_uuid.clone();
+            let (kept, refused): (Vec<String>, Vec<String>) =
+                std::mem::take(&mut proto_msg.also_rooms)
+                    .into_iter()
+                    .partition(|room_uuid| self.is_room_participant(room_uuid, &sender_uuid));
+            if !refused.is_empty() {
+                self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::TrustViolation(
+                    format!(
+                        "message {} from peer {} not shown in rooms {}, it is not a participant",
+                        proto_msg.uuid,
+                        sender_uuid,
+                        refused.join(", ")
+                    ),
+                )));
+            }
+            proto_msg.also_rooms = kept;
+        }
+        if let Some(MsgType::Text(_))
+        | Some(MsgType::File(_))
+        | Some(MsgType::FileOffer(_))
+        | Some(MsgType::Blob(_))
+        | Some(MsgType::Location(_))
+        | Some(MsgType::Pin(_))
+        | Some(MsgType::HistoryDigest(_))
+        | Some(MsgType::HistoryBackfill(_)) = &proto_msg.msg_type
+        {
+            if self.db.get_blocked_peers().contains(&proto_msg.sender_uuid) {
+                self.notify_observers(ChatAppEvent::Info(format!(
+                    "Message {} from blocked peer {} dropped",
+                    proto_msg.uuid, proto_msg.sender_uuid
+                )));
+                return;
+            }
+        }
+        // A message may arrive more than once when it was raced over several paths, or sent
+        // again as the ack of the first copy was lost: the stored copy is acked again
+        if let Some(MsgType::Text(_))
+        | Some(MsgType::File(_))
+        | Some(MsgType::FileOffer(_))
+        | Some(MsgType::Blob(_))
+        | Some(MsgType::Location(_)) = &proto_msg.msg_type
+        {
+            let stored = self.get_message(&proto_msg.uuid);
+            if stored.is_some() || self.file_offers.contains_key(&proto_msg.uuid) {
+                self.notify_observers(ChatAppEvent::Info(format!(
+                    "Duplicate of message {} dropped",
+                    proto_msg.uuid
+                )));
+                if let (Some(msg), Ok(endpoint)) =
+                    (stored, Endpoint::from_str(&proto_msg.source_endpoint))
+                {
+                    self.send_ack_to_peer(&msg, endpoint);
+                }
+                return;
+            }
+        }
+        if let Some(MsgType::Text(_))
+        | Some(MsgType::File(_))
+        | Some(MsgType::FileOffer(_))
+        | Some(MsgType::Blob(_))
+        | Some(MsgType::Location(_)) = &proto_msg.msg_type
+        {
+            if DTChatTime::from_expiration_millis(proto_msg.expires_at)
+                .is_some_and(|expires_at| expires_at < self.now())
+            {
+                self.notify_observers(ChatAppEvent::Info(format!(
+                    "Expired message {} discarded",
+                    proto_msg.uuid
+                )));
+                return;
+            }
+        }
+        match &proto_msg.msg_type {
+            Some(MsgType::Text(text_part)) => {
+                let chat_msg = ChatMessage::new_received(
+                    &proto_msg,
+                    Content::Text(text_part.text.clone()),
+                    self.now(),
+                );
+                self.treat_file_and_text(chat_msg, &proto_msg, from);
+            }
+
+            Some(MsgType::File(file_part)) => {
+                let file_name = match sanitize_file_name(&file_part.name) {
+                    Some(file_name) => self.file_policy.check(&file_name).map(|_| file_name),
+                    None => Err("no usable file name".to_string()),
+                };
+                let file_name = match file_name {
+                    Ok(file_name) => file_name,
+                    Err(reason) => {
+                        self.reject_file(&proto_msg, file_part.name.clone(), reason);
+                        return;
+                    }
+                };
+                if let Some(offer) = self.accepted_offers.remove(&proto_msg.uuid) {
+                    if offer.hash != file_hash(&file_part.data) {
+                        if self.corrupted_files.insert(proto_msg.uuid.clone()) {
+                            if let Ok(endpoint) = Endpoint::from_str(&proto_msg.source_endpoint) {
+                                self.accepted_offers.insert(offer.uuid.clone(), offer);
+                                let room_uuid = proto_msg.room_uuid.clone();
+                                let uuids = vec![proto_msg.uuid.clone()];
+                                self.send_selective_nack(endpoint, room_uuid, Vec::new(), uuids);
+                                return;
+                            }
+                        }
+                        self.corrupted_files.remove(&proto_msg.uuid);
+                        let reason = "the data does not match the offer".to_string();
+                        self.reject_file(&proto_msg, file_part.name.clone(), reason);
+                        return;
+                    }
+                    self.corrupted_files.remove(&proto_msg.uuid);
+                }
+                let content = match &file_part.audio {
+                    Some(audio) => Content::Audio(
+                        file_name.clone(),
+                        AudioInfo {
+                            codec: audio.codec.clone(),
+                            duration_ms: audio.duration_ms,
+                        },
+                    ),
+                    None => Content::File(file_name.clone()),
+                };
+                let mut chat_msg = ChatMessage::new_received(&proto_msg, content, self.now());
+                if let Some(msg) = &mut chat_msg {
+                    msg.file_info = Some(received_file_info(&file_name, file_part));
+                }
+                let size = file_part.data.len() as u64;
+                if let Some(limit) = self.max_file_size.filter(|limit| size > *limit) {
+                    let name = file_part.name.clone();
+                    self.notify_observers(ChatAppEvent::Error(
+                        ChatAppErrorEvent::ReceivedFileTooLarge(name, size, limit),
+                    ));
+                    if let (Some(msg), Ok(endpoint)) =
+                        (chat_msg, Endpoint::from_str(&proto_msg.source_endpoint))
+                    {
+                        self.send_nack_to_peer(&msg, endpoint, format!("over {} bytes", limit));
+                    }
+                    return;
+                }
+                // Within the quota of every room it is shown in, stored in the folder of
+                // the first one having its own
+                let folder = std::iter::once(&proto_msg.room_uuid)
+                    .chain(&proto_msg.also_rooms)
+                    .filter_map(|room_uuid| self.room_reception.get(room_uuid))
+                    .try_fold(None, |folder, room_reception| {
+                        room_reception.check_quota(size)?;
+                        Ok(folder.or_else(|| Some(room_reception.dir.clone())))
+                    });
+                let folder = match folder {
+                    Ok(folder) => folder.unwrap_or_else(|| self.reception_folder.clone()),
+                    Err(reason) => {
+                        self.refuse_file(chat_msg, &proto_msg, reason);
+                        return;
+                    }
+                };
+                let full_path = match stored_file_path(
+                    &folder,
+                    &file_name,
+                    &proto_msg.sender_uuid,
+                    self.file_collision_policy,
+                ) {
+                    Ok(full_path) => full_path,
+                    Err(reason) => {
+                        self.reject_file(&proto_msg, file_part.name.clone(), reason);
+                        return;
+                    }
+                };
+                match files::write(&full_path, &file_part.data) {
+                    Ok(_) => {
+                        self.notify_observers(ChatAppEvent::Info(format!(
+                            "File stored: {}",
+                            full_path.display()
+                        )));
+                        if let Some(msg) = &mut chat_msg {
+                            msg.stored_path = Some(full_path.to_string_lossy().to_string());
+                        }
+                    }
+                    Err(err) => {
+                        self.notify_observers(ChatAppEvent::Error(
+                            ChatAppErrorEvent::InternalError(format!(
+                                "Unable to save received file: {}",
+                                err
+                            )),
+                        ));
+                    }
+                }
+
+                self.treat_file_and_text(chat_msg, &proto_msg, from);
+            }
+
+            Some(MsgType::Blob(blob)) => {
+                let content = Content::Blob(blob.content_type.clone(), blob.data.clone());
+                let chat_msg = ChatMessage::new_received(&proto_msg, content, self.now());
+                if self.treat_file_and_text(chat_msg, &proto_msg, from) {
+                    self.route_blob(&proto_msg.uuid);
+                }
+            }
+
+            Some(MsgType::Location(location_part)) => {
+                let location = match location_from_proto(&proto_msg, location_part) {
+                    Ok(location) => location,
+                    Err(reason) => {
+                        self.notify_observers(ChatAppEvent::Error(
+                            ChatAppErrorEvent::InvalidMessage(format!(
+                                "Location {} from peer {} dropped: {}",
+                                proto_msg.uuid, proto_msg.sender_uuid, reason
+                            )),
+                        ));
+                        return;
+                    }
+                };
+                let chat_msg =
+                    ChatMessage::new_received(&proto_msg, Content::Location(location), self.now());
+                self.treat_file_and_text(chat_msg, &proto_msg, from);
+            }
+
+            Some(MsgType::FileOffer(offer)) => self.on_file_offer(&proto_msg, offer),
+
+            Some(MsgType::FileRequest(request)) => {
+                self.on_file_request(&proto_msg, &request.message_uuid)
+            }
+
+            Some(MsgType::Ack(ack)) => {
+                let message_uuid = self.acked_replica(&ack.message_uuid, &proto_msg.sender_uuid);
+                let rtt_ms = self.record_ack_round_trip(&proto_msg, &message_uuid);
+                let timestamp = self.to_local_millis(&proto_msg.sender_uuid, proto_msg.timestamp);
+                self.mark_as_acked(&message_uuid, timestamp, rtt_ms);
+            }
+
+            Some(MsgType::Nack(nack)) => {
+                let message_uuid = self.acked_replica(&nack.message_uuid, &proto_msg.sender_uuid);
+                if nack.refused {
+                    self.mark_as_refused(&message_uuid, nack.reason.clone());
+                } else {
+                    self.mark_as_nacked(&message_uuid, nack.reason.clone());
+                }
+            }
+
+            Some(MsgType::SelectiveNack(nack)) => self.on_selective_nack(&proto_msg, nack),
+
+            Some(MsgType::Keepalive(keepalive)) => {
+                if keepalive.reply {
+                    if let Ok(endpoint) = Endpoint::from_str(&proto_msg.source_endpoint) {
+                        self.send_keepalive(endpoint, false);
+                    }
+                }
+            }
+
+            Some(MsgType::WhoAreYou(who_are_you)) => {
+                self.peer_codecs
+                    .insert(proto_msg.sender_uuid.clone(), who_are_you.codecs.clone());
+                match Endpoint::from_str(&proto_msg.source_endpoint) {
+                    Ok(endpoint) => {
+                        self.send_i_am(&proto_msg.uuid, &who_are_you.challenge, endpoint.clone());
+                        // The key given here proves nothing, it is checked by challenging
+                        // the peer in turn
+                        if !who_are_you.public_key.is_empty() {
+                            self.challenge_peer_key(&proto_msg.sender_uuid, endpoint.clone());
+                        }
+                        self.send_profile_once(&proto_msg.sender_uuid, endpoint);
+                    }
+                    Err(_) => self.notify_observers(ChatAppEvent::Error(
+                        ChatAppErrorEvent::InvalidMessage(format!(
+                            "Cannot answer the query of peer {}: bad source endpoint",
+                            proto_msg.sender_uuid
+                        )),
+                    )),
+                }
+            }
+
+            Some(MsgType::HistoryDigest(digest)) => {
+                self.on_history_digest(&proto_msg, &digest.message_uuids, digest.since)
+            }
+
+            Some(MsgType::HistoryRequest(request)) => {
+                if let Some(endpoint) = self.room_peer_endpoint(&proto_msg) {
+                    let uuids: HashSet<&String> = request.message_uuids.iter().collect();
+                    self.send_history_backfill(&proto_msg.room_uuid, &uuids, endpoint);
+                }
+            }
+
+            Some(MsgType::HistoryBackfill(backfill)) => {
+                self.on_history_backfill(&proto_msg, &backfill.messages)
+            }
+
+            Some(MsgType::Pin(pin)) => {
+                // The message must belong to the room the sender is a participant of
+                if self.room_peer_endpoint(&proto_msg).is_some()
+                    && self
+                        .get_message(&pin.message_uuid)
+                        .is_some_and(|msg| msg.in_room(&proto_msg.room_uuid))
+                {
+                    self.set_pinned(&pin.message_uuid, pin.pinned);
+                }
+            }
+
+            Some(MsgType::IAm(i_am)) => {
+                // Only the versions of configured peers are kept, a stranger would grow it
+                if self
+                    .db
+                    .get_other_peers()
+                    .contains_key(&proto_msg.sender_uuid)
+                {
+                    self.peer_versions
+                        .insert(proto_msg.sender_uuid.clone(), i_am.version.clone());
+                }
+                self.peer_capabilities
+                    .insert(proto_msg.sender_uuid.clone(), i_am.capabilities.clone());
+                self.peer_codecs
+                    .insert(proto_msg.sender_uuid.clone(), i_am.codecs.clone());
+                self.on_signed_key(&proto_msg.sender_uuid, i_am);
+                let info = PeerNodeInfo {
+                    peer_uuid: proto_msg.sender_uuid.clone(),
+                    version: i_am.version.clone(),
+                    git_hash: i_am.git_hash.clone(),
+                    features: i_am.features.clone(),
+                    capabilities: i_am.capabilities.clone(),
+                    endpoints: i_am.endpoints.clone(),
+                };
+                self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::PeerInfo(
+                    i_am.request_uuid.clone(),
+                    info,
+                )));
+                if let Ok(endpoint) = Endpoint::from_str(&proto_msg.source_endpoint) {
+                    self.send_profile_once(&proto_msg.sender_uuid, endpoint);
+                }
+            }
+
+            Some(MsgType::Profile(profile)) => self.on_profile(&proto_msg, profile),
+
+            Some(MsgType::KeyRotation(rotation)) => {
+                let peer_uuid = &proto_msg.sender_uuid;
+                let now_ms = self.now().timestamp_millis();
+                match self.key_store.rotate(
+                    peer_uuid,
+                    &rotation.public_key,
+                    &rotation.signature,
+                    now_ms,
+                ) {
+                    Ok(()) => self.notify_observers(ChatAppEvent::Message(
+                        ChatAppInfoEvent::PeerKeyRotated(
+                            peer_uuid.clone(),
+                            fingerprint(&rotation.public_key),
+                        ),
+                    )),
+                    Err(reason) => self.notify_observers(ChatAppEvent::Error(
+                        ChatAppErrorEvent::TrustViolation(format!(
+                            "Key rotation of peer {} refused: {}",
+                            peer_uuid, reason
+                        )),
+                    )),
+                }
+            }
+
+            None => self.quarantine_unknown(&proto_msg, frame),
+        }
+    }
+
+    // Sent by a newer version of the protocol, kept and acked so that the sender does not
+    // retry it
+    fn quarantine_unknown(&mut self, proto_msg: &ProtoMessage, frame: Option<&[u8]>) {
+        let data = match frame {
+            Some(frame) => frame.to_vec(),
+            // The unknown part was lost when decoding
+            None => proto_msg.encode_to_vec().unwrap_or_default(),
+        };
+        let quarantined = QuarantinedMessage {
+            uuid: proto_msg.uuid.clone(),
+            sender_uuid: proto_msg.sender_uuid.clone(),
+            room_uuid: proto_msg.room_uuid.clone(),
+            sender_version: self.peer_versions.get(&proto_msg.sender_uuid).cloned(),
+            received_at_ms: self.now().timestamp_millis(),
+            data,
+        };
+        let from_sender: Vec<String> = self
+            .db
+            .get_quarantined()
+            .iter()
+            .filter(|msg| msg.sender_uuid == proto_msg.sender_uuid)
+            .map(|msg| msg.uuid.clone())
+            .collect();
+        if from_sender.len() >= MAX_QUARANTINED_PER_PEER {
+            let oldest = &from_sender[0];
+            if let Err(err) = self.db.remove_quarantined(oldest) {
+                self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::InternalError(
+                    format!("Failed to remove quarantined message {}: {}", oldest, err),
+                )));
+            }
+        }
+        if self.db.get_quarantined().len() >= MAX_QUARANTINED {
+            self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::InternalError(
+                format!(
+                    "The quarantine is full, message {} is not kept",
+                    proto_msg.uuid
+                ),
+            )));
+        } else if let Err(err) = self.db.quarantine(quarantined.clone()) {
+            self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::InternalError(
+                format!("Failed to quarantine message {}: {}", proto_msg.uuid, err),
+            )));
+        }
+        self.notify_observers(ChatAppEvent::Message(
+            ChatAppInfoEvent::UnsupportedMessageType(quarantined),
+        ));
+        // The ack only needs the uuid and room of the message
+        let stand_in =
+            ChatMessage::new_received(proto_msg, Content::Text(String::new()), self.now());
+        if let (Some(msg), Ok(endpoint)) =
+            (stand_in, Endpoint::from_str(&proto_msg.source_endpoint))
+        {
+            self.send_ack_to_peer(&msg, endpoint);
+        }
+    }
+
+    pub fn add_observer(&mut self, obs: Arc<Mutex<dyn AppEventObserver>>) -> ObserverId {
+        self.add_observer_filtered(obs, EventFilter::default())
+    }
+
+    pub fn add_observer_filtered(
+        &mut self,
+        obs: Arc<Mutex<dyn AppEventObserver>>,
+        filter: EventFilter,
+    ) -> ObserverId {
+        self.push_observer(ObserverRef::Strong(obs), filter)
+    }
+
+    // The n most recent events are given first, up to event_history_capacity, for the
+    // frontends attached after the start to rebuild their state
+    pub fn add_observer_with_replay(
+        &mut self,
+        obs: Arc<Mutex<dyn AppEventObserver>>,
+        n: usize,
+    ) -> ObserverId {
+        let replayed = self.recent_events(n, &EventFilter::default());
+        {
+            let mut obs = obs.lock().unwrap();
+            for event in replayed {
+                obs.on_event(event);
+            }
+        }
+        self.add_observer(obs)
+    }
+
+    // Not kept alive by the model, it stops being notified once dropped
+    pub fn add_weak_observer(
+        &mut self,
+        obs: Weak<Mutex<dyn AppEventObserver>>,
+        filter: EventFilter,
+    ) -> ObserverId {
+        self.push_observer(ObserverRef::Weak(obs), filter)
+    }
+
+    fn push_observer(&mut self, obs: ObserverRef, filter: EventFilter) -> ObserverId {
+        self.observers.retain(|(_, obs, _)| obs.get().is_some());
+        let id = ObserverId::new(self.next_observer_id);
+        self.next_observer_id += 1;
+        self.observers.push((id, obs, filter));
+        id
+    }
+
+    // False when the observer was already removed or dropped
+    pub fn remove_observer(&mut self, id: ObserverId) -> bool {
+        let found = self
+            .observers
+            .iter()
+            .any(|(observer_id, obs, _)| *observer_id == id && obs.get().is_some());
+        self.observers
+            .retain(|(observer_id, obs, _)| *observer_id != id && obs.get().is_some());
+        found
+    }
+
+    // Those still alive
+    pub fn observer_count(&self) -> usize {
+        self.observers
+            .iter()
+            .filter(|(_, obs, _)| obs.get().is_some())
+            .count()
+    }
+
+    // Every event is recorded to the history and the journal, only what the observers are
+    // given is coalesced. The held events past their window go first
+    pub fn notify_observers(&self, event: ChatAppEvent) {
+        tracing::trace!(?event);
+        self.record_event(&event);
+        let events = match &self.coalescer {
+            Some(coalescer) => {
+                let now_ms = self.now().timestamp_millis();
+                let mut coalescer = coalescer.lock().unwrap();
+                let mut events = coalescer.flush(now_ms);
+                events.extend(coalescer.offer(event, now_ms));
+                events
+            }
+            None => vec![event],
+        };
+        for event in events {
+            self.dispatch(event);
+        }
+    }
+
+    // The periodic work of the model: expiries, timeouts, deferred, scheduled and queued
+    // sends, retransmit requests, keepalives, reconnections and held events. Every frontend calls
+    // it, at least a few times per second, e.g. through scheduler::start_ticker
+    pub fn tick(&mut self, now: DTChatTime) {
+        self.expire_messages();
+        self.check_contact_plan(now);
+        self.check_ack_timeouts(now);
+        self.check_late_deliveries(now);
+        self.send_deferred();
+        self.dispatch_scheduled(now);
+        self.flush_send_queues_at(now);
+        self.report_gaps(now);
+        self.request_missing(now);
+        self.flush_sequences();
+        self.flush_db();
+        self.check_keepalives(now);
+        self.check_status_reports();
+        self.reconnect(now);
+        self.flush_coalesced_events(now);
+    }
+
+    // Delivers the progress events held past their window
+    fn flush_coalesced_events(&self, now: DTChatTime) {
+        let Some(coalescer) = &self.coalescer else {
+            return;
+        };
+        let events = coalescer.lock().unwrap().flush(now.timestamp_millis());
+        for event in events {
+            self.dispatch(event);
+        }
+    }
+
+    fn record_event(&self, event: &ChatAppEvent) {
+        {
+            let mut history = self.event_history.lock().unwrap();
+            if self.event_history_capacity > 0 {
+                if history.len() == self.event_history_capacity {
+                    history.pop_front();
+                }
+                history.push_back(event.clone());
+            }
+        }
+        if let Some(journal) = &self.journal {
+            journal.lock().unwrap().on_event(event.clone());
+        }
+    }
+
+    // To the observers only, the event was recorded when notified
+    fn dispatch(&self, event: ChatAppEvent) {
+        for (_, obs, filter) in &self.observers {
+            if !filter.matches(&event) {
+                continue;
+            }
+            if let Some(obs) = obs.get() {
+                obs.lock().unwrap().on_event(event.clone());
+            }
+        }
+    }
+
+    // The n most recent events matching the filter, oldest first
+    pub fn recent_events(&self, n: usize, filter: &EventFilter) -> Vec<ChatAppEvent> {
+        let history = self.event_history.lock().unwrap();
+        let mut events: Vec<ChatAppEvent> = history
+            .iter()
+            .rev()
+            .filter(|event| filter.matches(event))
+            .take(n)
+            .cloned()
+            .collect();
+        events.reverse();
+        events
+    }
+
+    pub fn get_other_peers_for_room(&self, room_uuid: &String) -> Option<Vec<(String, Endpoint)>> {
+        let rooms = self.db.get_rooms();
+        for (uuid, room) in rooms {
+            if *uuid != *room_uuid {
+                continue;
+            }
+            let mut is_allowed = false;
+            let mut participations: Vec<(String, Endpoint)> = Vec::new();
+            for reg in &room.participants {
+                if reg.0 == self.db.get_localpeer().uuid {
+                    is_allowed = true;
+                } else {
+                    participations.push(reg.clone());
+                }
+            }
+            if is_allowed {
+                return Some(participations);
+            }
+        }
+        None
+    }
+
+    // Sends the same message over every endpoint of the peer, each copy checked and handed
+    // over like a single send. Failed when no endpoint took it
+    pub fn send_to_peer_redundant(
+        &mut self,
+        content: &Content,
+        room_uuid: &String,
+        peer_uuid: String,
+    ) -> Result<String, ChatError> {
+        self.record_call(|| RecordedEvent::SendToPeerRedundant {
+            content: content.into(),
+            room_uuid: room_uuid.clone(),
+            peer_uuid: peer_uuid.clone(),
+        });
+        let endpoints = match self.db.get_other_peers().get(&peer_uuid) {
+            None => Err(ChatAppErrorEvent::PeerNotFound(peer_uuid.clone())),
+            Some(peer) if peer.endpoints.is_empty() => {
+                Err(ChatAppErrorEvent::NoEndpoint(peer_uuid.clone()))
+            }
+            Some(peer) => Ok(peer.endpoints.clone()),
+        }
+        .and_then(|endpoints| self.check_content_size(content).map(|_| endpoints));
+        let endpoints = match endpoints {
+            Ok(endpoints) => endpoints,
+            Err(error) => {
+                self.notify_observers(ChatAppEvent::Error(error.clone()));
+                return Err(ChatError::from(error)
+                    .with_peer(&peer_uuid)
+                    .with_room(room_uuid));
+            }
+        };
+        let mut chatmsg = ChatMessage::new_to_send(
+            &self.db.get_localpeer().uuid,
+            room_uuid,
+            content.clone(),
+            endpoints[0].clone(),
+            self.now(),
+        );
+        if let Some(path) = content.file_path() {
+            chatmsg.file_info = FileInfo::read(path).ok();
+        }
+        chatmsg.vector_clock = self.tick_room_clock(room_uuid);
+        chatmsg.lamport_time = self.tick_room_lamport_time(room_uuid);
+        chatmsg.sequence = self.next_sequence(&peer_uuid, &chatmsg);
+        if let Some(ttl_ms) = self.message_ttl_ms {
+            chatmsg.expires_at =
+                DTChatTime::from_timestamp_millis(chatmsg.send_time.timestamp_millis() + ttl_ms);
+        }
+        self.pending_send_list
+            .push((MessageType::Text, chatmsg.uuid.clone(), None));
+
+        let mut handed_over = None;
+        let mut last_error = None;
+        for endpoint in &endpoints {
+            let sent = self
+                .check_queue_capacity(endpoint)
+                .map_err(|error| (error, None))
+                .and_then(|_| {
+                    self.hand_over(&chatmsg, content, &peer_uuid, endpoint)
+                        .map_err(|(error, source)| (error, Some(source)))
+                });
+            match sent {
+                Ok(Some(_)) => {
+                    handed_over.get_or_insert_with(|| endpoint.clone());
+                }
+                Ok(None) => {}
+                Err((error, source)) => {
+                    self.notify_observers(ChatAppEvent::Error(error.clone()));
+                    let error = ChatError::from(error).with_endpoint(endpoint);
+                    last_error = Some(match source {
+                        Some(source) => error.with_source(source),
+                        None => error,
+                    });
+                }
+            }
+        }
+
+        let Some(endpoint) = handed_over else {
+            // No transport took a copy, the message is kept as failed
+            self.pending_send_list
+                .retain(|(_, token, _)| *token != chatmsg.uuid);
+            let message_uuid = chatmsg.uuid.clone();
+            self.add_message(chatmsg);
+            if let Some(message) = self.mark_as(&message_uuid, MarkIntent::Failed) {
+                self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::Failed(message)));
+            }
+            let error = last_error.unwrap_or_else(|| {
+                self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::NoEngineAttached));
+                ChatError::from(ChatAppErrorEvent::NoEngineAttached)
+            });
+            return Err(error
+                .with_peer(&peer_uuid)
+                .with_room(room_uuid)
+                .with_message_uuid(&message_uuid));
+        };
+        self.persist_pending_send(&chatmsg, &peer_uuid, &endpoint);
+        self.add_message(chatmsg.clone());
+        self.flush_send_queues();
+        Ok(chatmsg.uuid)
+    }
+
+    pub fn send_to_room(
+        &mut self,
+        content: &Content,
+        room_uuid: &String,
+        try_prediction: bool,
+    ) -> Result<RoomSendReport, ChatError> {
+        self.record_call(|| RecordedEvent::SendToRoom {
+            content: content.into(),
+            room_uuid: room_uuid.clone(),
+            try_prediction,
+        });
+        let participants = match self.get_other_peers_for_room(room_uuid) {
+            Some(participants) => self.apply_room_transport(room_uuid, participants),
+            None => {
+                let error = ChatAppErrorEvent::RoomNotFound(room_uuid.clone());
+                self.notify_observers(ChatAppEvent::Error(error.clone()));
+                return Err(ChatError::from(error));
+            }
+        };
+        // Alone in the room, or nobody reachable over its transport
+        if participants.is_empty() {
+            let error = ChatAppErrorEvent::NoParticipant(vec![room_uuid.clone()]);
+            self.notify_observers(ChatAppEvent::Error(error.clone()));
+            return Err(ChatError::from(error).with_room(room_uuid));
+        }
+        if let Some(room_msg) =
+            self.send_room_bundle(content, room_uuid, &participants, try_prediction)
+        {
+            return Ok(RoomSendReport {
+                room_msg,
+                failed: Vec::new(),
+            });
+        }
+        Ok(self.fan_out(content, room_uuid, participants, try_prediction))
+    }
+
+    // Each participant of several rooms is sent a single replica tagged with all of its
+    // rooms, from the first one given. Nothing is sent when a room is unknown. Room bundles
+    // carry a single room, every peer is sent its own replica
+    pub fn send_to_rooms(
+        &mut self,
+        content: &Content,
+        room_uuids: &[String],
+        try_prediction: bool,
+    ) -> Result<RoomSendReport, ChatError> {
+        self.record_call(|| RecordedEvent::SendToRooms {
+            content: content.into(),
+            room_uuids: room_uuids.to_vec(),
+            try_prediction,
+        });
+        let mut per_room = Vec::new();
+        for room_uuid in room_uuids {
+            match self.get_other_peers_for_room(room_uuid) {
+                Some(participants) => per_room.push((room_uuid, participants)),
+                None => {
+                    let error = ChatAppErrorEvent::RoomNotFound(room_uuid.clone());
+                    self.notify_observers(ChatAppEvent::Error(error.clone()));
+                    return Err(ChatError::from(error));
+                }
+            }
+        }
+        // Rooms of each peer, over the endpoint chosen for the first of them
+        let mut targets: Vec<(String, Endpoint, Vec<String>)> = Vec::new();
+        for (room_uuid, participants) in per_room {
+            for (peer_uuid, endpoint) in self.apply_room_transport(room_uuid, participants) {
+                match targets.iter_mut().find(|(peer, _, _)| *peer == peer_uuid) {
+                    Some((_, _, rooms)) if !rooms.contains(room_uuid) => {
+                        rooms.push(room_uuid.clone())
+                    }
+                    Some(_) => {}
+                    None => targets.push((peer_uuid, endpoint, vec![room_uuid.clone()])),
+                }
+            }
+        }
+        if targets.is_empty() {
+            let error = ChatAppErrorEvent::NoParticipant(room_uuids.to_vec());
+            self.notify_observers(ChatAppEvent::Error(error.clone()));
+            return Err(ChatError::from(error));
+        }
+        let mut room_msg = RoomMessage {
+            uuid: generate_uuid(),
+            room_uuid: room_uuids[0].clone(),
+            messages: Vec::new(),
+            peers: Vec::new(),
+            bundled: false,
+        };
+        let mut failed = Vec::new();
+        for (peer_uuid, endpoint, rooms) in targets {
+            // Already reported to the observers
+            match self.send_tagged_to_peer(
+                content,
+                &rooms[0],
+                &rooms[1..],
+                peer_uuid.clone(),
+                &endpoint,
+                try_prediction,
+            ) {
+                Ok(uuid) => {
+                    room_msg.peers.push(peer_uuid);
+                    room_msg.messages.push(uuid);
+                }
+                Err(error) => failed.push((peer_uuid, endpoint, error)),
+            }
+        }
+        self.db.add_room_message(room_msg.clone());
+        Ok(RoomSendReport { room_msg, failed })
+    }
+
+    // Endpoints of the participants as chosen by the transport of the room
+    fn apply_room_transport(
+        &mut self,
+        room_uuid: &String,
+        participants: Vec<(String, Endpoint)>,
+    ) -> Vec<(String, Endpoint)> {
+        let transport = match self.db.get_rooms().get(room_uuid) {
+            Some(room) => room.transport,
+            None => return participants,
+        };
+        let (proto, only) = match transport {
+            RoomTransport::Registered => return participants,
+            RoomTransport::BpOnly => (EndpointProto::Bp, true),
+            RoomTransport::PreferTcp => (EndpointProto::Tcp, false),
+            RoomTransport::PreferBp => (EndpointProto::Bp, false),
+        };
+        let mut targets = Vec::new();
+        for (peer_uuid, registered) in participants {
+            let mut candidates: Vec<Endpoint> = self
+                .db
+                .get_other_peers()
+                .get(&peer_uuid)
+                .map(|peer| peer.endpoints.clone())
+                .unwrap_or_default();
+            candidates.push(registered.clone());
+            candidates.retain(|endpoint| endpoint.proto == proto);
+            let chosen = self
+                .healthiest_endpoint(&candidates)
+                .filter(|endpoint| only || !self.link_health.is_degraded(&endpoint.to_string()));
+            match chosen {
+                Some(endpoint) => targets.push((peer_uuid, endpoint)),
+                None if !only => targets.push((peer_uuid, registered)),
+                None => self.notify_observers(ChatAppEvent::Error(
+                    ChatAppErrorEvent::PeerNotFound(format!(
+                        "{} has no {:?} endpoint for room {}",
+                        peer_uuid, proto, room_uuid
+                    )),
+                )),
+            }
+        }
+        targets
+    }
+
+    // Sends the content to every known peer but the blocked ones, over its healthiest endpoint,
+    // None without any peer to reach
+    pub fn broadcast(&mut self, content: &Content) -> Option<RoomMessage> {
+        self.record_call(|| RecordedEvent::Broadcast {
+            content: content.into(),
+        });
+        let blocked = self.db.get_blocked_peers();
+        let mut targets: Vec<(String, Endpoint)> = self
+            .db
+            .get_other_peers()
+            .values()
+            .filter(|peer| !blocked.contains(&peer.uuid))
+            .filter_map(|peer| {
+                let endpoint = self.healthiest_endpoint(&peer.endpoints)?;
+                Some((peer.uuid.clone(), endpoint))
+            })
+            .collect();
+        if targets.is_empty() {
+            return None;
+        }
+        targets.sort_by(|a, b| a.0.cmp(&b.0));
+        Some(self.fan_out(content, &BROADCAST_ROOM_UUID.to_string(), targets, true))
+    }
+
+    // One replica per peer, tracked as a room message
+    fn fan_out(
+        &mut self,
+        content: &Content,
+        room_uuid: &String,
+        targets: Vec<(String, Endpoint)>,
+        try_prediction: bool,
+    ) -> RoomSendReport {
+        let mut room_msg = RoomMessage {
+            uuid: generate_uuid(),
+            room_uuid: room_uuid.clone(),
+            messages: Vec::new(),
+            peers: Vec::new(),
+            bundled: false,
+        };
+        let mut failed = Vec::new();
+        for (peer_uuid, endpoint) in targets {
+            // Already reported to the observers
+            match self.send_tagged_to_peer(
+                content,
+                room_uuid,
+                &[],
+                peer_uuid.clone(),
+                &endpoint,
+                try_prediction,
+            ) {
+                Ok(uuid) => {
+                    room_msg.peers.push(peer_uuid);
+                    room_msg.messages.push(uuid);
+                }
+                Err(error) => failed.push((peer_uuid, endpoint, error)),
+            }
+        }
+        self.db.add_room_message(room_msg.clone());
+        RoomSendReport { room_msg, failed }
+    }
+
+    // One bundle to the BP endpoint of the room, standing for a replica per participant
+    // acked on its own. None to fan out instead, when the room has no such endpoint or a
+    // participant is not reached over BP
+    fn send_room_bundle(
+        &mut self,
+        content: &Content,
+        room_uuid: &String,
+        participants: &[(String, Endpoint)],
+        try_prediction: bool,
+    ) -> Option<RoomMessage> {
+        let bundle_endpoint = self
+            .db
+            .get_rooms()
+            .get(room_uuid)?
+            .bundle_endpoint
+            .clone()?;
+        // Files are offered to each peer
+        if self.network_engine.is_none()
+            || self.offer_files
+            || participants.len() < 2
+            || participants
+                .iter()
+                .any(|(_, endpoint)| endpoint.proto != EndpointProto::Bp)
+            || self.check_content_size(content).is_err()
+            || participants
+                .iter()
+                .any(|(_, endpoint)| self.check_queue_capacity(endpoint).is_err())
+        {
+            return None;
+        }
+        let local_endpoint = self.find_local_endpoint_for_protocol(EndpointProto::Bp)?;
+        let mut bundle_msg = ChatMessage::new_to_send(
+            &self.db.get_localpeer().uuid,
+            room_uuid,
+            content.clone(),
+            bundle_endpoint.clone(),
+            self.now(),
+        );
+        if let Some(path) = content.file_path() {
+            bundle_msg.file_info = FileInfo::read(path).ok();
+        }
+        bundle_msg.vector_clock = self.tick_room_clock(room_uuid);
+        bundle_msg.lamport_time = self.tick_room_lamport_time(room_uuid);
+        if let Some(ttl_ms) = self.message_ttl_ms {
+            bundle_msg.expires_at =
+                DTChatTime::from_timestamp_millis(bundle_msg.send_time.timestamp_millis() + ttl_ms);
+        }
+        let bytes = ProtoMessage::new_text(&bundle_msg, Some(local_endpoint.clone()))
+            .map_err(|err| err.to_string())
+            .and_then(|proto_msg| self.codec_for(&bundle_endpoint).encode(&proto_msg));
+        let bytes = match bytes {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::ProtocolEncode(
+                    bundle_msg.uuid.clone(),
+                    err,
+                )));
+                return None;
+            }
+        };
+        // The bundle has to fit the smallest contact budget of the participants
+        let budget = participants
+            .iter()
+            .filter_map(|(peer_uuid, _)| Some((peer_uuid.clone(), self.contact_budget(peer_uuid)?)))
+            .min_by_key(|(_, budget)| budget.bytes);
+        let oversized = budget
+            .as_ref()
+            .is_some_and(|(_, budget)| bytes.len() as u64 > budget.bytes);
+        let held =
+            (oversized && self.defer_oversized) || self.send_queues.handles(&EndpointProto::Bp);
+        // Acks and reports name the bundle, by the uuid of the room message
+        let mut room_msg = RoomMessage {
+            uuid: bundle_msg.uuid.clone(),
+            room_uuid: room_uuid.clone(),
+            messages: Vec::new(),
+            peers: Vec::new(),
+            bundled: true,
+        };
+        for (peer_uuid, endpoint) in participants {
+            let mut replica = bundle_msg.clone();
+            replica.uuid = generate_uuid();
+            replica.source_endpoint = endpoint.clone();
+            if self
+                .prediction_policy
+                .applies(try_prediction, &EndpointProto::Bp)
+            {
+                match self.predict_for_send(peer_uuid, bytes.len()) {
+                    Ok(arrival_time) => replica.predicted_arrival_time = Some(arrival_time),
+                    Err(skip) => replica.prediction_skipped = Some(skip),
+                }
+            } else {
+                replica.prediction_skipped = Some(PredictionSkip::NotRequested);
+            }
+            self.pending_send_list
+                .push((MessageType::Text, replica.uuid.clone(), None));
+            let stage = if held {
+                DeliveryStage::Queued
+            } else {
+                DeliveryStage::Sending(bundle_endpoint.to_string())
+            };
+            let now = self.now();
+            self.db
+                .add_timeline_entry(&replica.uuid, TimelineEntry::at(stage, now));
+            if let Some((_, budget)) = budget.as_ref().filter(|_| oversized) {
+                self.notify_observers(ChatAppEvent::Message(
+                    ChatAppInfoEvent::ContactBudgetExceeded(replica.clone(), budget.clone()),
+                ));
+            }
+            room_msg.peers.push(peer_uuid.clone());
+            room_msg.messages.push(replica.uuid.clone());
+            self.add_message(replica);
+        }
+        self.db.add_room_message(room_msg.clone());
+        // Once the room message is known, for the pending sends to keep the bundle uuid
+        for (replica_uuid, (peer_uuid, endpoint)) in room_msg.messages.iter().zip(participants) {
+            if let Some(replica) = self.get_message(replica_uuid) {
+                self.persist_pending_send(&replica, peer_uuid, endpoint);
+            }
+        }
+        // Waiting like a single send, under the uuid of the bundle
+        let peer_uuid =
+            budget.map_or_else(|| participants[0].0.clone(), |(peer_uuid, _)| peer_uuid);
+        if oversized && self.defer_oversized {
+            self.deferred_sends.push(DeferredSend {
+                message_uuid: room_msg.uuid.clone(),
+                peer_uuid,
+                local_endpoint: Some(local_endpoint),
+                endpoint: bundle_endpoint,
+                bytes,
+            });
+        } else if self.send_queues.handles(&EndpointProto::Bp) {
+            self.send_queues.push(QueuedSend {
+                message_uuid: room_msg.uuid.clone(),
+                peer_uuid,
+                local_endpoint: Some(local_endpoint),
+                endpoint: bundle_endpoint,
+                bulk: is_bulk(content, bytes.len()),
+                bytes,
+            });
+            self.flush_send_queues();
+        } else if let Some(engine) = &mut self.network_engine {
+            record_bytes_sent(&self.metrics, &EndpointProto::Bp, bytes.len());
+            engine.send(
+                Some(local_endpoint),
+                bundle_endpoint,
+                bytes,
+                room_msg.uuid.clone(),
+            );
+        }
+        Some(room_msg)
+    }
+
+    // A replica of a room bundle is sent again under the uuid of the bundle, for the
+    // receiver to drop it as a duplicate and ack the bundle
+    fn wire_message(&self, message: &ChatMessage) -> ChatMessage {
+        let mut wire_msg = message.clone();
+        if let Some(room_msg) = self.db.get_room_message_for_replica(&message.uuid) {
+            if room_msg.bundled {
+                wire_msg.uuid = room_msg.uuid.clone();
+            }
+        }
+        wire_msg
+    }
+
+    // The messages a send token stands for: the replicas of a room bundle, else the message
+    fn token_messages(&self, token: &String) -> Vec<String> {
+        match self.db.get_room_message(token) {
+            Some(room_msg) if room_msg.bundled => room_msg.messages.clone(),
+            _ => vec![token.clone()],
+        }
+    }
+
+    // Expired, failed or cancelled meanwhile otherwise, for a bundle once all its replicas are
+    fn still_sending(&self, token: &String) -> bool {
+        self.token_messages(token).iter().any(|uuid| {
+            self.get_message(uuid)
+                .is_some_and(|msg| msg.status == MessageStatus::Sending)
+        })
+    }
+
+    // Acks of a room bundle name the bundle, each participant acking its own replica
+    fn acked_replica(&self, message_uuid: &String, peer_uuid: &String) -> String {
+        self.db
+            .get_room_message(message_uuid)
+            .and_then(|room_msg| {
+                let index = room_msg.peers.iter().position(|peer| peer == peer_uuid)?;
+                room_msg.messages.get(index).cloned()
+            })
+            .unwrap_or_else(|| message_uuid.clone())
+    }
+
+    // The history attributes a sent message to the peer owning its endpoint
+    fn check_peer_endpoint(
+        &self,
+        peer_uuid: &String,
+        endpoint: &Endpoint,
+    ) -> Result<(), ChatAppErrorEvent> {
+        let local_peer = self.db.get_localpeer();
+        let peer = if *peer_uuid == local_peer.uuid {
+            local_peer
+        } else {
+            self.db
+                .get_other_peers()
+                .get(peer_uuid)
+                .ok_or_else(|| ChatAppErrorEvent::PeerNotFound(peer_uuid.clone()))?
+        };
+        if !peer.endpoints.contains(endpoint) {
+            return Err(ChatAppErrorEvent::EndpointMismatch(
+                peer_uuid.clone(),
+                endpoint.clone(),
+            ));
+        }
+        Ok(())
+    }
+
+    // A file that cannot be read is reported when encoding it, locations are checked instead
+    fn check_content_size(&self, content: &Content) -> Result<(), ChatAppErrorEvent> {
+        let (size, limit) = match content {
+            Content::Text(text) => (text.len() as u64, self.max_text_size),
+            Content::File(path) | Content::Audio(path, _) => (
+                fs::metadata(path).map_or(0, |metadata| metadata.len()),
+                self.max_file_size,
+            ),
+            Content::Blob(_, data) => (data.len() as u64, self.max_file_size),
+            Content::Location(location) => {
+                return location.check().map_err(ChatAppErrorEvent::InvalidMessage);
+            }
+        };
+        match limit {
+            Some(limit) if size > limit => Err(ChatAppErrorEvent::ContentTooLarge(size, limit)),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_queue_capacity(&self, endpoint: &Endpoint) -> Result<(), ChatAppErrorEvent> {
+        match self.send_queue_capacity {
+            Some(capacity) if self.queued_sends(endpoint) >= capacity => {
+                Err(ChatAppErrorEvent::QueueFull(endpoint.clone(), capacity))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Messages to the endpoint not sent yet, deferred and retried ones included
+    fn queued_sends(&self, endpoint: &Endpoint) -> usize {
+        let local_peer_uuid = &self.db.get_localpeer().uuid;
+        self.db
+            .iter_messages()
+            .filter(|msg| {
+                msg.status == MessageStatus::Sending
+                    && msg.sender_uuid == *local_peer_uuid
+                    && msg.source_endpoint == *endpoint
+            })
+            .count()
+    }
+
+    pub fn send_to_peer(
+        &mut self,
+        content: &Content,
+        room_uuid: &String,
+        peer_uuid: String,
+        endpoint: &Endpoint,
+        try_prediction: bool,
+    ) -> Result<String, ChatError> {
+        self.record_call(|| RecordedEvent::SendToPeer {
+            content: content.into(),
+            room_uuid: room_uuid.clone(),
+            peer_uuid: peer_uuid.clone(),
+            endpoint: endpoint.to_string(),
+            try_prediction,
+        });
+        self.send_tagged_to_peer(content, room_uuid, &[], peer_uuid, endpoint, try_prediction)
+    }
+
+    // Also shown in also_rooms, by the peer as well
+    fn send_tagged_to_peer(
+        &mut self,
+        content: &Content,
+        room_uuid: &String,
+        also_rooms: &[String],
+        peer_uuid: String,
+        endpoint: &Endpoint,
+        try_prediction: bool,
+    ) -> Result<String, ChatError> {
+        // Made first for the errors to name it, only kept once handed over
+        let mut chatmsg = ChatMessage::new_to_send(
+            &self.db.get_localpeer().uuid,
+            room_uuid,
+            content.clone(),
+            endpoint.clone(),
+            self.now(),
+        );
+        let checked = self
+            .check_peer_endpoint(&peer_uuid, endpoint)
+            .and_then(|_| self.check_content_size(content))
+            .and_then(|_| self.check_queue_capacity(endpoint));
+        if let Err(error) = checked {
+            self.notify_observers(ChatAppEvent::Error(error.clone()));
+            return Err(ChatError::from(error)
+                .with_peer(&peer_uuid)
+                .with_endpoint(endpoint)
+                .with_room(room_uuid)
+                .with_message_uuid(&chatmsg.uuid));
+        }
+        chatmsg.also_rooms = also_rooms.to_vec();
+        if let Some(path) = content.file_path() {
+            chatmsg.file_info = FileInfo::read(path).ok();
+        }
+        chatmsg.vector_clock = self.tick_room_clock(room_uuid);
+        chatmsg.lamport_time = self.tick_room_lamport_time(room_uuid);
+        chatmsg.sequence = self.next_sequence(&peer_uuid, &chatmsg);
+        if let Some(ttl_ms) = self.message_ttl_ms {
+            chatmsg.expires_at =
+                DTChatTime::from_timestamp_millis(chatmsg.send_time.timestamp_millis() + ttl_ms);
+        }
+        let sending_uuid = chatmsg.uuid.clone();
+        let _span = tracing::info_span!(
+            "send",
+            message_uuid = %sending_uuid,
+            %peer_uuid,
+            endpoint = %endpoint.to_string()
+        )
+        .entered();
+
+        self.pending_send_list
+            .push((MessageType::Text, sending_uuid.clone(), None));
+        if !self.failover_order.is_empty() {
+            self.failover_attempts.insert(
+                sending_uuid.clone(),
+                (peer_uuid.clone(), vec![endpoint.proto.clone()]),
+            );
+        }
+        let fallbacks = self.policy_fallbacks(&peer_uuid, endpoint);
+        if !fallbacks.is_empty() {
+            self.endpoint_fallbacks
+                .insert(sending_uuid.clone(), fallbacks);
+        }
+
+        let size_serialized = match self.hand_over(&chatmsg, content, &peer_uuid, endpoint) {
+            Ok(size_serialized) => size_serialized,
+            // Nothing was handed over, the message is not kept
+            Err((error, source)) => {
+                self.notify_observers(ChatAppEvent::Error(error.clone()));
+                self.pending_send_list
+                    .retain(|(_, token, _)| *token != chatmsg.uuid);
+                self.failover_attempts.remove(&chatmsg.uuid);
+                self.endpoint_fallbacks.remove(&chatmsg.uuid);
+                return Err(ChatError::from(error)
+                    .with_peer(&peer_uuid)
+                    .with_endpoint(endpoint)
+                    .with_room(room_uuid)
+                    .with_message_uuid(&chatmsg.uuid)
+                    .with_source(source));
+            }
+        };
+        if !self
+            .prediction_policy
+            .applies(try_prediction, &endpoint.proto)
+        {
+            chatmsg.prediction_skipped = Some(PredictionSkip::NotRequested);
+        } else if let Some(size_sent) = size_serialized {
+            match self.predict_for_send(&peer_uuid, size_sent) {
+                Ok(arrival_time) => chatmsg.predicted_arrival_time = Some(arrival_time),
+                Err(skip) => chatmsg.prediction_skipped = Some(skip),
+            }
+        }
+        if let Some(arrival_time) = chatmsg.predicted_arrival_time {
+            self.check_latency_budget(
+                &chatmsg,
+                arrival_time.timestamp_millis() - chatmsg.send_time.timestamp_millis(),
+            );
+        }
+        if endpoint.proto == EndpointProto::Bp {
+            self.send_duplicate_if_late(&chatmsg, &peer_uuid);
+        }
+        if size_serialized.is_some() {
+            self.persist_pending_send(&chatmsg, &peer_uuid, endpoint);
+        }
+        self.add_message(chatmsg.clone());
+        self.flush_send_queues();
+        return Ok(chatmsg.uuid);
+    }
+
+    // Encodes the message for the endpoint then defers, queues or sends it under the
+    // contact budget. The size handed over, None without engine
+    fn hand_over(
+        &mut self,
+        chatmsg: &ChatMessage,
+        content: &Content,
+        peer_uuid: &String,
+        endpoint: &Endpoint,
+    ) -> Result<Option<usize>, (ChatAppErrorEvent, std::io::Error)> {
+        let local_endpoint = self.find_local_endpoint_for_protocol(endpoint.proto.clone());
+        let mut size_serialized = None;
+        let budget = match endpoint.proto {
+            EndpointProto::Bp => self.contact_budget(peer_uuid),
+            _ => None,
+        };
+        let mut deferred = None;
+        let mut queued = None;
+        let mut encode_error = None;
+
+        let create_proto = if self.offers_files_to(peer_uuid) {
+            ProtoMessage::new_file_offer(chatmsg, local_endpoint.clone())
+        } else {
+            ProtoMessage::new_text(chatmsg, local_endpoint.clone())
+        };
+        let codec = self.codec_for(endpoint);
+        if let Some(engine) = &mut self.network_engine {
+            match create_proto {
+                Ok(create_proto) => match codec.encode(&create_proto) {
+                    Ok(bytes) => {
+                        size_serialized = Some(bytes.len());
+                        let oversized =
+                            budget.as_ref().is_some_and(|b| bytes.len() as u64 > b.bytes);
+                        if oversized && self.defer_oversized {
+                            deferred = Some(DeferredSend {
+                                message_uuid: chatmsg.uuid.clone(),
+                                peer_uuid: peer_uuid.clone(),
+                                local_endpoint,
+                                endpoint: endpoint.clone(),
+                                bytes,
+                            });
+                        } else if self.send_queues.handles(&endpoint.proto) {
+                            queued = Some(QueuedSend {
+                                message_uuid: chatmsg.uuid.clone(),
+                                peer_uuid: peer_uuid.clone(),
+                                local_endpoint,
+                                endpoint: endpoint.clone(),
+                                bulk: is_bulk(content, bytes.len()),
+                                bytes,
+                            });
+                        } else {
+                            record_bytes_sent(&self.metrics, &endpoint.proto, bytes.len());
+                            engine.send(
+                                local_endpoint,
+                                endpoint.clone(),
+                                bytes,
+                                chatmsg.uuid.clone(),
+                            );
+                        }
+                    }
+                    Err(err) => {
+                        encode_error = Some((
+                            ChatAppErrorEvent::ProtocolEncode(chatmsg.uuid.clone(), err.clone()),
+                            std::io::Error::new(std::io::ErrorKind::InvalidData, err),
+                        ));
+                    }
+                },
+                // The file of the content cannot be read
+                Err(err) => {
+                    encode_error = Some((
+                        ChatAppErrorEvent::InternalError(format!(
+                            "Failed to read the content of message {}: {}",
+                            chatmsg.uuid, err
+                        )),
+                        err,
+                    ))
+                }
+            }
+        }
+        if let Some(encode_error) = encode_error {
+            return Err(encode_error);
+        }
+        if let (Some(budget), Some(size)) = (budget, size_serialized) {
+            if size as u64 > budget.bytes {
+                self.notify_observers(ChatAppEvent::Message(
+                    ChatAppInfoEvent::ContactBudgetExceeded(chatmsg.clone(), budget),
+                ));
+            }
+        }
+        let stage = match (&deferred, size_serialized) {
+            (Some(_), _) => Some(DeliveryStage::Queued),
+            (None, Some(_)) if queued.is_some() => Some(DeliveryStage::Queued),
+            (None, Some(_)) => Some(DeliveryStage::Sending(endpoint.to_string())),
+            (None, None) => None,
+        };
+        if let Some(stage) = stage {
+            let now = self.now();
+            self.db
+                .add_timeline_entry(&chatmsg.uuid, TimelineEntry::at(stage, now));
+        }
+        self.deferred_sends.extend(deferred);
+        if let Some(queued) = queued {
+            self.send_queues.push(queued);
+        }
+        Ok(size_serialized)
+    }
+
+    // Bytes the ongoing or next contact with the peer can carry, None without contact plan
+    pub fn contact_budget(&self, peer_uuid: &String) -> Option<ContactBudget> {
+        let ASabrInitState::Enabled(a_sabr) = &self.a_sabr else {
+            return None;
+        };
+        let src_eid = self.find_local_endpoint_for_protocol(EndpointProto::Bp)?;
+        let dest_eid = self.find_peer_endpoint_for_protocol(peer_uuid.clone(), EndpointProto::Bp)?;
+        a_sabr.next_contact_budget(src_eid.endpoint.as_str(), dest_eid.endpoint.as_str())
+    }
+
+    // None for an unknown peer. The contact plan gives the BP contacts, the other
+    // protocols only count for the reachability
+    pub fn next_contact_window(&self, peer_uuid: &String) -> Option<NextContact> {
+        let peer = self.db.get_other_peers().get(peer_uuid)?;
+        let window = match (
+            &self.a_sabr,
+            self.find_local_endpoint_for_protocol(EndpointProto::Bp),
+            self.find_peer_endpoint_for_protocol(peer_uuid.clone(), EndpointProto::Bp),
+        ) {
+            (ASabrInitState::Enabled(a_sabr), Some(src_eid), Some(dest_eid)) => {
+                a_sabr.next_contact_window(src_eid.endpoint.as_str(), dest_eid.endpoint.as_str())
+            }
+            _ => None,
+        };
+        let now = self.now();
+        let in_contact = window.is_some_and(|(start, _)| start <= now);
+        let connected = peer
+            .endpoints
+            .iter()
+            .any(|endpoint| self.connected_endpoints.contains(&endpoint.to_string()));
+        Some(NextContact {
+            peer_uuid: peer_uuid.clone(),
+            start: window.map(|(start, _)| start),
+            end: window.map(|(_, end)| end),
+            in_contact,
+            reachable: in_contact || connected,
+        })
+    }
+
+    // In theory we should add transport overhead..
+    fn predict_for_send(
+        &mut self,
+        peer_uuid: &String,
+        size: usize,
+    ) -> Result<DTChatTime, PredictionSkip> {
+        if !matches!(self.a_sabr, ASabrInitState::Enabled(_)) {
+            return Err(PredictionSkip::Disabled);
+        }
+        let src_eid = self
+            .find_local_endpoint_for_protocol(EndpointProto::Bp)
+            .ok_or(PredictionSkip::NoBpEndpoint)?;
+        let dest_eid = self
+            .find_peer_endpoint_for_protocol(peer_uuid.clone(), EndpointProto::Bp)
+            .ok_or(PredictionSkip::NoBpEndpoint)?;
+        let ASabrInitState::Enabled(a_sabr) = &mut self.a_sabr else {
+            return Err(PredictionSkip::Disabled);
+        };
+        a_sabr
+            .predict(
+                src_eid.endpoint.as_str(),
+                dest_eid.endpoint.as_str(),
+                size as f64,
+            )
+            .map_err(|_| {
+                self.metrics.lock().unwrap().record_prediction_error();
+                PredictionSkip::NoRoute
+            })
+    }
+
+    pub fn prediction_policy(&self) -> PredictionPolicy {
+        self.prediction_policy
+    }
+
+    // Overrides prediction_policy from the configuration
+    pub fn set_prediction_policy(&mut self, policy: PredictionPolicy) {
+        self.prediction_policy = policy;
+    }
+
+    // Arrival of a message of this size sent now to the peer over BP
+    pub fn predict_arrival(
+        &mut self,
+        peer_uuid: &String,
+        size: u64,
+    ) -> Result<DTChatTime, ChatAppErrorEvent> {
+        let src_eid = self
+            .find_local_endpoint_for_protocol(EndpointProto::Bp)
+            .ok_or_else(|| ChatAppErrorEvent::InternalError("No local BP endpoint".to_string()))?;
+        let dest_eid = self
+            .find_peer_endpoint_for_protocol(peer_uuid.clone(), EndpointProto::Bp)
+            .ok_or_else(|| ChatAppErrorEvent::PeerNotFound(peer_uuid.clone()))?;
+        let ASabrInitState::Enabled(a_sabr) = &mut self.a_sabr else {
+            return Err(ChatAppErrorEvent::InternalError(
+                "A-SABR prediction disabled".to_string(),
+            ));
+        };
+        a_sabr
+            .predict(src_eid.endpoint.as_str(), dest_eid.endpoint.as_str(), size as f64)
+            .map_err(|err| ChatAppErrorEvent::InternalError(err.to_string()))
+    }
+
+    // Hands deferred messages to the engine once a contact can carry them
+    fn send_deferred(&mut self) {
+        let deferred_sends = std::mem::take(&mut self.deferred_sends);
+        for deferred in deferred_sends {
+            if !self.still_sending(&deferred.message_uuid) {
+                continue;
+            }
+            let fits = self
+                .contact_budget(&deferred.peer_uuid)
+                .is_some_and(|budget| deferred.bytes.len() as u64 <= budget.bytes);
+            let sent_uuids = self.token_messages(&deferred.message_uuid);
+            let now = self.now();
+            match &mut self.network_engine {
+                Some(engine) if fits => {
+                    record_bytes_sent(
+                        &self.metrics,
+                        &deferred.endpoint.proto,
+                        deferred.bytes.len(),
+                    );
+                    for uuid in &sent_uuids {
+                        let stage = DeliveryStage::Sending(deferred.endpoint.to_string());
+                        self.db
+                            .add_timeline_entry(uuid, TimelineEntry::at(stage, now));
+                    }
+                    engine.send(
+                        deferred.local_endpoint,
+                        deferred.endpoint,
+                        deferred.bytes,
+                        deferred.message_uuid,
+                    )
+                }
+                _ => self.deferred_sends.push(deferred),
+            }
+        }
+    }
+
+    // Hands the queued messages to the engine as their protocol allows
+    fn flush_send_queues(&mut self) {
+        self.flush_send_queues_at(self.now());
+    }
+
+    // From tick, the bandwidth budget follows its clock
+    fn flush_send_queues_at(&mut self, now: DTChatTime) {
+        if self.network_engine.is_none() {
+            return;
+        }
+        for uuid in self.send_queues.waiting_uuids() {
+            if !self.still_sending(&uuid) {
+                self.send_queues.remove(&uuid);
+            }
+        }
+        let sent_at = now;
+        let now = now.timestamp_millis();
+        // No contact is ever predicted without contact plan, nothing waits for one
+        let no_plan = !matches!(self.a_sabr, ASabrInitState::Enabled(_));
+        let contact_starts: HashMap<String, i64> = self
+            .send_queues
+            .peers_waiting_contact()
+            .into_iter()
+            .filter_map(|peer_uuid| {
+                if no_plan {
+                    return Some((peer_uuid, now));
+                }
+                let start = self.contact_budget(&peer_uuid)?.start.timestamp_millis();
+                (start <= now).then_some((peer_uuid, start))
+            })
+            .collect();
+        let (ready, throttled) = self
+            .send_queues
+            .take_ready(now, |peer_uuid| contact_starts.get(peer_uuid).copied());
+        for held in throttled {
+            for uuid in self.token_messages(&held.message_uuid) {
+                if let Some(message) = self.get_message(&uuid) {
+                    self.notify_observers(ChatAppEvent::Message(
+                        ChatAppInfoEvent::BandwidthThrottled(message, held.used, held.limit),
+                    ));
+                }
+            }
+        }
+        let ready: Vec<(QueuedSend, Vec<String>)> = ready
+            .into_iter()
+            .map(|queued| {
+                let sent_uuids = self.token_messages(&queued.message_uuid);
+                (queued, sent_uuids)
+            })
+            .collect();
+        let Some(engine) = &mut self.network_engine else {
+            return;
+        };
+        for (queued, sent_uuids) in ready {
+            record_bytes_sent(&self.metrics, &queued.endpoint.proto, queued.bytes.len());
+            for uuid in &sent_uuids {
+                let stage = DeliveryStage::Sending(queued.endpoint.to_string());
+                self.db
+                    .add_timeline_entry(uuid, TimelineEntry::at(stage, sent_at));
+            }
+            engine.send(
+                queued.local_endpoint,
+                queued.endpoint,
+                queued.bytes,
+                queued.message_uuid,
+            );
+        }
+    }
+
+    // Messages waiting in the queue of the protocol, 0 without queue
+    pub fn queued_for(&self, proto: &EndpointProto) -> usize {
+        self.send_queues.waiting(proto)
+    }
+
+    // Sent to the endpoint the peer has in the room once send_at is reached,
+    // returns the uuid of the schedule
+    pub fn schedule_send(
+        &mut self,
+        content: &Content,
+        room_uuid: &String,
+        peer_uuid: String,
+        send_at: DTChatTime,
+    ) -> Result<String, ChatAppErrorEvent> {
+        self.record_call(|| RecordedEvent::ScheduleSend {
+            content: content.into(),
+            room_uuid: room_uuid.clone(),
+            peer_uuid: peer_uuid.clone(),
+            send_at_ms: send_at.timestamp_millis(),
+        });
+        let endpoint = self
+            .get_other_peers_for_room(room_uuid)
+            .and_then(|participants| {
+                participants
+                    .into_iter()
+                    .find(|(uuid, _)| *uuid == peer_uuid)
+                    .map(|(_, endpoint)| endpoint)
+            })
+            .ok_or_else(|| ChatAppErrorEvent::PeerNotFound(peer_uuid.clone()))?;
+        Ok(self.push_scheduled(content, room_uuid, peer_uuid, endpoint, send_at, false))
+    }
+
+    // Sent over BP at the start of the next contact predicted with the peer
+    pub fn schedule_send_at_contact(
+        &mut self,
+        content: &Content,
+        room_uuid: &String,
+        peer_uuid: String,
+    ) -> Result<String, ChatAppErrorEvent> {
+        self.record_call(|| RecordedEvent::ScheduleSendAtContact {
+            content: content.into(),
+            room_uuid: room_uuid.clone(),
+            peer_uuid: peer_uuid.clone(),
+        });
+        let endpoint = self
+            .find_peer_endpoint_for_protocol(peer_uuid.clone(), EndpointProto::Bp)
+            .ok_or_else(|| ChatAppErrorEvent::PeerNotFound(peer_uuid.clone()))?;
+        let budget = self.contact_budget(&peer_uuid).ok_or_else(|| {
+            ChatAppErrorEvent::InternalError(format!(
+                "No contact predicted with peer {}",
+                peer_uuid
+            ))
+        })?;
+        Ok(self.push_scheduled(content, room_uuid, peer_uuid, endpoint, budget.start, true))
+    }
+
+    fn push_scheduled(
+        &mut self,
+        content: &Content,
+        room_uuid: &String,
+        peer_uuid: String,
+        endpoint: Endpoint,
+        send_at: DTChatTime,
+        try_prediction: bool,
+    ) -> String {
+        let scheduled = ScheduledSend {
+            uuid: generate_uuid(),
+            content: content.clone(),
+            room_uuid: room_uuid.clone(),
+            peer_uuid,
+            endpoint,
+            send_at,
+            try_prediction,
+        };
+        let uuid = scheduled.uuid.clone();
+        self.scheduled_sends.push(scheduled);
+        uuid
+    }
+
+    // False when the send is unknown or already dispatched
+    pub fn cancel_scheduled(&mut self, uuid: &String) -> bool {
+        let count = self.scheduled_sends.len();
+        self.scheduled_sends.retain(|scheduled| scheduled.uuid != *uuid);
+        self.scheduled_sends.len() != count
+    }
+
+    // Earliest first
+    pub fn list_scheduled(&self) -> Vec<ScheduledSend> {
+        let mut scheduled = self.scheduled_sends.clone();
+        scheduled.sort_by_key(|scheduled| scheduled.send_at);
+        scheduled
+    }
+
+    // Failures are reported by send_tagged_to_peer
+    fn dispatch_scheduled(&mut self, now: DTChatTime) {
+        let (due, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.scheduled_sends)
+            .into_iter()
+            .partition(|scheduled| scheduled.send_at <= now);
+        self.scheduled_sends = waiting;
+        for scheduled in due {
+            let _ = self.send_tagged_to_peer(
+                &scheduled.content,
+                &scheduled.room_uuid,
+                &[],
+                scheduled.peer_uuid,
+                &scheduled.endpoint,
+                scheduled.try_prediction,
+            );
+        }
+    }
+
+    // Races a late BP send with an already connected TCP endpoint,
+    // the receiver keeps whichever copy arrives first
+    fn send_duplicate_if_late(&mut self, chatmsg: &ChatMessage, peer_uuid: &String) {
+        let (Some(threshold_ms), Some(predicted)) =
+            (self.duplicate_send_threshold_ms, chatmsg.predicted_arrival_time)
+        else {
+            return;
+        };
+        if predicted.timestamp_millis() - chatmsg.send_time.timestamp_millis() <= threshold_ms {
+            return;
+        }
+        let Some(tcp_endpoint) =
+            self.find_peer_endpoint_for_protocol(peer_uuid.clone(), EndpointProto::Tcp)
+        else {
+            return;
+        };
+        if !self.connected_endpoints.contains(&tcp_endpoint.to_string()) {
+            return;
+        }
+
+        let local_endpoint = self.find_local_endpoint_for_protocol(EndpointProto::Tcp);
+        let token = generate_uuid();
+        let codec = self.codec_for(&tcp_endpoint);
+        let now = self.now();
+        if let Some(engine) = &mut self.network_engine {
+            match ProtoMessage::new_text(chatmsg, local_endpoint.clone()) {
+                Ok(proto_msg) => match codec.encode(&proto_msg) {
+                    Ok(bytes) => {
+                        self.pending_send_list.push((
+                            MessageType::Duplicate,
+                            token.clone(),
+                            Some(chatmsg.uuid.clone()),
+                        ));
+                        record_bytes_sent(&self.metrics, &tcp_endpoint.proto, bytes.len());
+                        engine.send(local_endpoint, tcp_endpoint.clone(), bytes, token);
+                        let stage = DeliveryStage::Retried(tcp_endpoint.to_string());
+                        self.db
+                            .add_timeline_entry(&chatmsg.uuid, TimelineEntry::at(stage, now));
+                        self.notify_observers(ChatAppEvent::Info(format!(
+                            "Message {} also sent over {}",
+                            chatmsg.uuid,
+                            tcp_endpoint.to_string()
+                        )));
+                    }
+                    Err(err) => {
+                        self.notify_observers(ChatAppEvent::Error(
+                            ChatAppErrorEvent::ProtocolEncode(chatmsg.uuid.clone(), err),
+                        ));
+                    }
+                },
+                Err(err) => self.notify_observers(ChatAppEvent::Error(
+                    ChatAppErrorEvent::InternalError(format!(
+                        "Failed to encode duplicate message: {}",
+                        err
+                    )),
+                )),
+            }
+        }
+    }
+
+    // Endpoints of the peer in the order of its policy, the unreachable ones left out
+    pub fn preferred_endpoints(&self, peer_uuid: &String) -> Vec<Endpoint> {
+        let Some(peer) = self.db.get_other_peers().get(peer_uuid) else {
+            return Vec::new();
+        };
+        if peer.preferences.is_empty() {
+            return peer.endpoints.clone();
+        }
+        let mut preferences: Vec<&EndpointPreference> = peer
+            .preferences
+            .iter()
+            .filter(|pref| {
+                !pref.when_reachable || !self.link_health.is_degraded(&pref.endpoint.to_string())
+            })
+            .collect();
+        // Stable, equal weights keep their declared order
+        preferences.sort_by(|a, b| b.weight.cmp(&a.weight));
+        preferences
+            .into_iter()
+            .map(|pref| pref.endpoint.clone())
+            .collect()
+    }
+
+    // The endpoints of the policy of the peer a failed send to endpoint goes on with, none
+    // for the peers without preferences
+    fn policy_fallbacks(&self, peer_uuid: &String, endpoint: &Endpoint) -> Vec<Endpoint> {
+        let has_policy = self
+            .db
+            .get_other_peers()
+            .get(peer_uuid)
+            .is_some_and(|peer| !peer.preferences.is_empty());
+        if !has_policy {
+            return Vec::new();
+        }
+        self.preferred_endpoints(peer_uuid)
+            .into_iter()
+            .filter(|preferred| preferred != endpoint)
+            .collect()
+    }
+
+    // Sends over the first endpoint of the policy of the peer, a failed send goes on with
+    // the next ones, then with the failover order
+    pub fn send_to_preferred(
+        &mut self,
+        content: &Content,
+        room_uuid: &String,
+        peer_uuid: String,
+        try_prediction: bool,
+    ) -> Result<String, ChatError> {
+        self.record_call(|| RecordedEvent::SendToPreferred {
+            content: content.into(),
+            room_uuid: room_uuid.clone(),
+            peer_uuid: peer_uuid.clone(),
+            try_prediction,
+        });
+        let mut chain = self.preferred_endpoints(&peer_uuid);
+        if chain.is_empty() {
+            let error = ChatAppErrorEvent::NoEndpoint(peer_uuid.clone());
+            self.notify_observers(ChatAppEvent::Error(error.clone()));
+            return Err(ChatError::from(error).with_room(room_uuid));
+        }
+        let endpoint = chain.remove(0);
+        let uuid = self.send_tagged_to_peer(
+            content,
+            room_uuid,
+            &[],
+            peer_uuid,
+            &endpoint,
+            try_prediction,
+        )?;
+        // send_tagged_to_peer set the chain of the peers with preferences
+        if !chain.is_empty() {
+            self.endpoint_fallbacks.entry(uuid.clone()).or_insert(chain);
+        }
+        Ok(uuid)
+    }
+
+    pub fn send_and_track(
+        &mut self,
+        content: &Content,
+        room_uuid: &String,
+        peer_uuid: String,
+        endpoint: &Endpoint,
+        try_prediction: bool,
+    ) -> Result<DeliveryHandle, ChatError> {
+        let uuid = self.send_to_peer(content, room_uuid, peer_uuid, endpoint, try_prediction)?;
+        let current = self.get_message(&uuid);
+        Ok(self.delivery_tracker.lock().unwrap().track(uuid, current))
+    }
+
+    pub fn send_ack_to_peer(&mut self, for_msg: &ChatMessage, target_endpoint: Endpoint) {
+        let local_endpoint = self.find_local_endpoint_for_protocol(target_endpoint.proto.clone());
+
+        let proto_msg = ProtoMessage::new_ack(
+            for_msg,
+            self.db.get_localpeer().uuid.clone(),
+            local_endpoint.clone(),
+            self.now().timestamp_millis(),
+        );
+        self.pending_send_list.push((
+            MessageType::Ack,
+            proto_msg.uuid.clone(),
+            Some(for_msg.uuid.clone()),
+        ));
+        let codec = self.codec_for(&target_endpoint);
+        if let Some(engine) = &mut self.network_engine {
+            match codec.encode(&proto_msg) {
+                Ok(bytes) => {
+                    record_bytes_sent(&self.metrics, &target_endpoint.proto, bytes.len());
+                    engine.send(
+                        local_endpoint,
+                        target_endpoint.clone(),
+                        bytes,
+                        proto_msg.uuid.clone(),
+                    );
+                    self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::AckSent(
+                        for_msg.clone(),
+                        target_endpoint.to_string(),
+                    )));
+                }
+                Err(err) => {
+                    self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::ProtocolEncode(
+                        proto_msg.uuid.clone(),
+                        err,
+                    )));
+                }
+            };
+        }
+    }
+
+    // A connection silent for idle_timeout_ms is reported closed like one closed by the engine.
+    // Peers not known to answer keepalives are left alone, their silence says nothing
+    fn check_keepalives(&mut self, now: DTChatTime) {
+        let Some(keepalive) = self.keepalive.clone() else {
+            return;
+        };
+        let now_ms = now.timestamp_millis();
+        let mut connected: Vec<String> = self.connected_endpoints.iter().cloned().collect();
+        connected.sort();
+        for remote in connected {
+            let Ok(endpoint) = Endpoint::from_str(&remote) else {
+                continue;
+            };
+            if endpoint.proto != EndpointProto::Tcp {
+                continue;
+            }
+            let answers = self
+                .peer_endpoint_of(&endpoint)
+                .is_some_and(|(peer_uuid, _)| self.peer_has_capability(&peer_uuid, "keepalive"));
+            if !answers {
+                continue;
+            }
+            let silent_ms = now_ms - self.last_heard.get(&remote).copied().unwrap_or(now_ms);
+            if silent_ms >= keepalive.idle_timeout_ms {
+                self.notify_observers(ChatAppEvent::Info(format!(
+                    "No answer from {} for {} ms, the connection is considered closed",
+                    remote, silent_ms
+                )));
+                self.on_engine_event(SocketEngineEvent::Connection(ConnectionEvent::Closed {
+                    remote: Some(endpoint),
+                }));
+                continue;
+            }
+            let since_sent_ms = now_ms - self.keepalive_sent.get(&remote).copied().unwrap_or(0);
+            if silent_ms >= keepalive.interval_ms && since_sent_ms >= keepalive.interval_ms {
+                self.keepalive_sent.insert(remote, now_ms);
+                self.send_keepalive(endpoint, true);
+            }
+        }
+    }
+
+    fn send_keepalive(&mut self, target_endpoint: Endpoint, reply: bool) {
+        let local_endpoint = self.find_local_endpoint_for_protocol(target_endpoint.proto.clone());
+        let proto_msg = ProtoMessage::new_keepalive(
+            self.db.get_localpeer().uuid.clone(),
+            local_endpoint.clone(),
+            self.now().timestamp_millis(),
+            reply,
+        );
+        self.send_control_message(proto_msg, local_endpoint, target_endpoint);
+    }
+
+    // A who-are-you opens the connection again
+    fn reconnect(&mut self, now: DTChatTime) {
+        for endpoint in self.reconnector.due(now.timestamp_millis()) {
+            match Endpoint::from_str(&endpoint) {
+                Ok(endpoint) => {
+                    self.query_peer_info(endpoint);
+                }
+                Err(_) => {
+                    self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::InternalError(
+                        format!("Cannot reconnect to {}: invalid endpoint", endpoint),
+                    )))
+                }
+            }
+        }
+    }
+
+    // Asks a peer for its version and capabilities, answered by a PeerInfo event
+    // carrying the returned request uuid
+    pub fn query_peer_info(&mut self, target_endpoint: Endpoint) -> String {
+        let local_endpoint = self.find_local_endpoint_for_protocol(target_endpoint.proto.clone());
+        let challenge = new_challenge();
+        let proto_msg = ProtoMessage::new_who_are_you(
+            self.db.get_localpeer().uuid.clone(),
+            local_endpoint.clone(),
+            self.now().timestamp_millis(),
+            self.public_key().unwrap_or_default(),
+            challenge.clone(),
+        );
+        let request_uuid = proto_msg.uuid.clone();
+        if self.key_challenges.len() >= MAX_KEY_CHALLENGES {
+            self.key_challenges.pop_front();
+        }
+        self.key_challenges.push_back((
+            request_uuid.clone(),
+            target_endpoint.to_string(),
+            challenge,
+        ));
+        self.send_control_message(proto_msg, local_endpoint, target_endpoint);
+        request_uuid
+    }
+
+    // Once per start and configured peer, unless a challenge to the endpoint is waiting
+    fn challenge_peer_key(&mut self, peer_uuid: &String, endpoint: Endpoint) {
+        let endpoint_name = endpoint.to_string();
+        if self.db.get_other_peers().contains_key(peer_uuid)
+            && !self.keys_verified.contains(peer_uuid)
+            && !self
+                .key_challenges
+                .iter()
+                .any(|(_, target, _)| *target == endpoint_name)
+        {
+            self.query_peer_info(endpoint);
+        }
+    }
+
+    // The challenge is signed without key_path too, an empty signature
+    fn send_i_am(&mut self, request_uuid: &str, challenge: &[u8], target_endpoint: Endpoint) {
+        let local_endpoint = self.find_local_endpoint_for_protocol(target_endpoint.proto.clone());
+        let node_info = self.get_node_info();
+        let i_am = IAmMessage {
+            request_uuid: request_uuid.to_string(),
+            version: node_info.version,
+            git_hash: node_info.git_hash,
+            features: node_info.features,
+            capabilities: CAPABILITIES.iter().map(|cap| cap.to_string()).collect(),
+            endpoints: self
+                .db
+                .get_localpeer()
+                .endpoints
+                .iter()
+                .map(|endpoint| endpoint.to_string())
+                .collect(),
+            codecs: supported_codecs(),
+            public_key: self.public_key().unwrap_or_default(),
+            signature: match &self.local_key {
+                Some(local_key) if !challenge.is_empty() => {
+                    local_key.sign_challenge(challenge, &self.db.get_localpeer().uuid)
+                }
+                _ => Vec::new(),
+            },
+        };
+        let proto_msg = ProtoMessage::new_i_am(
+            self.db.get_localpeer().uuid.clone(),
+            local_endpoint.clone(),
+            self.now().timestamp_millis(),
+            i_am,
+        );
+        self.send_control_message(proto_msg, local_endpoint, target_endpoint);
+    }
+
+    // None without key_path
+    pub fn public_key(&self) -> Option<Vec<u8>> {
+        self.local_key.as_ref().map(LocalKey::public_key)
+    }
+
+    pub fn get_peer_key(&self, peer_uuid: &String) -> Option<PeerKey> {
+        self.key_store.get(peer_uuid).cloned()
+    }
+
+    // Only the key of an IAm signing the challenge of a WhoAreYou sent is checked, a key
+    // replayed by another node cannot sign it
+    fn on_signed_key(&mut self, peer_uuid: &String, i_am: &IAmMessage) {
+        if i_am.public_key.is_empty() {
+            return;
+        }
+        let challenge = self
+            .key_challenges
+            .iter()
+            .position(|(request_uuid, _, _)| *request_uuid == i_am.request_uuid)
+            .and_then(|index| self.key_challenges.remove(index));
+        let refused = match challenge {
+            None => Some("it answers no challenge"),
+            Some((_, _, challenge))
+                if !verify_challenge(&i_am.public_key, &challenge, peer_uuid, &i_am.signature) =>
+            {
+                Some("the challenge is not signed by it")
+            }
+            Some(_) => None,
+        };
+        if let Some(reason) = refused {
+            self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::TrustViolation(
+                format!("Key of peer {} refused: {}", peer_uuid, reason),
+            )));
+            return;
+        }
+        self.keys_verified.insert(peer_uuid.clone());
+        self.check_peer_key(peer_uuid, &i_am.public_key);
+    }
+
+    // Recorded on first use, a different key is reported and not recorded, the peer is
+    // untrusted until trust_peer_key. Peers without key send an empty one
+    fn check_peer_key(&mut self, peer_uuid: &String, key: &[u8]) {
+        if key.is_empty() || !self.db.get_other_peers().contains_key(peer_uuid) {
+            return;
+        }
+        let now_ms = self.now().timestamp_millis();
+        let event = match self.key_store.check(peer_uuid, key, now_ms) {
+            Ok(KeyCheck::FirstUse) => {
+                ChatAppInfoEvent::PeerKeyRecorded(peer_uuid.clone(), fingerprint(key))
+            }
+            Ok(KeyCheck::Known) => return,
+            Ok(KeyCheck::Changed) => {
+                self.key_mismatches.insert(peer_uuid.clone());
+                ChatAppInfoEvent::PeerKeyChanged(peer_uuid.clone(), fingerprint(key))
+            }
+            Err(err) => {
+                self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::InternalError(
+                    format!("Failed to record the key of peer {}: {}", peer_uuid, err),
+                )));
+                return;
+            }
+        };
+        self.notify_observers(ChatAppEvent::Message(event));
+    }
+
+    // Replaces the recorded key of the peer, once its change was checked out of band, and
+    // gives the peer its configured trust back
+    pub fn trust_peer_key(&mut self, peer_uuid: &String, key: Vec<u8>) -> std::io::Result<()> {
+        self.key_store
+            .trust(peer_uuid, key, self.now().timestamp_millis())?;
+        self.key_mismatches.remove(peer_uuid);
+        Ok(())
+    }
+
+    // Generates a new local key and sends it, signed by the previous one, to every peer
+    // over its healthiest endpoint. The peers offline meanwhile see a changed key
+    pub fn rotate_key(&mut self) -> Result<(), ChatAppErrorEvent> {
+        let rotated = match &mut self.local_key {
+            Some(local_key) => local_key
+                .rotate()
+                .map_err(|err| ChatAppErrorEvent::InternalError(err.to_string())),
+            None => Err(ChatAppErrorEvent::InternalError(
+                "No local key, key_path is not set".to_string(),
+            )),
+        };
+        let (public_key, signature) = match rotated {
+            Ok(rotated) => rotated,
+            Err(error) => {
+                self.notify_observers(ChatAppEvent::Error(error.clone()));
+                return Err(error);
+            }
+        };
+        let peers: Vec<Peer> = self.db.get_other_peers().values().cloned().collect();
+        for peer in peers {
+            let Some(endpoint) = self.healthiest_endpoint(&peer.endpoints) else {
+                continue;
+            };
+            let local_endpoint = self.find_local_endpoint_for_protocol(endpoint.proto.clone());
+            let proto_msg = ProtoMessage::new_key_rotation(
+                self.db.get_localpeer().uuid.clone(),
+                local_endpoint.clone(),
+                self.now().timestamp_millis(),
+                public_key.clone(),
+                signature.clone(),
+            );
+            self.send_control_message(proto_msg, local_endpoint, endpoint);
+        }
+        Ok(())
+    }
+
+    fn send_profile(&mut self, target_endpoint: Endpoint) {
+        let local_endpoint = self.find_local_endpoint_for_protocol(target_endpoint.proto.clone());
+        let localpeer = self.db.get_localpeer();
+        let profile = ProfileMessage {
+            name: localpeer.name.clone(),
+            color: localpeer.color.clone(),
+            avatar_hash: localpeer.avatar_hash.clone().unwrap_or_default(),
+        };
+        let proto_msg = ProtoMessage::new_profile(
+            localpeer.uuid.clone(),
+            local_endpoint.clone(),
+            self.now().timestamp_millis(),
+            profile,
+        );
+        self.send_control_message(proto_msg, local_endpoint, target_endpoint);
+    }
+
+    // On the first handshake with a known peer since the start
+    fn send_profile_once(&mut self, peer_uuid: &String, target_endpoint: Endpoint) {
+        if self.db.get_other_peers().contains_key(peer_uuid)
+            && self.profiles_sent.insert(peer_uuid.clone())
+        {
+            self.send_profile(target_endpoint);
+        }
+    }
+
+    // Changes how the local peer is shown and sends the new profile to every peer, over
+    // its healthiest endpoint
+    pub fn set_profile(&mut self, name: String, color: String, avatar_hash: Option<String>) {
+        let mut localpeer = self.db.get_localpeer().clone();
+        localpeer.name = name;
+        localpeer.color = color;
+        localpeer.avatar_hash = avatar_hash;
+        let peers: Vec<Peer> = self.db.get_other_peers().values().cloned().collect();
+        self.db.set_peers(localpeer, peers.clone());
+        for peer in peers {
+            if let Some(endpoint) = self.healthiest_endpoint(&peer.endpoints) {
+                self.profiles_sent.insert(peer.uuid);
+                self.send_profile(endpoint);
+            }
+        }
+    }
+
+    // Kept apart from the configured peer, only from fully trusted peers. The name is
+    // sanitized and refused when another peer, or the local one, goes by it
+    fn on_profile(&mut self, proto_msg: &ProtoMessage, profile: &ProfileMessage) {
+        let sender_uuid = &proto_msg.sender_uuid;
+        if !self.db.get_other_peers().contains_key(sender_uuid) {
+            return;
+        }
+        let name = sanitize_profile_name(&profile.name);
+        if let Some(name) = &name {
+            if self.name_taken(name, sender_uuid) {
+                self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::TrustViolation(
+                    format!(
+                        "profile of peer {} refused, another peer goes by {}",
+                        sender_uuid, name
+                    ),
+                )));
+                return;
+            }
+        }
+        let updated = PeerProfile {
+            name,
+            color: sanitize_profile_color(&profile.color),
+            avatar_hash: Some(profile.avatar_hash.clone()).filter(|hash| !hash.is_empty()),
+            received_at_ms: self.now().timestamp_millis(),
+        };
+        if let Some(previous) = self.peer_profiles.get(sender_uuid) {
+            if previous.name == updated.name
+                && previous.color == updated.color
+                && previous.avatar_hash == updated.avatar_hash
+            {
+                return;
+            }
+        }
+        if let Err(err) = self.peer_profiles.set(sender_uuid, updated.clone()) {
+            self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::InternalError(
+                format!(
+                    "Unable to store the profile of peer {}: {}",
+                    sender_uuid, err
+                ),
+            )));
+        }
+        self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::PeerProfileUpdated(
+            sender_uuid.clone(),
+            updated,
+        )));
+    }
+
+    // Against the configured names and the names the other peers gave themselves. A peer
+    // may go by its own configured name
+    fn name_taken(&self, name: &str, peer_uuid: &String) -> bool {
+        let localpeer = self.db.get_localpeer();
+        let configured = std::iter::once(localpeer)
+            .chain(self.db.get_other_peers().values())
+            .any(|peer| peer.uuid != *peer_uuid && same_name(&peer.name, name));
+        configured
+            || self.peer_profiles.iter().any(|(uuid, profile)| {
+                uuid != peer_uuid && profile.name.as_deref().is_some_and(|n| same_name(n, name))
+            })
+    }
+
+    // As the peer described itself, see PeerProfile
+    pub fn get_peer_profile(&self, peer_uuid: &String) -> Option<PeerProfile> {
+        self.peer_profiles.get(peer_uuid).cloned()
+    }
+
+    // Sends a digest of the most recent texts of every room shared with the peer, the
+    // missing messages are then pushed and requested both ways
+    pub fn sync_history_with(&mut self, peer_uuid: &String) {
+        let shared: Vec<(String, Endpoint)> = self
+            .db
+            .get_rooms()
+            .keys()
+            .filter_map(|room_uuid| {
+                let endpoint = self
+                    .get_other_peers_for_room(room_uuid)?
+                    .into_iter()
+                    .find(|(uuid, _)| uuid == peer_uuid)?
+                    .1;
+                Some((room_uuid.clone(), endpoint))
+            })
+            .collect();
+        for (room_uuid, endpoint) in shared {
+            let mut messages = self.room_text_messages(&room_uuid);
+            let mut since = 0;
+            if messages.len() > self.history_digest_size {
+                messages.sort_by_key(|msg| msg.send_time.timestamp_millis());
+                messages.drain(..messages.len() - self.history_digest_size);
+                since = messages
+                    .first()
+                    .map_or(0, |msg| msg.send_time.timestamp_millis());
+            }
+            let local_endpoint = self.find_local_endpoint_for_protocol(endpoint.proto.clone());
+            let proto_msg = ProtoMessage::new_history_digest(
+                self.db.get_localpeer().uuid.clone(),
+                local_endpoint.clone(),
+                self.now().timestamp_millis(),
+                room_uuid.clone(),
+                messages.into_iter().map(|msg| msg.uuid).collect(),
+                since,
+            );
+            self.send_control_message(proto_msg, local_endpoint, endpoint);
+        }
+    }
+
+    fn sync_history_with_endpoint(&mut self, remote: &Endpoint) {
+        let peer_uuid = self
+            .db
+            .get_other_peers()
+            .values()
+            .find(|peer| peer.endpoints.contains(remote))
+            .map(|peer| peer.uuid.clone());
+        if let Some(peer_uuid) = peer_uuid {
+            self.sync_history_with(&peer_uuid);
+        }
+    }
+
+    // The peer and its configured endpoint behind a remote endpoint, which may be an
+    // ephemeral port of the host of the peer. None when unknown or shared by several peers
+    fn peer_endpoint_of(&self, remote: &Endpoint) -> Option<(String, Endpoint)> {
+        let peers = self.db.get_other_peers();
+        if let Some(found) = peers.values().find_map(|peer| {
+            peer.endpoints
+                .iter()
+                .find(|endpoint| *endpoint == remote)
+                .map(|endpoint| (peer.uuid.clone(), endpoint.clone()))
+        }) {
+            return Some(found);
+        }
+        let mut candidates = peers.values().filter_map(|peer| {
+            peer.endpoints
+                .iter()
+                .find(|endpoint| same_node(endpoint, remote))
+                .map(|endpoint| (peer.uuid.clone(), endpoint.clone()))
+        });
+        let found = candidates.next()?;
+        candidates.next().is_none().then_some(found)
+    }
+
+    // Only the frames of the peers configured with or advertising another codec than
+    // protobuf are read with it, a garbled protobuf frame of the others is not
+    fn may_use_other_codec(&self, remote: &Endpoint) -> bool {
+        let Some((peer_uuid, _)) = self.peer_endpoint_of(remote) else {
+            return false;
+        };
+        let configured = self
+            .db
+            .get_other_peers()
+            .get(&peer_uuid)
+            .is_some_and(|peer| peer.codec != WireCodec::Protobuf);
+        configured
+            || self.peer_codecs.get(&peer_uuid).is_some_and(|codecs| {
+                codecs
+                    .iter()
+                    .any(|codec| codec != WireCodec::Protobuf.name())
+            })
+    }
+
+    // Protobuf for the endpoints of no known peer
+    fn codec_for(&self, endpoint: &Endpoint) -> WireCodec {
+        self.db
+            .get_other_peers()
+            .values()
+            .find(|peer| peer.endpoints.contains(endpoint))
+            .map_or(WireCodec::Protobuf, |peer| {
+                peer.codec
+                    .negotiate(self.peer_codecs.get(&peer.uuid).map(Vec::as_slice))
+            })
+    }
+
+    // Files are not synchronized
+    fn room_text_messages(&self, room_uuid: &String) -> Vec<ChatMessage> {
+        self.db
+            .iter_messages()
+            .filter(|msg| msg.in_room(room_uuid) && matches!(msg.content, Content::Text(_)))
+            .map(Cow::into_owned)
+            .collect()
+    }
+
+    // False as well for the rooms the local peer is not a participant of
+    fn is_room_participant(&self, room_uuid: &String, peer_uuid: &String) -> bool {
+        self.get_other_peers_for_room(room_uuid)
+            .is_some_and(|participants| participants.iter().any(|(uuid, _)| uuid == peer_uuid))
+    }
+
+    // Only participants of the room take part in its synchronization and pins
+    fn room_peer_endpoint(&self, proto_msg: &ProtoMessage) -> Option<Endpoint> {
+        self.get_other_peers_for_room(&proto_msg.room_uuid)?;
+        if !self.is_room_participant(&proto_msg.room_uuid, &proto_msg.sender_uuid) {
+            self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::TrustViolation(
+                format!(
+                    "message {} about room {} from non participant {}",
+                    proto_msg.uuid, proto_msg.room_uuid, proto_msg.sender_uuid
+                ),
+            )));
+            return None;
+        }
+        Endpoint::from_str(&proto_msg.source_endpoint).ok()
+    }
+
+    // Our messages older than the window of the digest are not pushed, the peer left out
+    // its own ones as well
+    fn on_history_digest(&mut self, proto_msg: &ProtoMessage, remote_uuids: &[String], since: i64) {
+        let Some(endpoint) = self.room_peer_endpoint(proto_msg) else {
+            return;
+        };
+        let remote_uuids: HashSet<&String> = remote_uuids.iter().collect();
+        let local_uuids: Vec<String> = self
+            .room_text_messages(&proto_msg.room_uuid)
+            .into_iter()
+            .filter(|msg| msg.send_time.timestamp_millis() >= since)
+            .map(|msg| msg.uuid)
+            .collect();
+
+        let missing_there: HashSet<&String> = local_uuids
+            .iter()
+            .filter(|uuid| !remote_uuids.contains(uuid))
+            .collect();
+        if !missing_there.is_empty() {
+            self.send_history_backfill(&proto_msg.room_uuid, &missing_there, endpoint.clone());
+        }
+
+        let missing_here: Vec<String> = remote_uuids
+            .into_iter()
+            .filter(|uuid| self.get_message(uuid).is_none())
+            .cloned()
+            .collect();
+        if !missing_here.is_empty() {
+            let local_endpoint = self.find_local_endpoint_for_protocol(endpoint.proto.clone());
+            let request = ProtoMessage::new_history_request(
+                self.db.get_localpeer().uuid.clone(),
+                local_endpoint.clone(),
+                self.now().timestamp_millis(),
+                proto_msg.room_uuid.clone(),
+                missing_here,
+            );
+            self.send_control_message(request, local_endpoint, endpoint);
+        }
+    }
+
+    fn send_history_backfill(
+        &mut self,
+        room_uuid: &String,
+        uuids: &HashSet<&String>,
+        target_endpoint: Endpoint,
+    ) {
+        let local_endpoint = self.find_local_endpoint_for_protocol(target_endpoint.proto.clone());
+        let messages: Vec<ProtoMessage> = self
+            .room_text_messages(room_uuid)
+            .iter()
+            .filter(|msg| uuids.contains(&msg.uuid))
+            // Keeps the endpoint the message was originally sent from
+            .filter_map(|msg| ProtoMessage::new_text(msg, Some(msg.source_endpoint.clone())).ok())
+            .collect();
+        if messages.is_empty() {
+            return;
+        }
+        let proto_msg = ProtoMessage::new_history_backfill(
+            self.db.get_localpeer().uuid.clone(),
+            local_endpoint.clone(),
+            self.now().timestamp_millis(),
+            room_uuid.clone(),
+            messages,
+        );
+        self.send_control_message(proto_msg, local_endpoint, target_endpoint);
+    }
+
+    // Backfilled messages are stored as they are, without being acked
+    // The responder vouches for its own texts. A fully trusted responder also relays the
+    // texts of the other fully trusted participants of the room, never ours nor the ones of
+    // limited peers, whose uuids would be the easiest to forge
+    fn backfill_sender_allowed(
+        &self,
+        responder: &String,
+        sender: &String,
+        participants: &[(String, Endpoint)],
+    ) -> bool {
+        if sender == responder {
+            return true;
+        }
+        *sender != self.db.get_localpeer().uuid
+            && self.trust_of(responder) == TrustLevel::Full
+            && self.trust_of(sender) == TrustLevel::Full
+            && participants.iter().any(|(uuid, _)| uuid == sender)
+    }
+
+    fn on_history_backfill(&mut self, proto_msg: &ProtoMessage, messages: &[ProtoMessage]) {
+        if self.room_peer_endpoint(proto_msg).is_none() {
+            return;
+        }
+        let participants = self
+            .get_other_peers_for_room(&proto_msg.room_uuid)
+            .unwrap_or_default();
+        let mut added = 0;
+        let mut rejected = 0;
+        for inner in messages {
+            let Some(MsgType::Text(text_part)) = &inner.msg_type else {
+                continue;
+            };
+            let in_room = inner.room_uuid == proto_msg.room_uuid
+                || inner.also_rooms.contains(&proto_msg.room_uuid);
+            if !in_room || self.get_message(&inner.uuid).is_some() {
+                continue;
+            }
+            // Relayed by another participant
+            if self.db.get_blocked_peers().contains(&inner.sender_uuid) {
+                continue;
+            }
+            if !self.backfill_sender_allowed(
+                &proto_msg.sender_uuid,
+                &inner.sender_uuid,
+                &participants,
+            ) {
+                rejected += 1;
+                continue;
+            }
+            let Some(mut msg) =
+                ChatMessage::new_received(inner, Content::Text(text_part.text.clone()), self.now())
+            else {
+                continue;
+            };
+            // Relayed from one of its other rooms, each checked like a received message
+            if msg.room_uuid != proto_msg.room_uuid
+                && !self.is_room_participant(&msg.room_uuid, &msg.sender_uuid)
+            {
+                rejected += 1;
+                continue;
+            }
+            msg.also_rooms
+                .retain(|room_uuid| self.is_room_participant(room_uuid, &msg.sender_uuid));
+            self.merge_room_clock(&msg);
+            self.db.add_message(msg);
+            added += 1;
+        }
+        if rejected > 0 {
+            self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::TrustViolation(
+                format!(
+                    "{} backfilled messages of room {} from {} by peers it cannot relay",
+                    rejected, proto_msg.room_uuid, proto_msg.sender_uuid
+                ),
+            )));
+        }
+        self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::HistoryBackfilled(
+            proto_msg.sender_uuid.clone(),
+            proto_msg.room_uuid.clone(),
+            added,
+        )));
+    }
+
+    fn send_control_message(
+        &mut self,
+        proto_msg: ProtoMessage,
+        local_endpoint: Option<Endpoint>,
+        target_endpoint: Endpoint,
+    ) {
+        self.pending_send_list
+            .push((MessageType::Control, proto_msg.uuid.clone(), None));
+        let codec = self.codec_for(&target_endpoint);
+        if let Some(engine) = &mut self.network_engine {
+            match codec.encode(&proto_msg) {
+                Ok(bytes) => {
+                    record_bytes_sent(&self.metrics, &target_endpoint.proto, bytes.len());
+                    engine.send(local_endpoint, target_endpoint, bytes, proto_msg.uuid);
+                }
+                Err(err) => {
+                    self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::ProtocolEncode(
+                        proto_msg.uuid.clone(),
+                        err,
+                    )));
+                }
+            };
+        }
+    }
+
+    pub fn send_nack_to_peer(
+        &mut self,
+        for_msg: &ChatMessage,
+        target_endpoint: Endpoint,
+        reason: String,
+    ) {
+        self.send_nack(for_msg, target_endpoint, reason, false);
+    }
+
+    // A nack the sender must not retry after, the message is given up on its side
+    pub fn send_refusal_to_peer(
+        &mut self,
+        for_msg: &ChatMessage,
+        target_endpoint: Endpoint,
+        reason: String,
+    ) {
+        self.send_nack(for_msg, target_endpoint, reason, true);
+    }
+
+    fn send_nack(
+        &mut self,
+        for_msg: &ChatMessage,
+        target_endpoint: Endpoint,
+        reason: String,
+        refused: bool,
+    ) {
+        let local_endpoint = self.find_local_endpoint_for_protocol(target_endpoint.proto.clone());
+
+        let proto_msg = ProtoMessage::new_nack(
+            for_msg,
+            self.db.get_localpeer().uuid.clone(),
             local_endpoint.clone(),
-            DTChatTime::now().timestamp_millis(),
+            self.now().timestamp_millis(),
+            reason,
+            refused,
         );
         self.pending_send_list.push((
-            MessageType::Ack,
+            MessageType::Nack,
             proto_msg.uuid.clone(),
             Some(for_msg.uuid.clone()),
         ));
+        let codec = self.codec_for(&target_endpoint);
         if let Some(engine) = &mut self.network_engine {
-            match proto_msg.encode_to_vec() {
+            match codec.encode(&proto_msg) {
                 Ok(bytes) => {
-                    engine.send_async(
+                    record_bytes_sent(&self.metrics, &target_endpoint.proto, bytes.len());
+                    engine.send(
                         local_endpoint,
                         target_endpoint.clone(),
                         bytes,
                         proto_msg.uuid.clone(),
                     );
-                    self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::AckSent(
-                        for_msg.clone(),
-                        target_endpoint.to_string(),
+                }
+                Err(err) => {
+                    self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::ProtocolEncode(
+                        proto_msg.uuid.clone(),
+                        err,
                     )));
                 }
+            };
+        }
+    }
+
+    // False when a message with the same uuid is stored already, nobody is notified then
+    fn add_message(&mut self, mut new_msg: ChatMessage) -> bool {
+        new_msg.mentions = self.resolve_mentions(&new_msg);
+        if !self.db.add_message(new_msg.clone()) {
+            return false;
+        }
+        self.merge_room_clock(&new_msg);
+
+        let local_peer_uuid = self.db.get_localpeer().uuid.clone();
+        if local_peer_uuid == new_msg.sender_uuid {
+            self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::Sending(new_msg)));
+            return true;
+        }
+        let mentioned = !new_msg.muted && new_msg.mentions.contains(&local_peer_uuid);
+        self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::Received(
+            new_msg.clone(),
+        )));
+        if mentioned {
+            self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::Mentioned(new_msg)));
+        }
+        true
+    }
+
+    // Against the uuids and the configured names, never the ones the peers give themselves.
+    // Names are matched regardless of case, unknown ones are ignored and those several peers
+    // go by are reported instead
+    fn resolve_mentions(&self, msg: &ChatMessage) -> Vec<String> {
+        let Content::Text(text) = &msg.content else {
+            return Vec::new();
+        };
+        let local_peer = self.db.get_localpeer();
+        let mut mentions = Vec::new();
+        for name in mention_names(text) {
+            let mut peers: Vec<String> = std::iter::once(local_peer)
+                .chain(self.db.get_other_peers().values())
+                .filter(|peer| peer.uuid == name || same_name(&peer.name, name))
+                .map(|peer| peer.uuid.clone())
+                .collect();
+            peers.sort();
+            match peers.as_slice() {
+                [] => {}
+                [peer_uuid] => {
+                    if !mentions.contains(peer_uuid) {
+                        mentions.push(peer_uuid.clone());
+                    }
+                }
+                _ => self.notify_observers(ChatAppEvent::Message(
+                    ChatAppInfoEvent::AmbiguousMention(msg.clone(), name.to_string(), peers),
+                )),
+            }
+        }
+        mentions
+    }
+
+    // Counts a new message of the local peer in the room, returns the clock to send with it
+    fn tick_room_clock(&mut self, room_uuid: &String) -> HashMap<String, u64> {
+        let local_peer_uuid = self.db.get_localpeer().uuid.clone();
+        let clock = self.room_clocks.entry(room_uuid.clone()).or_default();
+        *clock.entry(local_peer_uuid).or_default() += 1;
+        clock.clone()
+    }
+
+    fn tick_room_lamport_time(&mut self, room_uuid: &String) -> u64 {
+        let time = self.room_lamport_times.entry(room_uuid.clone()).or_default();
+        *time += 1;
+        *time
+    }
+
+    // Received and imported messages move both clocks of the room forward
+    fn merge_room_clock(&mut self, msg: &ChatMessage) {
+        let time = self.room_lamport_times.entry(msg.room_uuid.clone()).or_default();
+        *time = (*time).max(msg.lamport_time);
+        let clock = self.room_clocks.entry(msg.room_uuid.clone()).or_default();
+        for (peer_uuid, count) in &msg.vector_clock {
+            let known = clock.entry(peer_uuid.clone()).or_default();
+            *known = (*known).max(*count);
+        }
+    }
+
+    // The peer uuid, as the peer may come back from another endpoint or port, the remote
+    // endpoint for the unknown peers
+    fn catch_up_key(&self, remote: &Endpoint) -> String {
+        self.peer_endpoint_of(remote)
+            .map_or_else(|| remote.to_string(), |(peer_uuid, _)| peer_uuid)
+    }
+
+    fn catch_up(&mut self, remote: &Endpoint) {
+        let key = self.catch_up_key(remote);
+        let (Some(since), Some(catch_up_after_ms)) =
+            (self.disconnected_since.remove(&key), self.catch_up_after_ms)
+        else {
+            return;
+        };
+        let now = self.now();
+        if now.timestamp_millis() - since.timestamp_millis() < catch_up_after_ms {
+            return;
+        }
+        let peer_uuid = (key != remote.to_string()).then_some(key);
+        let summary = CatchUpSummary::new(
+            remote.to_string(),
+            peer_uuid,
+            since,
+            now,
+            self.db.get_all_messages(),
+            &self.db.get_localpeer().uuid,
+        );
+        self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::CatchUp(summary)));
+    }
+
+    // Each overdue message is reported once
+    fn check_ack_timeouts(&mut self, now: DTChatTime) {
+        let Some(ack_timeout) = self.ack_timeout.clone() else {
+            return;
+        };
+        let local_uuid = self.db.get_localpeer().uuid.clone();
+        let now_ms = now.timestamp_millis();
+        let overdue: Vec<ChatMessage> = self
+            .db
+            .iter_messages()
+            .filter(|msg| {
+                msg.sender_uuid == local_uuid
+                    && matches!(
+                        msg.status,
+                        MessageStatus::Sent
+                            | MessageStatus::InCustody
+                            | MessageStatus::DeliveredToNode
+                    )
+                    && !self.ack_overdue.contains(&msg.uuid)
+                    && ack_timeout
+                        .deadline(msg)
+                        .is_some_and(|deadline| now_ms > deadline)
+            })
+            .map(Cow::into_owned)
+            .collect();
+        for message in overdue {
+            self.ack_overdue.insert(message.uuid.clone());
+            self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::AckOverdue(
+                message.uuid.clone(),
+            )));
+            if ack_timeout.retry {
+                self.resend(&message);
+            }
+        }
+    }
+
+    // A late message may hint at a failed contact or link
+    fn check_late_deliveries(&mut self, now: DTChatTime) {
+        let Some(margin_ms) = self.late_delivery_margin_ms else {
+            return;
+        };
+        let local_uuid = self.db.get_localpeer().uuid.clone();
+        let now_ms = now.timestamp_millis();
+        let late: Vec<(ChatMessage, DTChatTime)> = self
+            .db
+            .iter_messages()
+            .filter(|msg| {
+                msg.sender_uuid == local_uuid
+                    && matches!(
+                        msg.status,
+                        MessageStatus::Sending
+                            | MessageStatus::Sent
+                            | MessageStatus::InCustody
+                            | MessageStatus::DeliveredToNode
+                    )
+                    && !self.late_deliveries.contains(&msg.uuid)
+            })
+            .filter_map(|msg| {
+                let predicted = msg.predicted_arrival_time?;
+                (now_ms > predicted.timestamp_millis() + margin_ms)
+                    .then(|| (msg.into_owned(), predicted))
+            })
+            .collect();
+        for (message, predicted) in late {
+            self.late_deliveries.insert(message.uuid.clone());
+            self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::DeliveryLate(
+                message, predicted, margin_ms,
+            )));
+        }
+    }
+
+    // Sends a local message again to the endpoint it was sent to, unless the peer refused it
+    pub fn retry(&mut self, uuid: &String) -> Result<(), ChatAppErrorEvent> {
+        let message = self
+            .get_message(uuid)
+            .filter(|message| message.sender_uuid == self.db.get_localpeer().uuid)
+            .ok_or_else(|| ChatAppErrorEvent::MessageNotFound(uuid.clone()))?;
+        if message.status == MessageStatus::Refused {
+            return Err(ChatAppErrorEvent::InvalidMessage(format!(
+                "message {} was refused by the peer",
+                uuid
+            )));
+        }
+        if self.network_engine.is_none() {
+            return Err(ChatAppErrorEvent::NoEngineAttached);
+        }
+        self.resend(&message);
+        Ok(())
+    }
+
+    // Sends the message again to the endpoint it was sent to, its status is left untouched
+    fn resend(&mut self, message: &ChatMessage) {
+        let endpoint = message.source_endpoint.clone();
+        let local_endpoint = self.find_local_endpoint_for_protocol(endpoint.proto.clone());
+        let wire_msg = self.wire_message(message);
+        let bytes = match ProtoMessage::new_text(&wire_msg, local_endpoint.clone()) {
+            Ok(proto_msg) => match self.codec_for(&endpoint).encode(&proto_msg) {
+                Ok(bytes) => bytes,
                 Err(err) => {
                     self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::ProtocolEncode(
-                        format!("Failed to encode ACK: {}", err),
+                        message.uuid.clone(),
+                        err,
+                    )));
+                    return;
+                }
+            },
+            Err(err) => {
+                self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::InternalError(
+                    format!("Failed to encode message {}: {}", message.uuid, err),
+                )));
+                return;
+            }
+        };
+        let now = self.now();
+        let Some(engine) = &mut self.network_engine else {
+            return;
+        };
+        self.pending_send_list
+            .push((MessageType::Text, message.uuid.clone(), None));
+        record_bytes_sent(&self.metrics, &endpoint.proto, bytes.len());
+        let stage = DeliveryStage::Retried(endpoint.to_string());
+        engine.send(local_endpoint, endpoint, bytes, message.uuid.clone());
+        self.db
+            .add_timeline_entry(&message.uuid, TimelineEntry::at(stage, now));
+    }
+
+    fn check_contact_plan(&mut self, now: DTChatTime) {
+        let ASabrInitState::Enabled(a_sabr) = &self.a_sabr else {
+            return;
+        };
+        if self.cp_expiry_reported {
+            return;
+        }
+        let horizon = a_sabr.horizon();
+        let remaining_ms = horizon.timestamp_millis() - now.timestamp_millis();
+        if remaining_ms <= self.cp_expiry_warning_ms {
+            self.cp_expiry_reported = true;
+            self.notify_observers(ChatAppEvent::Message(
+                ChatAppInfoEvent::ContactPlanExpiring(horizon),
+            ));
+        }
+    }
+
+    // Against the budget of every room the message is shown in
+    fn check_latency_budget(&mut self, msg: &ChatMessage, latency_ms: i64) {
+        let exceeded: Vec<Room> = msg
+            .rooms()
+            .filter(|room_uuid| {
+                self.latency_budgets
+                    .get(*room_uuid)
+                    .is_some_and(|max_latency_ms| latency_ms > *max_latency_ms)
+            })
+            .filter_map(|room_uuid| self.db.get_rooms().get(room_uuid).cloned())
+            .collect();
+        for room in exceeded {
+            self.notify_observers(ChatAppEvent::Message(
+                ChatAppInfoEvent::LatencyBudgetExceeded(room),
+            ));
+        }
+    }
+
+    fn mark_as_acked(&mut self, message_uuid: &String, timestamp: i64, rtt_ms: Option<i64>) {
+        tracing::debug!(%message_uuid, "ack received");
+        // Messages sent over several paths can be acked more than once
+        if self
+            .get_message(message_uuid)
+            .is_some_and(|msg| msg.status == MessageStatus::ReceivedByPeer)
+        {
+            return;
+        }
+        if let Some(received_at) = DTChatTime::from_timestamp_millis(timestamp) {
+            if let Some(message) = self.mark_as(&message_uuid, MarkIntent::Acked(received_at)) {
+                self.check_latency_budget(
+                    &message,
+                    received_at.timestamp_millis() - message.send_time.timestamp_millis(),
+                );
+                let replica_uuid = message.uuid.clone();
+                self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::AckReceived(
+                    message, rtt_ms,
+                )));
+                self.check_room_message_delivered(&replica_uuid);
+            } else {
+                self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::MessageNotFound(
+                    format!("Received ack for unknown message: {}", message_uuid),
+                )));
+            }
+        } else {
+            self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::ProtocolDecode(
+                format!(
+                    "Protobuf decode error: invalid timestamp to ack message {}",
+                    message_uuid
+                ),
+            )));
+        }
+    }
+
+    // Entry point for the BP status reports, the engine only reports the hand over to the agent
+    pub fn mark_as_in_custody(&mut self, message_uuid: &String) {
+        // A late report must not hide an ack nor a failure
+        if !self
+            .get_message(message_uuid)
+            .is_some_and(|msg| msg.status == MessageStatus::Sent)
+        {
+            return;
+        }
+        if let Some(message) = self.mark_as(message_uuid, MarkIntent::InCustody) {
+            self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::InCustody(message)));
+        }
+    }
+
+    // For the transports giving BP status reports
+    fn check_status_reports(&mut self) {
+        let reports = match &mut self.network_engine {
+            Some(engine) => engine.take_status_reports(),
+            None => return,
+        };
+        for report in reports {
+            self.on_bundle_status_report(report);
+        }
+    }
+
+    // Entry point for the BP status reports of the sent messages, the other bundles are
+    // ignored. Each report is notified, along with the status it moved the message to
+    pub fn on_bundle_status_report(&mut self, report: BundleStatusReport) {
+        if let Some(room_msg) = self.db.get_room_message(&report.token).cloned() {
+            for replica_uuid in room_msg.messages {
+                self.on_bundle_status_report(BundleStatusReport {
+                    token: replica_uuid,
+                    ..report.clone()
+                });
+            }
+            return;
+        }
+        let Some(message) = self.get_message(&report.token) else {
+            return;
+        };
+        let uuid = message.uuid.clone();
+        // A late report must not hide an ack nor a failure
+        let settled =
+            is_given_up(&message.status) || message.status == MessageStatus::ReceivedByPeer;
+        match &report.status {
+            BundleStatus::Received => self.mark_as_in_custody(&uuid),
+            BundleStatus::Forwarded => {
+                self.mark_as_in_custody(&uuid);
+                let stage = DeliveryStage::Forwarded(report.node_eid.clone());
+                let now = self.now();
+                self.db
+                    .add_timeline_entry(&uuid, TimelineEntry::at(stage, now));
+            }
+            BundleStatus::Delivered if !settled => {
+                self.mark_as(&uuid, MarkIntent::DeliveredToNode);
+            }
+            BundleStatus::Deleted(_) if !settled => {
+                if let Some(message) = self.mark_as(&uuid, MarkIntent::Failed) {
+                    self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::Failed(message)));
+                }
+            }
+            _ => {}
+        }
+        if let Some(message) = self.get_message(&uuid) {
+            self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::BundleStatus(
+                message, report,
+            )));
+        }
+    }
+
+    // Intents without a time of their own are at the time of the model
+    fn mark_as(&mut self, uuid: &String, intent: MarkIntent) -> Option<ChatMessage> {
+        let now = self.now();
+        self.db.mark_as(uuid, intent, now)
+    }
+
+    fn mark_as_expired(&mut self, message_uuid: &String) {
+        if self.send_queues.remove(message_uuid) {
+            self.flush_send_queues();
+        }
+        if let Some(message) = self.mark_as(message_uuid, MarkIntent::Expired) {
+            self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::Expired(message)));
+        }
+    }
+
+    // Messages of a blocked peer are dropped on reception, without being acked, as are
+    // its history digests and backfills. Kept across restarts with blocked_peers_path
+    pub fn block_peer(&mut self, peer_uuid: &String) -> std::io::Result<()> {
+        self.db.set_blocked(peer_uuid, true)
+    }
+
+    pub fn unblock_peer(&mut self, peer_uuid: &String) -> std::io::Result<()> {
+        self.db.set_blocked(peer_uuid, false)
+    }
+
+    // Messages of a muted peer are stored with their muted flag set
+    pub fn mute_peer(&mut self, peer_uuid: &String) {
+        self.db.set_muted(peer_uuid, true);
+    }
+
+    pub fn unmute_peer(&mut self, peer_uuid: &String) {
+        self.db.set_muted(peer_uuid, false);
+    }
+
+    // Checkpoint of the stored conversation, to roll it back with restore. The clocks and
+    // sequence numbers of the model keep going, None when the database cannot roll back
+    pub fn snapshot(&self) -> Option<Snapshot> {
+        self.db.snapshot()
+    }
+
+    pub fn restore(&mut self, snapshot: Snapshot) -> std::io::Result<()> {
+        self.db.restore(snapshot)
+    }
+
+    // Messages of unknown types, oldest first
+    pub fn get_quarantined(&self) -> Vec<QuarantinedMessage> {
+        self.db.get_quarantined().to_vec()
+    }
+
+    pub fn get_blocked_peers(&self) -> Vec<String> {
+        let mut peers: Vec<String> = self.db.get_blocked_peers().iter().cloned().collect();
+        peers.sort();
+        peers
+    }
+
+    pub fn get_muted_peers(&self) -> Vec<String> {
+        let mut peers: Vec<String> = self.db.get_muted_peers().iter().cloned().collect();
+        peers.sort();
+        peers
+    }
+
+    // False for an unknown message
+    pub fn pin_message(&mut self, uuid: &String) -> bool {
+        self.pin_locally(uuid, true)
+    }
+
+    pub fn unpin_message(&mut self, uuid: &String) -> bool {
+        self.pin_locally(uuid, false)
+    }
+
+    fn pin_locally(&mut self, uuid: &String, pinned: bool) -> bool {
+        let Some(message) = self.set_pinned(uuid, pinned) else {
+            return false;
+        };
+        if self.propagate_pins {
+            for (_, endpoint) in self
+                .get_other_peers_for_room(&message.room_uuid)
+                .unwrap_or_default()
+            {
+                let local_endpoint = self.find_local_endpoint_for_protocol(endpoint.proto.clone());
+                let proto_msg = ProtoMessage::new_pin(
+                    self.db.get_localpeer().uuid.clone(),
+                    local_endpoint.clone(),
+                    self.now().timestamp_millis(),
+                    &message,
+                    pinned,
+                );
+                self.send_control_message(proto_msg, local_endpoint, endpoint);
+            }
+        }
+        true
+    }
+
+    fn set_pinned(&mut self, uuid: &String, pinned: bool) -> Option<ChatMessage> {
+        let message = self.get_message(uuid)?;
+        if message.pinned == pinned {
+            return Some(message);
+        }
+        let message = self.mark_as(uuid, MarkIntent::Pinned(pinned))?;
+        self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::PinChanged(
+            message.clone(),
+        )));
+        Some(message)
+    }
+
+    pub fn get_pinned_messages(&self, room_uuid: &String) -> Vec<ChatMessage> {
+        self.db
+            .iter_messages()
+            .filter(|msg| msg.in_room(room_uuid) && msg.pinned)
+            .map(Cow::into_owned)
+            .collect()
+    }
+
+    // Reported to every observer so that all the frontends agree, false for an unknown message
+    pub fn mark_as_read(&mut self, uuid: &String) -> bool {
+        match self.get_message(uuid) {
+            None => false,
+            Some(message) if message.read_locally => true,
+            Some(_) => match self.mark_as(uuid, MarkIntent::ReadLocally) {
+                Some(message) => {
+                    self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::ReadLocally(
+                        message,
                     )));
+                    true
+                }
+                None => false,
+            },
+        }
+    }
+
+    pub fn mark_room_as_read(&mut self, room_uuid: &String) {
+        for message in self.get_unread_messages(room_uuid) {
+            self.mark_as_read(&message.uuid);
+        }
+    }
+
+    pub fn get_unread_messages(&self, room_uuid: &String) -> Vec<ChatMessage> {
+        self.db
+            .iter_messages()
+            .filter(|msg| msg.in_room(room_uuid) && !msg.read_locally)
+            .map(Cow::into_owned)
+            .collect()
+    }
+
+    // Only local messages still being sent can be cancelled. Once handed to the transport,
+    // that is out of the deferred sends and the send queues, it must withdraw every copy,
+    // the socket engine cannot: CancelNotSupported and the send goes on
+    pub fn cancel_send(&mut self, uuid: &String) -> Result<ChatMessage, ChatAppErrorEvent> {
+        let message = self
+            .get_message(uuid)
+            .ok_or_else(|| ChatAppErrorEvent::MessageNotFound(uuid.clone()))?;
+        if message.sender_uuid != self.db.get_localpeer().uuid
+            || message.status != MessageStatus::Sending
+        {
+            return Err(ChatAppErrorEvent::InvalidMessage(format!(
+                "Message {} is not being sent",
+                uuid
+            )));
+        }
+        let pending_tokens: Vec<String> = self
+            .pending_send_list
+            .iter()
+            .filter(|(_, token, original)| token == uuid || original.as_ref() == Some(uuid))
+            .map(|(_, token, _)| token.clone())
+            .collect();
+        let waiting = self
+            .deferred_sends
+            .iter()
+            .any(|deferred| deferred.message_uuid == *uuid)
+            || self.send_queues.waiting_uuids().contains(uuid);
+        if !waiting {
+            if let Some(engine) = &mut self.network_engine {
+                let withdrawn = pending_tokens
+                    .iter()
+                    .filter(|token| engine.cancel(token))
+                    .count();
+                if withdrawn < pending_tokens.len() {
+                    return Err(ChatAppErrorEvent::CancelNotSupported(uuid.clone()));
                 }
-            };
+            }
+        }
+        self.pending_send_list.retain(|(_, token, _)| !pending_tokens.contains(token));
+        self.deferred_sends.retain(|deferred| deferred.message_uuid != *uuid);
+        let freed = self.send_queues.remove(uuid);
+        self.failover_attempts.remove(uuid);
+        self.endpoint_fallbacks.remove(uuid);
+        let message = self
+            .mark_as(uuid, MarkIntent::Cancelled)
+            .ok_or_else(|| ChatAppErrorEvent::MessageNotFound(uuid.clone()))?;
+        self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::Cancelled(
+            message.clone(),
+        )));
+        if freed {
+            self.flush_send_queues();
         }
+        Ok(message)
     }
 
-    fn add_message(&mut self, new_msg: ChatMessage) {
-        self.db.add_message(new_msg.clone());
+    // Forgets everything exchanged with a peer, which stays configured. The room messages
+    // sent by the other participants of its rooms are kept
+    pub fn purge_peer(&mut self, peer_uuid: &String) -> Result<PurgeSummary, ChatAppErrorEvent> {
+        let purged = self.purge_peer_data(peer_uuid);
+        match &purged {
+            Ok(summary) => self.notify_observers(ChatAppEvent::Message(
+                ChatAppInfoEvent::PeerPurged(summary.clone()),
+            )),
+            Err(error) => self.notify_observers(ChatAppEvent::Error(error.clone())),
+        }
+        purged
+    }
 
-        let event = if self.db.get_localpeer().uuid == new_msg.sender_uuid {
-            ChatAppEvent::Message(ChatAppInfoEvent::Sending(new_msg.clone()))
-        } else {
-            ChatAppEvent::Message(ChatAppInfoEvent::Received(new_msg.clone()))
+    // Stops at the first failed write, what was deleted before stays deleted
+    fn purge_peer_data(&mut self, peer_uuid: &String) -> Result<PurgeSummary, ChatAppErrorEvent> {
+        let Some(peer) = self.db.get_other_peers().get(peer_uuid).cloned() else {
+            return Err(ChatAppErrorEvent::PeerNotFound(peer_uuid.clone()));
+        };
+        let local_uuid = self.db.get_localpeer().uuid.clone();
+        let mut summary = PurgeSummary {
+            peer_uuid: peer_uuid.clone(),
+            ..Default::default()
         };
-        self.notify_observers(event);
-    }
 
-    fn mark_as_acked(&mut self, message_uuid: &String, timestamp: i64) {
-        if let Some(received_at) = DTChatTime::from_timestamp_millis(timestamp) {
-            if let Some(message) = self
+        let related: Vec<ChatMessage> = self
+            .db
+            .iter_messages()
+            .filter(|msg| {
+                msg.sender_uuid == *peer_uuid
+                    || (msg.sender_uuid == local_uuid
+                        && peer.endpoints.contains(&msg.source_endpoint))
+            })
+            .map(Cow::into_owned)
+            .collect();
+        for msg in &related {
+            if msg.sender_uuid == local_uuid
+                && msg.status == MessageStatus::Sending
+                && self.cancel_send(&msg.uuid).is_ok()
+            {
+                summary.cancelled_sends += 1;
+            }
+        }
+        let scheduled = self.scheduled_sends.len();
+        self.scheduled_sends
+            .retain(|scheduled| scheduled.peer_uuid != *peer_uuid);
+        summary.cancelled_sends += scheduled - self.scheduled_sends.len();
+
+        let offers = self.file_offers.len() + self.accepted_offers.len();
+        self.file_offers
+            .retain(|_, (offer, _)| offer.sender_uuid != *peer_uuid);
+        self.accepted_offers
+            .retain(|_, offer| offer.sender_uuid != *peer_uuid);
+        summary.file_offers = offers - self.file_offers.len() - self.accepted_offers.len();
+
+        let uuids: HashSet<String> = related.iter().map(|msg| msg.uuid.clone()).collect();
+        for path in related.iter().filter_map(|msg| msg.stored_path.as_ref()) {
+            if fs::remove_file(path).is_ok() {
+                summary.files += 1;
+            }
+        }
+        summary.messages = self
+            .db
+            .remove_messages(&uuids)
+            .map_err(|err| ChatAppErrorEvent::InternalError(err.to_string()))?;
+        self.corrupted_files.retain(|uuid| !uuids.contains(uuid));
+        self.ack_overdue.retain(|uuid| !uuids.contains(uuid));
+        self.late_deliveries.retain(|uuid| !uuids.contains(uuid));
+
+        let rooms: Vec<String> = self
+            .db
+            .get_rooms()
+            .values()
+            .filter(|room| {
+                room.participants.iter().any(|(uuid, _)| uuid == peer_uuid)
+                    && room
+                        .participants
+                        .iter()
+                        .all(|(uuid, _)| *uuid == local_uuid || uuid == peer_uuid)
+            })
+            .map(|room| room.uuid.clone())
+            .collect();
+        for room_uuid in rooms {
+            if self.db.get_draft(&room_uuid).is_some() {
+                self.db
+                    .save_draft(&room_uuid, String::new())
+                    .map_err(|err| ChatAppErrorEvent::InternalError(err.to_string()))?;
+                summary.drafts += 1;
+            }
+        }
+
+        summary.key_removed = self
+            .key_store
+            .remove(peer_uuid)
+            .map_err(|err| ChatAppErrorEvent::InternalError(err.to_string()))?;
+        self.peer_profiles
+            .remove(peer_uuid)
+            .map_err(|err| ChatAppErrorEvent::InternalError(err.to_string()))?;
+        self.keys_verified.remove(peer_uuid);
+        self.key_mismatches.remove(peer_uuid);
+        self.profiles_sent.remove(peer_uuid);
+        self.peer_versions.remove(peer_uuid);
+        self.peer_capabilities.remove(peer_uuid);
+        self.peer_codecs.remove(peer_uuid);
+
+        let quarantined: Vec<String> = self
+            .db
+            .get_quarantined()
+            .iter()
+            .filter(|msg| msg.sender_uuid == *peer_uuid)
+            .map(|msg| msg.uuid.clone())
+            .collect();
+        for uuid in quarantined {
+            if self
                 .db
-                .mark_as(&message_uuid, MarkIntent::Acked(received_at))
+                .remove_quarantined(&uuid)
+                .map_err(|err| ChatAppErrorEvent::InternalError(err.to_string()))?
             {
-                self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::AckReceived(
-                    message,
-                )));
-            } else {
-                self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::MessageNotFound(
-                    format!("Received ack for unknown message: {}", message_uuid),
-                )));
+                summary.quarantined += 1;
+            }
+        }
+
+        // Sequence numbers both ways, link state and the rate limits of the peer
+        self.sent_sequences.forget(peer_uuid);
+        self.received_sequences
+            .retain(|(sender_uuid, _), _| sender_uuid != peer_uuid);
+        self.clock_skew.forget(peer_uuid);
+        self.latency.forget(peer_uuid);
+        self.failover_attempts
+            .retain(|uuid, (attempt_peer, _)| !uuids.contains(uuid) && attempt_peer != peer_uuid);
+        self.endpoint_fallbacks
+            .retain(|uuid, _| !uuids.contains(uuid));
+        let endpoints: Vec<String> = peer.endpoints.iter().map(|e| e.to_string()).collect();
+        for endpoint in &endpoints {
+            self.link_health.forget(endpoint);
+            self.last_heard.remove(endpoint);
+            self.keepalive_sent.remove(endpoint);
+            self.disconnected_since.remove(endpoint);
+            if let Some(limiter) = &mut self.rate_limiter {
+                limiter.forget(endpoint);
             }
+        }
+        if let Some(limiter) = &mut self.rate_limiter {
+            limiter.forget(peer_uuid);
+        }
+
+        if let Some(journal) = &self.journal {
+            summary.log_lines += journal
+                .lock()
+                .unwrap()
+                .forget_peer(peer_uuid, &endpoints)
+                .map_err(|err| ChatAppErrorEvent::InternalError(err.to_string()))?;
+        }
+        if let Some(recorder) = &mut self.replay_recorder {
+            summary.log_lines += recorder
+                .forget_peer(peer_uuid, &endpoints)
+                .map_err(|err| ChatAppErrorEvent::InternalError(err.to_string()))?;
+        }
+        Ok(summary)
+    }
+
+    // Gives up the local messages still waiting for delivery once their lifetime is over
+    fn expire_messages(&mut self) {
+        let local_uuid = self.db.get_localpeer().uuid.clone();
+        let now = self.now();
+        let expired: Vec<String> = self
+            .db
+            .iter_messages()
+            .filter(|msg| {
+                msg.sender_uuid == local_uuid
+                    && matches!(
+                        msg.status,
+                        MessageStatus::Sending
+                            | MessageStatus::Sent
+                            | MessageStatus::InCustody
+                            | MessageStatus::DeliveredToNode
+                    )
+                    && msg.is_expired(now)
+            })
+            .map(|msg| msg.uuid.clone())
+            .collect();
+        for uuid in expired {
+            self.mark_as_expired(&uuid);
+        }
+    }
+
+    fn mark_as_nacked(&mut self, message_uuid: &String, reason: String) {
+        tracing::debug!(%message_uuid, %reason, "nack received");
+        if let Some(message) = self.mark_as(message_uuid, MarkIntent::Failed) {
+            self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::NackReceived(
+                message, reason,
+            )));
         } else {
-            self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::ProtocolDecode(
-                format!(
-                    "Protobuf decode error: invalid timestamp to ack message {}",
-                    message_uuid
-                ),
+            self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::MessageNotFound(
+                format!("Received nack for unknown message: {}", message_uuid),
+            )));
+        }
+    }
+
+    // Terminal, the message is not retried whatever its endpoints
+    fn mark_as_refused(&mut self, message_uuid: &String, reason: String) {
+        tracing::debug!(%message_uuid, %reason, "refusal received");
+        if let Some(message) = self.mark_as(message_uuid, MarkIntent::Refused) {
+            self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::Refused(
+                message, reason,
+            )));
+        } else {
+            self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::MessageNotFound(
+                format!("Received refusal for unknown message: {}", message_uuid),
             )));
         }
     }
@@ -494,6 +4974,252 @@ impl ChatModel {
     pub fn get_other_peers(&self) -> HashMap<String, Peer> {
         self.db.get_other_peers().clone()
     }
+    // Every endpoint seen, healthiest first
+    pub fn get_link_status(&self) -> Vec<LinkStatus> {
+        self.link_health.statuses()
+    }
+
+    // The first endpoint among the healthiest ones, None when there is none
+    pub fn healthiest_endpoint(&self, endpoints: &[Endpoint]) -> Option<Endpoint> {
+        endpoints
+            .iter()
+            .rev()
+            .max_by(|a, b| {
+                let score = |endpoint: &Endpoint| self.link_health.score(&endpoint.to_string());
+                score(a).total_cmp(&score(b))
+            })
+            .cloned()
+    }
+
+    // Links are those of the configured endpoints, the ephemeral ports TCP peers connect
+    // from being mapped to them
+    fn link_key(&self, endpoint: &Endpoint) -> String {
+        match self.peer_endpoint_of(endpoint) {
+            Some((_, configured)) => configured.to_string(),
+            None => endpoint.to_string(),
+        }
+    }
+
+    // A failure when given its reason, degradations and recoveries are reported
+    fn record_link(&mut self, endpoint: &Endpoint, failure: Option<String>) {
+        let endpoint = self.link_key(endpoint);
+        let now = self.now();
+        let change = match failure {
+            Some(reason) => self.link_health.record_failure(&endpoint, reason, now),
+            None => self.link_health.record_success(&endpoint),
+        };
+        let Some(status) = change else {
+            return;
+        };
+        let event = if status.degraded {
+            ChatAppInfoEvent::LinkDegraded(status)
+        } else {
+            ChatAppInfoEvent::LinkRecovered(status)
+        };
+        self.notify_observers(ChatAppEvent::Message(event));
+    }
+
+    // ACK round trips per protocol, None until an ack of the peer was received
+    pub fn get_peer_latency_stats(&self, peer_uuid: &String) -> Option<PeerLatencyStats> {
+        self.latency.stats(peer_uuid)
+    }
+
+    // None for an unknown peer, see ConversationStats::to_csv for the export
+    pub fn get_conversation_stats(&self, peer_uuid: &String) -> Option<ConversationStats> {
+        let peer = self.db.get_other_peers().get(peer_uuid)?;
+        let local_peer_uuid = &self.db.get_localpeer().uuid;
+        let sent: Vec<Cow<ChatMessage>> = self
+            .db
+            .iter_messages()
+            .filter(|msg| {
+                msg.sender_uuid == *local_peer_uuid && peer.endpoints.contains(&msg.source_endpoint)
+            })
+            .collect();
+        let received: Vec<Cow<ChatMessage>> = self
+            .db
+            .iter_messages()
+            .filter(|msg| msg.sender_uuid == *peer_uuid)
+            .collect();
+        let sent: Vec<&ChatMessage> = sent.iter().map(Cow::as_ref).collect();
+        let received: Vec<&ChatMessage> = received.iter().map(Cow::as_ref).collect();
+        Some(ConversationStats::new(peer_uuid, &sent, &received))
+    }
+
+    // Per room, per peer and per day, computed by the database
+    pub fn get_statistics(&self) -> Statistics {
+        self.db.get_statistics()
+    }
+
+    // Messages of the room, oldest first, returns how many were written
+    pub fn export_history(
+        &self,
+        room_uuid: &String,
+        format: ExportFormat,
+        path: &str,
+    ) -> std::io::Result<usize> {
+        let mut messages: Vec<ChatMessage> = self
+            .db
+            .iter_messages()
+            .filter(|msg| msg.in_room(room_uuid))
+            .map(Cow::into_owned)
+            .collect();
+        messages.sort_by_key(|msg| msg.send_time);
+        export_messages(&messages, format, path)?;
+        Ok(messages.len())
+    }
+
+    // Merges a dump of export_history, a message already known keeps the most advanced status
+    pub fn import_history(&mut self, path: &str) -> std::io::Result<ImportSummary> {
+        let mut summary = ImportSummary::default();
+        for exported in import_messages(path)? {
+            let Some(imported) = exported.into_message() else {
+                summary.invalid += 1;
+                continue;
+            };
+            match self.get_message(&imported.uuid) {
+                None => {
+                    self.merge_room_clock(&imported);
+                    self.db.add_message(imported);
+                    summary.added += 1;
+                }
+                Some(known) if imported.status.progress() > known.status.progress() => {
+                    let merged = ChatMessage {
+                        send_completed: imported.send_completed.or(known.send_completed),
+                        predicted_arrival_time: imported
+                            .predicted_arrival_time
+                            .or(known.predicted_arrival_time),
+                        receive_time: imported.receive_time.or(known.receive_time),
+                        received_from: known.received_from.clone(),
+                        bundle: known.bundle.clone(),
+                        vector_clock: known.vector_clock.clone(),
+                        lamport_time: known.lamport_time,
+                        read_locally: known.read_locally,
+                        pinned: known.pinned,
+                        muted: known.muted,
+                        stored_path: known.stored_path.clone(),
+                        prediction_skipped: known.prediction_skipped,
+                        file_info: known.file_info.clone(),
+                        sequence: known.sequence,
+                        ..imported
+                    };
+                    self.db.replace_message(merged);
+                    summary.updated += 1;
+                }
+                Some(_) => summary.unchanged += 1,
+            }
+        }
+        self.notify_observers(ChatAppEvent::Info(format!(
+            "History imported from {}: {} added, {} updated, {} unchanged, {} invalid",
+            path, summary.added, summary.updated, summary.unchanged, summary.invalid
+        )));
+        Ok(summary)
+    }
+
+    pub fn get_room_message(&self, uuid: &String) -> Option<RoomMessage> {
+        self.db.get_room_message(uuid).cloned()
+    }
+
+    pub fn get_room_message_status(&self, room_message_uuid: &String) -> Option<RoomMessageStatus> {
+        let room_msg = self.db.get_room_message(room_message_uuid)?;
+        let replicas = room_msg
+            .peers
+            .iter()
+            .zip(&room_msg.messages)
+            .map(|(peer_uuid, uuid)| {
+                let status = self.get_message(uuid).map(|msg| msg.status);
+                (peer_uuid.clone(), status)
+            })
+            .collect();
+        Some(RoomMessageStatus {
+            room_message_uuid: room_message_uuid.clone(),
+            replicas,
+        })
+    }
+
+    fn check_room_message_delivered(&mut self, replica_uuid: &String) {
+        let Some(room_msg) = self.get_room_message_for_replica(replica_uuid) else {
+            return;
+        };
+        if self
+            .get_room_message_status(&room_msg.uuid)
+            .is_some_and(|status| status.all_acked())
+        {
+            self.notify_observers(ChatAppEvent::Message(
+                ChatAppInfoEvent::RoomMessageDelivered(room_msg),
+            ));
+        }
+    }
+
+    // The room message a replica sent to a single peer belongs to
+    pub fn get_room_message_for_replica(&self, replica_uuid: &String) -> Option<RoomMessage> {
+        self.db.get_room_message_for_replica(replica_uuid).cloned()
+    }
+
+    // Replaces the PrototypeDecoder
+    pub fn set_legacy_decoder(&mut self, decoder: Box<dyn LegacyDecoder>) {
+        self.legacy_decoder = decoder;
+    }
+
+    // Received blobs of this content type go to the handler, returns the one replaced
+    pub fn register_blob_handler(
+        &mut self,
+        content_type: &str,
+        handler: Box<dyn BlobHandler>,
+    ) -> Option<Box<dyn BlobHandler>> {
+        self.extensions.register(content_type, handler)
+    }
+
+    pub fn unregister_blob_handler(&mut self, content_type: &str) -> Option<Box<dyn BlobHandler>> {
+        self.extensions.unregister(content_type)
+    }
+
+    pub fn blob_content_types(&self) -> Vec<String> {
+        self.extensions.content_types()
+    }
+
+    fn route_blob(&mut self, uuid: &String) {
+        let Some(msg) = self.get_message(uuid) else {
+            return;
+        };
+        let Content::Blob(content_type, _) = &msg.content else {
+            return;
+        };
+        match self.extensions.handler_mut(content_type) {
+            Some(handler) => handler.on_blob(&msg),
+            None => self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::UnhandledBlob(
+                msg.clone(),
+            ))),
+        }
+    }
+
+    // Replaying a recording into the model must not record it again
+    pub fn stop_replay_recording(&mut self) {
+        self.replay_recorder = None;
+    }
+
+    // Of the local calls, made by the frontends only: the model calls the inner methods
+    // itself, whose sends a replay makes again on its own
+    fn record_call(&mut self, call: impl FnOnce() -> RecordedEvent) {
+        let now = self.now();
+        if let Some(recorder) = &mut self.replay_recorder {
+            recorder.append(call(), now);
+        }
+    }
+
+    fn decode_legacy(&self, frame: &[u8], from: &Endpoint) -> Option<ProtoMessage> {
+        if !self.legacy_frames {
+            return None;
+        }
+        let mut proto_msg = self.legacy_decoder.decode(frame)?;
+        if proto_msg.source_endpoint.is_empty() {
+            proto_msg.source_endpoint = from.to_string();
+        }
+        Some(proto_msg)
+    }
+
+    pub fn soak_config(&self) -> Option<SoakConfig> {
+        self.soak.clone()
+    }
     pub fn get_localpeer(&self) -> Peer {
         self.db.get_localpeer().clone()
     }
@@ -505,25 +5231,85 @@ impl ChatModel {
         self.db.get_last_messages(count).to_vec()
     }
 
+    // Most recent messages of the room, oldest first
+    pub fn get_last_messages_for_room(&self, room_uuid: &String, count: usize) -> Vec<ChatMessage> {
+        self.db.get_last_messages_for_room(room_uuid, count)
+    }
+
     pub fn get_all_messages(&self) -> Vec<ChatMessage> {
         self.db.get_all_messages().clone()
     }
 
+    // Oldest first, borrowing the history instead of cloning it like get_all_messages, or
+    // reading it one message at a time from a persistent database
+    pub fn iter_messages(&self) -> impl Iterator<Item = Cow<'_, ChatMessage>> + '_ {
+        self.db.iter_messages()
+    }
+
+    // Every stage the message went through, oldest first, empty for an unknown message
+    pub fn get_message_timeline(&self, uuid: &String) -> Vec<TimelineEntry> {
+        self.db.get_timeline(uuid).to_vec()
+    }
+
+    pub fn get_message(&self, uuid: &String) -> Option<ChatMessage> {
+        self.db.get_message(uuid)
+    }
+
+    // Chat message whose bytes an engine token carries: the message itself, the message a
+    // duplicate was sent for, or the room message of a bundle. None for acks and control
+    // messages
+    pub fn lookup_message_by_token(&self, token: &String) -> Option<String> {
+        if let Some((msg_type, _, original)) = self
+            .pending_send_list
+            .iter()
+            .find(|(_, pending, _)| pending == token)
+        {
+            return match msg_type {
+                MessageType::Text => Some(token.clone()),
+                MessageType::Duplicate => original.clone(),
+                MessageType::Ack | MessageType::Nack | MessageType::Control => None,
+            };
+        }
+        if self.db.get_room_message(token).is_some() || self.get_message(token).is_some() {
+            return Some(token.clone());
+        }
+        None
+    }
+
     pub fn mark_as_sent(&mut self, target_uuid: &String) {
+        tracing::debug!(message_uuid = %target_uuid, "transfer completed");
+        // Frees a place in the queue of its protocol
+        if self.send_queues.remove(target_uuid) {
+            self.flush_send_queues();
+        }
+        // A room bundle carries all of its replicas
+        if let Some(room_msg) = self.db.get_room_message(target_uuid).cloned() {
+            for replica_uuid in &room_msg.messages {
+                self.mark_as_sent(replica_uuid);
+            }
+            return;
+        }
         if let Some(pos) = self
             .pending_send_list
             .iter()
             .position(|(_, s, _)| s == target_uuid)
         {
             let (msg_type, _uuid, _) = self.pending_send_list.remove(pos);
-            if msg_type == MessageType::Ack {
+            if msg_type != MessageType::Text {
                 return;
             }
-
-            if let Some(message) = self
-                .db
-                .mark_as(&target_uuid, MarkIntent::Sent(DTChatTime::now()))
+            self.failover_attempts.remove(target_uuid);
+            self.endpoint_fallbacks.remove(target_uuid);
+            // Already reported by another path
+            if self
+                .get_message(target_uuid)
+                .is_some_and(|message| message.status != MessageStatus::Sending)
             {
+                return;
+            }
+
+            let sent_at = self.now();
+            if let Some(message) = self.mark_as(&target_uuid, MarkIntent::Sent(sent_at)) {
                 self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::Sent(message)));
             } else {
                 self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::MessageNotFound(
@@ -534,7 +5320,153 @@ impl ChatModel {
         }
     }
 
+    fn persist_pending_send(
+        &mut self,
+        chatmsg: &ChatMessage,
+        peer_uuid: &String,
+        endpoint: &Endpoint,
+    ) {
+        let bundle_uuid = self
+            .db
+            .get_room_message_for_replica(&chatmsg.uuid)
+            .filter(|room_msg| room_msg.bundled)
+            .map(|room_msg| room_msg.uuid.clone());
+        let pending = PendingSend {
+            message: ExportedMessage::from(chatmsg),
+            peer_uuid: peer_uuid.clone(),
+            endpoint: endpoint.to_string(),
+            bundle_uuid,
+        };
+        if let Err(err) = self.db.add_pending_send(pending) {
+            self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::InternalError(
+                format!("Failed to persist the send of {}: {}", chatmsg.uuid, err),
+            )));
+        }
+    }
+
+    // The room bundles of the pending replicas the database lost, for their resends to go
+    // by the uuid of the bundle and its acks to find them
+    fn restore_room_bundles(&mut self) {
+        let mut bundles: HashMap<String, RoomMessage> = HashMap::new();
+        for pending in self.db.get_pending_sends() {
+            let Some(bundle_uuid) = &pending.bundle_uuid else {
+                continue;
+            };
+            if self.db.get_room_message(bundle_uuid).is_some() {
+                continue;
+            }
+            let room_msg = bundles
+                .entry(bundle_uuid.clone())
+                .or_insert_with(|| RoomMessage {
+                    uuid: bundle_uuid.clone(),
+                    room_uuid: pending.message.room_uuid.clone(),
+                    messages: Vec::new(),
+                    peers: Vec::new(),
+                    bundled: true,
+                });
+            room_msg.messages.push(pending.message.uuid.clone());
+            room_msg.peers.push(pending.peer_uuid.clone());
+        }
+        for room_msg in bundles.into_values() {
+            self.db.add_room_message(room_msg);
+        }
+    }
+
+    // Sends interrupted by the last stop are sent again, or failed when their peer or
+    // endpoint is gone and expired when their lifetime is over
+    fn reconcile_pending_sends(&mut self) {
+        self.restore_room_bundles();
+        for pending in self.db.get_pending_sends().to_vec() {
+            let uuid = pending.message.uuid.clone();
+            let Some(message) = pending.message.into_message() else {
+                let _ = self.db.remove_pending_send(&uuid);
+                continue;
+            };
+            // Already known from an imported history
+            if self.get_message(&uuid).is_some() {
+                let _ = self.db.remove_pending_send(&uuid);
+                continue;
+            }
+            let endpoint = Endpoint::from_str(&pending.endpoint)
+                .ok()
+                .filter(|_| self.db.get_other_peers().contains_key(&pending.peer_uuid));
+            self.merge_room_clock(&message);
+            self.db.add_message(message.clone());
+            if message.is_expired(self.now()) {
+                self.mark_as_expired(&uuid);
+                continue;
+            }
+            let Some(endpoint) = endpoint else {
+                if let Some(message) = self.mark_as(&uuid, MarkIntent::Failed) {
+                    self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::Failed(message)));
+                }
+                continue;
+            };
+            let local_endpoint = self.find_local_endpoint_for_protocol(endpoint.proto.clone());
+            let wire_msg = self.wire_message(&message);
+            let bytes = ProtoMessage::new_text(&wire_msg, local_endpoint.clone())
+                .map_err(|err| err.to_string())
+                .and_then(|proto_msg| self.codec_for(&endpoint).encode(&proto_msg));
+            let now = self.now();
+            match (bytes, &mut self.network_engine) {
+                (Ok(bytes), Some(_)) if self.send_queues.handles(&endpoint.proto) => {
+                    self.pending_send_list
+                        .push((MessageType::Text, uuid.clone(), None));
+                    self.send_queues.push(QueuedSend {
+                        message_uuid: uuid.clone(),
+                        peer_uuid: pending.peer_uuid.clone(),
+                        local_endpoint,
+                        endpoint,
+                        bulk: is_bulk(&message.content, bytes.len()),
+                        bytes,
+                    });
+                    self.db
+                        .add_timeline_entry(&uuid, TimelineEntry::at(DeliveryStage::Queued, now));
+                    self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::Sending(
+                        message,
+                    )));
+                }
+                (Ok(bytes), Some(engine)) => {
+                    self.pending_send_list
+                        .push((MessageType::Text, uuid.clone(), None));
+                    record_bytes_sent(&self.metrics, &endpoint.proto, bytes.len());
+                    let stage = DeliveryStage::Retried(endpoint.to_string());
+                    engine.send(local_endpoint, endpoint, bytes, uuid.clone());
+                    self.db
+                        .add_timeline_entry(&uuid, TimelineEntry::at(stage, now));
+                    self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::Sending(
+                        message,
+                    )));
+                }
+                _ => {
+                    if let Some(message) = self.mark_as(&uuid, MarkIntent::Failed) {
+                        self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::Failed(
+                            message,
+                        )));
+                    }
+                }
+            }
+        }
+        self.flush_send_queues();
+    }
+
+    fn has_pending_send(&self, message_uuid: &String) -> bool {
+        self.pending_send_list
+            .iter()
+            .any(|(msg_type, uuid, _)| *msg_type == MessageType::Text && uuid == message_uuid)
+    }
+
     fn mark_pending_message_as_failed(&mut self, target_uuid: &String) {
+        tracing::debug!(message_uuid = %target_uuid, "transfer failed");
+        if self.send_queues.remove(target_uuid) {
+            self.flush_send_queues();
+        }
+        if let Some(room_msg) = self.db.get_room_message(target_uuid).cloned() {
+            for replica_uuid in &room_msg.messages {
+                self.mark_pending_message_as_failed(replica_uuid);
+            }
+            return;
+        }
         if let Some(pos) = self
             .pending_send_list
             .iter()
@@ -543,12 +5475,38 @@ impl ChatModel {
             let (msg_type, _uuid, _) = self.pending_send_list.remove(pos);
 
             match msg_type {
-                MessageType::Ack => {}
+                MessageType::Ack
+                | MessageType::Nack
+                | MessageType::Duplicate
+                | MessageType::Control => {}
                 // TODO: what is the strategy ? retries ? Maybe "nothing", the handling of this can be user
                 // action, like pressing a "retry" button,
                 MessageType::Text => {
-                    if let Some(_message) = self.db.mark_as(&target_uuid, MarkIntent::Failed) {
-                        // TODO: Same
+                    // Only the last path of a message sent over several ones can fail it
+                    if self.has_pending_send(target_uuid)
+                        || self
+                            .get_message(target_uuid)
+                            .is_some_and(|message| message.status != MessageStatus::Sending)
+                    {
+                        return;
+                    }
+                    // An expired message is not worth another attempt
+                    if self
+                        .get_message(target_uuid)
+                        .is_some_and(|message| message.is_expired(self.now()))
+                    {
+                        self.failover_attempts.remove(target_uuid);
+                        self.endpoint_fallbacks.remove(target_uuid);
+                        self.mark_as_expired(target_uuid);
+                        return;
+                    }
+                    if self.fall_back(target_uuid) || self.failover(target_uuid) {
+                        return;
+                    }
+                    if let Some(message) = self.mark_as(&target_uuid, MarkIntent::Failed) {
+                        self.notify_observers(ChatAppEvent::Message(ChatAppInfoEvent::Failed(
+                            message,
+                        )));
                     } else {
                         self.notify_observers(ChatAppEvent::Error(
                             ChatAppErrorEvent::MessageNotFound(format!(
@@ -562,6 +5520,108 @@ impl ChatModel {
         }
     }
 
+    // Sends the message again on the next endpoint of the policy of its peer
+    fn fall_back(&mut self, message_uuid: &String) -> bool {
+        let Some(mut chain) = self.endpoint_fallbacks.remove(message_uuid) else {
+            return false;
+        };
+        let Some(message) = self.get_message(message_uuid) else {
+            return false;
+        };
+        while !chain.is_empty() {
+            let peer_endpoint = chain.remove(0);
+            if self.send_again_over(&message, &peer_endpoint) {
+                if !chain.is_empty() {
+                    self.endpoint_fallbacks.insert(message_uuid.clone(), chain);
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    // Sends the message again on the next protocol of the failover order the peer has
+    fn failover(&mut self, message_uuid: &String) -> bool {
+        let Some((peer_uuid, mut tried)) = self.failover_attempts.remove(message_uuid) else {
+            return false;
+        };
+        let Some(message) = self.get_message(message_uuid) else {
+            return false;
+        };
+        for proto in self.failover_order.clone() {
+            if tried.contains(&proto) {
+                continue;
+            }
+            tried.push(proto.clone());
+            let Some(peer_endpoint) =
+                self.find_peer_endpoint_for_protocol(peer_uuid.clone(), proto.clone())
+            else {
+                continue;
+            };
+            if self.send_again_over(&message, &peer_endpoint) {
+                self.failover_attempts.insert(message_uuid.clone(), (peer_uuid, tried));
+                return true;
+            }
+        }
+        false
+    }
+
+    // False when the message cannot be encoded or there is no engine
+    fn send_again_over(&mut self, message: &ChatMessage, peer_endpoint: &Endpoint) -> bool {
+        let message_uuid = &message.uuid;
+        let local_endpoint = self.find_local_endpoint_for_protocol(peer_endpoint.proto.clone());
+        let wire_msg = self.wire_message(message);
+        let bytes = match ProtoMessage::new_text(&wire_msg, local_endpoint.clone()) {
+            Ok(proto_msg) => match self.codec_for(peer_endpoint).encode(&proto_msg) {
+                Ok(bytes) => bytes,
+                Err(_) => return false,
+            },
+            Err(_) => return false,
+        };
+        let now = self.now();
+        let Some(engine) = &mut self.network_engine else {
+            return false;
+        };
+        self.pending_send_list.push((MessageType::Text, message_uuid.clone(), None));
+        record_bytes_sent(&self.metrics, &peer_endpoint.proto, bytes.len());
+        engine.send(local_endpoint, peer_endpoint.clone(), bytes, message_uuid.clone());
+        let stage = DeliveryStage::Retried(peer_endpoint.to_string());
+        self.db
+            .add_timeline_entry(message_uuid, TimelineEntry::at(stage, now));
+        if message.source_endpoint != *peer_endpoint {
+            self.move_to_endpoint(message, peer_endpoint);
+        }
+        self.notify_observers(ChatAppEvent::Info(format!(
+            "Message {} sent again over {}",
+            message_uuid,
+            peer_endpoint.to_string()
+        )));
+        true
+    }
+
+    // The endpoint of a local message is the one it was last sent to, retry and the pending
+    // send of the next start use it
+    fn move_to_endpoint(&mut self, message: &ChatMessage, peer_endpoint: &Endpoint) {
+        let mut moved = message.clone();
+        moved.source_endpoint = peer_endpoint.clone();
+        self.db.replace_message(moved.clone());
+        let pending = self
+            .db
+            .get_pending_sends()
+            .iter()
+            .find(|pending| pending.message.uuid == message.uuid)
+            .cloned();
+        if let Some(mut pending) = pending {
+            pending.message = ExportedMessage::from(&moved);
+            pending.endpoint = peer_endpoint.to_string();
+            if let Err(err) = self.db.add_pending_send(pending) {
+                self.notify_observers(ChatAppEvent::Error(ChatAppErrorEvent::InternalError(
+                    format!("Failed to persist the send of {}: {}", message.uuid, err),
+                )));
+            }
+        }
+    }
+
     fn find_peer_endpoint_for_protocol(
         &self,
         peer_id: String,
@@ -0,0 +1,150 @@
+use std::{error::Error, fmt, sync::Arc};
+
+use crate::{event::ChatAppErrorEvent, net::Endpoint};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChatErrorKind {
+    ProtocolDecode,
+    ProtocolEncode,
+    InvalidMessage,
+    MessageNotFound,
+    PeerNotFound,
+    RoomNotFound,
+    NoEngineAttached,
+    TrustViolation,
+    QueueFull,
+    EndpointMismatch,
+    NoEndpoint,
+    NoParticipant,
+    RateLimited,
+    ContentTooLarge,
+    ReceivedFileTooLarge,
+    FileRejected,
+    CancelNotSupported,
+    Internal,
+}
+
+// Returned by the sends, with the ChatAppErrorEvent the observers were given as its cause
+#[derive(Clone, Debug)]
+pub struct ChatError {
+    pub kind: ChatErrorKind,
+    pub cause: ChatAppErrorEvent,
+    // Of the message refused, also when it was not kept
+    pub message_uuid: Option<String>,
+    pub peer_uuid: Option<String>,
+    pub endpoint: Option<Endpoint>,
+    pub room_uuid: Option<String>,
+    // Shared to keep the error Clone, like the events
+    pub source: Option<Arc<dyn Error + Send + Sync>>,
+}
+
+impl ChatError {
+    pub fn with_message_uuid(mut self, message_uuid: &str) -> Self {
+        self.message_uuid = Some(message_uuid.to_string());
+        self
+    }
+
+    pub fn with_peer(mut self, peer_uuid: &str) -> Self {
+        self.peer_uuid = Some(peer_uuid.to_string());
+        self
+    }
+
+    pub fn with_endpoint(mut self, endpoint: &Endpoint) -> Self {
+        self.endpoint = Some(endpoint.clone());
+        self
+    }
+
+    pub fn with_room(mut self, room_uuid: &str) -> Self {
+        self.room_uuid = Some(room_uuid.to_string());
+        self
+    }
+
+    pub fn with_source(mut self, source: impl Error + Send + Sync + 'static) -> Self {
+        self.source = Some(Arc::new(source));
+        self
+    }
+}
+
+impl fmt::Display for ChatError {
+    // The context the cause does not already name
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cause = self.cause.to_string();
+        write!(f, "{}", cause)?;
+        let unnamed = |id: &String| !cause.contains(id.as_str());
+        if let Some(peer_uuid) = self.peer_uuid.as_ref().filter(|id| unnamed(id)) {
+            write!(f, " (peer {})", peer_uuid)?;
+        }
+        if let Some(endpoint) = self.endpoint.as_ref().map(|e| e.to_string()) {
+            if unnamed(&endpoint) {
+                write!(f, " (endpoint {})", endpoint)?;
+            }
+        }
+        if let Some(room_uuid) = self.room_uuid.as_ref().filter(|id| unnamed(id)) {
+            write!(f, " (room {})", room_uuid)?;
+        }
+        if let Some(message_uuid) = self.message_uuid.as_ref().filter(|id| unnamed(id)) {
+            write!(f, " (message {})", message_uuid)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ChatError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn Error + 'static))
+    }
+}
+
+// The uuids and endpoint the event names are set, the sites add the others
+impl From<ChatAppErrorEvent> for ChatError {
+    fn from(event: ChatAppErrorEvent) -> Self {
+        let kind = match &event {
+            ChatAppErrorEvent::ProtocolDecode(_) => ChatErrorKind::ProtocolDecode,
+            ChatAppErrorEvent::ProtocolEncode(_, _) => ChatErrorKind::ProtocolEncode,
+            ChatAppErrorEvent::InvalidMessage(_) => ChatErrorKind::InvalidMessage,
+            ChatAppErrorEvent::MessageNotFound(_) => ChatErrorKind::MessageNotFound,
+            ChatAppErrorEvent::PeerNotFound(_) => ChatErrorKind::PeerNotFound,
+            ChatAppErrorEvent::RoomNotFound(_) => ChatErrorKind::RoomNotFound,
+            ChatAppErrorEvent::NoEngineAttached => ChatErrorKind::NoEngineAttached,
+            ChatAppErrorEvent::TrustViolation(_) => ChatErrorKind::TrustViolation,
+            ChatAppErrorEvent::QueueFull(_, _) => ChatErrorKind::QueueFull,
+            ChatAppErrorEvent::EndpointMismatch(_, _) => ChatErrorKind::EndpointMismatch,
+            ChatAppErrorEvent::NoEndpoint(_) => ChatErrorKind::NoEndpoint,
+            ChatAppErrorEvent::NoParticipant(_) => ChatErrorKind::NoParticipant,
+            ChatAppErrorEvent::RateLimited(_) => ChatErrorKind::RateLimited,
+            ChatAppErrorEvent::ContentTooLarge(_, _) => ChatErrorKind::ContentTooLarge,
+            ChatAppErrorEvent::ReceivedFileTooLarge(_, _, _) => ChatErrorKind::ReceivedFileTooLarge,
+            ChatAppErrorEvent::FileRejected(_, _) => ChatErrorKind::FileRejected,
+            ChatAppErrorEvent::CancelNotSupported(_) => ChatErrorKind::CancelNotSupported,
+            ChatAppErrorEvent::InternalError(_) => ChatErrorKind::Internal,
+        };
+        let mut error = Self {
+            kind,
+            cause: event.clone(),
+            message_uuid: None,
+            peer_uuid: None,
+            endpoint: None,
+            room_uuid: None,
+            source: None,
+        };
+        match event {
+            ChatAppErrorEvent::ProtocolEncode(message_uuid, _)
+            | ChatAppErrorEvent::MessageNotFound(message_uuid)
+            | ChatAppErrorEvent::CancelNotSupported(message_uuid) => {
+                error.message_uuid = Some(message_uuid)
+            }
+            ChatAppErrorEvent::PeerNotFound(peer_uuid)
+            | ChatAppErrorEvent::NoEndpoint(peer_uuid) => error.peer_uuid = Some(peer_uuid),
+            ChatAppErrorEvent::EndpointMismatch(peer_uuid, endpoint) => {
+                error.peer_uuid = Some(peer_uuid);
+                error.endpoint = Some(endpoint);
+            }
+            ChatAppErrorEvent::QueueFull(endpoint, _) => error.endpoint = Some(endpoint),
+            ChatAppErrorEvent::RoomNotFound(room_uuid) => error.room_uuid = Some(room_uuid),
+            _ => {}
+        }
+        error
+    }
+}
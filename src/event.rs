@@ -1,5 +1,21 @@
-use crate::message::ChatMessage;
-pub use socket_engine::event::{ConnectionEvent, DataEvent, ErrorEvent};
+use std::fmt;
+
+pub use crate::net::{ConnectionEvent, DataEvent, ErrorEvent};
+use crate::{
+    bundle::BundleStatusReport,
+    catch_up::CatchUpSummary,
+    config::{conflicts::ConfigConflict, validation::Diagnostic, ConfigDiff},
+    db::QuarantinedMessage,
+    dtchat::{Peer, PurgeSummary, Room},
+    link_health::LinkStatus,
+    message::{ChatMessage, RoomMessage},
+    net::Endpoint,
+    node_info::{NodeInfo, PeerNodeInfo},
+    prediction::ContactBudget,
+    profile::PeerProfile,
+    reception::FileOffer,
+    time::DTChatTime,
+};
 
 #[derive(Clone, Debug)]
 pub enum ChatAppEvent {
@@ -12,35 +28,294 @@ pub enum ChatAppEvent {
 
 #[derive(Clone, Debug)]
 pub enum ChatAppInfoEvent {
+    Started(NodeInfo),
     Sending(ChatMessage),
     Sent(ChatMessage),
+    InCustody(ChatMessage),
+    // The message is given with the status the report moved it to, if any
+    BundleStatus(ChatMessage, BundleStatusReport),
     Received(ChatMessage),
+    // Follows the Received of a message mentioning the local peer, unless its sender is muted
+    Mentioned(ChatMessage),
+    // Name of a mention several peers go by, with their uuids, none of them is mentioned
+    AmbiguousMention(ChatMessage, String, Vec<String>),
+    // Follows the Received of a blob whose content type has no registered handler
+    UnhandledBlob(ChatMessage),
+    SourceMismatch(ChatMessage),
     AckSent(ChatMessage, String),
-    AckReceived(ChatMessage),
+    // With the round trip in milliseconds when it was measured
+    AckReceived(ChatMessage, Option<i64>),
+    // Every replica of the room message was acked
+    RoomMessageDelivered(RoomMessage),
+    NackReceived(ChatMessage, String),
+    // Refused for good by the peer, with its reason
+    Refused(ChatMessage, String),
+    Failed(ChatMessage),
+    Expired(ChatMessage),
+    Cancelled(ChatMessage),
+    // Uuid of a sent message still not acked after its ack_timeout
+    AckOverdue(String),
+    // Not acked past its predicted arrival time, given with the margin in milliseconds
+    DeliveryLate(ChatMessage, DTChatTime, i64),
+    // Shown to the local user by one of the frontends
+    ReadLocally(ChatMessage),
+    // Pinned or unpinned, locally or by a participant of the room
+    PinChanged(ChatMessage),
+    // To be answered with accept_file or decline_file
+    FileOffered(FileOffer),
+    ConfigReloaded(ConfigDiff),
+    ConfigConflictResolved(ConfigConflict),
+    ConfigWarning(Diagnostic),
+    IdentitySwitched(Peer),
+    LatencyBudgetExceeded(Room),
+    // The message does not fit in the next contact with its peer
+    ContactBudgetExceeded(ChatMessage, ContactBudget),
+    // A file held in its send queue, with the bytes already sent to its endpoint in the
+    // second or contact and the budget of the queue
+    BandwidthThrottled(ChatMessage, u64, u64),
+    // The health score of the endpoint went below or back above its thresholds
+    LinkDegraded(LinkStatus),
+    LinkRecovered(LinkStatus),
+    // End of the last contact of the plan
+    ContactPlanExpiring(DTChatTime),
+    CatchUp(CatchUpSummary),
+    // Peer uuid and reason
+    CanaryFailed(String, String),
+    // Peer uuid, room uuid and the sequence numbers of the peer not received
+    PossibleLoss(String, String, Vec<u64>),
+    // Peer uuid, room uuid and number of messages added by history sync
+    HistoryBackfilled(String, String, usize),
+    // Answer to query_peer_info, with the request uuid
+    PeerInfo(String, PeerNodeInfo),
+    // Of a type this node does not know, quarantined and acked
+    UnsupportedMessageType(QuarantinedMessage),
+    // Name, color or avatar changed by the profile the peer sent, per peer uuid
+    PeerProfileUpdated(String, PeerProfile),
+    // Peer uuid and fingerprint of the key recorded on first contact
+    PeerKeyRecorded(String, String),
+    // Peer uuid and fingerprint of the key presented instead of the recorded one
+    PeerKeyChanged(String, String),
+    // Peer uuid and fingerprint of the new key, signed by the previous one
+    PeerKeyRotated(String, String),
+    // Everything exchanged with the peer was deleted
+    PeerPurged(PurgeSummary),
+}
+
+impl ChatAppInfoEvent {
+    pub fn room_uuid(&self) -> Option<&String> {
+        match self {
+            ChatAppInfoEvent::Sending(msg)
+            | ChatAppInfoEvent::Sent(msg)
+            | ChatAppInfoEvent::InCustody(msg)
+            | ChatAppInfoEvent::BundleStatus(msg, _)
+            | ChatAppInfoEvent::Received(msg)
+            | ChatAppInfoEvent::Mentioned(msg)
+            | ChatAppInfoEvent::AmbiguousMention(msg, _, _)
+            | ChatAppInfoEvent::UnhandledBlob(msg)
+            | ChatAppInfoEvent::SourceMismatch(msg)
+            | ChatAppInfoEvent::ContactBudgetExceeded(msg, _)
+            | ChatAppInfoEvent::BandwidthThrottled(msg, _, _)
+            | ChatAppInfoEvent::DeliveryLate(msg, _, _)
+            | ChatAppInfoEvent::AckSent(msg, _)
+            | ChatAppInfoEvent::AckReceived(msg, _)
+            | ChatAppInfoEvent::NackReceived(msg, _)
+            | ChatAppInfoEvent::Refused(msg, _)
+            | ChatAppInfoEvent::Failed(msg)
+            | ChatAppInfoEvent::Expired(msg)
+            | ChatAppInfoEvent::Cancelled(msg)
+            | ChatAppInfoEvent::ReadLocally(msg)
+            | ChatAppInfoEvent::PinChanged(msg) => Some(&msg.room_uuid),
+            ChatAppInfoEvent::RoomMessageDelivered(room_msg) => Some(&room_msg.room_uuid),
+            ChatAppInfoEvent::LatencyBudgetExceeded(room) => Some(&room.uuid),
+            ChatAppInfoEvent::HistoryBackfilled(_, room_uuid, _)
+            | ChatAppInfoEvent::PossibleLoss(_, room_uuid, _) => Some(room_uuid),
+            ChatAppInfoEvent::FileOffered(offer) => Some(&offer.room_uuid),
+            ChatAppInfoEvent::UnsupportedMessageType(quarantined) => Some(&quarantined.room_uuid),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub enum NetworkEvent {
-    Data(DataEvent),
+    // With the uuid of the chat message behind the token, see lookup_message_by_token
+    Data(DataEvent, Option<String>),
     Connection(ConnectionEvent),
 }
 
 #[derive(Clone, Debug)]
 pub enum NetworkErrorEvent {
-    SocketError(ErrorEvent),
+    SocketError(ErrorEvent, Option<String>),
 }
 
 #[derive(Clone, Debug)]
 pub enum ChatAppErrorEvent {
     ProtocolDecode(String),
-    ProtocolEncode(String),
+    // Uuid of the message and why it could not be encoded
+    ProtocolEncode(String, String),
     InvalidMessage(String),
     MessageNotFound(String),
     PeerNotFound(String),
+    // Unknown, or without the local peer among its participants
+    RoomNotFound(String),
     NoEngineAttached,
+    TrustViolation(String),
+    // Endpoint and the number of messages already waiting to be sent to it
+    QueueFull(Endpoint, usize),
+    // Peer uuid and the endpoint given for a send, which is not one of the peer
+    EndpointMismatch(String, Endpoint),
+    // Uuid of a peer without any endpoint to send to
+    NoEndpoint(String),
+    // Rooms of a send without any participant to send to
+    NoParticipant(Vec<String>),
+    // Inbound message dropped, the sender goes over rate_limit
+    RateLimited(String),
+    // Size and limit in bytes of content refused by send_to_peer
+    ContentTooLarge(u64, u64),
+    // Name, size and limit of a received file refused before being written
+    ReceivedFileTooLarge(String, u64, u64),
+    // Name as sent by the peer and reason, the file is not written
+    FileRejected(String, String),
+    // Message uuid, already handed to a transport that cannot withdraw it
+    CancelNotSupported(String),
     InternalError(String),
 }
 
+impl fmt::Display for ChatAppErrorEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChatAppErrorEvent::ProtocolDecode(details) => {
+                write!(f, "Failed to decode protobuf: {}", details)
+            }
+            ChatAppErrorEvent::ProtocolEncode(msg_id, details) => {
+                write!(f, "Failed to encode message {}: {}", msg_id, details)
+            }
+            ChatAppErrorEvent::InvalidMessage(details) => {
+                write!(f, "Invalid message format: {}", details)
+            }
+            ChatAppErrorEvent::MessageNotFound(msg_id) => {
+                write!(f, "Message {} not found in history", msg_id)
+            }
+            ChatAppErrorEvent::PeerNotFound(peer_id) => write!(f, "Unknown peer: {}", peer_id),
+            ChatAppErrorEvent::RoomNotFound(room_id) => write!(f, "Unknown room: {}", room_id),
+            ChatAppErrorEvent::NoEngineAttached => write!(f, "Network engine not available"),
+            ChatAppErrorEvent::InternalError(details) => write!(f, "Internal error: {}", details),
+            ChatAppErrorEvent::TrustViolation(details) => write!(f, "Untrusted peer: {}", details),
+            ChatAppErrorEvent::QueueFull(endpoint, waiting) => write!(
+                f,
+                "Send queue full: {} messages already waiting for {}",
+                waiting,
+                endpoint.to_string()
+            ),
+            ChatAppErrorEvent::EndpointMismatch(peer_id, endpoint) => write!(
+                f,
+                "Wrong endpoint: {} is not an endpoint of peer {}",
+                endpoint.to_string(),
+                peer_id
+            ),
+            ChatAppErrorEvent::NoEndpoint(peer_id) => {
+                write!(f, "Peer {} has no endpoint to send to", peer_id)
+            }
+            ChatAppErrorEvent::NoParticipant(room_ids) => {
+                write!(
+                    f,
+                    "No participant of rooms {} to send to",
+                    room_ids.join(", ")
+                )
+            }
+            ChatAppErrorEvent::RateLimited(details) => write!(f, "Rate limited: {}", details),
+            ChatAppErrorEvent::ContentTooLarge(size, limit) => {
+                write!(
+                    f,
+                    "Content of {} bytes over the {} bytes limit",
+                    size, limit
+                )
+            }
+            ChatAppErrorEvent::FileRejected(name, reason) => {
+                write!(f, "Received file {} rejected: {}", name, reason)
+            }
+            ChatAppErrorEvent::CancelNotSupported(msg_id) => write!(
+                f,
+                "Message {} is with a transport that cannot cancel it",
+                msg_id
+            ),
+            ChatAppErrorEvent::ReceivedFileTooLarge(name, size, limit) => write!(
+                f,
+                "Received file {} of {} bytes over the {} bytes limit",
+                name, size, limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ChatAppErrorEvent {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventCategory {
+    Info,
+    Messages,
+    Errors,
+    Network,
+}
+
+impl ChatAppEvent {
+    pub fn category(&self) -> EventCategory {
+        match self {
+            ChatAppEvent::Info(_) => EventCategory::Info,
+            ChatAppEvent::Message(_) => EventCategory::Messages,
+            ChatAppEvent::Error(_) => EventCategory::Errors,
+            ChatAppEvent::SocketEngineInfo(_) | ChatAppEvent::SocketEngineError(_) => {
+                EventCategory::Network
+            }
+        }
+    }
+}
+
+// Selects the events an observer is woken for, the default one lets everything through
+#[derive(Clone, Debug, Default)]
+pub struct EventFilter {
+    // Empty for every category
+    pub categories: Vec<EventCategory>,
+    // Drops the message events of the other rooms, events unrelated to a room are kept
+    pub room_uuid: Option<String>,
+}
+
+impl EventFilter {
+    pub fn only(categories: &[EventCategory]) -> Self {
+        Self {
+            categories: categories.to_vec(),
+            room_uuid: None,
+        }
+    }
+
+    pub fn for_room(mut self, room_uuid: &str) -> Self {
+        self.room_uuid = Some(room_uuid.to_string());
+        self
+    }
+
+    pub fn matches(&self, event: &ChatAppEvent) -> bool {
+        if !self.categories.is_empty() && !self.categories.contains(&event.category()) {
+            return false;
+        }
+        match (&self.room_uuid, event) {
+            (Some(room_uuid), ChatAppEvent::Message(info)) => {
+                !matches!(info.room_uuid(), Some(uuid) if uuid != room_uuid)
+            }
+            _ => true,
+        }
+    }
+}
+
+// Given when an observer is added, to remove it. Only the model makes them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ObserverId(u64);
+
+impl ObserverId {
+    pub(crate) fn new(id: u64) -> Self {
+        Self(id)
+    }
+}
+
 pub trait AppEventObserver: Send + Sync {
     fn on_event(&mut self, event: ChatAppEvent);
 }
@@ -0,0 +1,360 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    file_info::AudioInfo,
+    message::{ChatMessage, Content, Location, MessageStatus},
+    net::Endpoint,
+    time::DTChatTime,
+};
+
+#[derive(Clone, Debug, Default)]
+pub struct ImportSummary {
+    pub added: usize,
+    // Known messages for which the dump had a more advanced status
+    pub updated: usize,
+    pub unchanged: usize,
+    // Records whose endpoint or times could not be read
+    pub invalid: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+// One message of a dump, times in milliseconds since the epoch
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExportedMessage {
+    pub uuid: String,
+    pub sender_uuid: String,
+    pub room_uuid: String,
+    #[serde(default)]
+    pub also_rooms: Vec<String>,
+    pub content: String,
+    #[serde(default)]
+    pub mentions: Vec<String>,
+    pub is_file: bool,
+    // What content does not hold for the blobs, locations and voice notes, None for texts
+    // and files and in older dumps
+    #[serde(default)]
+    pub details: Option<ContentDetails>,
+    pub status: MessageStatus,
+    pub source_endpoint: String,
+    pub send_time: i64,
+    pub send_completed: Option<i64>,
+    pub predicted_arrival_time: Option<i64>,
+    pub receive_time: Option<i64>,
+    pub expires_at: Option<i64>,
+    // Actual minus predicted arrival, positive when late
+    pub prediction_error_ms: Option<i64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum ContentDetails {
+    Blob {
+        content_type: String,
+        data: Vec<u8>,
+    },
+    Location {
+        latitude: f64,
+        longitude: f64,
+        altitude: Option<f64>,
+        accuracy: Option<f64>,
+        timestamp: i64,
+    },
+    // The path is the content
+    Audio {
+        codec: String,
+        duration_ms: u64,
+    },
+}
+
+impl ContentDetails {
+    fn of(content: &Content) -> Option<Self> {
+        match content {
+            Content::Text(_) | Content::File(_) => None,
+            Content::Blob(content_type, data) => Some(Self::Blob {
+                content_type: content_type.clone(),
+                data: data.clone(),
+            }),
+            Content::Location(location) => Some(Self::Location {
+                latitude: location.latitude,
+                longitude: location.longitude,
+                altitude: location.altitude,
+                accuracy: location.accuracy,
+                timestamp: location.timestamp.timestamp_millis(),
+            }),
+            Content::Audio(_, audio) => Some(Self::Audio {
+                codec: audio.codec.clone(),
+                duration_ms: audio.duration_ms,
+            }),
+        }
+    }
+
+    // None when the time of a location cannot be read
+    fn into_content(self, content: String) -> Option<Content> {
+        Some(match self {
+            Self::Blob { content_type, data } => Content::Blob(content_type, data),
+            Self::Location {
+                latitude,
+                longitude,
+                altitude,
+                accuracy,
+                timestamp,
+            } => Content::Location(Location {
+                latitude,
+                longitude,
+                altitude,
+                accuracy,
+                timestamp: DTChatTime::from_timestamp_millis(timestamp)?,
+            }),
+            Self::Audio { codec, duration_ms } => {
+                Content::Audio(content, AudioInfo { codec, duration_ms })
+            }
+        })
+    }
+}
+
+fn millis(time: Option<DTChatTime>) -> Option<i64> {
+    time.map(|time| time.timestamp_millis())
+}
+
+impl From<&ChatMessage> for ExportedMessage {
+    fn from(msg: &ChatMessage) -> Self {
+        let prediction_error_ms = match (msg.receive_time, msg.predicted_arrival_time) {
+            (Some(actual), Some(predicted)) => {
+                Some(actual.timestamp_millis() - predicted.timestamp_millis())
+            }
+            _ => None,
+        };
+        Self {
+            uuid: msg.uuid.clone(),
+            sender_uuid: msg.sender_uuid.clone(),
+            room_uuid: msg.room_uuid.clone(),
+            also_rooms: msg.also_rooms.clone(),
+            content: msg.content_as_string(),
+            mentions: msg.mentions.clone(),
+            is_file: msg.content.file_path().is_some(),
+            details: ContentDetails::of(&msg.content),
+            status: msg.status.clone(),
+            source_endpoint: msg.source_endpoint.to_string(),
+            send_time: msg.send_time.timestamp_millis(),
+            send_completed: millis(msg.send_completed),
+            predicted_arrival_time: millis(msg.predicted_arrival_time),
+            receive_time: millis(msg.receive_time),
+            expires_at: millis(msg.expires_at),
+            prediction_error_ms,
+        }
+    }
+}
+
+impl ExportedMessage {
+    // None when the endpoint or a time cannot be parsed back
+    pub fn into_message(self) -> Option<ChatMessage> {
+        let time = |ms: Option<i64>| match ms {
+            Some(ms) => DTChatTime::from_timestamp_millis(ms).map(Some),
+            None => Some(None),
+        };
+        let content = match self.details {
+            Some(details) => details.into_content(self.content)?,
+            None if self.is_file => Content::File(self.content),
+            None => Content::Text(self.content),
+        };
+        Some(ChatMessage {
+            content,
+            send_time: DTChatTime::from_timestamp_millis(self.send_time)?,
+            send_completed: time(self.send_completed)?,
+            predicted_arrival_time: time(self.predicted_arrival_time)?,
+            receive_time: time(self.receive_time)?,
+            expires_at: time(self.expires_at)?,
+            source_endpoint: Endpoint::from_str(&self.source_endpoint).ok()?,
+            received_from: None,
+            bundle: None,
+            // Clocks are not exported, the message sorts first in causal order
+            vector_clock: HashMap::new(),
+            lamport_time: 0,
+            // Imported history is not notified
+            read_locally: true,
+            pinned: false,
+            muted: false,
+            stored_path: None,
+            prediction_skipped: None,
+            file_info: None,
+            sequence: 0,
+            uuid: self.uuid,
+            sender_uuid: self.sender_uuid,
+            room_uuid: self.room_uuid,
+            also_rooms: self.also_rooms,
+            mentions: self.mentions,
+            status: self.status,
+        })
+    }
+}
+
+const CSV_HEADER: &str = "uuid,sender_uuid,room_uuid,content,is_file,status,source_endpoint,\
+    send_time,send_completed,predicted_arrival_time,receive_time,expires_at,prediction_error_ms,\
+    details";
+
+// Spreadsheets run the cells starting with one of =+-@ as formulas, these get a leading
+// quote, as do the cells starting with a quote so that the import can take it off again
+pub(crate) fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\'']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+fn csv_unescape(value: &str) -> String {
+    value.strip_prefix('\'').unwrap_or(value).to_string()
+}
+
+fn csv_time(value: Option<i64>) -> String {
+    value.map_or(String::new(), |ms| ms.to_string())
+}
+
+// The JSON of the details, empty for texts and files
+fn csv_details(details: &Option<ContentDetails>) -> io::Result<String> {
+    match details {
+        Some(details) => Ok(csv_field(&serde_json::to_string(details)?)),
+        None => Ok(String::new()),
+    }
+}
+
+fn write_csv(out: &mut impl Write, messages: &[ExportedMessage]) -> io::Result<()> {
+    writeln!(out, "{}", CSV_HEADER)?;
+    for msg in messages {
+        writeln!(
+            out,
+            "{},{},{},{},{},{:?},{},{},{},{},{},{},{},{}",
+            csv_field(&msg.uuid),
+            csv_field(&msg.sender_uuid),
+            csv_field(&msg.room_uuid),
+            csv_field(&msg.content),
+            msg.is_file,
+            msg.status,
+            csv_field(&msg.source_endpoint),
+            msg.send_time,
+            csv_time(msg.send_completed),
+            csv_time(msg.predicted_arrival_time),
+            csv_time(msg.receive_time),
+            csv_time(msg.expires_at),
+            csv_time(msg.prediction_error_ms),
+            csv_details(&msg.details)?,
+        )?;
+    }
+    Ok(())
+}
+
+pub fn export_messages(
+    messages: &[ChatMessage],
+    format: ExportFormat,
+    path: &str,
+) -> io::Result<()> {
+    let exported: Vec<ExportedMessage> = messages.iter().map(ExportedMessage::from).collect();
+    let mut out = BufWriter::new(File::create(path)?);
+    match format {
+        ExportFormat::Json => serde_json::to_writer_pretty(&mut out, &exported)?,
+        ExportFormat::Csv => write_csv(&mut out, &exported)?,
+    }
+    out.flush()
+}
+
+// Fields of every record, quoted fields may hold commas, quotes and line breaks
+fn parse_csv(content: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => record.push(std::mem::take(&mut field)),
+            ('\r', false) => {}
+            ('\n', false) => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            _ => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+}
+
+fn invalid(reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+fn csv_record(fields: &[String]) -> io::Result<ExportedMessage> {
+    // Dumps written before the details column have 13
+    if fields.len() != 13 && fields.len() != 14 {
+        return Err(invalid(format!("expected 14 fields, got {}", fields.len())));
+    }
+    let number = |index: usize| {
+        fields[index]
+            .parse::<i64>()
+            .map_err(|_| invalid(format!("not a time: {}", fields[index])))
+    };
+    let optional = |index: usize| match fields[index].is_empty() {
+        true => Ok(None),
+        false => number(index).map(Some),
+    };
+    Ok(ExportedMessage {
+        uuid: csv_unescape(&fields[0]),
+        sender_uuid: csv_unescape(&fields[1]),
+        room_uuid: csv_unescape(&fields[2]),
+        // Not in the CSV dumps
+        also_rooms: Vec::new(),
+        content: csv_unescape(&fields[3]),
+        mentions: Vec::new(),
+        is_file: fields[4] == "true",
+        details: match fields.get(13).filter(|details| !details.is_empty()) {
+            Some(details) => Some(serde_json::from_str(details)?),
+            None => None,
+        },
+        status: serde_json::from_value(serde_json::Value::String(fields[5].clone()))
+            .map_err(|_| invalid(format!("unknown status {}", fields[5])))?,
+        source_endpoint: csv_unescape(&fields[6]),
+        send_time: number(7)?,
+        send_completed: optional(8)?,
+        predicted_arrival_time: optional(9)?,
+        receive_time: optional(10)?,
+        expires_at: optional(11)?,
+        prediction_error_ms: optional(12)?,
+    })
+}
+
+// Reads a dump written by export_messages, in either format
+pub fn import_messages(path: &str) -> io::Result<Vec<ExportedMessage>> {
+    let content = fs::read_to_string(path)?;
+    if content.trim_start().starts_with('[') {
+        return Ok(serde_json::from_str(&content)?);
+    }
+    parse_csv(&content)
+        .iter()
+        .skip(1)
+        .map(|fields| csv_record(fields.as_slice()))
+        .collect()
+}
@@ -0,0 +1,41 @@
+use std::collections::HashMap;
+
+use crate::message::ChatMessage;
+
+// Takes the received blobs of a content type, e.g. telemetry or forms, registered by the
+// application as these payloads are not described in this crate
+pub trait BlobHandler: Send + Sync {
+    // The message is stored and acked already, its content is a Content::Blob
+    fn on_blob(&mut self, msg: &ChatMessage);
+}
+
+// One handler per content type, matched exactly
+#[derive(Default)]
+pub struct ExtensionRegistry {
+    handlers: HashMap<String, Box<dyn BlobHandler>>,
+}
+
+impl ExtensionRegistry {
+    // Returns the handler replaced, if any
+    pub fn register(
+        &mut self,
+        content_type: &str,
+        handler: Box<dyn BlobHandler>,
+    ) -> Option<Box<dyn BlobHandler>> {
+        self.handlers.insert(content_type.to_string(), handler)
+    }
+
+    pub fn unregister(&mut self, content_type: &str) -> Option<Box<dyn BlobHandler>> {
+        self.handlers.remove(content_type)
+    }
+
+    pub fn handler_mut(&mut self, content_type: &str) -> Option<&mut Box<dyn BlobHandler>> {
+        self.handlers.get_mut(content_type)
+    }
+
+    pub fn content_types(&self) -> Vec<String> {
+        let mut content_types: Vec<String> = self.handlers.keys().cloned().collect();
+        content_types.sort();
+        content_types
+    }
+}
@@ -0,0 +1,171 @@
+// Every pointer given to these functions must be NULL, come from this module
+// or be a valid NUL terminated UTF-8 string. A lock poisoned by a panic of another thread
+// gives NULL rather than a panic unwinding into the caller
+#![allow(clippy::missing_safety_doc)]
+
+use std::{
+    ffi::{c_char, CStr, CString},
+    ptr,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex, Weak,
+    },
+};
+
+use socket_engine::engine::Engine;
+
+use crate::{
+    dtchat::ChatModel,
+    event::{AppEventObserver, ChatAppEvent},
+    journal::event_record,
+    message::Content,
+    net::{EngineObserver, SocketEngineEvent},
+    scheduler::start_ticker,
+};
+
+struct EventQueue {
+    events: Sender<String>,
+}
+
+impl AppEventObserver for EventQueue {
+    fn on_event(&mut self, event: ChatAppEvent) {
+        // The handle may already be freed
+        let _ = self.events.send(event_record(&event).to_string());
+    }
+}
+
+// The model owns the engine, a strong reference back would keep both alive after dtchat_free
+struct ModelObserver {
+    model: Weak<Mutex<ChatModel>>,
+}
+
+impl EngineObserver for ModelObserver {
+    fn on_engine_event(&mut self, event: SocketEngineEvent) {
+        let Some(model) = self.model.upgrade() else {
+            return;
+        };
+        // Poisoned, the calls through the handle give NULL from now on
+        if let Ok(mut model) = model.lock() {
+            model.on_engine_event(event);
+        }
+    }
+}
+
+pub struct DtchatHandle {
+    model: Arc<Mutex<ChatModel>>,
+    events: Mutex<Receiver<String>>,
+}
+
+unsafe fn to_str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    CStr::from_ptr(s).to_str().ok()
+}
+
+fn to_c_string(s: String) -> *mut c_char {
+    CString::new(s).map_or(ptr::null_mut(), CString::into_raw)
+}
+
+// Loads the configuration as the given peer and starts listening and the periodic work,
+// NULL on error, to be released with dtchat_free
+#[no_mangle]
+pub unsafe extern "C" fn dtchat_create(
+    config_file: *const c_char,
+    peer_uuid: *const c_char,
+) -> *mut DtchatHandle {
+    let (Some(config_file), Some(peer_uuid)) = (to_str(config_file), to_str(peer_uuid)) else {
+        return ptr::null_mut();
+    };
+    let Ok(model) = ChatModel::for_peer(config_file, peer_uuid) else {
+        return ptr::null_mut();
+    };
+    let model = Arc::new(Mutex::new(model));
+    let (tx, rx) = mpsc::channel();
+
+    let mut engine = Engine::new();
+    engine.add_observer(Arc::new(Mutex::new(ModelObserver {
+        model: Arc::downgrade(&model),
+    })));
+    {
+        let Ok(mut model) = model.lock() else {
+            return ptr::null_mut();
+        };
+        model.add_observer(Arc::new(Mutex::new(EventQueue { events: tx })));
+        model.start(engine);
+    }
+    start_ticker(&model);
+    Box::into_raw(Box::new(DtchatHandle {
+        model,
+        events: Mutex::new(rx),
+    }))
+}
+
+// Sent to the first endpoint of the peer, returns the message uuid or NULL,
+// to be released with dtchat_string_free
+#[no_mangle]
+pub unsafe extern "C" fn dtchat_send_text(
+    handle: *mut DtchatHandle,
+    room_uuid: *const c_char,
+    peer_uuid: *const c_char,
+    text: *const c_char,
+) -> *mut c_char {
+    let Some(handle) = handle.as_ref() else {
+        return ptr::null_mut();
+    };
+    let (Some(room_uuid), Some(peer_uuid), Some(text)) =
+        (to_str(room_uuid), to_str(peer_uuid), to_str(text))
+    else {
+        return ptr::null_mut();
+    };
+    let Ok(mut model) = handle.model.lock() else {
+        return ptr::null_mut();
+    };
+    let Some(endpoint) = model
+        .get_other_peers()
+        .get(peer_uuid)
+        .and_then(|peer| peer.endpoints.first().cloned())
+    else {
+        return ptr::null_mut();
+    };
+    match model.send_to_peer(
+        &Content::Text(text.to_string()),
+        &room_uuid.to_string(),
+        peer_uuid.to_string(),
+        &endpoint,
+        false,
+    ) {
+        Ok(uuid) => to_c_string(uuid),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+// Next event as a JSON object, NULL when there is none,
+// to be released with dtchat_string_free
+#[no_mangle]
+pub unsafe extern "C" fn dtchat_poll_event(handle: *mut DtchatHandle) -> *mut c_char {
+    let Some(handle) = handle.as_ref() else {
+        return ptr::null_mut();
+    };
+    let Ok(events) = handle.events.lock() else {
+        return ptr::null_mut();
+    };
+    match events.try_recv() {
+        Ok(event) => to_c_string(event),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dtchat_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dtchat_free(handle: *mut DtchatHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
@@ -0,0 +1,125 @@
+use std::{io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+// Larger received thumbnails are dropped
+const THUMBNAIL_MAX_BYTES: usize = 16 * 1024;
+// Thumbnails fit in a square of this side, in pixels
+#[cfg(feature = "thumbnails")]
+const THUMBNAIL_MAX_SIDE: u32 = 128;
+// Larger images are not decoded, a small file may hold a huge image
+#[cfg(feature = "thumbnails")]
+const IMAGE_MAX_SIDE: u32 = 8192;
+
+// Sent along with the data of a file, so that frontends can show it without reading it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileInfo {
+    pub mime_type: String,
+    pub size: u64,
+    // A JPEG, only made with the thumbnails feature
+    pub thumbnail: Option<Vec<u8>>,
+}
+
+#[cfg(feature = "thumbnails")]
+fn read_image(data: &[u8]) -> Option<image::ImageReader<io::Cursor<&[u8]>>> {
+    let mut reader = image::ImageReader::new(io::Cursor::new(data))
+        .with_guessed_format()
+        .ok()?;
+    let mut limits = image::Limits::default();
+    limits.max_image_width = Some(IMAGE_MAX_SIDE);
+    limits.max_image_height = Some(IMAGE_MAX_SIDE);
+    reader.limits(limits);
+    Some(reader)
+}
+
+// Downscaled to fit THUMBNAIL_MAX_SIDE, None for the images that cannot be decoded
+#[cfg(feature = "thumbnails")]
+fn make_thumbnail(data: &[u8]) -> Option<Vec<u8>> {
+    let image = read_image(data)?.decode().ok()?;
+    let thumbnail = image
+        .thumbnail(THUMBNAIL_MAX_SIDE, THUMBNAIL_MAX_SIDE)
+        .to_rgb8();
+    let mut jpeg = io::Cursor::new(Vec::new());
+    thumbnail
+        .write_to(&mut jpeg, image::ImageFormat::Jpeg)
+        .ok()?;
+    Some(jpeg.into_inner()).filter(|jpeg| jpeg.len() <= THUMBNAIL_MAX_BYTES)
+}
+
+#[cfg(feature = "thumbnails")]
+fn fits_thumbnail(thumbnail: &[u8]) -> bool {
+    read_image(thumbnail)
+        .and_then(|reader| reader.into_dimensions().ok())
+        .is_some_and(|(width, height)| width <= THUMBNAIL_MAX_SIDE && height <= THUMBNAIL_MAX_SIDE)
+}
+
+#[cfg(not(feature = "thumbnails"))]
+fn make_thumbnail(_data: &[u8]) -> Option<Vec<u8>> {
+    None
+}
+
+// Without an image decoder only the size in bytes is checked
+#[cfg(not(feature = "thumbnails"))]
+fn fits_thumbnail(_thumbnail: &[u8]) -> bool {
+    true
+}
+
+// A thumbnail given by a peer, kept only when within the sizes of the ones made here
+pub fn valid_thumbnail(thumbnail: &[u8]) -> bool {
+    !thumbnail.is_empty() && thumbnail.len() <= THUMBNAIL_MAX_BYTES && fits_thumbnail(thumbnail)
+}
+
+impl FileInfo {
+    pub fn from_data(file_name: &str, data: &[u8]) -> Self {
+        let mime_type = mime_type_of(file_name);
+        let thumbnail = mime_type
+            .starts_with("image/")
+            .then(|| make_thumbnail(data))
+            .flatten();
+        Self {
+            mime_type: mime_type.to_string(),
+            size: data.len() as u64,
+            thumbnail,
+        }
+    }
+
+    pub fn read(path: &str) -> io::Result<Self> {
+        Ok(Self::from_data(path, &std::fs::read(path)?))
+    }
+}
+
+// Of a voice note, given by the recording frontend
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AudioInfo {
+    pub codec: String,
+    pub duration_ms: u64,
+}
+
+// Guessed from the extension, ignoring case
+pub fn mime_type_of(file_name: &str) -> &'static str {
+    let extension = Path::new(file_name)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "txt" | "log" => "text/plain",
+        "md" => "text/markdown",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "opus" => "audio/opus",
+        "m4a" => "audio/mp4",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
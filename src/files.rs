@@ -0,0 +1,48 @@
+use std::{fs::File, io, path::Path};
+
+// Filesystem accesses of the stores, the logs and the received files. The native feature
+// has them, without it, e.g. in a wasm32 build, they fail with Unsupported and the model
+// keeps what it can in memory
+
+#[cfg(feature = "native")]
+pub fn read(path: &Path) -> io::Result<Vec<u8>> {
+    std::fs::read(path)
+}
+
+#[cfg(feature = "native")]
+pub fn write(path: &Path, data: &[u8]) -> io::Result<()> {
+    std::fs::write(path, data)
+}
+
+// Append-only, readable by the local user only: the logs carry the message contents
+#[cfg(feature = "native")]
+pub fn open_log(path: &Path) -> io::Result<File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+#[cfg(not(feature = "native"))]
+fn unsupported(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{}: files need the native feature", path.display()),
+    )
+}
+
+#[cfg(not(feature = "native"))]
+pub fn read(path: &Path) -> io::Result<Vec<u8>> {
+    Err(unsupported(path))
+}
+
+#[cfg(not(feature = "native"))]
+pub fn write(path: &Path, _data: &[u8]) -> io::Result<()> {
+    Err(unsupported(path))
+}
+
+#[cfg(not(feature = "native"))]
+pub fn open_log(path: &Path) -> io::Result<File> {
+    Err(unsupported(path))
+}
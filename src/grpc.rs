@@ -0,0 +1,210 @@
+use std::{borrow::Cow, net::SocketAddr, pin::Pin, sync::Arc};
+
+use tokio_stream::{Stream, StreamExt};
+use tonic::{transport::Server, Request, Response, Status};
+
+use crate::{
+    async_model::AsyncChatModel,
+    error::{ChatError, ChatErrorKind},
+    event::{ChatAppEvent, EventCategory, EventFilter},
+    message::{ChatMessage, Content},
+    scheduler::start_ticker,
+    time::DTChatTime,
+};
+
+pub mod control {
+    tonic::include_proto!("control");
+}
+
+use control::{
+    chat_control_server::{ChatControl, ChatControlServer},
+    Event, HistoryMessage, HistoryReply, HistoryRequest, ListRequest, PeerInfo, PeerList, RoomInfo,
+    RoomList, SendReply, SendRequest, StreamRequest,
+};
+
+fn parse_category(name: &str) -> Result<EventCategory, Status> {
+    match name {
+        "Info" => Ok(EventCategory::Info),
+        "Messages" => Ok(EventCategory::Messages),
+        "Errors" => Ok(EventCategory::Errors),
+        "Network" => Ok(EventCategory::Network),
+        _ => Err(Status::invalid_argument(format!(
+            "unknown category {}",
+            name
+        ))),
+    }
+}
+
+fn send_error(error: ChatError) -> Status {
+    match error.kind {
+        ChatErrorKind::PeerNotFound | ChatErrorKind::RoomNotFound => {
+            Status::not_found(error.to_string())
+        }
+        ChatErrorKind::QueueFull => Status::resource_exhausted(error.to_string()),
+        _ => Status::failed_precondition(error.to_string()),
+    }
+}
+
+fn history_message(msg: ChatMessage) -> HistoryMessage {
+    HistoryMessage {
+        uuid: msg.uuid.clone(),
+        sender_uuid: msg.sender_uuid.clone(),
+        room_uuid: msg.room_uuid.clone(),
+        content: msg.content_as_string(),
+        is_file: msg.content.file_path().is_some(),
+        send_time: msg.send_time.timestamp_millis(),
+        receive_time: msg.receive_time.map_or(0, |time| time.timestamp_millis()),
+        status: format!("{:?}", msg.status),
+    }
+}
+
+pub struct ControlService {
+    model: Arc<AsyncChatModel>,
+}
+
+impl ControlService {
+    pub fn new(model: Arc<AsyncChatModel>) -> Self {
+        Self { model }
+    }
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<Event, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl ChatControl for ControlService {
+    async fn send_message(
+        &self,
+        request: Request<SendRequest>,
+    ) -> Result<Response<SendReply>, Status> {
+        let request = request.into_inner();
+        let content = Content::Text(request.text);
+        let model = self.model.model();
+        let mut model = model.lock().unwrap();
+        let message_uuids = if request.peer_uuid.is_empty() {
+            model
+                .send_to_room(&content, &request.room_uuid, request.try_prediction)
+                .map_err(send_error)?
+                .room_msg
+                .messages
+        } else {
+            let endpoint = model
+                .get_other_peers()
+                .get(&request.peer_uuid)
+                .and_then(|peer| peer.endpoints.first().cloned())
+                .ok_or_else(|| Status::not_found(format!("no peer {}", request.peer_uuid)))?;
+            let uuid = model
+                .send_to_peer(
+                    &content,
+                    &request.room_uuid,
+                    request.peer_uuid,
+                    &endpoint,
+                    request.try_prediction,
+                )
+                .map_err(send_error)?;
+            vec![uuid]
+        };
+        Ok(Response::new(SendReply { message_uuids }))
+    }
+
+    async fn list_peers(&self, _: Request<ListRequest>) -> Result<Response<PeerList>, Status> {
+        let model = self.model.model();
+        let model = model.lock().unwrap();
+        let peers = model
+            .get_other_peers()
+            .into_values()
+            .chain(std::iter::once(model.get_localpeer()))
+            .map(|peer| PeerInfo {
+                uuid: peer.uuid,
+                name: peer.name,
+                endpoints: peer.endpoints.iter().map(|e| e.to_string()).collect(),
+            })
+            .collect();
+        Ok(Response::new(PeerList { peers }))
+    }
+
+    async fn list_rooms(&self, _: Request<ListRequest>) -> Result<Response<RoomList>, Status> {
+        let model = self.model.model();
+        let rooms = model
+            .lock()
+            .unwrap()
+            .get_rooms()
+            .into_values()
+            .map(|room| RoomInfo {
+                uuid: room.uuid,
+                name: room.name,
+                participants: room
+                    .participants
+                    .into_iter()
+                    .map(|(peer_uuid, _)| peer_uuid)
+                    .collect(),
+            })
+            .collect();
+        Ok(Response::new(RoomList { rooms }))
+    }
+
+    async fn get_history(
+        &self,
+        request: Request<HistoryRequest>,
+    ) -> Result<Response<HistoryReply>, Status> {
+        let request = request.into_inner();
+        let model = self.model.model();
+        let mut messages: Vec<ChatMessage> = model
+            .lock()
+            .unwrap()
+            .iter_messages()
+            .filter(|msg| request.room_uuid.is_empty() || msg.in_room(&request.room_uuid))
+            .map(Cow::into_owned)
+            .collect();
+        // The most recent ones
+        if request.limit > 0 {
+            let skipped = messages.len().saturating_sub(request.limit as usize);
+            messages.drain(..skipped);
+        }
+        Ok(Response::new(HistoryReply {
+            messages: messages.into_iter().map(history_message).collect(),
+        }))
+    }
+
+    type StreamEventsStream = EventStream;
+
+    async fn stream_events(
+        &self,
+        request: Request<StreamRequest>,
+    ) -> Result<Response<EventStream>, Status> {
+        let request = request.into_inner();
+        let categories = request
+            .categories
+            .iter()
+            .map(|name| parse_category(name))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut filter = EventFilter::only(&categories);
+        if !request.room_uuid.is_empty() {
+            filter = filter.for_room(&request.room_uuid);
+        }
+        let events = self
+            .model
+            .events()
+            .filter(move |event| filter.matches(event))
+            .map(|event: ChatAppEvent| {
+                Ok(Event {
+                    time: DTChatTime::now().timestamp_millis(),
+                    category: format!("{:?}", event.category()),
+                    description: format!("{:?}", event),
+                })
+            });
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+// Runs until the server fails, on the runtime of the caller
+// Also runs the periodic work of the model, the caller must not tick it
+pub async fn serve(
+    model: Arc<AsyncChatModel>,
+    address: SocketAddr,
+) -> Result<(), tonic::transport::Error> {
+    start_ticker(&model.model());
+    Server::builder()
+        .add_service(ChatControlServer::new(ControlService::new(model)))
+        .serve(address)
+        .await
+}
@@ -0,0 +1,376 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    dtchat::{generate_uuid, ChatModel},
+    event::{AppEventObserver, ChatAppEvent},
+    journal::event_record,
+    message::{ChatMessage, Content},
+};
+
+// Larger bodies are refused before being read
+const MAX_BODY_BYTES: usize = 64 * 1024;
+// The request line and the headers together
+const MAX_HEAD_BYTES: u64 = 8 * 1024;
+// Connections handled at once, event streams included, the others get a 503
+const MAX_WORKERS: usize = 16;
+// A client that connects and sends nothing must not hold a worker
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+// Header carrying the token given by GET /csrf, required by every POST
+const CSRF_HEADER: &str = "x-csrf-token";
+
+// Forwards every event to the open SSE streams, closed streams are dropped on the next event
+#[derive(Default)]
+pub(crate) struct EventHub {
+    streams: Vec<Sender<String>>,
+}
+
+impl EventHub {
+    pub(crate) fn subscribe(&mut self) -> Receiver<String> {
+        let (tx, rx) = mpsc::channel();
+        self.streams.push(tx);
+        rx
+    }
+}
+
+impl AppEventObserver for EventHub {
+    fn on_event(&mut self, event: ChatAppEvent) {
+        let record = event_record(&event).to_string();
+        self.streams.retain(|tx| tx.send(record.clone()).is_ok());
+    }
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    query: HashMap<String, String>,
+    // Lowercase names
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+impl HttpRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(|value| value.as_str())
+    }
+}
+
+// InvalidData for the requests too large to be read
+fn read_request(stream: &TcpStream) -> io::Result<HttpRequest> {
+    let mut reader = BufReader::new(stream);
+    let mut head = (&mut reader).take(MAX_HEAD_BYTES);
+    let mut request_line = String::new();
+    head.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut header = String::new();
+        if head.read_line(&mut header)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "headers too large or truncated",
+            ));
+        }
+        if header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+    let content_length: usize = headers
+        .get("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    if content_length > MAX_BODY_BYTES {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "body too large"));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    // No percent-decoding, uuids do not need it
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    Ok(HttpRequest {
+        method,
+        path: path.to_string(),
+        query,
+        headers,
+        body,
+    })
+}
+
+fn respond(stream: &mut TcpStream, status: &str, body: Value) {
+    let body = body.to_string();
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+}
+
+fn message_json(msg: &ChatMessage) -> Value {
+    json!({
+        "uuid": msg.uuid,
+        "sender_uuid": msg.sender_uuid,
+        "room_uuid": msg.room_uuid,
+        "content": msg.content_as_string(),
+        "is_file": msg.content.file_path().is_some(),
+        "send_time": msg.send_time.timestamp_millis(),
+        "receive_time": msg.receive_time.map(|time| time.timestamp_millis()),
+        "status": format!("{:?}", msg.status),
+    })
+}
+
+pub(crate) fn peers(model: &ChatModel) -> Value {
+    let mut peers: Vec<Value> = model
+        .get_other_peers()
+        .into_values()
+        .chain(std::iter::once(model.get_localpeer()))
+        .map(|peer| {
+            json!({
+                "uuid": peer.uuid,
+                "name": peer.name,
+                "endpoints": peer.endpoints.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+    peers.sort_by_key(|peer| peer["uuid"].to_string());
+    Value::Array(peers)
+}
+
+pub(crate) fn rooms(model: &ChatModel) -> Value {
+    let mut rooms: Vec<Value> = model
+        .get_rooms()
+        .into_values()
+        .map(|room| {
+            json!({
+                "uuid": room.uuid,
+                "name": room.name,
+                "participants": room.participants.iter().map(|(uuid, _)| uuid).collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+    rooms.sort_by_key(|room| room["uuid"].to_string());
+    Value::Array(rooms)
+}
+
+// The most recent messages last
+pub(crate) fn messages(model: &ChatModel, room: Option<&String>, limit: Option<usize>) -> Value {
+    let mut messages: Vec<ChatMessage> = model
+        .iter_messages()
+        .filter(|msg| match room {
+            Some(room) => msg.in_room(room),
+            None => true,
+        })
+        .map(Cow::into_owned)
+        .collect();
+    if let Some(limit) = limit {
+        let skipped = messages.len().saturating_sub(limit);
+        messages.drain(..skipped);
+    }
+    Value::Array(messages.iter().map(message_json).collect())
+}
+
+// To the whole room when peer_uuid is missing
+#[derive(Deserialize)]
+pub(crate) struct SendBody {
+    room_uuid: String,
+    peer_uuid: Option<String>,
+    text: String,
+    #[serde(default)]
+    try_prediction: bool,
+}
+
+pub(crate) fn send(model: &mut ChatModel, body: SendBody) -> Result<Vec<String>, String> {
+    let content = Content::Text(body.text);
+    match body.peer_uuid {
+        None => model
+            .send_to_room(&content, &body.room_uuid, body.try_prediction)
+            .map(|report| report.room_msg.messages)
+            .map_err(|err| err.to_string()),
+        Some(peer_uuid) => {
+            let endpoint = model
+                .get_other_peers()
+                .get(&peer_uuid)
+                .and_then(|peer| peer.endpoints.first().cloned())
+                .ok_or_else(|| format!("no peer {}", peer_uuid))?;
+            model
+                .send_to_peer(
+                    &content,
+                    &body.room_uuid,
+                    peer_uuid,
+                    &endpoint,
+                    body.try_prediction,
+                )
+                .map(|uuid| vec![uuid])
+                .map_err(|err| err.to_string())
+        }
+    }
+}
+
+// Holds the connection until the client goes away
+fn stream_events(mut stream: TcpStream, hub: &Arc<Mutex<EventHub>>) {
+    let events = hub.lock().unwrap().subscribe();
+    let headers = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+                   Cache-Control: no-cache\r\n\r\n";
+    if stream.write_all(headers.as_bytes()).is_err() {
+        return;
+    }
+    for record in events {
+        if write!(stream, "data: {}\n\n", record).is_err() {
+            return;
+        }
+    }
+}
+
+// A cross-site form cannot set the content type to JSON nor add the token header, and
+// without CORS headers no other origin can read the token
+fn check_post(request: &HttpRequest, csrf_token: &str) -> Result<(), (&'static str, Value)> {
+    let is_json = request
+        .header("content-type")
+        .and_then(|content_type| content_type.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"));
+    if !is_json {
+        return Err((
+            "415 Unsupported Media Type",
+            json!({ "error": "expected application/json" }),
+        ));
+    }
+    if request.header(CSRF_HEADER) != Some(csrf_token) {
+        return Err((
+            "403 Forbidden",
+            json!({ "error": "missing or wrong CSRF token" }),
+        ));
+    }
+    Ok(())
+}
+
+fn handle(
+    mut stream: TcpStream,
+    model: &Arc<Mutex<ChatModel>>,
+    hub: &Arc<Mutex<EventHub>>,
+    csrf_token: &str,
+) {
+    if stream.set_read_timeout(Some(READ_TIMEOUT)).is_err() {
+        return;
+    }
+    let request = match read_request(&stream) {
+        Ok(request) => request,
+        Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+            return respond(
+                &mut stream,
+                "413 Payload Too Large",
+                json!({ "error": err.to_string() }),
+            );
+        }
+        Err(_) => return,
+    };
+    if request.method == "POST" {
+        if let Err((status, body)) = check_post(&request, csrf_token) {
+            return respond(&mut stream, status, body);
+        }
+    }
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/csrf") => respond(&mut stream, "200 OK", json!({ "token": csrf_token })),
+        ("GET", "/events") => stream_events(stream, hub),
+        ("GET", "/peers") => respond(&mut stream, "200 OK", peers(&model.lock().unwrap())),
+        ("GET", "/rooms") => respond(&mut stream, "200 OK", rooms(&model.lock().unwrap())),
+        // ?room=<uuid>&limit=<n>
+        ("GET", "/messages") => {
+            let limit = request
+                .query
+                .get("limit")
+                .and_then(|limit| limit.parse().ok());
+            let messages = messages(&model.lock().unwrap(), request.query.get("room"), limit);
+            respond(&mut stream, "200 OK", messages)
+        }
+        ("POST", "/messages") => match serde_json::from_slice::<SendBody>(&request.body) {
+            Ok(body) => match send(&mut model.lock().unwrap(), body) {
+                Ok(uuids) => respond(&mut stream, "200 OK", json!({ "message_uuids": uuids })),
+                Err(error) => respond(&mut stream, "409 Conflict", json!({ "error": error })),
+            },
+            Err(err) => respond(
+                &mut stream,
+                "400 Bad Request",
+                json!({ "error": err.to_string() }),
+            ),
+        },
+        _ => respond(
+            &mut stream,
+            "404 Not Found",
+            json!({ "error": "not found" }),
+        ),
+    }
+}
+
+// Frees its worker slot when the connection is done
+struct Worker(Arc<AtomicUsize>);
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// REST endpoints and an SSE event stream for web dashboards served from the same origin,
+// None when no http_address is configured. The CSRF token changes on every start
+pub fn start_gateway(model: Arc<Mutex<ChatModel>>) -> Option<io::Result<JoinHandle<()>>> {
+    let address = model.lock().unwrap().http_address()?;
+    let listener = match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(err) => return Some(Err(err)),
+    };
+    let hub = Arc::new(Mutex::new(EventHub::default()));
+    model.lock().unwrap().add_observer(hub.clone());
+    let csrf_token: Arc<str> = generate_uuid().into();
+    let workers = Arc::new(AtomicUsize::new(0));
+    Some(Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            if workers.fetch_add(1, Ordering::SeqCst) >= MAX_WORKERS {
+                workers.fetch_sub(1, Ordering::SeqCst);
+                respond(
+                    &mut stream,
+                    "503 Service Unavailable",
+                    json!({ "error": "too many connections" }),
+                );
+                continue;
+            }
+            let worker = Worker(workers.clone());
+            let model = model.clone();
+            let hub = hub.clone();
+            let csrf_token = csrf_token.clone();
+            thread::spawn(move || {
+                let _worker = worker;
+                handle(stream, &model, &hub, &csrf_token)
+            });
+        }
+    })))
+}
@@ -0,0 +1,126 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    dtchat::ChatModel,
+    http_gateway::{messages, peers, rooms, send, EventHub, SendBody},
+};
+
+// Longer requests close the connection, a send carries at most a text or a file path
+const MAX_REQUEST_BYTES: u64 = 64 * 1024;
+
+// One JSON object per line, e.g. {"op": "messages", "room": "<uuid>", "limit": 20},
+// each answered by one JSON line
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Request {
+    Send(SendBody),
+    Peers,
+    Rooms,
+    Messages {
+        room: Option<String>,
+        limit: Option<usize>,
+    },
+    // The connection then only carries event records, one per line
+    Subscribe,
+}
+
+// Holds the connection until the client goes away
+fn stream_events(mut stream: UnixStream, hub: &Arc<Mutex<EventHub>>) {
+    let events = hub.lock().unwrap().subscribe();
+    for record in events {
+        if writeln!(stream, "{}", record).is_err() {
+            return;
+        }
+    }
+}
+
+fn handle(stream: UnixStream, model: &Arc<Mutex<ChatModel>>, hub: &Arc<Mutex<EventHub>>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut line = String::new();
+        match (&mut reader)
+            .take(MAX_REQUEST_BYTES + 1)
+            .read_line(&mut line)
+        {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+        if line.len() as u64 > MAX_REQUEST_BYTES {
+            let error = format!("request over {} bytes", MAX_REQUEST_BYTES);
+            let _ = writeln!(writer, "{}", json!({ "error": error }));
+            return;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(Request::Subscribe) => return stream_events(writer, hub),
+            Ok(Request::Send(body)) => match send(&mut model.lock().unwrap(), body) {
+                Ok(uuids) => json!({ "message_uuids": uuids }),
+                Err(error) => json!({ "error": error }),
+            },
+            Ok(Request::Peers) => peers(&model.lock().unwrap()),
+            Ok(Request::Rooms) => rooms(&model.lock().unwrap()),
+            Ok(Request::Messages { room, limit }) => {
+                messages(&model.lock().unwrap(), room.as_ref(), limit)
+            }
+            Err(err) => json!({ "error": err.to_string() }),
+        };
+        if writeln!(writer, "{}", response).is_err() {
+            return;
+        }
+    }
+}
+
+fn remove_socket(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+// Control socket of the daemon mode, any number of clients of the local user can attach
+// to it. A socket left at path by a previous run is replaced, any other file is not
+pub fn start_ipc(model: Arc<Mutex<ChatModel>>, path: &Path) -> io::Result<JoinHandle<()>> {
+    remove_socket(path)?;
+    // Bound aside and only moved to path once restricted to its owner
+    let mut bound = PathBuf::from(path).into_os_string();
+    bound.push(format!(".{}", std::process::id()));
+    let bound = PathBuf::from(bound);
+    remove_socket(&bound)?;
+    let listener = UnixListener::bind(&bound)?;
+    let restricted = fs::set_permissions(&bound, fs::Permissions::from_mode(0o600))
+        .and_then(|_| fs::rename(&bound, path));
+    if let Err(err) = restricted {
+        let _ = fs::remove_file(&bound);
+        return Err(err);
+    }
+    let hub = Arc::new(Mutex::new(EventHub::default()));
+    model.lock().unwrap().add_observer(hub.clone());
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let model = model.clone();
+            let hub = hub.clone();
+            thread::spawn(move || handle(stream, &model, &hub));
+        }
+    }))
+}
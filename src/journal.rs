@@ -0,0 +1,90 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    db::encryption::{open_line, seal_line, Cipher},
+    event::{AppEventObserver, ChatAppEvent},
+    files::open_log,
+    time::DTChatTime,
+};
+
+// Also the shape of the events streamed by the HTTP gateway
+pub(crate) fn event_record(event: &ChatAppEvent) -> Value {
+    json!({
+        "time": DTChatTime::now().timestamp_millis(),
+        "category": format!("{:?}", event.category()),
+        "event": format!("{:?}", event),
+    })
+}
+
+// Rewrites an append-only log without the lines drop matches, through a temporary file
+// renamed over it. The lines that cannot be read are kept as they are. Returns the file to
+// append to and the number of lines dropped
+pub(crate) fn rewrite_log(
+    path: &Path,
+    cipher: Option<&Cipher>,
+    drop: impl Fn(&str) -> bool,
+) -> io::Result<(File, usize)> {
+    let mut kept = String::new();
+    let mut dropped = 0;
+    for line in fs::read_to_string(path)?.lines() {
+        if open_line(cipher, line).is_ok_and(|plain| drop(&plain)) {
+            dropped += 1;
+            continue;
+        }
+        kept.push_str(line);
+        kept.push('\n');
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, kept)?;
+    // Keeps the permissions of the log, a log readable by its owner only stays so
+    fs::set_permissions(&tmp, fs::metadata(path)?.permissions())?;
+    fs::rename(&tmp, path)?;
+    let file = open_log(path)?;
+    Ok((file, dropped))
+}
+
+// Append-only JSON lines record of every event, for audits after unattended contacts. The
+// events carry the message contents, each line is encrypted with a cipher
+pub struct EventJournal {
+    file: File,
+    path: PathBuf,
+    cipher: Option<Arc<Cipher>>,
+}
+
+impl EventJournal {
+    pub fn open(path: &str, cipher: Option<Arc<Cipher>>) -> io::Result<Self> {
+        let file = open_log(Path::new(path))?;
+        Ok(Self {
+            file,
+            path: PathBuf::from(path),
+            cipher,
+        })
+    }
+
+    // Drops the events naming the peer or one of its endpoints, returns how many
+    pub fn forget_peer(&mut self, peer_uuid: &str, endpoints: &[String]) -> io::Result<usize> {
+        let (file, dropped) = rewrite_log(&self.path, self.cipher.as_deref(), |line| {
+            line.contains(peer_uuid) || endpoints.iter().any(|endpoint| line.contains(endpoint))
+        })?;
+        self.file = file;
+        Ok(dropped)
+    }
+}
+
+impl AppEventObserver for EventJournal {
+    fn on_event(&mut self, event: ChatAppEvent) {
+        let Ok(line) = seal_line(self.cipher.as_deref(), event_record(&event).to_string()) else {
+            return;
+        };
+        // Unbuffered so nothing is lost if the node goes down,
+        // a failed write must not stop the chat
+        let _ = writeln!(self.file, "{}", line);
+    }
+}
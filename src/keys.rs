@@ -0,0 +1,223 @@
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey, SECRET_KEY_LENGTH};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::db::migration::{read_store, write_store, Store};
+
+// First bytes of the SHA-256 of a public key, to be compared out of band
+pub fn fingerprint(key: &[u8]) -> String {
+    Sha256::digest(key)
+        .iter()
+        .take(8)
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+const CHALLENGE_LENGTH: usize = 32;
+
+// Random bytes sent in a WhoAreYou, the IAm answering it signs them
+pub fn new_challenge() -> Vec<u8> {
+    let mut challenge = vec![0; CHALLENGE_LENGTH];
+    OsRng.fill_bytes(&mut challenge);
+    challenge
+}
+
+// Along with the uuid of the signer, another peer cannot present the signature as its own
+fn challenge_payload(challenge: &[u8], signer_uuid: &str) -> Vec<u8> {
+    let mut payload = challenge.to_vec();
+    payload.extend_from_slice(signer_uuid.as_bytes());
+    payload
+}
+
+fn verifying_key(key: &[u8]) -> Option<VerifyingKey> {
+    let bytes = <[u8; 32]>::try_from(key).ok()?;
+    VerifyingKey::from_bytes(&bytes).ok()
+}
+
+// False for a malformed key or signature too
+pub fn verify_challenge(key: &[u8], challenge: &[u8], signer_uuid: &str, signature: &[u8]) -> bool {
+    let (Some(key), Ok(signature)) = (verifying_key(key), Signature::from_slice(signature)) else {
+        return false;
+    };
+    key.verify(&challenge_payload(challenge, signer_uuid), &signature)
+        .is_ok()
+}
+
+// Only readable by its owner. Written to a temporary file renamed over the previous one, a
+// crash leaves either seed whole
+fn write_seed(path: &Path, signing: &SigningKey) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    // The mode is only given to a file being created
+    let _ = fs::remove_file(&tmp);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&tmp)?;
+    file.write_all(&signing.to_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+// Signing key of the local peer, its seed is kept in a file
+pub struct LocalKey {
+    signing: SigningKey,
+    path: PathBuf,
+}
+
+impl LocalKey {
+    // Created and written when there is no file yet
+    pub fn open(path: &str) -> io::Result<Self> {
+        let path = PathBuf::from(path);
+        let signing = match fs::read(&path) {
+            Ok(seed) => {
+                let seed: [u8; SECRET_KEY_LENGTH] = seed.try_into().map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, "not an ed25519 seed")
+                })?;
+                SigningKey::from_bytes(&seed)
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let signing = SigningKey::generate(&mut OsRng);
+                write_seed(&path, &signing)?;
+                signing
+            }
+            Err(err) => return Err(err),
+        };
+        Ok(Self { signing, path })
+    }
+
+    pub fn public_key(&self) -> Vec<u8> {
+        self.signing.verifying_key().to_bytes().to_vec()
+    }
+
+    // Answer to the challenge of a WhoAreYou, signed as the local peer
+    pub fn sign_challenge(&self, challenge: &[u8], signer_uuid: &str) -> Vec<u8> {
+        self.signing
+            .sign(&challenge_payload(challenge, signer_uuid))
+            .to_bytes()
+            .to_vec()
+    }
+
+    // Replaces the key, returns the new public key signed by the previous one
+    pub fn rotate(&mut self) -> io::Result<(Vec<u8>, Vec<u8>)> {
+        let next = SigningKey::generate(&mut OsRng);
+        let public_key = next.verifying_key().to_bytes().to_vec();
+        let signature = self.signing.sign(&public_key).to_bytes().to_vec();
+        write_seed(&self.path, &next)?;
+        self.signing = next;
+        Ok((public_key, signature))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PeerKey {
+    pub key: Vec<u8>,
+    pub first_seen_ms: i64,
+    pub rotated_at_ms: Option<i64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyCheck {
+    // Recorded as the key of the peer
+    FirstUse,
+    Known,
+    // Differs from the recorded one, which is kept until trusted again. The peer is not
+    // trusted meanwhile
+    Changed,
+}
+
+// Public keys of the peers, pinned on first use
+#[derive(Default)]
+pub struct KeyStore {
+    // Per peer uuid
+    keys: HashMap<String, PeerKey>,
+    // Only kept in memory without it
+    path: Option<PathBuf>,
+}
+
+impl KeyStore {
+    // The keys are restored from the file, if any, and written back on every change
+    pub fn open(path: &str) -> io::Result<Self> {
+        let keys = read_store(Store::PeerKeys, Path::new(path), None)?.unwrap_or_default();
+        Ok(Self {
+            keys,
+            path: Some(PathBuf::from(path)),
+        })
+    }
+
+    fn write(&self) -> io::Result<()> {
+        match &self.path {
+            Some(path) => write_store(path, &self.keys, None),
+            None => Ok(()),
+        }
+    }
+
+    pub fn get(&self, peer_uuid: &str) -> Option<&PeerKey> {
+        self.keys.get(peer_uuid)
+    }
+
+    pub fn check(&mut self, peer_uuid: &str, key: &[u8], now_ms: i64) -> io::Result<KeyCheck> {
+        match self.keys.get(peer_uuid) {
+            Some(known) if known.key == key => Ok(KeyCheck::Known),
+            Some(_) => Ok(KeyCheck::Changed),
+            None => {
+                self.trust(peer_uuid, key.to_vec(), now_ms)?;
+                Ok(KeyCheck::FirstUse)
+            }
+        }
+    }
+
+    // Replaces the recorded key, once checked out of band
+    pub fn trust(&mut self, peer_uuid: &str, key: Vec<u8>, now_ms: i64) -> io::Result<()> {
+        self.keys.insert(
+            peer_uuid.to_string(),
+            PeerKey {
+                key,
+                first_seen_ms: now_ms,
+                rotated_at_ms: None,
+            },
+        );
+        self.write()
+    }
+
+    // False when no key was recorded for the peer
+    pub fn remove(&mut self, peer_uuid: &str) -> io::Result<bool> {
+        if self.keys.remove(peer_uuid).is_none() {
+            return Ok(false);
+        }
+        self.write()?;
+        Ok(true)
+    }
+
+    // The new key must be signed by the recorded one
+    pub fn rotate(
+        &mut self,
+        peer_uuid: &str,
+        new_key: &[u8],
+        signature: &[u8],
+        now_ms: i64,
+    ) -> Result<(), String> {
+        let known = self
+            .keys
+            .get_mut(peer_uuid)
+            .ok_or_else(|| "no key recorded for the peer".to_string())?;
+        let current = verifying_key(&known.key)
+            .ok_or_else(|| "the recorded key is not an ed25519 key".to_string())?;
+        let signature =
+            Signature::from_slice(signature).map_err(|_| "malformed signature".to_string())?;
+        current
+            .verify(new_key, &signature)
+            .map_err(|_| "not signed by the recorded key".to_string())?;
+        known.key = new_key.to_vec();
+        known.rotated_at_ms = Some(now_ms);
+        self.write().map_err(|err| err.to_string())
+    }
+}
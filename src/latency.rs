@@ -0,0 +1,93 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::{net::EndpointProto, stats::percentile};
+
+// Round trips kept per peer and protocol, the oldest are dropped first
+const MAX_SAMPLES: usize = 256;
+
+// ACK round trips, from the local send to the local reception of the ack
+#[derive(Clone, Debug, Default)]
+pub struct LatencyStats {
+    pub count: usize,
+    pub min_ms: Option<i64>,
+    pub mean_ms: Option<i64>,
+    pub p95_ms: Option<i64>,
+    pub last_ms: Option<i64>,
+}
+
+impl LatencyStats {
+    // Oldest sample first
+    fn new(samples: &[i64]) -> Self {
+        let mut sorted = samples.to_vec();
+        sorted.sort_unstable();
+        let mean_ms = match sorted.len() {
+            0 => None,
+            len => Some(sorted.iter().sum::<i64>() / len as i64),
+        };
+        Self {
+            count: sorted.len(),
+            min_ms: sorted.first().copied(),
+            mean_ms,
+            p95_ms: percentile(&sorted, 95),
+            last_ms: samples.last().copied(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PeerLatencyStats {
+    pub peer_uuid: String,
+    pub overall: LatencyStats,
+    // Per protocol name, e.g. "tcp"
+    pub per_protocol: HashMap<String, LatencyStats>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LatencyTracker {
+    // Per peer uuid and protocol name
+    samples: HashMap<(String, String), VecDeque<i64>>,
+    // Most recent round trip per peer uuid, whatever the protocol
+    last: HashMap<String, i64>,
+}
+
+impl LatencyTracker {
+    pub fn record(&mut self, peer_uuid: &str, proto: &EndpointProto, rtt_ms: i64) {
+        let proto = format!("{:?}", proto).to_lowercase();
+        let samples = self
+            .samples
+            .entry((peer_uuid.to_string(), proto))
+            .or_default();
+        if samples.len() == MAX_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(rtt_ms);
+        self.last.insert(peer_uuid.to_string(), rtt_ms);
+    }
+
+    pub fn forget(&mut self, peer_uuid: &str) {
+        self.samples.retain(|(peer, _), _| peer != peer_uuid);
+        self.last.remove(peer_uuid);
+    }
+
+    // None until an ack of the peer was received
+    pub fn stats(&self, peer_uuid: &str) -> Option<PeerLatencyStats> {
+        let last_ms = *self.last.get(peer_uuid)?;
+        let mut all = Vec::new();
+        let mut per_protocol = HashMap::new();
+        for ((peer, proto), samples) in &self.samples {
+            if peer != peer_uuid {
+                continue;
+            }
+            let samples: Vec<i64> = samples.iter().copied().collect();
+            all.extend_from_slice(&samples);
+            per_protocol.insert(proto.clone(), LatencyStats::new(&samples));
+        }
+        let mut overall = LatencyStats::new(&all);
+        overall.last_ms = Some(last_ms);
+        Some(PeerLatencyStats {
+            peer_uuid: peer_uuid.to_string(),
+            overall,
+            per_protocol,
+        })
+    }
+}
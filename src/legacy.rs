@@ -0,0 +1,59 @@
+use prost::Message;
+
+use crate::proto::{proto_message::MsgType, AckMessage, ProtoMessage, TextMessage};
+
+// Converts frames of the previous dtchat prototype, PrototypeDecoder unless the application
+// registers another one
+pub trait LegacyDecoder: Send + Sync {
+    fn decode(&self, frame: &[u8]) -> Option<ProtoMessage>;
+}
+
+// Layout of the first protobuf messages of the prototype: no source endpoint, the text at
+// field 5 and the ack at field 6, where the source endpoint and the text are now
+#[derive(Clone, PartialEq, Message)]
+struct PrototypeMessage {
+    #[prost(string, tag = "1")]
+    uuid: String,
+    #[prost(string, tag = "2")]
+    sender_uuid: String,
+    #[prost(int64, tag = "3")]
+    timestamp: i64,
+    #[prost(string, tag = "4")]
+    room_uuid: String,
+    #[prost(oneof = "PrototypeType", tags = "5, 6")]
+    msg_type: Option<PrototypeType>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+enum PrototypeType {
+    #[prost(message, tag = "5")]
+    Text(TextMessage),
+    #[prost(message, tag = "6")]
+    Ack(AckMessage),
+}
+
+// Texts and acks of the prototype. It gave no source endpoint, the model fills it with
+// the transport level sender
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PrototypeDecoder;
+
+impl LegacyDecoder for PrototypeDecoder {
+    fn decode(&self, frame: &[u8]) -> Option<ProtoMessage> {
+        let legacy = PrototypeMessage::decode(frame).ok()?;
+        let msg_type = match legacy.msg_type? {
+            PrototypeType::Text(text) => MsgType::Text(text),
+            PrototypeType::Ack(ack) => MsgType::Ack(ack),
+        };
+        if legacy.uuid.is_empty() || legacy.sender_uuid.is_empty() {
+            return None;
+        }
+        Some(ProtoMessage {
+            uuid: legacy.uuid,
+            sender_uuid: legacy.sender_uuid,
+            timestamp: legacy.timestamp,
+            room_uuid: legacy.room_uuid,
+            msg_type: Some(msg_type),
+            ..ProtoMessage::default()
+        })
+    }
+}
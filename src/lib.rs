@@ -2,16 +2,62 @@ pub mod proto {
     include!(concat!(env!("OUT_DIR"), "/proto.rs"));
 }
 
+#[cfg(feature = "async")]
+pub mod async_model;
+pub mod bundle;
+pub mod catch_up;
+pub mod clock_skew;
+pub mod coalesce;
+pub mod codec;
 pub mod config;
 pub mod db;
+pub mod delivery;
 pub mod dtchat;
+pub mod error;
 pub mod event;
+pub mod export;
+pub mod extension;
+pub mod file_info;
+pub(crate) mod files;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod http_gateway;
+#[cfg(unix)]
+pub mod ipc;
+pub mod journal;
+pub mod keys;
+pub mod latency;
+pub mod legacy;
+pub mod link_health;
 pub mod message;
+pub mod metrics;
+#[cfg(feature = "testkit")]
+pub mod mock_engine;
+pub mod net;
+#[cfg(feature = "testkit")]
+pub mod net_sim;
+pub mod node_info;
 pub mod prediction;
+pub mod profile;
 pub mod proto_message;
+pub mod rate_limit;
+pub mod reception;
+pub mod reconnect;
+pub mod replay;
+pub mod retransmit;
+pub mod scheduler;
+pub mod send_queue;
+pub mod soak;
+pub mod stats;
+#[cfg(feature = "testkit")]
+pub mod testkit;
 pub mod time;
+pub mod transport;
+#[cfg(feature = "webhook")]
+pub mod webhook;
 
-pub use socket_engine::{
-    endpoint::{Endpoint, EndpointProto},
-    engine::Engine,
-};
+pub use net::{Endpoint, EndpointProto};
+#[cfg(feature = "native")]
+pub use socket_engine::engine::Engine;
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::time::DTChatTime;
+
+// Weight of the latest outcome in the score
+const SMOOTHING: f64 = 0.2;
+// Apart so that a link on the edge does not flap between the two
+const DEGRADED_BELOW: f64 = 0.5;
+const RECOVERED_ABOVE: f64 = 0.8;
+
+// Keepalives on the TCP connections, the peers must have the keepalive capability
+#[derive(Debug, Clone, Deserialize)]
+pub struct KeepaliveConfig {
+    // Silence after which a keepalive is sent, and between two of them
+    pub interval_ms: i64,
+    // Silence after which the connection is considered closed
+    pub idle_timeout_ms: i64,
+}
+
+// Health of a remote endpoint, from its connections, sends and receptions
+#[derive(Clone, Debug)]
+pub struct LinkStatus {
+    pub endpoint: String,
+    // Moving average of the outcomes, from 0 (failing) to 1 (healthy)
+    pub score: f64,
+    pub connected: bool,
+    pub degraded: bool,
+    pub successes: u64,
+    pub failures: u64,
+    pub last_failure: Option<(DTChatTime, String)>,
+}
+
+impl LinkStatus {
+    fn new(endpoint: &str) -> Self {
+        Self {
+            endpoint: endpoint.to_string(),
+            score: 1.0,
+            connected: false,
+            degraded: false,
+            successes: 0,
+            failures: 0,
+            last_failure: None,
+        }
+    }
+
+    // True when the link became degraded or recovered
+    fn record(&mut self, success: bool) -> bool {
+        let outcome = if success { 1.0 } else { 0.0 };
+        self.score = (1.0 - SMOOTHING) * self.score + SMOOTHING * outcome;
+        let degraded = match self.degraded {
+            false => self.score < DEGRADED_BELOW,
+            true => self.score < RECOVERED_ABOVE,
+        };
+        let changed = degraded != self.degraded;
+        self.degraded = degraded;
+        changed
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LinkHealth {
+    // Per endpoint, as given by to_string
+    links: HashMap<String, LinkStatus>,
+}
+
+impl LinkHealth {
+    // The status when the link recovered
+    pub fn record_success(&mut self, endpoint: &str) -> Option<LinkStatus> {
+        let link = self.link(endpoint);
+        link.successes += 1;
+        link.record(true).then(|| link.clone())
+    }
+
+    // The status when the link became degraded
+    pub fn record_failure(
+        &mut self,
+        endpoint: &str,
+        reason: String,
+        time: DTChatTime,
+    ) -> Option<LinkStatus> {
+        let link = self.link(endpoint);
+        link.failures += 1;
+        link.last_failure = Some((time, reason));
+        link.record(false).then(|| link.clone())
+    }
+
+    pub fn set_connected(&mut self, endpoint: &str, connected: bool) {
+        self.link(endpoint).connected = connected;
+    }
+
+    pub fn is_degraded(&self, endpoint: &str) -> bool {
+        self.links.get(endpoint).is_some_and(|link| link.degraded)
+    }
+
+    // Endpoints never seen are considered healthy
+    pub fn score(&self, endpoint: &str) -> f64 {
+        self.links.get(endpoint).map_or(1.0, |link| link.score)
+    }
+
+    pub fn forget(&mut self, endpoint: &str) {
+        self.links.remove(endpoint);
+    }
+
+    // Healthiest first
+    pub fn statuses(&self) -> Vec<LinkStatus> {
+        let mut statuses: Vec<LinkStatus> = self.links.values().cloned().collect();
+        statuses.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then(a.endpoint.cmp(&b.endpoint))
+        });
+        statuses
+    }
+
+    fn link(&mut self, endpoint: &str) -> &mut LinkStatus {
+        self.links
+            .entry(endpoint.to_string())
+            .or_insert_with(|| LinkStatus::new(endpoint))
+    }
+}
@@ -1,21 +1,38 @@
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
+use clap::{Parser, ValueEnum};
+#[cfg(unix)]
+use dtchat_backend::ipc::start_ipc;
 use dtchat_backend::{
+    bundle::BundleStatus,
     dtchat::ChatModel,
     event::{
         AppEventObserver, ChatAppErrorEvent, ChatAppEvent, ChatAppInfoEvent, NetworkErrorEvent,
         NetworkEvent,
     },
-    message::{ChatMessage, Content, MessageStatus},
-    time::DTChatTime,
+    message::{insert_with_strategy, ChatMessage, Content, MessageStatus, SortStrategy},
+    http_gateway::start_gateway,
+    metrics::start_exporter,
+    soak::start_soak,
+    time::{DTChatTime, DisplayPrefs},
+};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Position, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Paragraph},
+    DefaultTerminal, Frame,
 };
 use socket_engine::{
+    endpoint::Endpoint,
     engine::Engine,
     event::{ConnectionEvent, DataEvent},
 };
 
-use std::collections::VecDeque;
-use std::io::{self, Write};
+use std::collections::{HashMap, VecDeque};
 
 // Helper function to safely extract first 8 characters of a message ID
 fn safe_message_id_display(id: &str) -> &str {
@@ -26,14 +43,268 @@ fn safe_message_id_display(id: &str) -> &str {
     }
 }
 
-#[derive(Clone, Debug)]
+fn token_display(token: &str, message_uuid: Option<&str>) -> String {
+    match message_uuid {
+        Some(uuid) => format!("message: {}", safe_message_id_display(uuid)),
+        None => format!("token: {}", safe_message_id_display(token)),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum EventLevel {
-    Info,    // Informations normales
     Debug,   // Détails techniques (réseau)
+    Info,    // Informations normales
     Warning, // Problèmes non critiques
     Error,   // Erreurs
 }
 
+fn parse_endpoint(value: &str) -> Result<Endpoint, String> {
+    Endpoint::from_str(value).map_err(|e| e.to_string())
+}
+
+const COMMANDS: &str =
+    "/file <path>, /room <uuid>, /peers, /history <n>, /retry <uuid>, /predict <peer>, \
+     /contact <peer>";
+// Size of the message whose arrival /predict gives
+const PREDICTED_SIZE: u64 = 1024;
+
+// Typed at the prompt, any other input is sent as text
+enum Command {
+    File(String),
+    Room(String),
+    Peers,
+    History(usize),
+    Retry(String),
+    Predict(String),
+    Contact(String),
+}
+
+// None for a text to send, Err for an unknown or incomplete command
+fn parse_command(input: &str) -> Option<Result<Command, String>> {
+    let rest = input.strip_prefix('/')?;
+    let (name, arg) = match rest.split_once(' ') {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (rest, ""),
+    };
+    let needs_arg = |command: fn(String) -> Command| match arg {
+        "" => Err(format!("/{} needs an argument", name)),
+        arg => Ok(command(arg.to_string())),
+    };
+    Some(match name {
+        "file" => needs_arg(Command::File),
+        "room" => needs_arg(Command::Room),
+        "peers" => Ok(Command::Peers),
+        "history" => arg
+            .parse()
+            .map(Command::History)
+            .map_err(|_| "/history needs a number of messages".to_string()),
+        "retry" => needs_arg(Command::Retry),
+        "predict" => needs_arg(Command::Predict),
+        "contact" => needs_arg(Command::Contact),
+        _ => Err(format!("Unknown command /{}, use {}", name, COMMANDS)),
+    })
+}
+
+// Where texts and files typed at the prompt go
+enum Target {
+    Peer(String, Endpoint),
+    Room(String),
+}
+
+fn send_content(model: &Mutex<ChatModel>, target: &Target, content: Content) {
+    let mut model = model.lock().unwrap();
+    match target {
+        // Failures are reported as error events
+        Target::Peer(peer_uuid, endpoint) => {
+            let _ = model.send_to_peer(
+                &content,
+                &"room".to_string(),
+                peer_uuid.clone(),
+                endpoint,
+                false,
+            );
+        }
+        Target::Room(room_uuid) => {
+            let _ = model.send_to_room(&content, room_uuid, false);
+        }
+    }
+}
+
+fn run_command(
+    command: Command,
+    model: &Mutex<ChatModel>,
+    screen: &Mutex<TerminalScreen>,
+    target: &mut Target,
+) {
+    let mut report = |level: EventLevel, message: String| {
+        screen.lock().unwrap().add_app_event(level, message);
+    };
+    match command {
+        Command::File(path) => send_content(model, target, Content::File(path)),
+        Command::Room(room_uuid) => match model.lock().unwrap().get_rooms().get(&room_uuid) {
+            Some(room) => {
+                report(
+                    EventLevel::Info,
+                    format!("Now sending to room {}", room.name),
+                );
+                screen
+                    .lock()
+                    .unwrap()
+                    .set_active_room(Some(room_uuid.clone()));
+                *target = Target::Room(room_uuid);
+            }
+            None => report(EventLevel::Error, format!("Unknown room {}", room_uuid)),
+        },
+        Command::Peers => {
+            let model = model.lock().unwrap();
+            let blocked = model.get_blocked_peers();
+            let mut peers: Vec<_> = model.get_other_peers().into_values().collect();
+            peers.sort_by(|a, b| a.name.cmp(&b.name));
+            for peer in peers {
+                let endpoints: Vec<String> = peer
+                    .endpoints
+                    .iter()
+                    .map(|endpoint| endpoint.to_string())
+                    .collect();
+                let state = if blocked.contains(&peer.uuid) {
+                    " (blocked)"
+                } else {
+                    ""
+                };
+                report(
+                    EventLevel::Info,
+                    format!(
+                        "{} {}{}: {}",
+                        peer.name,
+                        peer.uuid,
+                        state,
+                        endpoints.join(", ")
+                    ),
+                );
+            }
+        }
+        Command::History(count) => {
+            let messages = match target {
+                Target::Room(room_uuid) => model
+                    .lock()
+                    .unwrap()
+                    .get_last_messages_for_room(room_uuid, count),
+                Target::Peer(..) => model.lock().unwrap().get_last_messages(count),
+            };
+            for msg in messages {
+                report(
+                    EventLevel::Info,
+                    format!(
+                        "{} {} {:?}: {}",
+                        safe_message_id_display(&msg.uuid),
+                        safe_message_id_display(&msg.sender_uuid),
+                        msg.status,
+                        msg.content_as_string()
+                    ),
+                );
+            }
+        }
+        Command::Retry(uuid) => match model.lock().unwrap().retry(&uuid) {
+            Ok(()) => report(EventLevel::Info, format!("Message {} sent again", uuid)),
+            Err(err) => report(
+                EventLevel::Error,
+                format!("Cannot retry {}: {:?}", uuid, err),
+            ),
+        },
+        Command::Predict(peer_uuid) => {
+            match model
+                .lock()
+                .unwrap()
+                .predict_arrival(&peer_uuid, PREDICTED_SIZE)
+            {
+                Ok(arrival) => {
+                    let delay_s =
+                        (arrival.timestamp_millis() - DTChatTime::now().timestamp_millis()) / 1000;
+                    report(
+                        EventLevel::Info,
+                        format!(
+                            "{} bytes sent now to {} would arrive in {} s",
+                            PREDICTED_SIZE, peer_uuid, delay_s
+                        ),
+                    );
+                }
+                Err(err) => report(
+                    EventLevel::Error,
+                    format!("No prediction for {}: {:?}", peer_uuid, err),
+                ),
+            }
+        }
+        Command::Contact(peer_uuid) => {
+            let next = model.lock().unwrap().next_contact_window(&peer_uuid);
+            let Some(next) = next else {
+                report(EventLevel::Error, format!("Unknown peer {}", peer_uuid));
+                return;
+            };
+            let reachable = if next.reachable {
+                "reachable"
+            } else {
+                "unreachable"
+            };
+            let now_ms = DTChatTime::now().timestamp_millis();
+            let contact = match (next.start, next.end) {
+                (Some(_), Some(end)) if next.in_contact => format!(
+                    "in contact for {} min",
+                    (end.timestamp_millis() - now_ms) / 60_000
+                ),
+                (Some(start), _) => format!(
+                    "next contact in {} min",
+                    (start.timestamp_millis() - now_ms) / 60_000
+                ),
+                _ => "no contact planned".to_string(),
+            };
+            report(
+                EventLevel::Info,
+                format!("{} is {}, {}", peer_uuid, reachable, contact),
+            );
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "DTChat terminal client")]
+struct Args {
+    /// Configuration file, overrides CONFIG_PATH
+    #[arg(long)]
+    config: Option<String>,
+
+    /// Format of the configuration file, guessed from its extension by default
+    #[arg(long, value_parser = ["yaml", "toml", "json"])]
+    config_format: Option<String>,
+
+    /// Uuid of the local peer in the configuration, overrides PEER_UUID
+    #[arg(long)]
+    peer_uuid: Option<String>,
+
+    /// Local identity profile of the configuration to use instead of a peer uuid
+    #[arg(long, conflicts_with = "peer_uuid")]
+    profile: Option<String>,
+
+    /// Endpoint to listen on instead of the local peer ones, e.g. "tcp 127.0.0.1:6500"
+    #[arg(long, value_parser = parse_endpoint)]
+    listen: Vec<Endpoint>,
+
+    /// Events below this level are not displayed
+    #[arg(long, value_enum, default_value_t = EventLevel::Debug)]
+    log_level: EventLevel,
+
+    /// Print events as plain lines instead of rendering the chat screen, taking no commands
+    #[arg(long)]
+    headless: bool,
+
+    /// Print events as plain lines and take commands from the --socket control socket only
+    #[arg(long, conflicts_with = "headless")]
+    daemon: bool,
+
+    /// Unix-domain control socket of the daemon mode
+    #[arg(long, default_value = "dtchat.sock")]
+    socket: String,
+}
+
 #[derive(Clone, Debug)]
 pub struct EventWithLevel {
     level: EventLevel,
@@ -41,61 +312,216 @@ pub struct EventWithLevel {
     timestamp: DTChatTime,
 }
 
+// Events kept per pane, the older ones are dropped
+const EVENTS_KEPT: usize = 50;
+// Height of an expanded event pane, borders included
+const EVENT_PANE_HEIGHT: u16 = 8;
+// Lines scrolled by PageUp and PageDown
+const SCROLL_PAGE: usize = 10;
+// How long the main loop waits for a key before running its periodic checks
+const INPUT_POLL: Duration = Duration::from_millis(200);
+
+fn status_style(status: &MessageStatus) -> (&'static str, Color) {
+    match status {
+        MessageStatus::Failed => ("FAILED", Color::Red),
+        MessageStatus::Expired => ("EXPIRED", Color::Magenta),
+        MessageStatus::Cancelled => ("CANCELLED", Color::DarkGray),
+        MessageStatus::Refused => ("REFUSED", Color::Red),
+        MessageStatus::ReceivedByPeer => ("ACKED", Color::Green),
+        MessageStatus::Sent => ("SENT", Color::Yellow),
+        MessageStatus::InCustody => ("CUSTODY", Color::Cyan),
+        MessageStatus::DeliveredToNode => ("DELIVERED", Color::LightGreen),
+        MessageStatus::Sending => ("SENDING", Color::DarkGray),
+        MessageStatus::Received => ("RECEIVED", Color::Blue),
+    }
+}
+
+fn level_style(level: &EventLevel) -> (&'static str, Color) {
+    match level {
+        EventLevel::Info => ("[INFO ]", Color::Cyan),
+        EventLevel::Debug => ("[DEBUG]", Color::DarkGray),
+        EventLevel::Warning => ("[WARN ]", Color::Yellow),
+        EventLevel::Error => ("[ERROR]", Color::Red),
+    }
+}
+
 pub struct TerminalScreen {
     local_uuid: String,
-    messages: VecDeque<ChatMessage>,
+    // Sorted relatively to the local peer, at most max_lines per room
+    messages: Vec<ChatMessage>,
+    // Uuid and name of the configured rooms, by name
+    rooms: Vec<(String, String)>,
+    // Only its messages are shown, every message when None
+    active_room: Option<String>,
+    // Received messages per room uuid since it was last shown
+    unread: HashMap<String, usize>,
     network_events: VecDeque<EventWithLevel>, // Événements réseau
     app_events: VecDeque<EventWithLevel>,     // Événements d'application
     max_lines: usize,
     input_line: String,
+    // In characters from the start of input_line
+    cursor: usize,
+    // Messages hidden below the message pane, 0 follows the latest ones
+    scroll: usize,
+    show_network_events: bool,
+    show_app_events: bool,
+    min_level: EventLevel,
+    // None in headless mode, the events are printed as plain lines instead
+    terminal: Option<DefaultTerminal>,
+    display_prefs: DisplayPrefs,
 }
 
 impl TerminalScreen {
-    pub fn new(local_uuid: String, max_lines: usize) -> Self {
+    pub fn new(
+        local_uuid: String,
+        max_lines: usize,
+        min_level: EventLevel,
+        headless: bool,
+        display_prefs: DisplayPrefs,
+        mut rooms: Vec<(String, String)>,
+    ) -> Self {
+        rooms.sort_by(|a, b| a.1.cmp(&b.1));
         Self {
             local_uuid,
-            messages: VecDeque::new(),
+            messages: Vec::new(),
+            rooms,
+            active_room: None,
+            unread: HashMap::new(),
             network_events: VecDeque::new(),
             app_events: VecDeque::new(),
             max_lines,
             input_line: String::new(),
+            cursor: 0,
+            scroll: 0,
+            show_network_events: true,
+            show_app_events: true,
+            min_level,
+            terminal: (!headless).then(ratatui::init),
+            display_prefs,
         }
     }
 
+    pub fn is_headless(&self) -> bool {
+        self.terminal.is_none()
+    }
+
+    // Gives the terminal back in the state it was found
+    pub fn close(&mut self) {
+        if self.terminal.take().is_some() {
+            ratatui::restore();
+        }
+    }
+
+    fn print_event(&self, event: &EventWithLevel) {
+        let time_str = event
+            .timestamp
+            .ts_to_str(true, true, None, &self.display_prefs);
+        println!("{} {:?} {}", time_str, event.level, event.message);
+    }
+
     pub fn set_input(&mut self, input: String) {
+        self.cursor = input.chars().count();
         self.input_line = input;
     }
 
-    fn add_network_event(&mut self, level: EventLevel, message: String) {
+    fn push_event(&mut self, network: bool, level: EventLevel, message: String) {
+        if level < self.min_level {
+            return;
+        }
         let event = EventWithLevel {
             level,
             message,
             timestamp: DTChatTime::now(),
         };
-        self.network_events.push_back(event);
-
-        // Garder seulement les 6 derniers événements réseau
-        if self.network_events.len() > 10 {
-            self.network_events.pop_front();
+        if self.is_headless() {
+            self.print_event(&event);
+        }
+        let events = if network {
+            &mut self.network_events
+        } else {
+            &mut self.app_events
+        };
+        events.push_back(event);
+        if events.len() > EVENTS_KEPT {
+            events.pop_front();
         }
     }
 
+    fn add_network_event(&mut self, level: EventLevel, message: String) {
+        self.push_event(true, level, message);
+    }
+
     fn add_app_event(&mut self, level: EventLevel, message: String) {
-        let event = EventWithLevel {
-            level,
-            message,
-            timestamp: DTChatTime::now(),
-        };
-        self.app_events.push_back(event);
+        self.push_event(false, level, message);
+    }
+
+    pub fn set_active_room(&mut self, room_uuid: Option<String>) {
+        if let Some(room_uuid) = &room_uuid {
+            self.unread.remove(room_uuid);
+        }
+        self.active_room = room_uuid;
+        self.scroll = 0;
+    }
+
+    fn is_shown(&self, msg: &ChatMessage) -> bool {
+        self.active_room
+            .as_ref()
+            .map_or(true, |room_uuid| msg.in_room(room_uuid))
+    }
+
+    fn add_message(&mut self, msg: ChatMessage) {
+        if self.messages.iter().any(|m| m.uuid == msg.uuid) {
+            return;
+        }
+        if msg.sender_uuid != self.local_uuid && !self.is_shown(&msg) {
+            *self.unread.entry(msg.room_uuid.clone()).or_default() += 1;
+        }
+        let room_uuid = msg.room_uuid.clone();
+        insert_with_strategy(
+            &mut self.messages,
+            msg,
+            SortStrategy::Relative(self.local_uuid.clone()),
+        );
+        let in_room = self
+            .messages
+            .iter()
+            .filter(|m| m.room_uuid == room_uuid)
+            .count();
+        if in_room > self.max_lines {
+            if let Some(oldest) = self.messages.iter().position(|m| m.room_uuid == room_uuid) {
+                self.messages.remove(oldest);
+            }
+        }
+    }
 
-        // Garder seulement les 6 derniers événements d'application
-        if self.app_events.len() > 10 {
-            self.app_events.pop_front();
+    // The configured rooms and the other ones messages were seen in
+    fn room_list(&self) -> Line<'static> {
+        let mut names: Vec<(String, String)> = self.rooms.clone();
+        for msg in &self.messages {
+            if !names.iter().any(|(uuid, _)| *uuid == msg.room_uuid) {
+                names.push((msg.room_uuid.clone(), msg.room_uuid.clone()));
+            }
         }
+        let mut spans = vec![Span::raw("Rooms:")];
+        for (uuid, name) in names {
+            let name = match self.unread.get(&uuid) {
+                Some(count) => format!("{} ({})", name, count),
+                None => name,
+            };
+            spans.push(Span::raw("  "));
+            if self.active_room.as_ref() == Some(&uuid) {
+                spans.push(Span::styled(
+                    format!("[{}]", name),
+                    Style::new().fg(Color::White).add_modifier(Modifier::BOLD),
+                ));
+            } else {
+                spans.push(Span::raw(name));
+            }
+        }
+        Line::from(spans)
     }
 
     fn update_message_status(&mut self, msg: ChatMessage) {
-        println!("{:?}", msg.get_shipment_status_otp());
         for m in &mut self.messages {
             if m.uuid == msg.uuid || m.uuid.starts_with(&msg.uuid) {
                 *m = msg;
@@ -104,137 +530,223 @@ impl TerminalScreen {
         }
     }
 
-    pub fn render(&self) {
-        // Clear screen and move cursor to top
-        print!("\x1b[2J\x1b[H");
-
-        // Print header with connection info
-        println!(
-            "\x1b[1;96m=== DTChat - {} ===\x1b[0m",
-            self.local_uuid.to_uppercase()
-        );
-        println!();
+    // Returns the line submitted with Enter, Ctrl-C submits "quit"
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<String> {
+        if key.kind != KeyEventKind::Press {
+            return None;
+        }
+        let input_len = self.input_line.chars().count();
+        let byte_cursor = |line: &str, cursor: usize| {
+            line.char_indices()
+                .nth(cursor)
+                .map_or(line.len(), |(index, _)| index)
+        };
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Some("quit".to_string());
+            }
+            KeyCode::Enter => {
+                self.cursor = 0;
+                return Some(std::mem::take(&mut self.input_line));
+            }
+            KeyCode::Char(c) => {
+                let index = byte_cursor(&self.input_line, self.cursor);
+                self.input_line.insert(index, c);
+                self.cursor += 1;
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                let index = byte_cursor(&self.input_line, self.cursor);
+                self.input_line.remove(index);
+            }
+            KeyCode::Delete if self.cursor < input_len => {
+                let index = byte_cursor(&self.input_line, self.cursor);
+                self.input_line.remove(index);
+            }
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(input_len),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = input_len,
+            KeyCode::Up => self.scroll += 1,
+            KeyCode::Down => self.scroll = self.scroll.saturating_sub(1),
+            KeyCode::PageUp => self.scroll += SCROLL_PAGE,
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(SCROLL_PAGE),
+            KeyCode::F(2) => self.show_network_events = !self.show_network_events,
+            KeyCode::F(3) => self.show_app_events = !self.show_app_events,
+            _ => {}
+        }
+        let shown = self.messages.iter().filter(|m| self.is_shown(m)).count();
+        self.scroll = self.scroll.min(shown.saturating_sub(1));
+        None
+    }
 
-        // Layout en deux colonnes : Messages | Events
-        // === SECTION MESSAGES (Colonne gauche) ===
-        println!("\x1b[1;37mMessages ({}):\x1b[0m", self.messages.len());
+    fn message_line(&self, msg: &ChatMessage) -> Line<'static> {
+        let (status_indicator, status_color) = status_style(&msg.status);
 
-        if self.messages.is_empty() {
-            println!("  \x1b[90mEmpty chat\x1b[0m");
-        } else {
-            // Afficher les 8 derniers messages
-            for msg in self.messages.iter().rev().take(8).rev() {
-                let (status_indicator, status_color) = match &msg.status {
-                    MessageStatus::Failed => ("FAILED", "\x1b[31m"),
-                    MessageStatus::ReceivedByPeer => ("ACKED", "\x1b[32m"),
-                    MessageStatus::Sent => ("SENT", "\x1b[33m"),
-                    MessageStatus::Sending => ("SENDING", "\x1b[90m"),
-                    MessageStatus::Received => ("RECEIVED", "\x1b[34m"),
-                };
+        // Nouveau format : [<STATUS>] [acked_time:send_time] <message>
+        let acked_time_str = match msg.receive_time {
+            Some(t) => t.ts_to_str(false, true, None, &self.display_prefs),
+            None => "???".to_string(),
+        };
 
-                // Nouveau format : [<STATUS>] [acked_time:send_time] <message>
-                let acked_time_str = match msg.receive_time {
-                    Some(t) => t.ts_to_str(false, true, None, &chrono::Local),
-                    None => "???".to_string(),
-                };
+        let send_time_str: String = msg
+            .send_time
+            .ts_to_str(false, true, None, &self.display_prefs);
 
-                let send_time_str: String =
-                    msg.send_time.ts_to_str(false, true, None, &chrono::Local);
+        let time_display = format!("[{}:{}]", send_time_str, acked_time_str);
 
-                let time_display = format!("[{}:{}]", send_time_str, acked_time_str);
+        let msg_color = if msg.sender_uuid == self.local_uuid {
+            Color::White
+        } else {
+            Color::Blue
+        };
 
-                let msg_color = if msg.sender_uuid == self.local_uuid {
-                    "\x1b[37m"
+        let display_text = match &msg.content {
+            Content::Text(str) | Content::File(str) | Content::Audio(str, _) => {
+                if str.len() > 40 {
+                    format!("{}...", &str[..37])
                 } else {
-                    "\x1b[34m"
-                };
-
-                let display_text = match &msg.content {
-                    Content::Text(str) | Content::File(str) => {
-                        if str.len() > 40 {
-                            format!("{}...", &str[..37])
-                        } else {
-                            str.clone()
-                        }
-                    }
-                };
-                println!(
-                    "  {}[{}] {} {}{}\x1b[0m",
-                    status_color, status_indicator, time_display, msg_color, display_text
-                );
+                    str.clone()
+                }
             }
-        }
-
-        println!();
-
-        // === SECTION ÉVÉNEMENTS RÉSEAU ===
-        println!(
-            "\x1b[1;37mNetwork Events ({}):\x1b[0m",
-            self.network_events.len()
-        );
-
-        if self.network_events.is_empty() {
-            println!("  \x1b[90mAucun événement réseau\x1b[0m");
-        } else {
-            // Afficher les 6 derniers événements réseau
-            for event in self.network_events.iter().rev().take(6).rev() {
-                let (level_indicator, color) = match event.level {
-                    EventLevel::Info => ("[INFO ]", "\x1b[36m"),    // Cyan
-                    EventLevel::Debug => ("[DEBUG]", "\x1b[90m"),   // Gris
-                    EventLevel::Warning => ("[WARN ]", "\x1b[33m"), // Jaune
-                    EventLevel::Error => ("[ERROR]", "\x1b[31m"),   // Rouge
-                };
+            Content::Blob(..) | Content::Location(_) => msg.content_as_string(),
+        };
+        Line::from(vec![
+            Span::styled(
+                format!("[{}] {} ", status_indicator, time_display),
+                Style::new().fg(status_color),
+            ),
+            Span::styled(display_text, Style::new().fg(msg_color)),
+        ])
+    }
 
-                let time_str = event.timestamp.ts_to_str(false, true, None, &chrono::Local);
+    fn event_line(&self, event: &EventWithLevel) -> Line<'static> {
+        let (level_indicator, color) = level_style(&event.level);
+        let time_str = event
+            .timestamp
+            .ts_to_str(false, true, None, &self.display_prefs);
+        Line::styled(
+            format!("{} [{}] {}", level_indicator, time_str, event.message),
+            Style::new().fg(color),
+        )
+    }
 
-                println!(
-                    "  {}{} [{}] {}\x1b[0m",
-                    color, level_indicator, time_str, event.message
-                );
-            }
+    // Collapsed to its title line, the latest events at the bottom otherwise
+    fn draw_events(
+        &self,
+        frame: &mut Frame,
+        area: Rect,
+        title: &str,
+        key: &str,
+        events: &VecDeque<EventWithLevel>,
+        expanded: bool,
+    ) {
+        let bold = Style::new().add_modifier(Modifier::BOLD);
+        if !expanded {
+            let title = format!("+ {} ({}) [{}]", title, events.len(), key);
+            frame.render_widget(Paragraph::new(Line::styled(title, bold)), area);
+            return;
         }
+        let lines: Vec<Line> = if events.is_empty() {
+            vec![Line::styled(
+                "Aucun événement",
+                Style::new().fg(Color::DarkGray),
+            )]
+        } else {
+            events.iter().map(|event| self.event_line(event)).collect()
+        };
+        let height = area.height.saturating_sub(2) as usize;
+        let offset = lines.len().saturating_sub(height) as u16;
+        let block = Block::bordered().title(Line::styled(
+            format!("- {} ({}) [{}]", title, events.len(), key),
+            bold,
+        ));
+        frame.render_widget(Paragraph::new(lines).block(block).scroll((offset, 0)), area);
+    }
 
-        println!();
+    fn draw(&self, frame: &mut Frame) {
+        let event_pane =
+            |expanded: bool| Constraint::Length(if expanded { EVENT_PANE_HEIGHT } else { 1 });
+        let [header_area, messages_area, network_area, app_area, input_area] = Layout::vertical([
+            Constraint::Length(2),
+            Constraint::Min(3),
+            event_pane(self.show_network_events),
+            event_pane(self.show_app_events),
+            Constraint::Length(3),
+        ])
+        .areas(frame.area());
 
-        // === SECTION ÉVÉNEMENTS D'APPLICATION ===
-        println!("\x1b[1;37mApp Events ({}):\x1b[0m", self.app_events.len());
+        let header = vec![
+            Line::styled(
+                format!("=== DTChat - {} ===", self.local_uuid.to_uppercase()),
+                Style::new()
+                    .fg(Color::LightCyan)
+                    .add_modifier(Modifier::BOLD),
+            ),
+            self.room_list(),
+        ];
+        frame.render_widget(Paragraph::new(header), header_area);
 
-        if self.app_events.is_empty() {
-            println!("  \x1b[90mAucun événement d'application\x1b[0m");
+        let shown: Vec<&ChatMessage> = self.messages.iter().filter(|m| self.is_shown(m)).collect();
+        let lines: Vec<Line> = if shown.is_empty() {
+            vec![Line::styled("Empty chat", Style::new().fg(Color::DarkGray))]
         } else {
-            // Afficher les 6 derniers événements d'application
-            for event in self.app_events.iter().rev().take(6).rev() {
-                let (level_indicator, color) = match event.level {
-                    EventLevel::Info => ("[INFO ]", "\x1b[36m"),    // Cyan
-                    EventLevel::Debug => ("[DEBUG]", "\x1b[90m"),   // Gris
-                    EventLevel::Warning => ("[WARN ]", "\x1b[33m"), // Jaune
-                    EventLevel::Error => ("[ERROR]", "\x1b[31m"),   // Rouge
-                };
-
-                let time_str = event.timestamp.ts_to_str(false, true, None, &chrono::Local);
-
-                println!(
-                    "  {}{} [{}] {}\x1b[0m",
-                    color, level_indicator, time_str, event.message
-                );
-            }
+            shown.iter().map(|msg| self.message_line(msg)).collect()
+        };
+        let height = messages_area.height.saturating_sub(2) as usize;
+        let max_offset = lines.len().saturating_sub(height);
+        let scroll = self.scroll.min(max_offset);
+        let mut title = format!("Messages ({})", shown.len());
+        if scroll > 0 {
+            title.push_str(&format!(" - {} newer below", scroll));
         }
+        let block = Block::bordered().title(Line::styled(
+            title,
+            Style::new().add_modifier(Modifier::BOLD),
+        ));
+        frame.render_widget(
+            Paragraph::new(lines)
+                .block(block)
+                .scroll(((max_offset - scroll) as u16, 0)),
+            messages_area,
+        );
 
-        let used_lines = 8
-            + self.messages.len().min(8).max(1)
-            + self.network_events.len().min(6).max(1)
-            + self.app_events.len().min(6).max(1);
-
-        let remaining_lines = self.max_lines.saturating_sub(used_lines + 2); // +2 pour input
-        for _ in 0..remaining_lines {
-            println!();
-        }
+        self.draw_events(
+            frame,
+            network_area,
+            "Network Events",
+            "F2",
+            &self.network_events,
+            self.show_network_events,
+        );
+        self.draw_events(
+            frame,
+            app_area,
+            "App Events",
+            "F3",
+            &self.app_events,
+            self.show_app_events,
+        );
 
-        // === LIGNE D'INPUT EN BAS ===
-        println!("\x1b[1;96m─────────────────────────────────────────────────────────────────────────────\x1b[0m");
-        print!("\x1b[1;37m> \x1b[0m{}", self.input_line);
+        let prompt = Line::from(vec![
+            Span::styled("> ", Style::new().add_modifier(Modifier::BOLD)),
+            Span::raw(self.input_line.clone()),
+        ]);
+        let block = Block::bordered().border_style(Style::new().fg(Color::LightCyan));
+        frame.render_widget(Paragraph::new(prompt).block(block), input_area);
+        frame.set_cursor_position(Position::new(
+            input_area.x + 3 + self.cursor as u16,
+            input_area.y + 1,
+        ));
+    }
 
-        io::stdout().flush().unwrap();
+    pub fn render(&mut self) {
+        // Taken out for the time of the draw, which borrows the rest of the screen
+        let Some(mut terminal) = self.terminal.take() else {
+            return;
+        };
+        let _ = terminal.draw(|frame| self.draw(frame));
+        self.terminal = Some(terminal);
     }
 }
 
@@ -243,7 +755,7 @@ impl AppEventObserver for TerminalScreen {
         match event {
             ChatAppEvent::SocketEngineInfo(info_event) => {
                 let (level, event_text) = match info_event {
-                    NetworkEvent::Data(data_event) => match data_event {
+                    NetworkEvent::Data(data_event, message_uuid) => match data_event {
                         DataEvent::Received { data, from } => (
                             EventLevel::Info,
                             format!("Received {} bytes from {}", data.len(), from.to_string()),
@@ -255,19 +767,19 @@ impl AppEventObserver for TerminalScreen {
                         } => (
                             EventLevel::Info,
                             format!(
-                                "Sent {} bytes to {} (token: {})",
+                                "Sent {} bytes to {} ({})",
                                 bytes_sent,
                                 to.to_string(),
-                                safe_message_id_display(&token)
+                                token_display(&token, message_uuid.as_deref())
                             ),
                         ),
                         DataEvent::Sending { token, to, bytes } => (
                             EventLevel::Info,
                             format!(
-                                "Sending {} bytes to {} (token: {})",
+                                "Sending {} bytes to {} ({})",
                                 bytes,
                                 to.to_string(),
-                                safe_message_id_display(&token)
+                                token_display(&token, message_uuid.as_deref())
                             ),
                         ),
                     },
@@ -301,38 +813,125 @@ impl AppEventObserver for TerminalScreen {
             }
             ChatAppEvent::SocketEngineError(error_event) => {
                 let error_text = match error_event {
-                    NetworkErrorEvent::SocketError(socket_error) => {
+                    NetworkErrorEvent::SocketError(socket_error, Some(message_uuid)) => format!(
+                        "Socket error for message {}: {:?}",
+                        safe_message_id_display(&message_uuid),
+                        socket_error
+                    ),
+                    NetworkErrorEvent::SocketError(socket_error, None) => {
                         format!("Socket error: {:?}", socket_error)
                     }
                 };
                 self.add_network_event(EventLevel::Error, error_text);
             }
             ChatAppEvent::Message(info_event) => match info_event {
+                ChatAppInfoEvent::Started(info) => {
+                    self.add_app_event(
+                        EventLevel::Info,
+                        format!(
+                            "dtchat {} ({}) started as peer {} with {}, features [{}]",
+                            info.version,
+                            info.git_hash,
+                            info.peer_uuid,
+                            info.config_path,
+                            info.features.join(", ")
+                        ),
+                    );
+                }
                 ChatAppInfoEvent::Sending(chat_message) => {
                     let msg_id = safe_message_id_display(&chat_message.uuid);
                     self.add_app_event(EventLevel::Info, format!("Sending message {}", msg_id));
 
-                    if !self.messages.iter().any(|m| m.uuid == chat_message.uuid) {
-                        self.messages.push_back(chat_message);
-                        if self.messages.len() > self.max_lines {
-                            self.messages.pop_front();
-                        }
-                    }
+                    self.add_message(chat_message);
                 }
                 ChatAppInfoEvent::Sent(sent_message) => {
                     self.update_message_status(sent_message);
                 }
+                ChatAppInfoEvent::SourceMismatch(msg) => {
+                    let msg_id = safe_message_id_display(&msg.uuid);
+                    self.add_app_event(
+                        EventLevel::Warning,
+                        format!(
+                            "Message {} claims to come from {} but was received from {}",
+                            msg_id,
+                            msg.source_endpoint.to_string(),
+                            msg.received_from
+                                .as_ref()
+                                .map_or("??".to_string(), |ep| ep.to_string())
+                        ),
+                    );
+                }
+                ChatAppInfoEvent::InCustody(msg) => {
+                    let uuid = msg.uuid.clone();
+                    self.update_message_status(msg);
+                    let msg_id = safe_message_id_display(&uuid);
+                    self.add_app_event(
+                        EventLevel::Info,
+                        format!("Message {} taken in custody", msg_id),
+                    );
+                }
+                ChatAppInfoEvent::BundleStatus(msg, report) => {
+                    let msg_id = safe_message_id_display(&msg.uuid);
+                    let text = match &report.status {
+                        BundleStatus::Received => format!("received by {}", report.node_eid),
+                        BundleStatus::Forwarded => format!("forwarded by {}", report.node_eid),
+                        BundleStatus::Delivered => format!("delivered to {}", report.node_eid),
+                        BundleStatus::Deleted(reason) => {
+                            format!("deleted by {}: {}", report.node_eid, reason)
+                        }
+                    };
+                    self.update_message_status(msg);
+                    self.add_network_event(
+                        EventLevel::Info,
+                        format!("Bundle of message {} {}", msg_id, text),
+                    );
+                }
                 ChatAppInfoEvent::Received(chat_message) => {
                     let uuid = chat_message.uuid.clone();
                     let msg_id = safe_message_id_display(&uuid);
                     self.update_message_status(chat_message.clone());
-                    self.add_app_event(EventLevel::Info, format!("Message {} received", msg_id));
-                    if !self.messages.iter().any(|m| m.uuid == chat_message.uuid) {
-                        self.messages.push_back(chat_message);
-                        if self.messages.len() > self.max_lines {
-                            self.messages.pop_front();
-                        }
-                    }
+                    let text = match (&chat_message.bundle, chat_message.receive_time) {
+                        (Some(bundle), Some(receive_time)) => format!(
+                            "Message {} received, {} ms in the network since {}",
+                            msg_id,
+                            bundle.time_in_network_ms(receive_time),
+                            bundle.source_eid
+                        ),
+                        _ => format!("Message {} received", msg_id),
+                    };
+                    self.add_app_event(EventLevel::Info, text);
+                    self.add_message(chat_message);
+                }
+                ChatAppInfoEvent::Mentioned(msg) => {
+                    self.add_app_event(
+                        EventLevel::Warning,
+                        format!(
+                            "Mentioned by {} in message {}",
+                            msg.sender_uuid,
+                            safe_message_id_display(&msg.uuid)
+                        ),
+                    );
+                }
+                ChatAppInfoEvent::AmbiguousMention(msg, name, peer_uuids) => {
+                    self.add_app_event(
+                        EventLevel::Warning,
+                        format!(
+                            "Mention @{} in message {} left out, it names peers {}",
+                            name,
+                            safe_message_id_display(&msg.uuid),
+                            peer_uuids.join(", ")
+                        ),
+                    );
+                }
+                ChatAppInfoEvent::UnhandledBlob(msg) => {
+                    self.add_app_event(
+                        EventLevel::Debug,
+                        format!(
+                            "No handler for {} in message {}",
+                            msg.content_as_string(),
+                            safe_message_id_display(&msg.uuid)
+                        ),
+                    );
                 }
                 ChatAppInfoEvent::AckSent(msg, _peer_uuid) => {
                     let uuid = msg.uuid.clone();
@@ -343,42 +942,296 @@ impl AppEventObserver for TerminalScreen {
                         format!("Ack sent for message {}", msg_id),
                     );
                 }
-                ChatAppInfoEvent::AckReceived(msg) => {
+                ChatAppInfoEvent::AckReceived(msg, rtt_ms) => {
+                    let uuid = msg.uuid.clone();
+                    self.update_message_status(msg);
+                    let msg_id = safe_message_id_display(&uuid);
+                    let rtt = rtt_ms.map_or(String::new(), |ms| format!(" after {} ms", ms));
+                    self.add_app_event(
+                        EventLevel::Info,
+                        format!("Ack received for message {}{}", msg_id, rtt),
+                    );
+                }
+                ChatAppInfoEvent::RoomMessageDelivered(room_msg) => {
+                    self.add_app_event(
+                        EventLevel::Info,
+                        format!(
+                            "Message {} delivered to the {} participants of room {}",
+                            safe_message_id_display(&room_msg.uuid),
+                            room_msg.peers.len(),
+                            room_msg.room_uuid
+                        ),
+                    );
+                }
+                ChatAppInfoEvent::NackReceived(msg, reason) => {
+                    let uuid = msg.uuid.clone();
+                    self.update_message_status(msg);
+                    let msg_id = safe_message_id_display(&uuid);
+                    self.add_app_event(
+                        EventLevel::Warning,
+                        format!("Message {} refused by peer: {}", msg_id, reason),
+                    );
+                }
+                ChatAppInfoEvent::Refused(msg, reason) => {
+                    let uuid = msg.uuid.clone();
+                    self.update_message_status(msg);
+                    let msg_id = safe_message_id_display(&uuid);
+                    self.add_app_event(
+                        EventLevel::Error,
+                        format!("Message {} refused for good by peer: {}", msg_id, reason),
+                    );
+                }
+                ChatAppInfoEvent::Failed(msg) => {
                     let uuid = msg.uuid.clone();
                     self.update_message_status(msg);
                     let msg_id = safe_message_id_display(&uuid);
+                    self.add_app_event(
+                        EventLevel::Warning,
+                        format!("Failed to send message {}", msg_id),
+                    );
+                }
+                ChatAppInfoEvent::ReadLocally(msg) => self.update_message_status(msg),
+                ChatAppInfoEvent::PinChanged(msg) => {
+                    let msg_id = safe_message_id_display(&msg.uuid);
+                    let action = if msg.pinned { "pinned" } else { "unpinned" };
+                    self.add_app_event(EventLevel::Info, format!("Message {} {}", msg_id, action));
+                    self.update_message_status(msg);
+                }
+                ChatAppInfoEvent::AckOverdue(uuid) => {
+                    let msg_id = safe_message_id_display(&uuid);
+                    self.add_app_event(
+                        EventLevel::Warning,
+                        format!("Message {} still not acked", msg_id),
+                    );
+                }
+                ChatAppInfoEvent::DeliveryLate(msg, predicted, margin_ms) => {
+                    let msg_id = safe_message_id_display(&msg.uuid);
+                    let (minutes, hours) = predicted.mins_hours(&self.display_prefs);
+                    self.add_app_event(
+                        EventLevel::Warning,
+                        format!(
+                            "Message {} predicted at {:02}:{:02} still not acked {} ms later",
+                            msg_id, hours, minutes, margin_ms
+                        ),
+                    );
+                }
+                ChatAppInfoEvent::Cancelled(msg) => {
+                    let uuid = msg.uuid.clone();
+                    self.update_message_status(msg);
+                    let msg_id = safe_message_id_display(&uuid);
+                    self.add_app_event(EventLevel::Info, format!("Message {} cancelled", msg_id));
+                }
+                ChatAppInfoEvent::Expired(msg) => {
+                    let uuid = msg.uuid.clone();
+                    self.update_message_status(msg);
+                    let msg_id = safe_message_id_display(&uuid);
+                    self.add_app_event(
+                        EventLevel::Warning,
+                        format!("Message {} expired before delivery", msg_id),
+                    );
+                }
+                ChatAppInfoEvent::ConfigReloaded(diff) => {
+                    self.add_app_event(
+                        EventLevel::Info,
+                        format!("Configuration reloaded: {}", diff),
+                    );
+                }
+                ChatAppInfoEvent::ConfigConflictResolved(conflict) => {
+                    self.add_app_event(
+                        EventLevel::Warning,
+                        format!("Configuration conflict, {}", conflict),
+                    );
+                }
+                ChatAppInfoEvent::IdentitySwitched(peer) => {
+                    self.local_uuid = peer.uuid.clone();
+                    self.add_app_event(
+                        EventLevel::Info,
+                        format!("Now acting as {} ({})", peer.name, peer.uuid),
+                    );
+                }
+                ChatAppInfoEvent::LinkDegraded(status) => {
+                    self.add_app_event(
+                        EventLevel::Warning,
+                        format!(
+                            "Link to {} degraded ({} failures out of {})",
+                            status.endpoint,
+                            status.failures,
+                            status.failures + status.successes
+                        ),
+                    );
+                }
+                ChatAppInfoEvent::LinkRecovered(status) => {
+                    self.add_app_event(
+                        EventLevel::Info,
+                        format!("Link to {} recovered", status.endpoint),
+                    );
+                }
+                ChatAppInfoEvent::ContactPlanExpiring(horizon) => {
+                    let (minutes, hours) = horizon.mins_hours(&self.display_prefs);
+                    self.add_app_event(
+                        EventLevel::Warning,
+                        format!(
+                            "Contact plan has no contact after {:02}:{:02}, upload a new one",
+                            hours, minutes
+                        ),
+                    );
+                }
+                ChatAppInfoEvent::LatencyBudgetExceeded(room) => {
+                    self.add_app_event(
+                        EventLevel::Warning,
+                        format!("Room {} exceeded its latency budget", room.name),
+                    );
+                }
+                ChatAppInfoEvent::CatchUp(summary) => {
+                    let hours = (summary.partition_end.timestamp_millis()
+                        - summary.partition_start.timestamp_millis())
+                        / 3_600_000;
+                    self.add_app_event(
+                        EventLevel::Info,
+                        format!(
+                            "Back in touch with {}: you missed {} messages in {} rooms over {}h",
+                            summary.remote,
+                            summary.total(),
+                            summary.rooms.len(),
+                            hours
+                        ),
+                    );
+                }
+                ChatAppInfoEvent::CanaryFailed(peer_uuid, reason) => {
+                    self.add_app_event(
+                        EventLevel::Error,
+                        format!("Canary to peer {} failed: {}", peer_uuid, reason),
+                    );
+                }
+                ChatAppInfoEvent::ContactBudgetExceeded(msg, budget) => {
+                    let (minutes, hours) = budget.end.mins_hours(&self.display_prefs);
+                    self.add_app_event(
+                        EventLevel::Warning,
+                        format!(
+                            "Message {} too large for the next contact ({} bytes until {:02}:{:02})",
+                            msg.uuid, budget.bytes, hours, minutes
+                        ),
+                    );
+                }
+                ChatAppInfoEvent::BandwidthThrottled(msg, used, limit) => {
+                    self.add_app_event(
+                        EventLevel::Info,
+                        format!(
+                            "File {} held by the bandwidth budget ({} of {} bytes sent)",
+                            safe_message_id_display(&msg.uuid),
+                            used,
+                            limit
+                        ),
+                    );
+                }
+                ChatAppInfoEvent::PeerKeyRecorded(peer_uuid, fingerprint) => {
+                    self.add_app_event(
+                        EventLevel::Info,
+                        format!("Recorded key {} of peer {}", fingerprint, peer_uuid),
+                    );
+                }
+                ChatAppInfoEvent::PeerKeyChanged(peer_uuid, fingerprint) => {
+                    self.add_app_event(
+                        EventLevel::Warning,
+                        format!(
+                            "Peer {} presented key {} instead of the recorded one",
+                            peer_uuid, fingerprint
+                        ),
+                    );
+                }
+                ChatAppInfoEvent::PeerKeyRotated(peer_uuid, fingerprint) => {
                     self.add_app_event(
                         EventLevel::Info,
-                        format!("Ack received for message {}", msg_id),
+                        format!("Peer {} rotated its key to {}", peer_uuid, fingerprint),
+                    );
+                }
+                ChatAppInfoEvent::PeerPurged(summary) => {
+                    self.add_app_event(
+                        EventLevel::Info,
+                        format!(
+                            "Purged peer {}: {} messages, {} files, {} drafts, {} sends cancelled",
+                            summary.peer_uuid,
+                            summary.messages,
+                            summary.files,
+                            summary.drafts,
+                            summary.cancelled_sends
+                        ),
+                    );
+                }
+                ChatAppInfoEvent::PeerProfileUpdated(peer_uuid, profile) => {
+                    self.add_app_event(
+                        EventLevel::Info,
+                        format!(
+                            "Peer {} calls itself {} ({}), self-asserted",
+                            peer_uuid,
+                            profile.name.as_deref().unwrap_or("-"),
+                            profile.color.as_deref().unwrap_or("-")
+                        ),
+                    );
+                }
+                ChatAppInfoEvent::PeerInfo(_request_uuid, info) => {
+                    self.add_app_event(
+                        EventLevel::Info,
+                        format!(
+                            "Peer {} runs version {} ({}), capabilities: {}",
+                            info.peer_uuid,
+                            info.version,
+                            info.git_hash,
+                            info.capabilities.join(", ")
+                        ),
+                    );
+                }
+                ChatAppInfoEvent::FileOffered(offer) => {
+                    self.add_app_event(
+                        EventLevel::Info,
+                        format!(
+                            "Peer {} offers file {} ({} bytes)",
+                            offer.sender_uuid, offer.name, offer.size
+                        ),
+                    );
+                }
+                ChatAppInfoEvent::PossibleLoss(peer_uuid, room_uuid, missing) => {
+                    self.add_app_event(
+                        EventLevel::Warning,
+                        format!(
+                            "{} messages of peer {} missing in room {}",
+                            missing.len(),
+                            peer_uuid,
+                            room_uuid
+                        ),
+                    );
+                }
+                ChatAppInfoEvent::HistoryBackfilled(peer_uuid, room_uuid, count) => {
+                    if count > 0 {
+                        self.add_app_event(
+                            EventLevel::Info,
+                            format!(
+                                "Recovered {} messages of room {} from peer {}",
+                                count, room_uuid, peer_uuid
+                            ),
+                        );
+                    }
+                }
+                ChatAppInfoEvent::ConfigWarning(diagnostic) => {
+                    self.add_app_event(
+                        EventLevel::Warning,
+                        format!("Configuration {}", diagnostic),
+                    );
+                }
+                ChatAppInfoEvent::UnsupportedMessageType(quarantined) => {
+                    self.add_app_event(
+                        EventLevel::Info,
+                        format!(
+                            "Quarantined message {} of unknown type from peer {} (version {})",
+                            quarantined.uuid,
+                            quarantined.sender_uuid,
+                            quarantined.sender_version.as_deref().unwrap_or("unknown")
+                        ),
                     );
                 }
             },
             ChatAppEvent::Error(error_event) => {
-                let error_text = match error_event {
-                    ChatAppErrorEvent::ProtocolDecode(details) => {
-                        format!("Failed to decode protobuf: {}", details)
-                    }
-                    ChatAppErrorEvent::ProtocolEncode(details) => {
-                        format!("Failed to encode protobuf: {}", details)
-                    }
-                    ChatAppErrorEvent::InvalidMessage(details) => {
-                        format!("Invalid message format: {}", details)
-                    }
-                    ChatAppErrorEvent::MessageNotFound(msg_id) => {
-                        format!("Message {} not found in history", msg_id)
-                    }
-                    ChatAppErrorEvent::PeerNotFound(peer_id) => {
-                        format!("Unknown peer: {}", peer_id)
-                    }
-                    ChatAppErrorEvent::NoEngineAttached => {
-                        "Network engine not available".to_string()
-                    }
-                    ChatAppErrorEvent::InternalError(details) => {
-                        format!("Internal error: {}", details)
-                    }
-                };
-
-                self.add_app_event(EventLevel::Error, error_text);
+                self.add_app_event(EventLevel::Error, error_event.to_string());
             }
             ChatAppEvent::Info(info) => self.add_app_event(EventLevel::Error, info),
         }
@@ -387,55 +1240,127 @@ impl AppEventObserver for TerminalScreen {
     }
 }
 
+// Waits at most INPUT_POLL for the screen to submit a line so that the caller keeps
+// running its periodic checks
+fn next_input(screen: &Mutex<TerminalScreen>) -> Option<String> {
+    if !event::poll(INPUT_POLL).unwrap_or(false) {
+        return None;
+    }
+    let mut screen = screen.lock().unwrap();
+    let input = match event::read() {
+        Ok(Event::Key(key)) => screen.handle_key(key),
+        // Drawn again below at the new size
+        Ok(Event::Resize(..)) => None,
+        _ => return None,
+    };
+    screen.render();
+    input
+}
+
 fn main() {
-    let view_height: usize = 10;
+    let args = Args::parse();
+    // Messages kept per room for the scrollback
+    let history_lines: usize = 500;
 
-    let chat_model = Arc::new(Mutex::new(ChatModel::new()));
+    // The backend reads these from the environment, set them before building the model
+    if let Some(config) = &args.config {
+        std::env::set_var("CONFIG_PATH", config);
+    }
+    if let Some(config_format) = &args.config_format {
+        std::env::set_var("CONFIG_FORMAT", config_format);
+    }
+    if let Some(peer_uuid) = &args.peer_uuid {
+        std::env::set_var("PEER_UUID", peer_uuid);
+    }
+
+    let chat_model = Arc::new(Mutex::new(match &args.profile {
+        Some(profile) => ChatModel::new_with_identity(profile),
+        None => ChatModel::new(),
+    }));
     let mut network_engine = Engine::new();
     let local_peer = chat_model.lock().unwrap().get_localpeer();
     let binding = chat_model.lock().unwrap().get_other_peers();
     let distant_peer = binding.iter().next().unwrap().1;
+    let mut target = Target::Peer(distant_peer.uuid.clone(), distant_peer.endpoints[0].clone());
 
     network_engine.add_observer(chat_model.clone());
+    let rooms = chat_model
+        .lock()
+        .unwrap()
+        .get_rooms()
+        .into_values()
+        .map(|room| (room.uuid, room.name))
+        .collect();
     let screen = Arc::new(Mutex::new(TerminalScreen::new(
         local_peer.uuid,
-        view_height,
+        history_lines,
+        args.log_level,
+        args.headless || args.daemon,
+        chat_model.lock().unwrap().display_prefs(),
+        rooms,
     )));
 
     chat_model.lock().unwrap().add_observer(screen.clone());
-    chat_model.lock().unwrap().start(network_engine);
+    if args.listen.is_empty() {
+        chat_model.lock().unwrap().start(network_engine);
+    } else {
+        chat_model.lock().unwrap().start_on(network_engine, args.listen);
+    }
+    start_soak(chat_model.clone());
+    if let Some(Err(err)) = start_exporter(chat_model.clone()) {
+        chat_model.lock().unwrap().notify_observers(ChatAppEvent::Error(
+            ChatAppErrorEvent::InternalError(format!("Cannot start the metrics exporter: {}", err)),
+        ));
+    }
+    if let Some(Err(err)) = start_gateway(chat_model.clone()) {
+        chat_model.lock().unwrap().notify_observers(ChatAppEvent::Error(
+            ChatAppErrorEvent::InternalError(format!("Cannot start the HTTP gateway: {}", err)),
+        ));
+    }
+    if args.daemon {
+        #[cfg(unix)]
+        let started = start_ipc(chat_model.clone(), std::path::Path::new(&args.socket)).map(|_| ());
+        #[cfg(not(unix))]
+        let started: std::io::Result<()> = Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "no Unix-domain sockets on this platform",
+        ));
+        // Nothing could drive the daemon without its socket
+        if let Err(err) = started {
+            screen.lock().unwrap().close();
+            eprintln!("Cannot start the control socket {}: {}", args.socket, err);
+            std::process::exit(1);
+        }
+    }
 
     loop {
+        {
+            let mut model = chat_model.lock().unwrap();
+            let now = model.now();
+            model.tick(now);
+        }
         screen.lock().unwrap().render();
 
-        let mut input = String::new();
-        if io::stdin().read_line(&mut input).is_ok() {
+        // The daemon is driven through its control socket only, the headless mode runs
+        // detached from any terminal and takes no input
+        let input = if args.daemon || args.headless {
+            thread::sleep(INPUT_POLL);
+            None
+        } else {
+            next_input(&screen)
+        };
+        if let Some(input) = input {
             let input = input.trim();
             if input == "quit" || input == "exit" {
                 break;
             }
-            if !input.is_empty() {
-                chat_model.lock().unwrap().send_to_peer(
-                    &Content::Text(input.to_string()),
-                    &"room".to_string(),
-                    distant_peer.uuid.clone(),
-                    &distant_peer.endpoints[0],
-                    false,
-                );
-                // // Room message test
-                // chat_model.lock().unwrap().send_to_room(
-                //     &input.to_string(),
-                //     &"1".to_string(),
-                //     false,
-                // );
-                chat_model.lock().unwrap().send_to_peer(
-                    &Content::File(input.to_string()), // provide the path
-                    &"room".to_string(),
-                    distant_peer.uuid.clone(),
-                    &distant_peer.endpoints[0],
-                    false,
-                );
+            match parse_command(input) {
+                _ if input.is_empty() => {}
+                Some(Ok(command)) => run_command(command, &chat_model, &screen, &mut target),
+                Some(Err(err)) => screen.lock().unwrap().add_app_event(EventLevel::Error, err),
+                None => send_content(&chat_model, &target, Content::Text(input.to_string())),
             }
         }
     }
+    screen.lock().unwrap().close();
 }
@@ -1,27 +1,153 @@
 use core::cmp::Ordering;
-use socket_engine::endpoint::Endpoint;
+use std::collections::HashMap;
 
-use crate::{dtchat::generate_uuid, proto::ProtoMessage, time::DTChatTime};
+use serde::{Deserialize, Serialize};
 
+use crate::{
+    bundle::BundleMetadata,
+    dtchat::generate_uuid,
+    error::ChatError,
+    file_info::{AudioInfo, FileInfo},
+    net::{Endpoint, EndpointProto},
+    prediction::PredictionSkip,
+    proto::ProtoMessage,
+    time::DTChatTime,
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RoomMessage {
     pub uuid: String,
     pub room_uuid: String,
     pub messages: Vec<String>, // list of uuid replica
+    pub peers: Vec<String>,    // recipient of each replica, same order
+    // Sent as a single bundle, the replicas go by the uuid of the room message on the wire
+    #[serde(default)]
+    pub bundled: bool,
+}
+
+// Outcome of send_to_room and send_to_rooms per participant
+#[derive(Clone, Debug)]
+pub struct RoomSendReport {
+    // With a replica per participant whose send was handed over or queued
+    pub room_msg: RoomMessage,
+    // (peer uuid, endpoint, error) of the sends refused at once, to retry with send_to_peer
+    pub failed: Vec<(String, Endpoint, ChatError)>,
+}
+
+impl RoomSendReport {
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RoomMessageStatus {
+    pub room_message_uuid: String,
+    // (peer uuid, status), None when the replica cannot be found
+    pub replicas: Vec<(String, Option<MessageStatus>)>,
+}
+
+impl RoomMessageStatus {
+    pub fn acked_count(&self) -> usize {
+        self.replicas
+            .iter()
+            .filter(|(_, status)| *status == Some(MessageStatus::ReceivedByPeer))
+            .count()
+    }
+
+    pub fn all_acked(&self) -> bool {
+        self.acked_count() == self.replicas.len()
+    }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum MessageStatus {
     Sending,
     Sent,
+    // Taken in custody by a BP node, not yet delivered
+    InCustody,
+    // Delivered to the node of the peer by BP, not yet acked by its chat
+    DeliveredToNode,
     ReceivedByPeer,
     Failed,
     Received,
+    Expired,
+    // Withdrawn by the local user before it left
+    Cancelled,
+    // Refused for good by the peer, e.g. over its quota, never sent again
+    Refused,
+}
+
+impl MessageStatus {
+    // Orders the statuses a message goes through, to keep the most advanced of two copies
+    pub fn progress(&self) -> u8 {
+        match self {
+            MessageStatus::Sending => 0,
+            MessageStatus::Failed
+            | MessageStatus::Expired
+            | MessageStatus::Cancelled
+            | MessageStatus::Refused => 1,
+            MessageStatus::Sent => 2,
+            MessageStatus::InCustody => 3,
+            MessageStatus::DeliveredToNode => 4,
+            MessageStatus::ReceivedByPeer | MessageStatus::Received => 5,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub enum Content {
     Text(String), // message
     File(String), // path
+    // Content type and payload of another application, not meant to be shown as a message
+    Blob(String, Vec<u8>),
+    Location(Location),
+    // Path of a voice note, sent as a file
+    Audio(String, AudioInfo),
+}
+
+impl Content {
+    // Files and voice notes
+    pub fn file_path(&self) -> Option<&String> {
+        match self {
+            Content::File(path) | Content::Audio(path, _) => Some(path),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    // Degrees, WGS 84
+    pub latitude: f64,
+    pub longitude: f64,
+    // Meters above sea level
+    pub altitude: Option<f64>,
+    // Radius in meters
+    pub accuracy: Option<f64>,
+    // When the position was taken, it may be older than the message
+    pub timestamp: DTChatTime,
+}
+
+impl Location {
+    pub fn check(&self) -> Result<(), String> {
+        if !(-90.0..=90.0).contains(&self.latitude) {
+            return Err(format!("latitude {} out of range", self.latitude));
+        }
+        if !(-180.0..=180.0).contains(&self.longitude) {
+            return Err(format!("longitude {} out of range", self.longitude));
+        }
+        if self.altitude.is_some_and(|altitude| !altitude.is_finite()) {
+            return Err("altitude is not a number".to_string());
+        }
+        if self
+            .accuracy
+            .is_some_and(|accuracy| !accuracy.is_finite() || accuracy < 0.0)
+        {
+            return Err("accuracy must be a positive number".to_string());
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -29,13 +155,54 @@ pub struct ChatMessage {
     pub uuid: String,
     pub sender_uuid: String,
     pub room_uuid: String,
+    // Other rooms of a message sent to several at once, shown in each of them
+    pub also_rooms: Vec<String>,
     pub content: Content,
+    // Uuids of the known peers named with an @ in a text, the local peer included
+    pub mentions: Vec<String>,
     pub send_time: DTChatTime,
     pub send_completed: Option<DTChatTime>,
     pub predicted_arrival_time: Option<DTChatTime>,
     pub receive_time: Option<DTChatTime>,
+    pub expires_at: Option<DTChatTime>,
     pub status: MessageStatus,
     pub source_endpoint: Endpoint,
+    // Transport level sender of a received message, source_endpoint being the claimed one
+    pub received_from: Option<Endpoint>,
+    // Bundle a message received over BP came in, when the transport exposes it
+    pub bundle: Option<BundleMetadata>,
+    // Messages of each peer seen in the room when this one was sent, empty for older peers
+    pub vector_clock: HashMap<String, u64>,
+    // Logical time in the room, 0 for older peers
+    pub lamport_time: u64,
+    // Seen by the local user, whatever frontend showed it
+    pub read_locally: bool,
+    pub pinned: bool,
+    // From a muted peer, observers should not notify it
+    pub muted: bool,
+    // Where a received file was written
+    pub stored_path: Option<String>,
+    // Local messages sent without predicted arrival time, and why
+    pub prediction_skipped: Option<PredictionSkip>,
+    // Files only
+    pub file_info: Option<FileInfo>,
+    // Among the messages of the sender to the receiver in the room, 0 when unnumbered
+    pub sequence: u64,
+}
+
+fn host(address: &str) -> &str {
+    address.rsplit_once(':').map_or(address, |(host, _)| host)
+}
+
+// TCP peers connect from an ephemeral port, only the hosts are compared for IP endpoints
+pub fn same_node(a: &Endpoint, b: &Endpoint) -> bool {
+    if a.proto != b.proto {
+        return false;
+    }
+    match a.proto {
+        EndpointProto::Bp => a.endpoint == b.endpoint,
+        _ => host(&a.endpoint) == host(&b.endpoint),
+    }
 }
 
 fn get_timestamps_frm_opt(datetime_opt: Option<DTChatTime>) -> Option<i64> {
@@ -45,54 +212,150 @@ fn get_timestamps_frm_opt(datetime_opt: Option<DTChatTime>) -> Option<i64> {
     None
 }
 
+// Names following an @ at the start of a word, e.g. "bob-2" in "ping @bob-2.", quoted when
+// they hold spaces, e.g. "Bob Smith" in "ping @\"Bob Smith\""
+pub fn mention_names(text: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut previous: Option<char> = None;
+    // Quoted names are not searched for mentions
+    let mut resume = 0;
+    for (index, c) in text.char_indices() {
+        if index >= resume && c == '@' && !previous.is_some_and(|p| p.is_alphanumeric()) {
+            let rest = &text[index + 1..];
+            let name = match rest.strip_prefix('"') {
+                Some(quoted) => quoted.find('"').map(|end| {
+                    resume = index + end + 3;
+                    quoted[..end].trim()
+                }),
+                None => {
+                    let end = rest
+                        .find(|c: char| !(c.is_alphanumeric() || matches!(c, '-' | '_' | '.')))
+                        .unwrap_or(rest.len());
+                    Some(rest[..end].trim_end_matches('.'))
+                }
+            };
+            if let Some(name) = name.filter(|name| !name.is_empty()) {
+                names.push(name);
+            }
+        }
+        previous = Some(c);
+    }
+    names
+}
+
 impl ChatMessage {
     pub fn new_to_send(
         sender_uuid: &String,
         room_uuid: &String,
         content: Content,
         source_endpoint: Endpoint,
+        send_time: DTChatTime,
     ) -> Self {
         ChatMessage {
             uuid: generate_uuid(),
             sender_uuid: sender_uuid.clone(),
             room_uuid: room_uuid.clone(),
+            also_rooms: Vec::new(),
             content: content.clone(),
-            send_time: DTChatTime::now(),
+            mentions: Vec::new(),
+            send_time,
             send_completed: None,
             predicted_arrival_time: None,
             receive_time: None,
+            expires_at: None,
             status: MessageStatus::Sending,
             source_endpoint,
+            received_from: None,
+            bundle: None,
+            vector_clock: HashMap::new(),
+            lamport_time: 0,
+            read_locally: true,
+            pinned: false,
+            muted: false,
+            stored_path: None,
+            prediction_skipped: None,
+            file_info: None,
+            sequence: 0,
         }
     }
 
+    pub fn in_room(&self, room_uuid: &str) -> bool {
+        self.room_uuid == room_uuid || self.also_rooms.iter().any(|room| room == room_uuid)
+    }
+
+    // The room it was sent to, then the others it is shown in
+    pub fn rooms(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.room_uuid).chain(&self.also_rooms)
+    }
+
     #[inline]
     pub fn content_as_string(&self) -> String {
         match &self.content {
-            Content::Text(str) | Content::File(str) => str.clone(),
+            Content::Text(str) | Content::File(str) | Content::Audio(str, _) => str.clone(),
+            Content::Blob(content_type, data) => format!("{} ({} bytes)", content_type, data.len()),
+            Content::Location(location) => {
+                format!("{:.6}, {:.6}", location.latitude, location.longitude)
+            }
         }
     }
 
-    pub fn new_received(proto_msg: &ProtoMessage, content: Content) -> Option<Self> {
+    // Voice notes only
+    pub fn duration_ms(&self) -> Option<u64> {
+        match &self.content {
+            Content::Audio(_, audio) => Some(audio.duration_ms),
+            _ => None,
+        }
+    }
+
+    pub fn new_received(
+        proto_msg: &ProtoMessage,
+        content: Content,
+        receive_time: DTChatTime,
+    ) -> Option<Self> {
         if let Some(datetime) = DTChatTime::from_timestamp_millis(proto_msg.timestamp) {
             if let Some(source_endpoint) = Endpoint::from_str(&proto_msg.source_endpoint).ok() {
                 return Some(ChatMessage {
                     uuid: proto_msg.uuid.clone(),
                     sender_uuid: proto_msg.sender_uuid.clone(),
                     room_uuid: proto_msg.room_uuid.clone(),
+                    also_rooms: proto_msg.also_rooms.clone(),
                     content,
+                    mentions: Vec::new(),
                     send_time: datetime.clone(),
                     send_completed: Some(datetime),
                     predicted_arrival_time: None,
-                    receive_time: Some(DTChatTime::now()),
+                    receive_time: Some(receive_time),
+                    expires_at: DTChatTime::from_expiration_millis(proto_msg.expires_at),
                     status: MessageStatus::Received,
                     source_endpoint,
+                    received_from: None,
+                    bundle: None,
+                    vector_clock: proto_msg.vector_clock.clone(),
+                    lamport_time: proto_msg.lamport_time,
+                    read_locally: false,
+                    pinned: false,
+                    muted: false,
+                    stored_path: None,
+                    prediction_skipped: None,
+                    file_info: None,
+                    sequence: proto_msg.sequence,
                 });
             }
         }
         None
     }
 
+    pub fn is_expired(&self, now: DTChatTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at < now)
+    }
+
+    pub fn has_source_mismatch(&self) -> bool {
+        let Some(from) = &self.received_from else {
+            return false;
+        };
+        !same_node(from, &self.source_endpoint)
+    }
+
     pub fn get_shipment_status_otp(&self) -> (DTChatTime, Option<DTChatTime>, Option<DTChatTime>) {
         return (
             self.send_time,
@@ -114,6 +377,10 @@ impl ChatMessage {
 pub enum SortStrategy {
     Standard,
     Relative(String),
+    // Follows the vector clocks rather than the send timestamps
+    Causal,
+    // Follows the Lamport timestamps, ignoring the wall clocks of the peers
+    Logical,
 }
 
 pub fn insert_with_strategy(
@@ -128,6 +395,12 @@ pub fn insert_with_strategy(
         SortStrategy::Relative(peer_uuid) => messages
             .binary_search_by(|msg| relative_cmp(msg, &new_msg, peer_uuid.as_str()))
             .unwrap_or_else(|i| i),
+        SortStrategy::Causal => messages
+            .binary_search_by(|msg| causal_cmp(msg, &new_msg))
+            .unwrap_or_else(|i| i),
+        SortStrategy::Logical => messages
+            .binary_search_by(|msg| logical_cmp(msg, &new_msg))
+            .unwrap_or_else(|i| i),
     };
     messages.insert(idx, new_msg.clone());
 }
@@ -139,6 +412,8 @@ pub fn sort_with_strategy(messages: &mut Vec<ChatMessage>, strategy: SortStrateg
         SortStrategy::Relative(for_peer) => {
             messages.sort_by(|a, b| relative_cmp(a, b, for_peer.as_str()))
         }
+        SortStrategy::Causal => messages.sort_by(causal_cmp),
+        SortStrategy::Logical => messages.sort_by(logical_cmp),
     }
 }
 pub fn standard_cmp(a: &ChatMessage, b: &ChatMessage) -> Ordering {
@@ -169,3 +444,19 @@ pub fn relative_cmp(a: &ChatMessage, b: &ChatMessage, ctx_peer_uuid: &str) -> Or
     };
     anchor_a.cmp(&anchor_b)
 }
+
+// A message sent after another one was seen has a strictly larger clock sum, so ordering
+// by the sum keeps causality and stays a total order, concurrent messages by send time
+pub fn causal_cmp(a: &ChatMessage, b: &ChatMessage) -> Ordering {
+    let sum_a: u64 = a.vector_clock.values().sum();
+    let sum_b: u64 = b.vector_clock.values().sum();
+    sum_a.cmp(&sum_b).then_with(|| standard_cmp(a, b))
+}
+
+// Ties are broken by sender then uuid, so every peer displays the same order
+pub fn logical_cmp(a: &ChatMessage, b: &ChatMessage) -> Ordering {
+    a.lamport_time
+        .cmp(&b.lamport_time)
+        .then_with(|| a.sender_uuid.cmp(&b.sender_uuid))
+        .then_with(|| a.uuid.cmp(&b.uuid))
+}
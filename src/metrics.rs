@@ -0,0 +1,194 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    io::{self, Read, Write as _},
+    net::TcpListener,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+
+use crate::{
+    dtchat::ChatModel,
+    event::{AppEventObserver, ChatAppEvent, ChatAppInfoEvent},
+    net::EndpointProto,
+};
+
+// A scraper that connects and sends nothing must not hold the exporter thread
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Upper bounds of the ACK round-trip buckets, in milliseconds
+const RTT_BUCKETS_MS: [i64; 10] = [
+    100, 500, 1_000, 5_000, 10_000, 60_000, 300_000, 900_000, 3_600_000, 86_400_000,
+];
+
+#[derive(Clone, Debug, Default)]
+pub struct Histogram {
+    // Cumulative count per bucket of RTT_BUCKETS_MS
+    pub buckets: Vec<(i64, u64)>,
+    pub count: u64,
+    pub sum: i64,
+}
+
+impl Histogram {
+    fn new(bounds: &[i64]) -> Self {
+        Self {
+            buckets: bounds.iter().map(|bound| (*bound, 0)).collect(),
+            count: 0,
+            sum: 0,
+        }
+    }
+
+    fn observe(&mut self, value: i64) {
+        for (bound, count) in self.buckets.iter_mut() {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MetricsSnapshot {
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub messages_failed: u64,
+    // Serialized bytes handed to the engine, per protocol name
+    pub bytes_sent: HashMap<String, u64>,
+    pub ack_rtt_ms: Histogram,
+    pub prediction_errors: u64,
+}
+
+impl Default for MetricsSnapshot {
+    fn default() -> Self {
+        Self {
+            messages_sent: 0,
+            messages_received: 0,
+            messages_failed: 0,
+            bytes_sent: HashMap::new(),
+            ack_rtt_ms: Histogram::new(&RTT_BUCKETS_MS),
+            prediction_errors: 0,
+        }
+    }
+}
+
+impl MetricsSnapshot {
+    // Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            ("dtchat_messages_sent_total", self.messages_sent),
+            ("dtchat_messages_received_total", self.messages_received),
+            ("dtchat_messages_failed_total", self.messages_failed),
+            ("dtchat_prediction_errors_total", self.prediction_errors),
+        ];
+        for (name, value) in counters {
+            let _ = writeln!(out, "# TYPE {} counter\n{} {}", name, name, value);
+        }
+
+        let _ = writeln!(out, "# TYPE dtchat_bytes_sent_total counter");
+        let mut protocols: Vec<_> = self.bytes_sent.iter().collect();
+        protocols.sort();
+        for (proto, bytes) in protocols {
+            let _ = writeln!(
+                out,
+                "dtchat_bytes_sent_total{{proto=\"{}\"}} {}",
+                proto, bytes
+            );
+        }
+
+        let rtt = &self.ack_rtt_ms;
+        let _ = writeln!(out, "# TYPE dtchat_ack_rtt_ms histogram");
+        for (bound, count) in &rtt.buckets {
+            let _ = writeln!(
+                out,
+                "dtchat_ack_rtt_ms_bucket{{le=\"{}\"}} {}",
+                bound, count
+            );
+        }
+        let _ = writeln!(out, "dtchat_ack_rtt_ms_bucket{{le=\"+Inf\"}} {}", rtt.count);
+        let _ = writeln!(out, "dtchat_ack_rtt_ms_sum {}", rtt.sum);
+        let _ = writeln!(out, "dtchat_ack_rtt_ms_count {}", rtt.count);
+        out
+    }
+}
+
+// Message counters come from the events, the model records what no event carries
+#[derive(Default)]
+pub struct Metrics {
+    snapshot: MetricsSnapshot,
+}
+
+impl Metrics {
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.snapshot.clone()
+    }
+
+    pub(crate) fn record_bytes_sent(&mut self, proto: &EndpointProto, bytes: usize) {
+        let proto = format!("{:?}", proto).to_lowercase();
+        *self.snapshot.bytes_sent.entry(proto).or_default() += bytes as u64;
+    }
+
+    pub(crate) fn record_prediction_error(&mut self) {
+        self.snapshot.prediction_errors += 1;
+    }
+}
+
+impl AppEventObserver for Metrics {
+    fn on_event(&mut self, event: ChatAppEvent) {
+        let ChatAppEvent::Message(info) = event else {
+            return;
+        };
+        match info {
+            ChatAppInfoEvent::Sent(_) => self.snapshot.messages_sent += 1,
+            ChatAppInfoEvent::Received(_) => self.snapshot.messages_received += 1,
+            ChatAppInfoEvent::Failed(_)
+            | ChatAppInfoEvent::Expired(_)
+            | ChatAppInfoEvent::Refused(_, _) => {
+                self.snapshot.messages_failed += 1
+            }
+            ChatAppInfoEvent::AckReceived(msg, _) => {
+                if let Some(receive_time) = msg.receive_time {
+                    self.snapshot.ack_rtt_ms.observe(
+                        receive_time.timestamp_millis() - msg.send_time.timestamp_millis(),
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Answers every HTTP request with the current snapshot, for Prometheus scrapes,
+// None when no metrics_address is configured
+pub fn start_exporter(model: Arc<Mutex<ChatModel>>) -> Option<io::Result<JoinHandle<()>>> {
+    let address = model.lock().unwrap().metrics_address()?;
+    let listener = match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(err) => return Some(Err(err)),
+    };
+    Some(Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            if stream.set_read_timeout(Some(READ_TIMEOUT)).is_err() {
+                continue;
+            }
+            // The request itself does not matter
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request);
+            let body = model.lock().unwrap().metrics_snapshot().to_prometheus();
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+        }
+    })))
+}
@@ -0,0 +1,337 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    bundle::{BundleMetadata, BundleStatus, BundleStatusReport},
+    net::{
+        ConnectionEvent, DataEvent, Endpoint, EndpointProto, EngineObserver, ErrorEvent,
+        SocketEngineEvent,
+    },
+    time::DTChatTime,
+    transport::Transport,
+};
+
+// Given to the bundles of the BP sends
+const BUNDLE_LIFETIME_MS: u64 = 3_600_000;
+
+type Observer = Arc<Mutex<dyn EngineObserver + Send>>;
+
+struct InFlight {
+    id: u64,
+    from: Endpoint,
+    to: Endpoint,
+    data: Vec<u8>,
+    token: String,
+    sender: Observer,
+    sent_at: DTChatTime,
+}
+
+// What a test or a simulator can see of a send not delivered yet
+#[derive(Clone, Debug)]
+pub struct InFlightSend {
+    pub id: u64,
+    pub from: Endpoint,
+    pub to: Endpoint,
+    pub data: Vec<u8>,
+}
+
+#[derive(Default)]
+struct NetworkState {
+    next_id: u64,
+    // Per listening endpoint, as given by to_string
+    listeners: HashMap<String, Observer>,
+    // Sends in the order they were made, nothing moves until the network is stepped
+    in_flight: VecDeque<InFlight>,
+    // Events raised while the model was locked by the call, given on the next step
+    notifications: VecDeque<(Observer, SocketEngineEvent)>,
+    // Of the BP sends delivered, until the receiver takes it: receiving and sending
+    // endpoints, as given by to_string, with the primary block
+    bundles: Vec<(String, String, BundleMetadata)>,
+    // Delivered reports of the BP sends, per sending endpoint as given by to_string
+    status_reports: Vec<(String, BundleStatusReport)>,
+}
+
+// In-process network shared by the MockEngines of several nodes. Every event is given
+// to the nodes on the thread stepping the network, which must not hold their locks
+#[derive(Clone, Default)]
+pub struct MockNetwork {
+    state: Arc<Mutex<NetworkState>>,
+}
+
+impl MockNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The engine of a node, its events are given to node
+    pub fn engine(&self, node: Observer) -> MockEngine {
+        MockEngine {
+            network: self.clone(),
+            node,
+            listening: Vec::new(),
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.state.lock().unwrap().in_flight.len()
+    }
+
+    // Oldest first
+    pub fn peek(&self) -> Vec<InFlightSend> {
+        self.state
+            .lock()
+            .unwrap()
+            .in_flight
+            .iter()
+            .map(|send| InFlightSend {
+                id: send.id,
+                from: send.from.clone(),
+                to: send.to.clone(),
+                data: send.data.clone(),
+            })
+            .collect()
+    }
+
+    fn notify(&self, observer: &Observer, event: SocketEngineEvent) {
+        observer.lock().unwrap().on_engine_event(event);
+    }
+
+    // Gives the pending listener and error events
+    pub fn flush(&self) {
+        loop {
+            let next = self.state.lock().unwrap().notifications.pop_front();
+            let Some((observer, event)) = next else {
+                return;
+            };
+            self.notify(&observer, event);
+        }
+    }
+
+    fn take(&self, id: Option<u64>) -> Option<InFlight> {
+        self.flush();
+        let mut state = self.state.lock().unwrap();
+        let index = match id {
+            Some(id) => state.in_flight.iter().position(|send| send.id == id)?,
+            None => 0,
+        };
+        state.in_flight.remove(index)
+    }
+
+    fn report_sent(&self, send: &InFlight) {
+        self.notify(
+            &send.sender,
+            SocketEngineEvent::Data(DataEvent::Sending {
+                token: send.token.clone(),
+                to: send.to.clone(),
+                bytes: send.data.len(),
+            }),
+        );
+        self.notify(
+            &send.sender,
+            SocketEngineEvent::Data(DataEvent::Sent {
+                token: send.token.clone(),
+                to: send.to.clone(),
+                bytes_sent: send.data.len(),
+            }),
+        );
+    }
+
+    // The BP agent of the receiver reads the primary block, the one of the sender gets
+    // a delivery report
+    fn report_bundle(&self, send: &InFlight) {
+        let mut state = self.state.lock().unwrap();
+        let metadata = BundleMetadata {
+            source_eid: send.from.endpoint.clone(),
+            creation_time: send.sent_at,
+            lifetime_ms: BUNDLE_LIFETIME_MS,
+        };
+        state
+            .bundles
+            .push((send.to.to_string(), send.from.to_string(), metadata));
+        let report = BundleStatusReport {
+            token: send.token.clone(),
+            status: BundleStatus::Delivered,
+            node_eid: send.to.endpoint.clone(),
+            time: DTChatTime::now(),
+        };
+        state.status_reports.push((send.from.to_string(), report));
+    }
+
+    // Delivers the oldest send, the sender gets a connection failure when nobody listens
+    // on its endpoint. False when nothing is in flight
+    pub fn deliver_next(&self) -> bool {
+        self.deliver_send(None)
+    }
+
+    // False when the send is not in flight
+    pub fn deliver(&self, id: u64) -> bool {
+        self.deliver_send(Some(id))
+    }
+
+    fn deliver_send(&self, id: Option<u64>) -> bool {
+        let Some(send) = self.take(id) else {
+            return false;
+        };
+        let receiver = self
+            .state
+            .lock()
+            .unwrap()
+            .listeners
+            .get(&send.to.to_string())
+            .cloned();
+        match receiver {
+            Some(receiver) => {
+                self.report_sent(&send);
+                if send.to.proto == EndpointProto::Bp {
+                    self.report_bundle(&send);
+                }
+                self.notify(
+                    &receiver,
+                    SocketEngineEvent::Data(DataEvent::Received {
+                        data: send.data,
+                        from: send.from,
+                    }),
+                );
+            }
+            None => self.notify(
+                &send.sender,
+                SocketEngineEvent::Error(ErrorEvent::ConnectionFailed {
+                    endpoint: send.to,
+                    reason: "nothing listens on this endpoint".to_string(),
+                    token: send.token,
+                }),
+            ),
+        }
+        true
+    }
+
+    // Drops the oldest send on the way, the sender still sees it sent
+    pub fn drop_next(&self) -> bool {
+        self.drop_in_flight(None)
+    }
+
+    pub fn drop_send(&self, id: u64) -> bool {
+        self.drop_in_flight(Some(id))
+    }
+
+    fn drop_in_flight(&self, id: Option<u64>) -> bool {
+        let Some(send) = self.take(id) else {
+            return false;
+        };
+        self.report_sent(&send);
+        true
+    }
+
+    // The oldest send fails, the sender gets a SendFailed as from a refused socket
+    pub fn fail_next(&self) -> bool {
+        let Some(send) = self.take(None) else {
+            return false;
+        };
+        self.notify(
+            &send.sender,
+            SocketEngineEvent::Error(ErrorEvent::SendFailed {
+                endpoint: send.to,
+                reason: "failed by the test".to_string(),
+                token: send.token,
+            }),
+        );
+        true
+    }
+
+    // Delivers until nothing is in flight, the acks and answers included.
+    // Returns the number of sends delivered
+    pub fn deliver_all(&self) -> usize {
+        let mut delivered = 0;
+        while self.deliver_next() {
+            delivered += 1;
+        }
+        delivered
+    }
+}
+
+// Transport of one node on a MockNetwork
+pub struct MockEngine {
+    network: MockNetwork,
+    node: Observer,
+    listening: Vec<Endpoint>,
+}
+
+impl Transport for MockEngine {
+    fn start_listener(&mut self, endpoint: Endpoint) {
+        let mut state = self.network.state.lock().unwrap();
+        state
+            .listeners
+            .insert(endpoint.to_string(), self.node.clone());
+        state.notifications.push_back((
+            self.node.clone(),
+            SocketEngineEvent::Connection(ConnectionEvent::ListenerStarted {
+                endpoint: endpoint.clone(),
+            }),
+        ));
+        self.listening.push(endpoint);
+    }
+
+    // Sent from the first listening endpoint unless from is given
+    fn send(&mut self, from: Option<Endpoint>, to: Endpoint, data: Vec<u8>, token: String) {
+        let mut state = self.network.state.lock().unwrap();
+        let Some(from) = from.or_else(|| self.listening.first().cloned()) else {
+            state.notifications.push_back((
+                self.node.clone(),
+                SocketEngineEvent::Error(ErrorEvent::SendFailed {
+                    endpoint: to,
+                    reason: "the node listens on no endpoint".to_string(),
+                    token,
+                }),
+            ));
+            return;
+        };
+        let id = state.next_id;
+        state.next_id += 1;
+        state.in_flight.push_back(InFlight {
+            id,
+            from,
+            to,
+            data,
+            token,
+            sender: self.node.clone(),
+            sent_at: DTChatTime::now(),
+        });
+    }
+
+    // Sends are only withdrawn before the network delivers them
+    fn cancel(&mut self, token: &str) -> bool {
+        let mut state = self.network.state.lock().unwrap();
+        let count = state.in_flight.len();
+        state.in_flight.retain(|send| send.token != token);
+        state.in_flight.len() != count
+    }
+
+    // Of the last bundle from this endpoint delivered to the node
+    fn take_bundle_metadata(&mut self, from: &Endpoint) -> Option<BundleMetadata> {
+        let mut state = self.network.state.lock().unwrap();
+        let from = from.to_string();
+        let index = state.bundles.iter().rposition(|(to, sender, _)| {
+            *sender == from
+                && self
+                    .listening
+                    .iter()
+                    .any(|listening| listening.to_string() == *to)
+        })?;
+        Some(state.bundles.remove(index).2)
+    }
+
+    fn take_status_reports(&mut self) -> Vec<BundleStatusReport> {
+        let mut state = self.network.state.lock().unwrap();
+        let (mine, others): (Vec<_>, Vec<_>) = std::mem::take(&mut state.status_reports)
+            .into_iter()
+            .partition(|(from, _)| {
+                self.listening
+                    .iter()
+                    .any(|listening| listening.to_string() == *from)
+            });
+        state.status_reports = others;
+        mine.into_iter().map(|(_, report)| report).collect()
+    }
+}
@@ -0,0 +1,125 @@
+// Endpoint and engine event types the model is written against: those of the socket engine
+// with the native feature, the same shapes without it so that the core builds for wasm32
+// and sends through a Transport of the application
+
+#[cfg(feature = "native")]
+pub use socket_engine::{
+    endpoint::{Endpoint, EndpointProto},
+    event::{ConnectionEvent, DataEvent, EngineObserver, ErrorEvent, SocketEngineEvent},
+};
+
+#[cfg(not(feature = "native"))]
+pub use portable::*;
+
+#[cfg(not(feature = "native"))]
+mod portable {
+    use std::fmt;
+
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    pub enum EndpointProto {
+        Tcp,
+        Udp,
+        Bp,
+    }
+
+    // "tcp 127.0.0.1:8000", "bp ipn:1.2", as written by the socket engine
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct Endpoint {
+        pub proto: EndpointProto,
+        pub endpoint: String,
+    }
+
+    impl Endpoint {
+        // Inherent like the one of the socket engine, the callers do not import FromStr
+        #[allow(clippy::should_implement_trait)]
+        pub fn from_str(value: &str) -> Result<Self, String> {
+            let Some((proto, endpoint)) = value.trim().split_once(char::is_whitespace) else {
+                return Err(format!("'{}': expected a protocol and an address", value));
+            };
+            let proto = match proto.to_lowercase().as_str() {
+                "tcp" => EndpointProto::Tcp,
+                "udp" => EndpointProto::Udp,
+                "bp" => EndpointProto::Bp,
+                other => return Err(format!("'{}': unknown protocol {}", value, other)),
+            };
+            let endpoint = endpoint.trim();
+            if endpoint.is_empty() {
+                return Err(format!("'{}': empty address", value));
+            }
+            Ok(Self {
+                proto,
+                endpoint: endpoint.to_string(),
+            })
+        }
+    }
+
+    impl fmt::Display for Endpoint {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let proto = match self.proto {
+                EndpointProto::Tcp => "tcp",
+                EndpointProto::Udp => "udp",
+                EndpointProto::Bp => "bp",
+            };
+            write!(f, "{} {}", proto, self.endpoint)
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    pub enum DataEvent {
+        Received {
+            data: Vec<u8>,
+            from: Endpoint,
+        },
+        Sending {
+            token: String,
+            to: Endpoint,
+            bytes: usize,
+        },
+        Sent {
+            token: String,
+            to: Endpoint,
+            bytes_sent: usize,
+        },
+    }
+
+    #[derive(Clone, Debug)]
+    pub enum ConnectionEvent {
+        ListenerStarted { endpoint: Endpoint },
+        Established { remote: Endpoint },
+        Closed { remote: Option<Endpoint> },
+    }
+
+    #[derive(Clone, Debug)]
+    pub enum ErrorEvent {
+        ConnectionFailed {
+            endpoint: Endpoint,
+            reason: String,
+            token: String,
+        },
+        SendFailed {
+            endpoint: Endpoint,
+            reason: String,
+            token: String,
+        },
+        ReceiveFailed {
+            endpoint: Endpoint,
+            reason: String,
+        },
+        SocketError {
+            endpoint: Endpoint,
+            reason: String,
+        },
+    }
+
+    #[derive(Clone, Debug)]
+    pub enum SocketEngineEvent {
+        Data(DataEvent),
+        Connection(ConnectionEvent),
+        Error(ErrorEvent),
+    }
+
+    // What a Transport reports its events to
+    pub trait EngineObserver {
+        fn on_engine_event(&mut self, event: SocketEngineEvent);
+    }
+}
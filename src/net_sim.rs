@@ -0,0 +1,217 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+};
+
+use crate::{
+    mock_engine::{InFlightSend, MockNetwork},
+    net::Endpoint,
+    time::{SimulatedTimeSource, TimeSource},
+};
+
+// Of the sends to an endpoint, everything off by default
+#[derive(Clone, Debug, Default)]
+pub struct LinkConditions {
+    pub latency_ms: i64,
+    // Added or removed at random, at most this much
+    pub jitter_ms: i64,
+    // Probabilities from 0 to 1
+    pub loss: f64,
+    // A reordered send arrives after the ones made right after it
+    pub reorder: f64,
+}
+
+// Delivers the sends of a MockNetwork as a real link would, on a simulated clock that
+// only moves with advance. Give the clock to the models with ChatModel::set_time_source
+// so that they see the same time. The same seed gives the same run
+pub struct NetworkSimulator {
+    network: MockNetwork,
+    clock: Arc<SimulatedTimeSource>,
+    start_ms: i64,
+    default_conditions: LinkConditions,
+    // Per destination endpoint, as given by to_string
+    conditions: HashMap<String, LinkConditions>,
+    // Absolute times the link to a destination endpoint is up, always up without windows
+    windows: HashMap<String, Vec<(i64, i64)>>,
+    // Due time and send id, and whether the send is lost
+    schedule: BTreeMap<(i64, u64), bool>,
+    // Sends in the schedule or held until a window that never comes
+    seen: HashSet<u64>,
+    rng: u64,
+}
+
+// Seconds of a relative "+N" time or of a plain number, absolute dates are not supported
+fn plan_time(field: &str) -> Option<i64> {
+    let seconds: f64 = field.strip_prefix('+').unwrap_or(field).parse().ok()?;
+    Some((seconds * 1000.0) as i64)
+}
+
+impl NetworkSimulator {
+    pub fn new(network: MockNetwork, clock: Arc<SimulatedTimeSource>, seed: u64) -> Self {
+        Self {
+            network,
+            start_ms: clock.now_millis(),
+            clock,
+            default_conditions: LinkConditions::default(),
+            conditions: HashMap::new(),
+            windows: HashMap::new(),
+            schedule: BTreeMap::new(),
+            seen: HashSet::new(),
+            // xorshift never leaves 0
+            rng: seed.wrapping_add(0x9E37_79B9_7F4A_7C15) | 1,
+        }
+    }
+
+    // For the endpoints without their own conditions
+    pub fn set_default_conditions(&mut self, conditions: LinkConditions) {
+        self.default_conditions = conditions;
+    }
+
+    pub fn set_conditions(&mut self, to: &Endpoint, conditions: LinkConditions) {
+        self.conditions.insert(to.to_string(), conditions);
+    }
+
+    // In milliseconds from the start of the simulation, sends made outside of them wait
+    // for the next one
+    pub fn set_windows(&mut self, to: &Endpoint, windows: Vec<(i64, i64)>) {
+        let mut windows: Vec<(i64, i64)> = windows
+            .into_iter()
+            .map(|(start, end)| (self.start_ms + start, self.start_ms + end))
+            .collect();
+        windows.sort();
+        self.windows.insert(to.to_string(), windows);
+    }
+
+    // Windows from the "a contact" lines and latency from the "a range" lines of an ION
+    // contact plan, for the link from from_node to to_node reaching the endpoint to.
+    // Returns the number of contacts found
+    pub fn load_contact_plan(
+        &mut self,
+        content: &str,
+        from_node: u64,
+        to_node: u64,
+        to: &Endpoint,
+    ) -> usize {
+        let mut windows = Vec::new();
+        let mut owlt_ms = None;
+        for line in content.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [_, kind, start, end, from, dest, value] = fields[..] else {
+                continue;
+            };
+            if from.parse::<u64>() != Ok(from_node) || dest.parse::<u64>() != Ok(to_node) {
+                continue;
+            }
+            let (Some(start), Some(end)) = (plan_time(start), plan_time(end)) else {
+                continue;
+            };
+            match kind {
+                "contact" => windows.push((start, end)),
+                "range" => owlt_ms = plan_time(value),
+                _ => {}
+            }
+        }
+        let count = windows.len();
+        self.set_windows(to, windows);
+        if let Some(owlt_ms) = owlt_ms {
+            let key = to.to_string();
+            let mut conditions = self
+                .conditions
+                .get(&key)
+                .unwrap_or(&self.default_conditions)
+                .clone();
+            conditions.latency_ms = owlt_ms;
+            self.conditions.insert(key, conditions);
+        }
+        count
+    }
+
+    // In milliseconds from the start of the simulation
+    pub fn now_ms(&self) -> i64 {
+        self.clock.now_millis() - self.start_ms
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        // 53 random bits give a uniform float in [0, 1)
+        let draw = (self.next_random() >> 11) as f64 / (1u64 << 53) as f64;
+        probability > 0.0 && draw < probability
+    }
+
+    // None when no window opens anymore
+    fn next_up(&self, to: &str, time: i64) -> Option<i64> {
+        let Some(windows) = self.windows.get(to) else {
+            return Some(time);
+        };
+        windows
+            .iter()
+            .find(|(_, end)| *end > time)
+            .map(|(start, _)| time.max(*start))
+    }
+
+    fn schedule_send(&mut self, send: &InFlightSend) {
+        self.seen.insert(send.id);
+        let to = send.to.to_string();
+        let conditions = self
+            .conditions
+            .get(&to)
+            .unwrap_or(&self.default_conditions)
+            .clone();
+        let Some(departure) = self.next_up(&to, self.clock.now_millis()) else {
+            return;
+        };
+        if self.chance(conditions.loss) {
+            self.schedule.insert((departure, send.id), true);
+            return;
+        }
+        let jitter = match conditions.jitter_ms {
+            jitter_ms if jitter_ms > 0 => {
+                (self.next_random() % (2 * jitter_ms as u64 + 1)) as i64 - jitter_ms
+            }
+            _ => 0,
+        };
+        let mut arrival = departure + (conditions.latency_ms + jitter).max(0);
+        if self.chance(conditions.reorder) {
+            arrival += conditions.latency_ms + conditions.jitter_ms + 1;
+        }
+        self.schedule.insert((arrival, send.id), false);
+    }
+
+    // Moves the clock by ms, delivering or losing on the way every send due by then,
+    // the ones made by the deliveries included. Returns the number of sends delivered
+    pub fn advance(&mut self, ms: i64) -> usize {
+        let target = self.clock.now_millis() + ms;
+        let mut delivered = 0;
+        loop {
+            self.network.flush();
+            for send in self.network.peek() {
+                if !self.seen.contains(&send.id) {
+                    self.schedule_send(&send);
+                }
+            }
+            let Some((&(due, id), &lost)) = self.schedule.iter().next() else {
+                break;
+            };
+            if due > target {
+                break;
+            }
+            self.schedule.remove(&(due, id));
+            self.seen.remove(&id);
+            self.clock.set_millis(due.max(self.clock.now_millis()));
+            // Cancelled sends are not in flight anymore
+            if lost {
+                self.network.drop_send(id);
+            } else if self.network.deliver(id) {
+                delivered += 1;
+            }
+        }
+        self.clock.set_millis(target);
+        delivered
+    }
+}
@@ -0,0 +1,83 @@
+use crate::net::Endpoint;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_HASH: &str = env!("DTCHAT_GIT_HASH");
+// Message kinds understood by this version
+pub const CAPABILITIES: [&str; 9] = [
+    "text",
+    "file",
+    "ack",
+    "nack",
+    "expiration",
+    "who_are_you",
+    "history_sync",
+    "file_offer",
+    "keepalive",
+];
+
+pub fn enabled_features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if cfg!(feature = "async") {
+        features.push("async");
+    }
+    if cfg!(feature = "cbor") {
+        features.push("cbor");
+    }
+    if cfg!(feature = "ffi") {
+        features.push("ffi");
+    }
+    if cfg!(feature = "grpc") {
+        features.push("grpc");
+    }
+    if cfg!(feature = "native") {
+        features.push("native");
+    }
+    if cfg!(feature = "sled") {
+        features.push("sled");
+    }
+    if cfg!(feature = "testkit") {
+        features.push("testkit");
+    }
+    if cfg!(feature = "thumbnails") {
+        features.push("thumbnails");
+    }
+    if cfg!(feature = "webhook") {
+        features.push("webhook");
+    }
+    if cfg!(feature = "with_delay") {
+        features.push("with_delay");
+    }
+    if cfg!(feature = "contact_suppression") {
+        features.push("contact_suppression");
+    }
+    if cfg!(feature = "contact_work_area") {
+        features.push("contact_work_area");
+    }
+    if cfg!(feature = "first_depleted") {
+        features.push("first_depleted");
+    }
+    features
+}
+
+// What a node is running, for admin tooling and bug reports
+#[derive(Clone, Debug)]
+pub struct NodeInfo {
+    pub version: String,
+    pub git_hash: String,
+    pub features: Vec<String>,
+    pub config_path: String,
+    pub db_type: String,
+    pub peer_uuid: String,
+    pub endpoints: Vec<Endpoint>,
+}
+
+// Answer of a peer to a WhoAreYou query
+#[derive(Clone, Debug)]
+pub struct PeerNodeInfo {
+    pub peer_uuid: String,
+    pub version: String,
+    pub git_hash: String,
+    pub features: Vec<String>,
+    pub capabilities: Vec<String>,
+    pub endpoints: Vec<String>,
+}
@@ -1,4 +1,9 @@
-use std::{collections::HashMap, io};
+use std::{
+    collections::HashMap,
+    fs, io,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+};
 
 use a_sabr::{
     bundle::Bundle,
@@ -8,17 +13,150 @@ use a_sabr::{
     routing::{aliases::build_generic_router, Router},
     types::{Date, NodeID},
 };
+use serde::{Deserialize, Serialize};
 
-use crate::time::DTChatTime;
+use crate::{
+    net::EndpointProto,
+    time::{DTChatTime, TimeSource},
+};
 
 pub struct PredictionConfig {
     ion_to_node_id: HashMap<String, NodeID>,
     router: Box<dyn Router<NoManagement, EVLManager> + 'static + Sync + Send>,
     cp_start_time: f64,
+    // Absolute end of the last contact, in seconds
+    cp_horizon: f64,
+    windows: Vec<ContactWindow>,
+    // Per (source, destination) ION ids
+    pair_health: HashMap<(String, String), PairHealth>,
+    // The one of the model using the plan
+    time_source: Arc<dyn TimeSource>,
     pub nodes_length : usize,
     pub contacts_length : usize,
 }
 
+// Times relative to the contact plan start, in seconds, rate in bytes per second
+struct ContactWindow {
+    tx_node: String,
+    rx_node: String,
+    start: f64,
+    end: f64,
+    rate: f64,
+}
+
+// Bytes that can still be sent during a contact
+#[derive(Clone, Debug)]
+pub struct ContactBudget {
+    pub start: DTChatTime,
+    pub end: DTChatTime,
+    pub bytes: u64,
+}
+
+// Which sends get a predicted arrival time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum PredictionPolicy {
+    // As asked by the try_prediction of each send
+    #[default]
+    PerSend,
+    Always,
+    Never,
+    // Only the sends over BP, whatever they asked
+    BpOnly,
+}
+
+impl PredictionPolicy {
+    pub fn applies(&self, try_prediction: bool, proto: &EndpointProto) -> bool {
+        match self {
+            PredictionPolicy::PerSend => try_prediction,
+            PredictionPolicy::Always => true,
+            PredictionPolicy::Never => false,
+            PredictionPolicy::BpOnly => *proto == EndpointProto::Bp,
+        }
+    }
+}
+
+// Why a sent message has no predicted arrival time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PredictionSkip {
+    // By the prediction policy or the send
+    NotRequested,
+    // No contact plan loaded
+    Disabled,
+    // The local peer or the destination has no BP endpoint
+    NoBpEndpoint,
+    // The router found no route, or failed
+    NoRoute,
+}
+
+// Ongoing or next direct contact with a peer, and whether it can be reached now
+#[derive(Clone, Debug)]
+pub struct NextContact {
+    pub peer_uuid: String,
+    // None without contact plan, or when no contact with the peer is left in it
+    pub start: Option<DTChatTime>,
+    pub end: Option<DTChatTime>,
+    pub in_contact: bool,
+    // In contact, or connected to one of its endpoints
+    pub reachable: bool,
+}
+
+// Router errors in a row before a pair is no longer routed
+const MAX_CONSECUTIVE_ERRORS: u32 = 3;
+// A degraded pair is given another chance after this long
+const DEGRADED_RETRY_AFTER_MS: i64 = 60_000;
+
+#[derive(Default)]
+struct PairHealth {
+    consecutive_errors: u32,
+    last_error: String,
+    degraded_until: Option<DTChatTime>,
+}
+
+// A node pair the router failed for, chat goes on without prediction for it
+#[derive(Clone, Debug)]
+pub struct DegradedPair {
+    pub source: String,
+    pub destination: String,
+    pub last_error: String,
+    pub retry_at: DTChatTime,
+}
+
+#[derive(Clone, Debug)]
+pub enum PredictionHealth {
+    Disabled,
+    // The contact plan or the router could not be loaded
+    Error(String),
+    Healthy,
+    Degraded(Vec<DegradedPair>),
+}
+
+fn parse_relative_time(field: &str) -> Option<f64> {
+    field.strip_prefix('+').unwrap_or(field).parse().ok()
+}
+
+// Only "a contact +start +end tx rx rate" lines carry what the budget needs
+fn parse_contact_windows(cp_path: &str) -> Vec<ContactWindow> {
+    let Ok(content) = fs::read_to_string(cp_path) else {
+        return Vec::new();
+    };
+    content
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 7 || fields[0] != "a" || fields[1] != "contact" {
+                return None;
+            }
+            Some(ContactWindow {
+                start: parse_relative_time(fields[2])?,
+                end: parse_relative_time(fields[3])?,
+                tx_node: fields[4].to_string(),
+                rx_node: fields[5].to_string(),
+                rate: fields[6].parse().ok()?,
+            })
+        })
+        .collect()
+}
+
 fn extract_ion_id_from_bp_address(bp_address: &str) -> String {
     if let Some(after_ipn) = bp_address.strip_prefix("ipn:") {
         if let Some(dot_pos) = after_ipn.find('.') {
@@ -29,11 +167,21 @@ fn extract_ion_id_from_bp_address(bp_address: &str) -> String {
 }
 
 impl PredictionConfig {
-    pub fn try_init(cp_path: String, algo : &str) -> io::Result<Self> {
+    // The plan starts at the current time of the source
+    pub fn try_init(
+        cp_path: String,
+        algo: &str,
+        time_source: Arc<dyn TimeSource>,
+    ) -> io::Result<Self> {
         let cp = IONContactPlan::parse::<NoManagement, EVLManager>(&cp_path)?;
 
         let nodes_length = cp.nodes.len();
         let contacts_length = cp.contacts.len();
+        let last_contact_end = cp
+            .contacts
+            .iter()
+            .map(|contact| contact.info.end)
+            .fold(0.0, f64::max);
 
         let node_index_map: HashMap<String, NodeID> = cp.nodes
             .iter()
@@ -50,17 +198,111 @@ impl PredictionConfig {
         let router: Box<dyn Router<NoManagement, EVLManager> + Send + Sync> =
             unsafe { std::mem::transmute(router_box) };
         // in seconds
-        let cp_start_time = DTChatTime::now().timestamp_millis() as f64 / 1000.0;
+        let cp_start_time =
+            DTChatTime::now_from(time_source.as_ref()).timestamp_millis() as f64 / 1000.0;
 
         Ok(PredictionConfig {
             ion_to_node_id: node_index_map,
             router,
             cp_start_time,
+            cp_horizon: cp_start_time + last_contact_end,
+            windows: parse_contact_windows(&cp_path),
+            pair_health: HashMap::new(),
+            time_source,
             nodes_length,
             contacts_length,
         })
     }
 
+    // The plan keeps its start, only the reads of the current time move to the source
+    pub fn set_time_source(&mut self, source: Arc<dyn TimeSource>) {
+        self.time_source = source;
+    }
+
+    fn now(&self) -> DTChatTime {
+        DTChatTime::now_from(self.time_source.as_ref())
+    }
+
+    // No prediction can be made past the last contact of the plan
+    pub fn horizon(&self) -> DTChatTime {
+        DTChatTime::from_seconds(self.cp_horizon)
+    }
+
+    // Ongoing or next direct contact between the two nodes, with the current time relative
+    // to the plan start
+    fn next_window(&self, source_eid: &str, dest_eid: &str) -> Option<(&ContactWindow, f64)> {
+        let source_ion = extract_ion_id_from_bp_address(source_eid);
+        let dest_ion = extract_ion_id_from_bp_address(dest_eid);
+        let now = self.now().timestamp_millis() as f64 / 1000.0 - self.cp_start_time;
+
+        let window = self
+            .windows
+            .iter()
+            .filter(|w| w.tx_node == source_ion && w.rx_node == dest_ion && w.end > now)
+            .min_by(|a, b| a.start.total_cmp(&b.start))?;
+        Some((window, now))
+    }
+
+    // Start and end of the ongoing or next direct contact between the two nodes
+    pub fn next_contact_window(
+        &self,
+        source_eid: &str,
+        dest_eid: &str,
+    ) -> Option<(DTChatTime, DTChatTime)> {
+        let (window, _) = self.next_window(source_eid, dest_eid)?;
+        Some((
+            DTChatTime::from_seconds(window.start + self.cp_start_time),
+            DTChatTime::from_seconds(window.end + self.cp_start_time),
+        ))
+    }
+
+    // Budget of the ongoing or next direct contact between the two nodes
+    pub fn next_contact_budget(&self, source_eid: &str, dest_eid: &str) -> Option<ContactBudget> {
+        let (window, now) = self.next_window(source_eid, dest_eid)?;
+        let usable_from = window.start.max(now);
+        Some(ContactBudget {
+            start: DTChatTime::from_seconds(window.start + self.cp_start_time),
+            end: DTChatTime::from_seconds(window.end + self.cp_start_time),
+            bytes: ((window.end - usable_from) * window.rate) as u64,
+        })
+    }
+
+    pub fn health(&self) -> PredictionHealth {
+        let now = self.now();
+        let degraded: Vec<DegradedPair> = self
+            .pair_health
+            .iter()
+            .filter_map(|((source, destination), health)| {
+                let retry_at = health.degraded_until.filter(|until| *until > now)?;
+                Some(DegradedPair {
+                    source: source.clone(),
+                    destination: destination.clone(),
+                    last_error: health.last_error.clone(),
+                    retry_at,
+                })
+            })
+            .collect();
+        if degraded.is_empty() {
+            PredictionHealth::Healthy
+        } else {
+            PredictionHealth::Degraded(degraded)
+        }
+    }
+
+    fn record_router_error(&mut self, pair: (String, String), error: String, panicked: bool) {
+        tracing::warn!(source = %pair.0, destination = %pair.1, %error, panicked, "router error");
+        let now_ms = self.now().timestamp_millis();
+        let health = self.pair_health.entry(pair).or_default();
+        health.consecutive_errors += 1;
+        health.last_error = error;
+        // The router is shared by every pair, after a panic its state may be inconsistent for
+        // any of them: only the pair that panicked is kept from being routed again soon
+        if panicked || health.consecutive_errors >= MAX_CONSECUTIVE_ERRORS {
+            health.degraded_until =
+                DTChatTime::from_timestamp_millis(now_ms + DEGRADED_RETRY_AFTER_MS);
+        }
+    }
+
     pub fn get_node_id(&self, ion_id: &str) -> Option<NodeID> {
         self.ion_to_node_id.get(ion_id).copied()
     }
@@ -73,6 +315,27 @@ impl PredictionConfig {
     ) -> io::Result<DTChatTime> {
         let source_ion = extract_ion_id_from_bp_address(source_eid);
         let dest_ion = extract_ion_id_from_bp_address(dest_eid);
+        let _span =
+            tracing::debug_span!("predict", %source_ion, %dest_ion, message_size).entered();
+        let pair = (source_ion.clone(), dest_ion.clone());
+        let now = self.now();
+
+        if let Some(health) = self.pair_health.get_mut(&pair) {
+            match health.degraded_until {
+                Some(until) if until > now => {
+                    return Err(io::Error::other(format!(
+                        "Prediction degraded from ION {source_ion} to ION {dest_ion}: {}",
+                        health.last_error
+                    )));
+                }
+                // Retried once, degraded again on the next error
+                Some(_) => {
+                    health.degraded_until = None;
+                    health.consecutive_errors = MAX_CONSECUTIVE_ERRORS - 1;
+                }
+                None => {}
+            }
+        }
 
         let source_node_id = self.get_node_id(&source_ion).ok_or_else(|| {
             io::Error::new(
@@ -98,15 +361,32 @@ impl PredictionConfig {
 
         let excluded_nodes = vec![];
         // in seconds
-        let cp_send_time =
-            DTChatTime::now().timestamp_millis() as f64 / 1000.0 - self.cp_start_time;
+        let cp_send_time = now.timestamp_millis() as f64 / 1000.0 - self.cp_start_time;
+
+        let router = &mut self.router;
+        // Only with the default panic = "unwind", an abort still takes the process down
+        let routed = panic::catch_unwind(AssertUnwindSafe(|| {
+            router.route(bundle.source, &bundle, cp_send_time, &excluded_nodes)
+        }));
+        let routed = match routed {
+            Ok(Err(e)) => {
+                self.record_router_error(pair, format!("{:?}", e), false);
+                Err(e)
+            }
+            Ok(routed) => {
+                self.pair_health.remove(&pair);
+                routed
+            }
+            Err(_) => {
+                self.record_router_error(pair, "router panicked".to_string(), true);
+                return Err(io::Error::other(format!(
+                    "A-SABR router panicked from ION {source_ion} to ION {dest_ion}"
+                )));
+            }
+        };
 
-        match self
-            .router
-            .route(bundle.source, &bundle, cp_send_time, &excluded_nodes)
-        {
+        match routed {
             Ok(Some(routing_output)) => {
-                // println!("Route found from ION {} to ION {}", source_ion, dest_ion);
                 // Only display the last element
                 if let Some((_contact_ptr, (_contact, route_stages))) =
                     routing_output.first_hops.iter().last()
@@ -117,17 +397,7 @@ impl PredictionConfig {
 
                         let delay = last_stage_borrowed.at_time;
 
-                        // println!(
-                        //     "CP start time in UTC: {:?}",
-                        //     DateTime::<Utc>::from_timestamp_millis(self.cp_start_time as i64)
-                        // );
-                        // println!(
-                        //     "Delivery time in UTC: {:?}",
-                        //     DateTime::<Utc>::from_timestamp_millis((delay + self.cp_start_time) as i64)
-                        // );
-                        // println!("CP send time: {}", cp_send_time);
-                        // println!("Delay in seconds: {}", delay);
-                        //  return Ok(f64_to_utc(delay + self.cp_start_time));
+                        tracing::debug!(cp_send_time, delivery = delay, "route found");
                         return Ok(DTChatTime::from_seconds(delay + self.cp_start_time));
                     }
                 }
@@ -136,7 +406,7 @@ impl PredictionConfig {
                 ))
             }
             Ok(None) => {
-                // eprintln!("No route found from ION {} to ION {}", source_ion, dest_ion);
+                tracing::debug!("no route found");
                 Err(io::Error::new(
                     io::ErrorKind::NotFound,
                     format!("No route found from ION {source_ion} to ION {dest_ion}"),
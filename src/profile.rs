@@ -0,0 +1,98 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::migration::{read_store, write_store, Store},
+    reception::sanitize_file_name,
+};
+
+const MAX_NAME_CHARS: usize = 64;
+const MAX_COLOR_CHARS: usize = 32;
+
+// How a peer described itself in its last profile. Self-asserted: it is shown next to the
+// configured name and color, never in their place nor to address the peer
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerProfile {
+    pub name: Option<String>,
+    pub color: Option<String>,
+    pub avatar_hash: Option<String>,
+    pub received_at_ms: i64,
+}
+
+// Sanitized like the received file names, so that no control character nor path reaches
+// a frontend, and shortened. None when nothing usable is left
+pub fn sanitize_profile_name(name: &str) -> Option<String> {
+    let name: String = sanitize_file_name(name)?
+        .chars()
+        .take(MAX_NAME_CHARS)
+        .collect();
+    Some(name.trim().to_string()).filter(|name| !name.is_empty())
+}
+
+pub fn sanitize_profile_color(color: &str) -> Option<String> {
+    let color: String = color
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_COLOR_CHARS)
+        .collect();
+    Some(color.trim().to_string()).filter(|color| !color.is_empty())
+}
+
+// Names compared case-insensitively and without surrounding spaces
+pub fn same_name(first: &str, second: &str) -> bool {
+    first.trim().to_lowercase() == second.trim().to_lowercase()
+}
+
+// Last profile of each peer
+#[derive(Default)]
+pub struct ProfileStore {
+    // Per peer uuid
+    profiles: HashMap<String, PeerProfile>,
+    // Only kept in memory without it
+    path: Option<PathBuf>,
+}
+
+impl ProfileStore {
+    // The profiles are restored from the file, if any, and written back on every change
+    pub fn open(path: &str) -> io::Result<Self> {
+        let profiles = read_store(Store::PeerProfiles, Path::new(path), None)?.unwrap_or_default();
+        Ok(Self {
+            profiles,
+            path: Some(PathBuf::from(path)),
+        })
+    }
+
+    fn write(&self) -> io::Result<()> {
+        match &self.path {
+            Some(path) => write_store(path, &self.profiles, None),
+            None => Ok(()),
+        }
+    }
+
+    pub fn get(&self, peer_uuid: &str) -> Option<&PeerProfile> {
+        self.profiles.get(peer_uuid)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &PeerProfile)> {
+        self.profiles.iter()
+    }
+
+    pub fn set(&mut self, peer_uuid: &str, profile: PeerProfile) -> io::Result<()> {
+        self.profiles.insert(peer_uuid.to_string(), profile);
+        self.write()
+    }
+
+    // False when the peer sent no profile
+    pub fn remove(&mut self, peer_uuid: &str) -> io::Result<bool> {
+        if self.profiles.remove(peer_uuid).is_none() {
+            return Ok(false);
+        }
+        self.write()?;
+        Ok(true)
+    }
+}
@@ -1,12 +1,21 @@
+use std::collections::HashMap;
 use std::io::{Error, ErrorKind};
 use std::path::Path;
 
+use crate::codec::supported_codecs;
 use crate::dtchat::generate_uuid;
+use crate::file_info::FileInfo;
 use crate::message::{ChatMessage, Content};
+use crate::net::Endpoint;
 use crate::proto::proto_message::MsgType;
-use crate::proto::{AckMessage, FileMessage, ProtoMessage, TextMessage};
+use crate::proto::{
+    AckMessage, AudioMetadata, BlobMessage, FileMessage, FileOfferMessage, FileRequestMessage,
+    HistoryBackfillMessage, HistoryDigestMessage, HistoryRequestMessage, IAmMessage,
+    KeepaliveMessage, KeyRotationMessage, LocationMessage, NackMessage, PinMessage, ProfileMessage,
+    ProtoMessage, SelectiveNackMessage, TextMessage, WhoAreYouMessage,
+};
+use crate::reception::{file_hash, FileOffer};
 use prost::Message;
-use socket_engine::endpoint::Endpoint;
 
 impl ProtoMessage {
     pub fn new_text(
@@ -15,35 +24,68 @@ impl ProtoMessage {
     ) -> Result<ProtoMessage, Error> {
         let msg_type = match &msg.content {
             Content::Text(text) => Some(MsgType::Text(TextMessage { text: text.clone() })),
-            Content::File(filepath) => {
-                let path = Path::new(filepath);
-                let data = std::fs::read(filepath)?;
-
-                // Return an error if there is no file name
-                let filename = path
-                    .file_name()
-                    .ok_or_else(|| {
-                        Error::new(ErrorKind::InvalidInput, "Invalid file path: no file name")
-                    })?
-                    .to_string_lossy()
-                    .into_owned();
-
-                Some(MsgType::File(FileMessage {
-                    name: filename,
-                    data,
-                }))
+            Content::File(filepath) => Some(MsgType::File(file_message(filepath, None)?)),
+            Content::Audio(filepath, audio) => {
+                let audio = AudioMetadata {
+                    codec: audio.codec.clone(),
+                    duration_ms: audio.duration_ms,
+                };
+                Some(MsgType::File(file_message(filepath, Some(audio))?))
             }
+            Content::Blob(content_type, data) => Some(MsgType::Blob(BlobMessage {
+                content_type: content_type.clone(),
+                data: data.clone(),
+            })),
+            Content::Location(location) => Some(MsgType::Location(LocationMessage {
+                latitude: location.latitude,
+                longitude: location.longitude,
+                altitude: location.altitude,
+                accuracy: location.accuracy,
+                timestamp: location.timestamp.timestamp_millis(),
+            })),
         };
+        Ok(Self::for_message(msg, local_endpoint, msg_type))
+    }
+
+    // Only the name, size and hash of a file or voice note, the rest is sent as with new_text
+    pub fn new_file_offer(
+        msg: &ChatMessage,
+        local_endpoint: Option<Endpoint>,
+    ) -> Result<ProtoMessage, Error> {
+        let Some(filepath) = msg.content.file_path() else {
+            return Self::new_text(msg, local_endpoint);
+        };
+        let data = std::fs::read(filepath)?;
+        let msg_type = Some(MsgType::FileOffer(FileOfferMessage {
+            name: file_name(filepath)?,
+            size: data.len() as u64,
+            hash: file_hash(&data),
+        }));
+        Ok(Self::for_message(msg, local_endpoint, msg_type))
+    }
 
-        Ok(ProtoMessage {
+    fn for_message(
+        msg: &ChatMessage,
+        local_endpoint: Option<Endpoint>,
+        msg_type: Option<MsgType>,
+    ) -> ProtoMessage {
+        ProtoMessage {
             uuid: msg.uuid.clone(),
             sender_uuid: msg.sender_uuid.clone(),
             timestamp: msg.send_time.timestamp_millis(),
             room_uuid: msg.room_uuid.clone(),
             source_endpoint: local_endpoint.map_or("??".to_string(), |ep| ep.to_string()),
+            expires_at: msg
+                .expires_at
+                .map_or(0, |expires_at| expires_at.timestamp_millis()),
+            vector_clock: msg.vector_clock.clone(),
+            lamport_time: msg.lamport_time,
+            sequence: msg.sequence,
+            also_rooms: msg.also_rooms.clone(),
             msg_type,
-        })
+        }
     }
+
     pub fn new_ack(
         for_msg: &ChatMessage,
         local_peer_uuid: String,
@@ -56,19 +98,312 @@ impl ProtoMessage {
             timestamp,
             room_uuid: for_msg.room_uuid.clone(),
             source_endpoint: local_endpoint.map_or("??".to_string(), |ep| ep.to_string()),
+            expires_at: 0,
+            vector_clock: HashMap::new(),
+            lamport_time: 0,
+            sequence: 0,
+            also_rooms: Vec::new(),
             msg_type: Some(MsgType::Ack(AckMessage {
                 message_uuid: for_msg.uuid.clone(),
             })),
         }
     }
 
+    pub fn new_nack(
+        for_msg: &ChatMessage,
+        local_peer_uuid: String,
+        local_endpoint: Option<Endpoint>,
+        timestamp: i64,
+        reason: String,
+        refused: bool,
+    ) -> ProtoMessage {
+        ProtoMessage {
+            uuid: generate_uuid(),
+            sender_uuid: local_peer_uuid,
+            timestamp,
+            room_uuid: for_msg.room_uuid.clone(),
+            source_endpoint: local_endpoint.map_or("??".to_string(), |ep| ep.to_string()),
+            expires_at: 0,
+            vector_clock: HashMap::new(),
+            lamport_time: 0,
+            sequence: 0,
+            also_rooms: Vec::new(),
+            msg_type: Some(MsgType::Nack(NackMessage {
+                message_uuid: for_msg.uuid.clone(),
+                reason,
+                refused,
+            })),
+        }
+    }
+
+    pub fn new_who_are_you(
+        local_peer_uuid: String,
+        local_endpoint: Option<Endpoint>,
+        timestamp: i64,
+        public_key: Vec<u8>,
+        challenge: Vec<u8>,
+    ) -> ProtoMessage {
+        ProtoMessage {
+            uuid: generate_uuid(),
+            sender_uuid: local_peer_uuid,
+            timestamp,
+            room_uuid: String::new(),
+            source_endpoint: local_endpoint.map_or("??".to_string(), |ep| ep.to_string()),
+            expires_at: 0,
+            vector_clock: HashMap::new(),
+            lamport_time: 0,
+            sequence: 0,
+            also_rooms: Vec::new(),
+            msg_type: Some(MsgType::WhoAreYou(WhoAreYouMessage {
+                codecs: supported_codecs(),
+                public_key,
+                challenge,
+            })),
+        }
+    }
+
+    // The request uuid is expected to be set in i_am
+    pub fn new_i_am(
+        local_peer_uuid: String,
+        local_endpoint: Option<Endpoint>,
+        timestamp: i64,
+        i_am: IAmMessage,
+    ) -> ProtoMessage {
+        ProtoMessage {
+            uuid: generate_uuid(),
+            sender_uuid: local_peer_uuid,
+            timestamp,
+            room_uuid: String::new(),
+            source_endpoint: local_endpoint.map_or("??".to_string(), |ep| ep.to_string()),
+            expires_at: 0,
+            vector_clock: HashMap::new(),
+            lamport_time: 0,
+            sequence: 0,
+            also_rooms: Vec::new(),
+            msg_type: Some(MsgType::IAm(i_am)),
+        }
+    }
+
+    // History sync and pins, about a room rather than a message of it
+    pub fn new_room_control(
+        local_peer_uuid: String,
+        local_endpoint: Option<Endpoint>,
+        timestamp: i64,
+        room_uuid: String,
+        msg_type: MsgType,
+    ) -> ProtoMessage {
+        ProtoMessage {
+            uuid: generate_uuid(),
+            sender_uuid: local_peer_uuid,
+            timestamp,
+            room_uuid,
+            source_endpoint: local_endpoint.map_or("??".to_string(), |ep| ep.to_string()),
+            expires_at: 0,
+            vector_clock: HashMap::new(),
+            lamport_time: 0,
+            sequence: 0,
+            also_rooms: Vec::new(),
+            msg_type: Some(msg_type),
+        }
+    }
+
+    pub fn new_history_digest(
+        local_peer_uuid: String,
+        local_endpoint: Option<Endpoint>,
+        timestamp: i64,
+        room_uuid: String,
+        message_uuids: Vec<String>,
+        since: i64,
+    ) -> ProtoMessage {
+        Self::new_room_control(
+            local_peer_uuid,
+            local_endpoint,
+            timestamp,
+            room_uuid,
+            MsgType::HistoryDigest(HistoryDigestMessage {
+                message_uuids,
+                since,
+            }),
+        )
+    }
+
+    pub fn new_history_request(
+        local_peer_uuid: String,
+        local_endpoint: Option<Endpoint>,
+        timestamp: i64,
+        room_uuid: String,
+        message_uuids: Vec<String>,
+    ) -> ProtoMessage {
+        Self::new_room_control(
+            local_peer_uuid,
+            local_endpoint,
+            timestamp,
+            room_uuid,
+            MsgType::HistoryRequest(HistoryRequestMessage { message_uuids }),
+        )
+    }
+
+    pub fn new_history_backfill(
+        local_peer_uuid: String,
+        local_endpoint: Option<Endpoint>,
+        timestamp: i64,
+        room_uuid: String,
+        messages: Vec<ProtoMessage>,
+    ) -> ProtoMessage {
+        Self::new_room_control(
+            local_peer_uuid,
+            local_endpoint,
+            timestamp,
+            room_uuid,
+            MsgType::HistoryBackfill(HistoryBackfillMessage { messages }),
+        )
+    }
+
+    pub fn new_pin(
+        local_peer_uuid: String,
+        local_endpoint: Option<Endpoint>,
+        timestamp: i64,
+        for_msg: &ChatMessage,
+        pinned: bool,
+    ) -> ProtoMessage {
+        Self::new_room_control(
+            local_peer_uuid,
+            local_endpoint,
+            timestamp,
+            for_msg.room_uuid.clone(),
+            MsgType::Pin(PinMessage {
+                message_uuid: for_msg.uuid.clone(),
+                pinned,
+            }),
+        )
+    }
+
+    pub fn new_file_request(
+        local_peer_uuid: String,
+        local_endpoint: Option<Endpoint>,
+        timestamp: i64,
+        offer: &FileOffer,
+    ) -> ProtoMessage {
+        Self::new_room_control(
+            local_peer_uuid,
+            local_endpoint,
+            timestamp,
+            offer.room_uuid.clone(),
+            MsgType::FileRequest(FileRequestMessage {
+                message_uuid: offer.uuid.clone(),
+            }),
+        )
+    }
+
+    pub fn new_selective_nack(
+        local_peer_uuid: String,
+        local_endpoint: Option<Endpoint>,
+        timestamp: i64,
+        room_uuid: String,
+        sequences: Vec<u64>,
+        message_uuids: Vec<String>,
+    ) -> ProtoMessage {
+        Self::new_room_control(
+            local_peer_uuid,
+            local_endpoint,
+            timestamp,
+            room_uuid,
+            MsgType::SelectiveNack(SelectiveNackMessage {
+                sequences,
+                message_uuids,
+            }),
+        )
+    }
+
+    pub fn new_keepalive(
+        local_peer_uuid: String,
+        local_endpoint: Option<Endpoint>,
+        timestamp: i64,
+        reply: bool,
+    ) -> ProtoMessage {
+        Self::new_room_control(
+            local_peer_uuid,
+            local_endpoint,
+            timestamp,
+            String::new(),
+            MsgType::Keepalive(KeepaliveMessage { reply }),
+        )
+    }
+
+    pub fn new_profile(
+        local_peer_uuid: String,
+        local_endpoint: Option<Endpoint>,
+        timestamp: i64,
+        profile: ProfileMessage,
+    ) -> ProtoMessage {
+        Self::new_room_control(
+            local_peer_uuid,
+            local_endpoint,
+            timestamp,
+            String::new(),
+            MsgType::Profile(profile),
+        )
+    }
+
+    pub fn new_key_rotation(
+        local_peer_uuid: String,
+        local_endpoint: Option<Endpoint>,
+        timestamp: i64,
+        public_key: Vec<u8>,
+        signature: Vec<u8>,
+    ) -> ProtoMessage {
+        Self::new_room_control(
+            local_peer_uuid,
+            local_endpoint,
+            timestamp,
+            String::new(),
+            MsgType::KeyRotation(KeyRotationMessage {
+                public_key,
+                signature,
+            }),
+        )
+    }
+
     pub fn encode_to_vec(&self) -> Result<Vec<u8>, prost::EncodeError> {
         let mut buf: Vec<u8> = Vec::with_capacity(self.encoded_len());
         self.encode(&mut buf)?;
+        tracing::trace!(message_uuid = %self.uuid, bytes = buf.len(), "encoded");
         Ok(buf)
     }
 
     pub fn decode_from_vec(vec: Vec<u8>) -> Result<ProtoMessage, prost::DecodeError> {
-        ProtoMessage::decode(vec.as_slice())
+        Self::decode_from_slice(vec.as_slice())
+    }
+
+    pub fn decode_from_slice(data: &[u8]) -> Result<ProtoMessage, prost::DecodeError> {
+        let decoded = ProtoMessage::decode(data);
+        match &decoded {
+            Ok(msg) => tracing::trace!(message_uuid = %msg.uuid, bytes = data.len(), "decoded"),
+            Err(err) => tracing::debug!(bytes = data.len(), %err, "undecodable frame"),
+        }
+        decoded
     }
 }
+
+// Return an error if there is no file name
+fn file_name(filepath: &str) -> Result<String, Error> {
+    Ok(Path::new(filepath)
+        .file_name()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Invalid file path: no file name"))?
+        .to_string_lossy()
+        .into_owned())
+}
+
+fn file_message(filepath: &str, audio: Option<AudioMetadata>) -> Result<FileMessage, Error> {
+    let name = file_name(filepath)?;
+    let data = std::fs::read(filepath)?;
+    let info = FileInfo::from_data(&name, &data);
+    Ok(FileMessage {
+        name,
+        data,
+        mime_type: info.mime_type,
+        size: info.size,
+        thumbnail: info.thumbnail.unwrap_or_default(),
+        audio,
+    })
+}
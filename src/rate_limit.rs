@@ -0,0 +1,72 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RateLimitConfig {
+    // Sustained rate allowed per sender
+    pub messages_per_second: f64,
+    // Messages accepted at once after a quiet period
+    pub burst: u32,
+}
+
+// How often the buckets full again are dropped
+const SWEEP_INTERVAL_MS: i64 = 10_000;
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill_ms: i64,
+}
+
+// One bucket per key, sender uuid or transport endpoint
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: HashMap<String, TokenBucket>,
+    last_sweep_ms: i64,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: HashMap::new(),
+            last_sweep_ms: 0,
+        }
+    }
+
+    // A bucket refilled to the burst is the one a new key gets, forged senders and
+    // endpoints must not grow the map for good
+    fn sweep(&mut self, now_ms: i64) {
+        if now_ms - self.last_sweep_ms < SWEEP_INTERVAL_MS {
+            return;
+        }
+        self.last_sweep_ms = now_ms;
+        let burst = self.config.burst as f64;
+        let rate = self.config.messages_per_second;
+        self.buckets.retain(|_, bucket| {
+            let elapsed_s = (now_ms - bucket.last_refill_ms).max(0) as f64 / 1000.0;
+            bucket.tokens + elapsed_s * rate < burst
+        });
+    }
+
+    // Takes a token from the bucket of the key, false when it is empty
+    pub fn allow(&mut self, key: &str, now_ms: i64) -> bool {
+        self.sweep(now_ms);
+        let burst = self.config.burst as f64;
+        let bucket = self.buckets.entry(key.to_string()).or_insert(TokenBucket {
+            tokens: burst,
+            last_refill_ms: now_ms,
+        });
+        let elapsed_s = (now_ms - bucket.last_refill_ms).max(0) as f64 / 1000.0;
+        bucket.tokens = (bucket.tokens + elapsed_s * self.config.messages_per_second).min(burst);
+        bucket.last_refill_ms = now_ms;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    pub fn forget(&mut self, key: &str) {
+        self.buckets.remove(key);
+    }
+}
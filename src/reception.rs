@@ -0,0 +1,172 @@
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{net::Endpoint, time::DTChatTime};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RoomReceptionConfig {
+    pub room_uuid: String,
+    pub dir: String,
+    pub quota_bytes: Option<u64>,
+}
+
+// Extensions are compared without the dot and ignoring case
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FilePolicy {
+    // Empty to accept any extension not denied
+    #[serde(default)]
+    pub allowed_extensions: Vec<String>,
+    #[serde(default)]
+    pub denied_extensions: Vec<String>,
+}
+
+impl FilePolicy {
+    pub fn check(&self, file_name: &str) -> Result<(), String> {
+        let extension = Path::new(file_name)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let listed = |extensions: &[String]| {
+            extensions.iter().any(|listed| {
+                listed
+                    .trim_start_matches('.')
+                    .eq_ignore_ascii_case(&extension)
+            })
+        };
+        if listed(&self.denied_extensions) {
+            return Err(format!("extension '{}' is denied", extension));
+        }
+        if !self.allowed_extensions.is_empty() && !listed(&self.allowed_extensions) {
+            return Err(format!("extension '{}' is not allowed", extension));
+        }
+        Ok(())
+    }
+}
+
+// Names come from the peers, only the last component is kept so that nothing is written
+// outside the reception folder, None when no usable name is left
+pub fn sanitize_file_name(name: &str) -> Option<String> {
+    let last = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let cleaned: String = last
+        .chars()
+        .filter(|c| !c.is_control() && *c != ':')
+        .collect();
+    // No hidden files, nor "." and ".."
+    let cleaned = cleaned.trim().trim_start_matches('.');
+    if cleaned.is_empty() {
+        return None;
+    }
+    Some(cleaned.to_string())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoomReception {
+    pub dir: PathBuf,
+    pub quota_bytes: Option<u64>,
+}
+
+// Per sender subfolders included
+pub fn dir_usage(dir: &Path) -> io::Result<u64> {
+    let mut total = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            total += metadata.len();
+        } else if metadata.is_dir() {
+            total += dir_usage(&entry.path())?;
+        }
+    }
+    Ok(total)
+}
+
+// What to do when a received file has the name of one already stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum CollisionPolicy {
+    // report.pdf is stored as report-1.pdf, report-2.pdf..
+    #[default]
+    Rename,
+    // One subfolder per sender uuid, renaming within it
+    PerSender,
+    Reject,
+}
+
+// Where a received file is written, Err with the reason when the policy rejects it
+pub fn stored_file_path(
+    folder: &Path,
+    file_name: &str,
+    sender_uuid: &str,
+    policy: CollisionPolicy,
+) -> Result<PathBuf, String> {
+    let folder = match policy {
+        CollisionPolicy::PerSender => {
+            let subfolder = sanitize_file_name(sender_uuid).unwrap_or("unknown".to_string());
+            let folder = folder.join(subfolder);
+            fs::create_dir_all(&folder)
+                .map_err(|err| format!("unable to create the sender folder: {err}"))?;
+            folder
+        }
+        _ => folder.to_path_buf(),
+    };
+    let path = folder.join(file_name);
+    if !path.exists() {
+        return Ok(path);
+    }
+    if policy == CollisionPolicy::Reject {
+        return Err(format!("a file named {} was already received", file_name));
+    }
+    let name = Path::new(file_name);
+    let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = name
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|suffix| folder.join(format!("{}-{}{}", stem, suffix, extension)))
+        .find(|path| !path.exists())
+        .ok_or_else(|| format!("no free name for {}", file_name))
+}
+
+impl RoomReception {
+    pub fn check_quota(&self, incoming_bytes: u64) -> Result<(), String> {
+        let Some(quota) = self.quota_bytes else {
+            return Ok(());
+        };
+        let used = dir_usage(&self.dir)
+            .map_err(|err| format!("unable to compute the reception folder usage: {err}"))?;
+        if used + incoming_bytes > quota {
+            return Err(format!(
+                "reception quota exceeded ({} bytes used, {} incoming, {} allowed)",
+                used, incoming_bytes, quota
+            ));
+        }
+        Ok(())
+    }
+}
+
+// A file announced by a peer, waiting for accept_file or decline_file
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileOffer {
+    // Uuid of the message the file is sent with once accepted
+    pub uuid: String,
+    pub sender_uuid: String,
+    pub room_uuid: String,
+    // Already sanitized
+    pub name: String,
+    pub size: u64,
+    pub hash: String,
+    pub source_endpoint: Endpoint,
+    pub offered_at: DTChatTime,
+}
+
+// Hex encoded SHA-256, compared with the hash of the offer once the file arrives
+pub fn file_hash(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+const DEFAULT_RETRY_MS: i64 = 5_000;
+const DEFAULT_MAX_RETRY_MS: i64 = 300_000;
+
+// What to do when the connection to an endpoint closes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum ReconnectPolicy {
+    // Right away, then every retry_ms until it succeeds
+    Immediate,
+    // After retry_ms, doubling the delay up to max_retry_ms
+    Backoff,
+    // Only when something is sent to it
+    #[default]
+    OnDemand,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReconnectConfig {
+    pub endpoint: String,
+    pub policy: ReconnectPolicy,
+    pub retry_ms: Option<i64>,
+    pub max_retry_ms: Option<i64>,
+}
+
+struct Attempt {
+    due_ms: i64,
+    delay_ms: i64,
+}
+
+// Endpoints waiting for a reconnection attempt
+#[derive(Default)]
+pub struct Reconnector {
+    // Per endpoint, as given by to_string
+    configs: HashMap<String, ReconnectConfig>,
+    attempts: HashMap<String, Attempt>,
+}
+
+impl Reconnector {
+    pub fn new(configs: Vec<ReconnectConfig>) -> Self {
+        Self {
+            configs: configs
+                .into_iter()
+                .map(|config| (config.endpoint.clone(), config))
+                .collect(),
+            attempts: HashMap::new(),
+        }
+    }
+
+    pub fn on_closed(&mut self, endpoint: &str, now_ms: i64) {
+        let Some(config) = self.configs.get(endpoint) else {
+            return;
+        };
+        let retry_ms = config.retry_ms.unwrap_or(DEFAULT_RETRY_MS);
+        let due_ms = match config.policy {
+            ReconnectPolicy::Immediate => now_ms,
+            ReconnectPolicy::Backoff => now_ms + retry_ms,
+            ReconnectPolicy::OnDemand => return,
+        };
+        self.attempts.insert(
+            endpoint.to_string(),
+            Attempt {
+                due_ms,
+                delay_ms: retry_ms,
+            },
+        );
+    }
+
+    pub fn on_established(&mut self, endpoint: &str) {
+        self.attempts.remove(endpoint);
+    }
+
+    // Endpoints to connect to now, each one is scheduled again until on_established
+    pub fn due(&mut self, now_ms: i64) -> Vec<String> {
+        let mut due = Vec::new();
+        for (endpoint, attempt) in self.attempts.iter_mut() {
+            if attempt.due_ms > now_ms {
+                continue;
+            }
+            if let Some(config) = self.configs.get(endpoint) {
+                if config.policy == ReconnectPolicy::Backoff {
+                    let max_ms = config.max_retry_ms.unwrap_or(DEFAULT_MAX_RETRY_MS);
+                    attempt.delay_ms = (attempt.delay_ms * 2).min(max_ms);
+                }
+            }
+            attempt.due_ms = now_ms + attempt.delay_ms;
+            due.push(endpoint.clone());
+        }
+        due.sort();
+        due
+    }
+}
@@ -0,0 +1,650 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::encryption::{open_line, seal_line, Cipher},
+    dtchat::ChatModel,
+    file_info::AudioInfo,
+    files::open_log,
+    journal::rewrite_log,
+    message::{Content, Location},
+    net::{ConnectionEvent, DataEvent, Endpoint, EngineObserver, ErrorEvent, SocketEngineEvent},
+    scheduler::TICK_INTERVAL_MS,
+    time::{DTChatTime, SimulatedTimeSource, TimeSource},
+};
+
+// Frames and blobs as base64 strings rather than arrays of numbers
+mod base64_data {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        STANDARD
+            .decode(String::deserialize(deserializer)?)
+            .map_err(D::Error::custom)
+    }
+}
+
+// The content given to a local call
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum RecordedContent {
+    Text {
+        text: String,
+    },
+    File {
+        path: String,
+    },
+    Blob {
+        content_type: String,
+        #[serde(with = "base64_data")]
+        data: Vec<u8>,
+    },
+    Location {
+        latitude: f64,
+        longitude: f64,
+        altitude: Option<f64>,
+        accuracy: Option<f64>,
+        timestamp_ms: i64,
+    },
+    Audio {
+        path: String,
+        codec: String,
+        duration_ms: u64,
+    },
+}
+
+impl From<&Content> for RecordedContent {
+    fn from(content: &Content) -> Self {
+        match content {
+            Content::Text(text) => Self::Text { text: text.clone() },
+            Content::File(path) => Self::File { path: path.clone() },
+            Content::Blob(content_type, data) => Self::Blob {
+                content_type: content_type.clone(),
+                data: data.clone(),
+            },
+            Content::Location(location) => Self::Location {
+                latitude: location.latitude,
+                longitude: location.longitude,
+                altitude: location.altitude,
+                accuracy: location.accuracy,
+                timestamp_ms: location.timestamp.timestamp_millis(),
+            },
+            Content::Audio(path, info) => Self::Audio {
+                path: path.clone(),
+                codec: info.codec.clone(),
+                duration_ms: info.duration_ms,
+            },
+        }
+    }
+}
+
+impl RecordedContent {
+    // None when the time of a location is out of range
+    pub fn to_content(&self) -> Option<Content> {
+        let content = match self {
+            Self::Text { text } => Content::Text(text.clone()),
+            Self::File { path } => Content::File(path.clone()),
+            Self::Blob { content_type, data } => Content::Blob(content_type.clone(), data.clone()),
+            Self::Location {
+                latitude,
+                longitude,
+                altitude,
+                accuracy,
+                timestamp_ms,
+            } => Content::Location(Location {
+                latitude: *latitude,
+                longitude: *longitude,
+                altitude: *altitude,
+                accuracy: *accuracy,
+                timestamp: DTChatTime::from_timestamp_millis(*timestamp_ms)?,
+            }),
+            Self::Audio {
+                path,
+                codec,
+                duration_ms,
+            } => Content::Audio(
+                path.clone(),
+                AudioInfo {
+                    codec: codec.clone(),
+                    duration_ms: *duration_ms,
+                },
+            ),
+        };
+        Some(content)
+    }
+}
+
+// An engine event as given to the model, or a local call made to it. Endpoints as given
+// by to_string
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum RecordedEvent {
+    Received {
+        #[serde(with = "base64_data")]
+        data: Vec<u8>,
+        from: String,
+    },
+    Sending {
+        token: String,
+        to: String,
+        bytes: usize,
+    },
+    Sent {
+        token: String,
+        to: String,
+        bytes_sent: usize,
+    },
+    ListenerStarted {
+        endpoint: String,
+    },
+    Established {
+        remote: String,
+    },
+    Closed {
+        remote: Option<String>,
+    },
+    ConnectionFailed {
+        endpoint: String,
+        reason: String,
+        token: String,
+    },
+    SendFailed {
+        endpoint: String,
+        reason: String,
+        token: String,
+    },
+    // The other errors are only reported to the observers, kept for reading and not replayed
+    Other {
+        description: String,
+    },
+    // The local calls, made again through the same methods. The messages they create get
+    // new uuids
+    SendToPeer {
+        content: RecordedContent,
+        room_uuid: String,
+        peer_uuid: String,
+        endpoint: String,
+        try_prediction: bool,
+    },
+    SendToPeerRedundant {
+        content: RecordedContent,
+        room_uuid: String,
+        peer_uuid: String,
+    },
+    SendToPreferred {
+        content: RecordedContent,
+        room_uuid: String,
+        peer_uuid: String,
+        try_prediction: bool,
+    },
+    SendToRoom {
+        content: RecordedContent,
+        room_uuid: String,
+        try_prediction: bool,
+    },
+    SendToRooms {
+        content: RecordedContent,
+        room_uuids: Vec<String>,
+        try_prediction: bool,
+    },
+    Broadcast {
+        content: RecordedContent,
+    },
+    ScheduleSend {
+        content: RecordedContent,
+        room_uuid: String,
+        peer_uuid: String,
+        send_at_ms: i64,
+    },
+    ScheduleSendAtContact {
+        content: RecordedContent,
+        room_uuid: String,
+        peer_uuid: String,
+    },
+}
+
+impl From<&SocketEngineEvent> for RecordedEvent {
+    fn from(event: &SocketEngineEvent) -> Self {
+        match event {
+            SocketEngineEvent::Data(DataEvent::Received { data, from }) => Self::Received {
+                data: data.clone(),
+                from: from.to_string(),
+            },
+            SocketEngineEvent::Data(DataEvent::Sending { token, to, bytes }) => Self::Sending {
+                token: token.clone(),
+                to: to.to_string(),
+                bytes: *bytes,
+            },
+            SocketEngineEvent::Data(DataEvent::Sent {
+                token,
+                to,
+                bytes_sent,
+            }) => Self::Sent {
+                token: token.clone(),
+                to: to.to_string(),
+                bytes_sent: *bytes_sent,
+            },
+            SocketEngineEvent::Connection(ConnectionEvent::ListenerStarted { endpoint }) => {
+                Self::ListenerStarted {
+                    endpoint: endpoint.to_string(),
+                }
+            }
+            SocketEngineEvent::Connection(ConnectionEvent::Established { remote }) => {
+                Self::Established {
+                    remote: remote.to_string(),
+                }
+            }
+            SocketEngineEvent::Connection(ConnectionEvent::Closed { remote }) => Self::Closed {
+                remote: remote.as_ref().map(|remote| remote.to_string()),
+            },
+            SocketEngineEvent::Error(ErrorEvent::ConnectionFailed {
+                endpoint,
+                reason,
+                token,
+            }) => Self::ConnectionFailed {
+                endpoint: endpoint.to_string(),
+                reason: reason.to_string(),
+                token: token.clone(),
+            },
+            SocketEngineEvent::Error(ErrorEvent::SendFailed {
+                endpoint,
+                reason,
+                token,
+            }) => Self::SendFailed {
+                endpoint: endpoint.to_string(),
+                reason: reason.to_string(),
+                token: token.clone(),
+            },
+            SocketEngineEvent::Error(error) => Self::Other {
+                description: format!("{:?}", error),
+            },
+        }
+    }
+}
+
+fn endpoint(value: &str) -> Option<Endpoint> {
+    Endpoint::from_str(value).ok()
+}
+
+impl RecordedEvent {
+    // To or from one of the endpoints, or a frame holding the uuid of the peer
+    fn concerns(&self, peer_uuid: &str, endpoints: &[String]) -> bool {
+        let endpoint = match self {
+            Self::Received { data, from } => {
+                let uuid = peer_uuid.as_bytes();
+                if data.windows(uuid.len()).any(|window| window == uuid) {
+                    return true;
+                }
+                Some(from)
+            }
+            Self::Sending { to, .. } | Self::Sent { to, .. } => Some(to),
+            Self::ListenerStarted { .. } | Self::Other { .. } => None,
+            Self::Established { remote } => Some(remote),
+            Self::Closed { remote } => remote.as_ref(),
+            Self::ConnectionFailed { endpoint, .. } | Self::SendFailed { endpoint, .. } => {
+                Some(endpoint)
+            }
+            Self::SendToPeer {
+                peer_uuid: to,
+                endpoint,
+                ..
+            } => {
+                if to == peer_uuid {
+                    return true;
+                }
+                Some(endpoint)
+            }
+            Self::SendToPeerRedundant { peer_uuid: to, .. }
+            | Self::SendToPreferred { peer_uuid: to, .. }
+            | Self::ScheduleSend { peer_uuid: to, .. }
+            | Self::ScheduleSendAtContact { peer_uuid: to, .. } => return to == peer_uuid,
+            Self::SendToRoom { .. } | Self::SendToRooms { .. } | Self::Broadcast { .. } => None,
+        };
+        endpoint.is_some_and(|endpoint| endpoints.contains(endpoint))
+    }
+
+    // None for Other and the local calls, and when an endpoint cannot be parsed back
+    pub fn to_engine_event(&self) -> Option<SocketEngineEvent> {
+        let event = match self {
+            Self::Received { data, from } => SocketEngineEvent::Data(DataEvent::Received {
+                data: data.clone(),
+                from: endpoint(from)?,
+            }),
+            Self::Sending { token, to, bytes } => SocketEngineEvent::Data(DataEvent::Sending {
+                token: token.clone(),
+                to: endpoint(to)?,
+                bytes: *bytes,
+            }),
+            Self::Sent {
+                token,
+                to,
+                bytes_sent,
+            } => SocketEngineEvent::Data(DataEvent::Sent {
+                token: token.clone(),
+                to: endpoint(to)?,
+                bytes_sent: *bytes_sent,
+            }),
+            Self::ListenerStarted { endpoint: listener } => {
+                SocketEngineEvent::Connection(ConnectionEvent::ListenerStarted {
+                    endpoint: endpoint(listener)?,
+                })
+            }
+            Self::Established { remote } => {
+                SocketEngineEvent::Connection(ConnectionEvent::Established {
+                    remote: endpoint(remote)?,
+                })
+            }
+            Self::Closed { remote } => SocketEngineEvent::Connection(ConnectionEvent::Closed {
+                remote: match remote {
+                    Some(remote) => Some(endpoint(remote)?),
+                    None => None,
+                },
+            }),
+            Self::ConnectionFailed {
+                endpoint: failed,
+                reason,
+                token,
+            } => SocketEngineEvent::Error(ErrorEvent::ConnectionFailed {
+                endpoint: endpoint(failed)?,
+                reason: reason.clone(),
+                token: token.clone(),
+            }),
+            Self::SendFailed {
+                endpoint: failed,
+                reason,
+                token,
+            } => SocketEngineEvent::Error(ErrorEvent::SendFailed {
+                endpoint: endpoint(failed)?,
+                reason: reason.clone(),
+                token: token.clone(),
+            }),
+            _ => return None,
+        };
+        Some(event)
+    }
+
+    // Makes the local call again, their errors go to the observers as when recorded.
+    // False for the engine events, and when the content or an endpoint cannot be parsed back
+    pub fn call(&self, model: &mut ChatModel) -> bool {
+        match self {
+            Self::SendToPeer {
+                content,
+                room_uuid,
+                peer_uuid,
+                endpoint: to,
+                try_prediction,
+            } => {
+                let (Some(content), Some(to)) = (content.to_content(), endpoint(to)) else {
+                    return false;
+                };
+                let _ = model.send_to_peer(
+                    &content,
+                    room_uuid,
+                    peer_uuid.clone(),
+                    &to,
+                    *try_prediction,
+                );
+            }
+            Self::SendToPeerRedundant {
+                content,
+                room_uuid,
+                peer_uuid,
+            } => {
+                let Some(content) = content.to_content() else {
+                    return false;
+                };
+                let _ = model.send_to_peer_redundant(&content, room_uuid, peer_uuid.clone());
+            }
+            Self::SendToPreferred {
+                content,
+                room_uuid,
+                peer_uuid,
+                try_prediction,
+            } => {
+                let Some(content) = content.to_content() else {
+                    return false;
+                };
+                let _ = model.send_to_preferred(
+                    &content,
+                    room_uuid,
+                    peer_uuid.clone(),
+                    *try_prediction,
+                );
+            }
+            Self::SendToRoom {
+                content,
+                room_uuid,
+                try_prediction,
+            } => {
+                let Some(content) = content.to_content() else {
+                    return false;
+                };
+                let _ = model.send_to_room(&content, room_uuid, *try_prediction);
+            }
+            Self::SendToRooms {
+                content,
+                room_uuids,
+                try_prediction,
+            } => {
+                let Some(content) = content.to_content() else {
+                    return false;
+                };
+                let _ = model.send_to_rooms(&content, room_uuids, *try_prediction);
+            }
+            Self::Broadcast { content } => {
+                let Some(content) = content.to_content() else {
+                    return false;
+                };
+                model.broadcast(&content);
+            }
+            Self::ScheduleSend {
+                content,
+                room_uuid,
+                peer_uuid,
+                send_at_ms,
+            } => {
+                let (Some(content), Some(send_at)) = (
+                    content.to_content(),
+                    DTChatTime::from_timestamp_millis(*send_at_ms),
+                ) else {
+                    return false;
+                };
+                let _ = model.schedule_send(&content, room_uuid, peer_uuid.clone(), send_at);
+            }
+            Self::ScheduleSendAtContact {
+                content,
+                room_uuid,
+                peer_uuid,
+            } => {
+                let Some(content) = content.to_content() else {
+                    return false;
+                };
+                let _ = model.schedule_send_at_contact(&content, room_uuid, peer_uuid.clone());
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+// One line of a replay log
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReplayEntry {
+    // DTChatTime::now when the model was given the event
+    pub time_ms: i64,
+    pub event: RecordedEvent,
+}
+
+// Where the log goes once it reaches its size, replacing the previous one
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(".1");
+    PathBuf::from(rotated)
+}
+
+// Append-only JSON lines record of the engine events given to the model and of the local
+// calls made to it. The frames received carry the message contents, each line is encrypted
+// with a cipher. Past max_bytes the log is moved to its .1 file, the older one dropped
+pub struct ReplayRecorder {
+    file: File,
+    path: PathBuf,
+    cipher: Option<Arc<Cipher>>,
+    size: u64,
+    max_bytes: u64,
+}
+
+impl ReplayRecorder {
+    pub fn open(path: &str, cipher: Option<Arc<Cipher>>, max_bytes: u64) -> io::Result<Self> {
+        let file = open_log(Path::new(path))?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            file,
+            path: PathBuf::from(path),
+            cipher,
+            size,
+            max_bytes,
+        })
+    }
+
+    // Drops the events exchanged with the endpoints of the peer, the frames naming it and
+    // the calls sending to it, from the rotated log as well. Returns how many
+    pub fn forget_peer(&mut self, peer_uuid: &str, endpoints: &[String]) -> io::Result<usize> {
+        let concerns = |line: &str| {
+            serde_json::from_str::<ReplayEntry>(line)
+                .is_ok_and(|entry| entry.event.concerns(peer_uuid, endpoints))
+        };
+        let mut dropped = 0;
+        let rotated = rotated_path(&self.path);
+        if rotated.exists() {
+            dropped += rewrite_log(&rotated, self.cipher.as_deref(), concerns)?.1;
+        }
+        let (file, forgotten) = rewrite_log(&self.path, self.cipher.as_deref(), concerns)?;
+        self.size = file.metadata()?.len();
+        self.file = file;
+        Ok(dropped + forgotten)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        fs::rename(&self.path, rotated_path(&self.path))?;
+        self.file = open_log(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    // At the time of the model recording
+    pub fn record(&mut self, event: &SocketEngineEvent, time: DTChatTime) {
+        self.append(RecordedEvent::from(event), time);
+    }
+
+    pub fn append(&mut self, event: RecordedEvent, time: DTChatTime) {
+        let entry = ReplayEntry {
+            time_ms: time.timestamp_millis(),
+            event,
+        };
+        // Unbuffered like the event journal, a failed write must not stop the chat
+        let Ok(line) = serde_json::to_string(&entry) else {
+            return;
+        };
+        let Ok(line) = seal_line(self.cipher.as_deref(), line) else {
+            return;
+        };
+        let line_bytes = line.len() as u64 + 1;
+        // Kept growing when the rename fails rather than losing the events
+        if self.size > 0 && self.size + line_bytes > self.max_bytes {
+            let _ = self.rotate();
+        }
+        if writeln!(self.file, "{}", line).is_ok() {
+            self.size += line_bytes;
+        }
+    }
+}
+
+fn read_lines(path: &Path, cipher: Option<&Cipher>) -> io::Result<Vec<ReplayEntry>> {
+    fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(&open_line(cipher, line)?)?))
+        .collect()
+}
+
+// The rotated log first, if any. With the cipher of the recording, plain lines are then
+// refused
+pub fn read_replay_log(path: &str, cipher: Option<&Cipher>) -> io::Result<Vec<ReplayEntry>> {
+    let path = Path::new(path);
+    let rotated = rotated_path(path);
+    let mut entries = Vec::new();
+    if rotated.exists() {
+        entries = read_lines(&rotated, cipher)?;
+    }
+    entries.extend(read_lines(path, cipher)?);
+    Ok(entries)
+}
+
+// Puts the time source of the model back once the replay is over, even on a panic
+struct RestoreTimeSource<'a> {
+    model: &'a mut ChatModel,
+    previous: Option<Arc<dyn TimeSource>>,
+}
+
+impl Drop for RestoreTimeSource<'_> {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            self.model.set_time_source(previous);
+        }
+    }
+}
+
+// Gives the recorded events and calls to the model in their order, the clock set to the
+// time each was recorded at. Between them, the clock moves by the steps of
+// scheduler::start_ticker and the model runs tick as it would live. The clock is the time
+// source of the model for the length of the replay, the previous one is then put back,
+// and the model stops recording.
+// The model should be fresh, built from the configuration and history the recording
+// started with, and have no engine attached: what it sends goes nowhere.
+// Returns the number of events and calls replayed
+pub fn replay(
+    model: &mut ChatModel,
+    entries: &[ReplayEntry],
+    clock: Arc<SimulatedTimeSource>,
+) -> usize {
+    let previous = model.set_time_source(clock.clone());
+    let mut restore = RestoreTimeSource {
+        model,
+        previous: Some(previous),
+    };
+    let model = &mut *restore.model;
+    model.stop_replay_recording();
+    let mut next_tick_ms =
+        entries.first().map_or(0, |entry| entry.time_ms) + TICK_INTERVAL_MS as i64;
+    let mut replayed = 0;
+    for entry in entries {
+        while next_tick_ms <= entry.time_ms {
+            clock.set_millis(next_tick_ms);
+            model.tick(model.now());
+            next_tick_ms += TICK_INTERVAL_MS as i64;
+        }
+        clock.set_millis(entry.time_ms);
+        let given = match entry.event.to_engine_event() {
+            Some(event) => {
+                model.on_engine_event(event);
+                true
+            }
+            None => entry.event.call(model),
+        };
+        if given {
+            replayed += 1;
+        }
+    }
+    replayed
+}
@@ -0,0 +1,197 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::migration::{read_store, write_store, Store},
+    net::Endpoint,
+};
+
+// Gaps larger than this are not requested, the sender most likely restarted its numbering.
+// Senders keep as many of their last messages per peer and room for the requests
+const MAX_GAP: u64 = 256;
+// Peer and room pairs numbered by a sender, the least recently used is forgotten past it
+const MAX_NUMBERINGS: usize = 1024;
+// Gaps are only reported this long after they were noticed, messages overtaking each other
+// are not lost
+pub const DEFAULT_REORDER_WINDOW_MS: i64 = 5_000;
+
+struct Gap {
+    // When it was noticed, reported or last requested
+    since_ms: i64,
+    reported: bool,
+}
+
+// Sequence numbers received from one peer in one room
+pub struct SequenceTracker {
+    highest: u64,
+    missing: BTreeMap<u64, Gap>,
+    // Where the last message of the peer came from, requests are sent there
+    pub endpoint: Endpoint,
+}
+
+impl SequenceTracker {
+    // The first message seen sets the start, what came before is not requested
+    pub fn new(sequence: u64, endpoint: Endpoint) -> Self {
+        Self {
+            highest: sequence,
+            missing: BTreeMap::new(),
+            endpoint,
+        }
+    }
+
+    // Of a message not seen before. A number already passed that is not missing means the
+    // peer restarted its numbering, what was missing from the previous one is forgotten
+    pub fn record(&mut self, sequence: u64, endpoint: Endpoint, now_ms: i64) {
+        self.endpoint = endpoint;
+        if sequence <= self.highest {
+            if self.missing.remove(&sequence).is_none() {
+                self.missing.clear();
+                self.highest = sequence;
+            }
+            return;
+        }
+        if sequence - self.highest <= MAX_GAP {
+            for missing in self.highest + 1..sequence {
+                let gap = Gap {
+                    since_ms: now_ms,
+                    reported: false,
+                };
+                self.missing.insert(missing, gap);
+            }
+        } else {
+            self.missing.clear();
+        }
+        self.highest = sequence;
+        // The sender no longer keeps these
+        self.missing = self
+            .missing
+            .split_off(&self.highest.saturating_sub(MAX_GAP));
+    }
+
+    // Missing for at least window_ms and not reported yet
+    pub fn overdue(&mut self, window_ms: i64, now_ms: i64) -> Vec<u64> {
+        let mut overdue = Vec::new();
+        for (sequence, gap) in self.missing.iter_mut() {
+            if !gap.reported && now_ms - gap.since_ms >= window_ms {
+                gap.reported = true;
+                gap.since_ms = now_ms;
+                overdue.push(*sequence);
+            }
+        }
+        overdue
+    }
+
+    // Reported for at least after_ms, they are due again after_ms later if still missing
+    pub fn due(&mut self, after_ms: i64, now_ms: i64) -> Vec<u64> {
+        let mut due = Vec::new();
+        for (sequence, gap) in self.missing.iter_mut() {
+            if gap.reported && now_ms - gap.since_ms >= after_ms {
+                gap.since_ms = now_ms;
+                due.push(*sequence);
+            }
+        }
+        due
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Numbering {
+    peer_uuid: String,
+    room_uuid: String,
+    last: u64,
+    used_ms: i64,
+    // Uuid of the last messages sent, per sequence number
+    sent: BTreeMap<u64, String>,
+}
+
+// Numbering of the messages sent per destination peer and room
+#[derive(Default)]
+pub struct SentSequences {
+    numberings: HashMap<(String, String), Numbering>,
+    // Numbering restarts with the process without it
+    path: Option<PathBuf>,
+    // Changed since last written
+    dirty: bool,
+}
+
+impl SentSequences {
+    // The numbering is restored from the file, if any, and written back by flush
+    pub fn open(path: &str) -> io::Result<Self> {
+        let numberings: Vec<Numbering> =
+            read_store(Store::Sequences, Path::new(path), None)?.unwrap_or_default();
+        Ok(Self {
+            numberings: numberings
+                .into_iter()
+                .map(|numbering| {
+                    let key = (numbering.peer_uuid.clone(), numbering.room_uuid.clone());
+                    (key, numbering)
+                })
+                .collect(),
+            path: Some(PathBuf::from(path)),
+            dirty: false,
+        })
+    }
+
+    // Each peer sees the messages sent to it in a room numbered from 1 without gaps
+    pub fn next(&mut self, peer_uuid: &str, room_uuid: &str, uuid: &str, now_ms: i64) -> u64 {
+        let key = (peer_uuid.to_string(), room_uuid.to_string());
+        if !self.numberings.contains_key(&key) && self.numberings.len() >= MAX_NUMBERINGS {
+            let oldest = self
+                .numberings
+                .iter()
+                .min_by_key(|(_, numbering)| numbering.used_ms)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.numberings.remove(&oldest);
+            }
+        }
+        let numbering = self.numberings.entry(key).or_insert_with(|| Numbering {
+            peer_uuid: peer_uuid.to_string(),
+            room_uuid: room_uuid.to_string(),
+            last: 0,
+            used_ms: now_ms,
+            sent: BTreeMap::new(),
+        });
+        numbering.last += 1;
+        numbering.used_ms = now_ms;
+        numbering.sent.insert(numbering.last, uuid.to_string());
+        if numbering.sent.len() > MAX_GAP as usize {
+            numbering.sent.pop_first();
+        }
+        self.dirty = true;
+        numbering.last
+    }
+
+    // None once the message is no longer kept
+    pub fn sent(&self, peer_uuid: &str, room_uuid: &str, sequence: u64) -> Option<&String> {
+        let key = (peer_uuid.to_string(), room_uuid.to_string());
+        self.numberings.get(&key)?.sent.get(&sequence)
+    }
+
+    // Numbering of the messages sent to the peer, in every room
+    pub fn forget(&mut self, peer_uuid: &str) {
+        let count = self.numberings.len();
+        self.numberings
+            .retain(|(numbered_peer, _), _| numbered_peer != peer_uuid);
+        self.dirty |= self.numberings.len() != count;
+    }
+
+    // Writes the numbering when it changed, called from the tick rather than on every send
+    pub fn flush(&mut self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.dirty {
+            return Ok(());
+        }
+        let numberings: Vec<&Numbering> = self.numberings.values().collect();
+        write_store(path, &numberings, None)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
@@ -0,0 +1,37 @@
+use std::{
+    sync::{Arc, Mutex, Weak},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{dtchat::ChatModel, message::Content, net::Endpoint, time::DTChatTime};
+
+// How often ChatModel::tick runs for the frontends without a loop of their own
+pub(crate) const TICK_INTERVAL_MS: u64 = 200;
+
+// A message waiting for its send time, sent by ChatModel::tick once due
+#[derive(Clone, Debug)]
+pub struct ScheduledSend {
+    pub uuid: String,
+    pub content: Content,
+    pub room_uuid: String,
+    pub peer_uuid: String,
+    pub endpoint: Endpoint,
+    pub send_at: DTChatTime,
+    pub try_prediction: bool,
+}
+
+// Calls ChatModel::tick until the model is dropped, for the frontends driven by their
+// callers (FFI, gRPC) rather than by a loop of their own
+pub fn start_ticker(model: &Arc<Mutex<ChatModel>>) -> JoinHandle<()> {
+    let model: Weak<Mutex<ChatModel>> = Arc::downgrade(model);
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_millis(TICK_INTERVAL_MS));
+        let Some(model) = model.upgrade() else {
+            return;
+        };
+        let mut model = model.lock().unwrap();
+        let now = model.now();
+        model.tick(now);
+    })
+}
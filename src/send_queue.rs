@@ -0,0 +1,279 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use serde::Deserialize;
+
+use crate::{
+    config::parse_proto,
+    message::Content,
+    net::{Endpoint, EndpointProto},
+};
+
+// Frames from this size on are held by the bandwidth budget, whatever their content
+const BULK_FRAME_BYTES: usize = 16 * 1024;
+
+// When the messages waiting in a queue are handed to the engine
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum FlushTrigger {
+    // As soon as max_in_flight allows it
+    #[default]
+    Immediate,
+    // Only while a contact predicted with the peer is ongoing, for BP
+    ContactStart,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SendQueueConfig {
+    // tcp, udp or bp
+    pub proto: String,
+    // Messages handed to the engine and not reported sent yet, None for no limit
+    pub max_in_flight: Option<usize>,
+    #[serde(default)]
+    pub flush: FlushTrigger,
+    // Bytes each endpoint may be sent per second. Once spent the bulk sends wait, the texts
+    // still go through
+    pub bytes_per_second: Option<u64>,
+    // Bytes each endpoint may be sent per contact predicted with its peer, the bulk sends
+    // waiting for an ongoing contact with budget left
+    pub bytes_per_contact: Option<u64>,
+}
+
+// A message encoded and waiting in the queue of its protocol
+pub struct QueuedSend {
+    pub message_uuid: String,
+    pub peer_uuid: String,
+    pub local_endpoint: Option<Endpoint>,
+    pub endpoint: Endpoint,
+    pub bytes: Vec<u8>,
+    // Held by the bandwidth budget, see is_bulk
+    pub bulk: bool,
+}
+
+// Files, audio and blobs, and the frames of BULK_FRAME_BYTES or more
+pub fn is_bulk(content: &Content, frame_bytes: usize) -> bool {
+    frame_bytes >= BULK_FRAME_BYTES
+        || matches!(
+            content,
+            Content::File(_) | Content::Audio(_, _) | Content::Blob(_, _)
+        )
+}
+
+// A bulk send held by the bandwidth budget of its queue
+pub struct Throttled {
+    pub message_uuid: String,
+    // Already sent to the endpoint in the second or contact, and the budget of either
+    pub used: u64,
+    pub limit: u64,
+}
+
+// Bytes sent to an endpoint in the current second and contact
+#[derive(Default)]
+struct Usage {
+    peer_uuid: String,
+    second_start: i64,
+    second_bytes: u64,
+    contact_start: Option<i64>,
+    contact_bytes: u64,
+}
+
+struct ProtoQueue {
+    proto: EndpointProto,
+    max_in_flight: Option<usize>,
+    flush: FlushTrigger,
+    bytes_per_second: Option<u64>,
+    bytes_per_contact: Option<u64>,
+    // Oldest first
+    waiting: VecDeque<QueuedSend>,
+    in_flight: HashSet<String>,
+    // Per endpoint
+    usage: HashMap<String, Usage>,
+    // Uuids of the sends already reported throttled
+    throttled: HashSet<String>,
+}
+
+impl ProtoQueue {
+    // Counts the send against the budget of its endpoint, unless it is a bulk one that does
+    // not fit. A bulk send larger than the whole budget goes alone at the start of a window
+    fn charge(
+        &mut self,
+        send: &QueuedSend,
+        now_ms: i64,
+        contact_start: Option<i64>,
+    ) -> Result<(), (u64, u64)> {
+        let usage = self.usage.entry(send.endpoint.to_string()).or_default();
+        usage.peer_uuid.clone_from(&send.peer_uuid);
+        if now_ms - usage.second_start >= 1000 {
+            usage.second_start = now_ms;
+            usage.second_bytes = 0;
+        }
+        if usage.contact_start != contact_start {
+            usage.contact_start = contact_start;
+            usage.contact_bytes = 0;
+        }
+        let size = send.bytes.len() as u64;
+        if send.bulk {
+            if let Some(limit) = self.bytes_per_second {
+                if usage.second_bytes > 0 && usage.second_bytes + size > limit {
+                    return Err((usage.second_bytes, limit));
+                }
+            }
+            if let Some(limit) = self.bytes_per_contact {
+                if contact_start.is_none()
+                    || (usage.contact_bytes > 0 && usage.contact_bytes + size > limit)
+                {
+                    return Err((usage.contact_bytes, limit));
+                }
+            }
+        }
+        usage.second_bytes += size;
+        usage.contact_bytes += size;
+        Ok(())
+    }
+}
+
+// One queue per configured protocol, sends over the others go to the engine right away.
+// The queued messages are persisted as pending sends, and queued again on restart
+#[derive(Default)]
+pub struct SendQueues {
+    queues: Vec<ProtoQueue>,
+}
+
+impl SendQueues {
+    // Unknown protocols are reported by the validation, the first queue of a protocol is kept
+    pub fn new(configs: &[SendQueueConfig]) -> Self {
+        let mut queues: Vec<ProtoQueue> = Vec::new();
+        for config in configs {
+            let Some(proto) = parse_proto(&config.proto) else {
+                continue;
+            };
+            if queues.iter().any(|queue| queue.proto == proto) {
+                continue;
+            }
+            queues.push(ProtoQueue {
+                proto,
+                max_in_flight: config.max_in_flight,
+                flush: config.flush,
+                bytes_per_second: config.bytes_per_second,
+                bytes_per_contact: config.bytes_per_contact,
+                waiting: VecDeque::new(),
+                in_flight: HashSet::new(),
+                usage: HashMap::new(),
+                throttled: HashSet::new(),
+            });
+        }
+        Self { queues }
+    }
+
+    pub fn handles(&self, proto: &EndpointProto) -> bool {
+        self.queues.iter().any(|queue| queue.proto == *proto)
+    }
+
+    // Sends over a protocol without queue are dropped, check handles first
+    pub fn push(&mut self, send: QueuedSend) {
+        if let Some(queue) = self
+            .queues
+            .iter_mut()
+            .find(|queue| queue.proto == send.endpoint.proto)
+        {
+            queue.waiting.push_back(send);
+        }
+    }
+
+    pub fn waiting(&self, proto: &EndpointProto) -> usize {
+        self.queues
+            .iter()
+            .find(|queue| queue.proto == *proto)
+            .map_or(0, |queue| queue.waiting.len())
+    }
+
+    pub fn waiting_uuids(&self) -> Vec<String> {
+        self.queues
+            .iter()
+            .flat_map(|queue| queue.waiting.iter())
+            .map(|send| send.message_uuid.clone())
+            .collect()
+    }
+
+    // Peers with a message waiting for a contact, or counted against a contact budget
+    pub fn peers_waiting_contact(&self) -> HashSet<String> {
+        let charged = self
+            .queues
+            .iter()
+            .flat_map(|queue| queue.usage.values())
+            .filter(|usage| usage.contact_start.is_some())
+            .map(|usage| usage.peer_uuid.clone());
+        self.queues
+            .iter()
+            .filter(|queue| {
+                queue.flush == FlushTrigger::ContactStart || queue.bytes_per_contact.is_some()
+            })
+            .flat_map(|queue| queue.waiting.iter())
+            .map(|send| send.peer_uuid.clone())
+            .chain(charged)
+            .collect()
+    }
+
+    // Whether the message was waiting or in flight, it no longer takes a place
+    pub fn remove(&mut self, message_uuid: &str) -> bool {
+        let mut removed = false;
+        for queue in &mut self.queues {
+            let count = queue.waiting.len();
+            queue
+                .waiting
+                .retain(|send| send.message_uuid != message_uuid);
+            queue.throttled.remove(message_uuid);
+            removed |= queue.waiting.len() != count || queue.in_flight.remove(message_uuid);
+        }
+        removed
+    }
+
+    // Messages each queue can hand to the engine now, in their order, and the ones newly
+    // held by the bandwidth budget. Those waiting for a contact are kept unless
+    // contact_start gives the start of an ongoing one with their peer
+    pub fn take_ready(
+        &mut self,
+        now_ms: i64,
+        contact_start: impl Fn(&str) -> Option<i64>,
+    ) -> (Vec<QueuedSend>, Vec<Throttled>) {
+        let mut ready = Vec::new();
+        let mut throttled = Vec::new();
+        for queue in &mut self.queues {
+            let mut kept = VecDeque::new();
+            while let Some(send) = queue.waiting.pop_front() {
+                let full = queue
+                    .max_in_flight
+                    .is_some_and(|max| queue.in_flight.len() >= max);
+                let started = contact_start(&send.peer_uuid);
+                let held = queue.flush == FlushTrigger::ContactStart && started.is_none();
+                if full || held {
+                    kept.push_back(send);
+                    continue;
+                }
+                match queue.charge(&send, now_ms, started) {
+                    Ok(()) => {
+                        queue.throttled.remove(&send.message_uuid);
+                        queue.in_flight.insert(send.message_uuid.clone());
+                        ready.push(send);
+                    }
+                    Err((used, limit)) => {
+                        if queue.throttled.insert(send.message_uuid.clone()) {
+                            throttled.push(Throttled {
+                                message_uuid: send.message_uuid.clone(),
+                                used,
+                                limit,
+                            });
+                        }
+                        kept.push_back(send);
+                    }
+                }
+            }
+            queue.waiting = kept;
+            // Once their second is over, only the usages of the ongoing contacts still count
+            queue.usage.retain(|_, usage| {
+                now_ms - usage.second_start < 1000
+                    || (usage.contact_start.is_some()
+                        && contact_start(&usage.peer_uuid) == usage.contact_start)
+            });
+        }
+        (ready, throttled)
+    }
+}
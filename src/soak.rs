@@ -0,0 +1,114 @@
+use serde::Deserialize;
+use std::{
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    delivery::DeliveryHandle,
+    dtchat::ChatModel,
+    event::{ChatAppEvent, ChatAppInfoEvent},
+    message::Content,
+    net::Endpoint,
+};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SoakConfig {
+    pub interval_ms: u64,
+    #[serde(default = "SoakConfig::default_timeout_ms")]
+    pub timeout_ms: u64,
+    // Besides the local peer, which always gets a canary over loopback
+    #[serde(default)]
+    pub peers: Vec<String>,
+    #[serde(default = "SoakConfig::default_room_uuid")]
+    pub room_uuid: String,
+}
+
+impl SoakConfig {
+    fn default_timeout_ms() -> u64 {
+        30_000
+    }
+
+    fn default_room_uuid() -> String {
+        "soak".to_string()
+    }
+}
+
+// (peer uuid, endpoint) of every canary target, the local peer first
+fn targets(model: &ChatModel, config: &SoakConfig) -> Vec<(String, Option<Endpoint>)> {
+    let local_peer = model.get_localpeer();
+    let mut targets = vec![(
+        local_peer.uuid.clone(),
+        local_peer.endpoints.first().cloned(),
+    )];
+    let peers = model.get_other_peers();
+    for peer_uuid in &config.peers {
+        let endpoint = peers
+            .get(peer_uuid)
+            .and_then(|peer| peer.endpoints.first().cloned());
+        targets.push((peer_uuid.clone(), endpoint));
+    }
+    targets
+}
+
+fn send_canaries(
+    model: &Arc<Mutex<ChatModel>>,
+    config: &SoakConfig,
+    round: u64,
+) -> Vec<(String, Result<DeliveryHandle, String>)> {
+    let mut model = model.lock().unwrap();
+    targets(&model, config)
+        .into_iter()
+        .map(|(peer_uuid, endpoint)| {
+            let handle = match endpoint {
+                Some(endpoint) => model
+                    .send_and_track(
+                        &Content::Text(format!("canary {}", round)),
+                        &config.room_uuid,
+                        peer_uuid.clone(),
+                        &endpoint,
+                        false,
+                    )
+                    .map_err(|err| err.to_string()),
+                None => Err("no endpoint to reach the peer".to_string()),
+            };
+            (peer_uuid, handle)
+        })
+        .collect()
+}
+
+fn check_canaries(
+    model: &Arc<Mutex<ChatModel>>,
+    config: &SoakConfig,
+    canaries: Vec<(String, Result<DeliveryHandle, String>)>,
+) {
+    let deadline = Instant::now() + Duration::from_millis(config.timeout_ms);
+    for (peer_uuid, handle) in canaries {
+        let result = handle.and_then(|handle| {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            handle.wait_acked(remaining).map_err(|err| err.to_string())
+        });
+        let event = match result {
+            Ok(msg) => {
+                ChatAppEvent::Info(format!("Canary {} acked by peer {}", msg.uuid, peer_uuid))
+            }
+            Err(reason) => ChatAppEvent::Message(ChatAppInfoEvent::CanaryFailed(peer_uuid, reason)),
+        };
+        model.lock().unwrap().notify_observers(event);
+    }
+}
+
+// Runs for the whole life of the process, None when soak is not configured
+pub fn start_soak(model: Arc<Mutex<ChatModel>>) -> Option<JoinHandle<()>> {
+    let config = model.lock().unwrap().soak_config()?;
+    Some(thread::spawn(move || {
+        let mut round = 0;
+        loop {
+            thread::sleep(Duration::from_millis(config.interval_ms));
+            round += 1;
+            let canaries = send_canaries(&model, &config, round);
+            check_canaries(&model, &config, canaries);
+        }
+    }))
+}
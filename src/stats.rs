@@ -0,0 +1,193 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+};
+
+use chrono::NaiveDate;
+
+use crate::{
+    delivery::is_given_up,
+    dtchat::Peer,
+    export::csv_field,
+    message::{ChatMessage, Content, Location},
+};
+
+// Messages going one way between the local peer and another one
+#[derive(Clone, Debug, Default)]
+pub struct DirectionStats {
+    pub count: usize,
+    // Text length, or file size while the file is still on disk
+    pub bytes: u64,
+    pub delivered: usize,
+    pub failed: usize,
+    // Send to ack for sent messages, send to reception for received ones
+    pub avg_latency_ms: Option<i64>,
+    pub p50_latency_ms: Option<i64>,
+    pub p95_latency_ms: Option<i64>,
+}
+
+fn payload_bytes(content: &Content) -> u64 {
+    match content {
+        Content::Text(text) => text.len() as u64,
+        Content::File(path) | Content::Audio(path, _) => {
+            fs::metadata(path).map_or(0, |metadata| metadata.len())
+        }
+        Content::Blob(_, data) => data.len() as u64,
+        Content::Location(_) => std::mem::size_of::<Location>() as u64,
+    }
+}
+
+// Nearest rank on sorted latencies
+pub(crate) fn percentile(sorted: &[i64], percent: usize) -> Option<i64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (percent * sorted.len()).div_ceil(100).max(1);
+    Some(sorted[rank - 1])
+}
+
+impl DirectionStats {
+    pub fn new(messages: &[&ChatMessage]) -> Self {
+        let mut latencies: Vec<i64> = messages
+            .iter()
+            .filter_map(|msg| {
+                let receive_time = msg.receive_time?;
+                Some(receive_time.timestamp_millis() - msg.send_time.timestamp_millis())
+            })
+            .collect();
+        latencies.sort_unstable();
+        let avg_latency_ms = match latencies.len() {
+            0 => None,
+            len => Some(latencies.iter().sum::<i64>() / len as i64),
+        };
+        Self {
+            count: messages.len(),
+            bytes: messages.iter().map(|msg| payload_bytes(&msg.content)).sum(),
+            delivered: messages
+                .iter()
+                .filter(|msg| msg.receive_time.is_some())
+                .count(),
+            failed: messages
+                .iter()
+                .filter(|msg| is_given_up(&msg.status))
+                .count(),
+            avg_latency_ms,
+            p50_latency_ms: percentile(&latencies, 50),
+            p95_latency_ms: percentile(&latencies, 95),
+        }
+    }
+
+    pub fn failure_rate(&self) -> f64 {
+        match self.count {
+            0 => 0.0,
+            count => self.failed as f64 / count as f64,
+        }
+    }
+
+    fn csv_row(&self, peer_uuid: &str, direction: &str) -> String {
+        let latency = |value: Option<i64>| value.map_or(String::new(), |ms| ms.to_string());
+        format!(
+            "{},{},{},{},{},{},{:.4},{},{},{}",
+            csv_field(peer_uuid),
+            direction,
+            self.count,
+            self.bytes,
+            self.delivered,
+            self.failed,
+            self.failure_rate(),
+            latency(self.avg_latency_ms),
+            latency(self.p50_latency_ms),
+            latency(self.p95_latency_ms),
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ConversationStats {
+    pub peer_uuid: String,
+    pub sent: DirectionStats,
+    pub received: DirectionStats,
+}
+
+impl ConversationStats {
+    // sent are the local messages addressed to the peer, received the ones it sent
+    pub fn new(peer_uuid: &str, sent: &[&ChatMessage], received: &[&ChatMessage]) -> Self {
+        Self {
+            peer_uuid: peer_uuid.to_string(),
+            sent: DirectionStats::new(sent),
+            received: DirectionStats::new(received),
+        }
+    }
+
+    pub const CSV_HEADER: &'static str = "peer_uuid,direction,count,bytes,delivered,failed,\
+        failure_rate,avg_latency_ms,p50_latency_ms,p95_latency_ms";
+
+    // Header then one row per direction
+    pub fn to_csv(&self) -> String {
+        format!(
+            "{}\n{}\n{}\n",
+            Self::CSV_HEADER,
+            self.sent.csv_row(&self.peer_uuid, "sent"),
+            self.received.csv_row(&self.peer_uuid, "received"),
+        )
+    }
+}
+
+// Over the whole history, for mission reports
+#[derive(Clone, Debug, Default)]
+pub struct Statistics {
+    // Per room uuid, the messages of every participant together
+    pub rooms: BTreeMap<String, DirectionStats>,
+    // Per peer uuid, the peers without any message included
+    pub peers: BTreeMap<String, ConversationStats>,
+    // Messages sent and received per UTC day
+    pub activity_by_day: BTreeMap<NaiveDate, usize>,
+}
+
+impl Statistics {
+    // The messages sent to a peer are those sent to one of its endpoints
+    pub fn new<'a>(
+        messages: impl Iterator<Item = &'a ChatMessage>,
+        local_peer_uuid: &str,
+        peers: &HashMap<String, Peer>,
+    ) -> Self {
+        let mut by_room: HashMap<&str, Vec<&ChatMessage>> = HashMap::new();
+        let mut sent: HashMap<&str, Vec<&ChatMessage>> = HashMap::new();
+        let mut received: HashMap<&str, Vec<&ChatMessage>> = HashMap::new();
+        let mut activity_by_day = BTreeMap::new();
+        for msg in messages {
+            by_room.entry(&msg.room_uuid).or_default().push(msg);
+            *activity_by_day
+                .entry(msg.send_time.date_naive())
+                .or_default() += 1;
+            if msg.sender_uuid != local_peer_uuid {
+                received.entry(&msg.sender_uuid).or_default().push(msg);
+                continue;
+            }
+            for peer in peers.values() {
+                if peer.endpoints.contains(&msg.source_endpoint) {
+                    sent.entry(&peer.uuid).or_default().push(msg);
+                }
+            }
+        }
+        let peers = peers
+            .keys()
+            .map(|peer_uuid| {
+                let stats = ConversationStats::new(
+                    peer_uuid,
+                    sent.get(peer_uuid.as_str()).map_or(&[], Vec::as_slice),
+                    received.get(peer_uuid.as_str()).map_or(&[], Vec::as_slice),
+                );
+                (peer_uuid.clone(), stats)
+            })
+            .collect();
+        Self {
+            rooms: by_room
+                .into_iter()
+                .map(|(room_uuid, msgs)| (room_uuid.to_string(), DirectionStats::new(&msgs)))
+                .collect(),
+            peers,
+            activity_by_day,
+        }
+    }
+}
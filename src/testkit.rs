@@ -0,0 +1,321 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use socket_engine::engine::Engine;
+
+use crate::{
+    dtchat::{generate_uuid, ChatModel},
+    error::ChatError,
+    event::{AppEventObserver, ChatAppEvent, ChatAppInfoEvent},
+    message::{ChatMessage, Content},
+    mock_engine::MockNetwork,
+    net::Endpoint,
+    transport::Transport,
+};
+
+pub const TESTKIT_ROOM_UUID: &str = "testkit";
+
+type RecordedEvents = (Mutex<Vec<ChatAppEvent>>, Condvar);
+
+// Keeps every event of a node so tests can wait for them
+#[derive(Clone, Default)]
+pub struct EventRecorder {
+    events: Arc<RecordedEvents>,
+}
+
+impl EventRecorder {
+    pub fn events(&self) -> Vec<ChatAppEvent> {
+        self.events.0.lock().unwrap().clone()
+    }
+
+    // Returns the first recorded event matching, even if it arrived before the call
+    pub fn wait_for<F>(&self, timeout: Duration, matches: F) -> Option<ChatAppEvent>
+    where
+        F: Fn(&ChatAppEvent) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        let (lock, cvar) = &*self.events;
+        let mut events = lock.lock().unwrap();
+        loop {
+            if let Some(event) = events.iter().find(|event| matches(event)) {
+                return Some(event.clone());
+            }
+            let remaining = deadline.checked_duration_since(Instant::now())?;
+            events = cvar.wait_timeout(events, remaining).unwrap().0;
+        }
+    }
+
+    fn wait_message(
+        &self,
+        uuid: &str,
+        timeout: Duration,
+        extract: fn(&ChatAppEvent) -> Option<&ChatMessage>,
+    ) -> Option<ChatMessage> {
+        let event = self.wait_for(timeout, |event| {
+            extract(event).is_some_and(|msg| msg.uuid == uuid)
+        })?;
+        extract(&event).cloned()
+    }
+
+    pub fn wait_received(&self, uuid: &str, timeout: Duration) -> Option<ChatMessage> {
+        self.wait_message(uuid, timeout, |event| match event {
+            ChatAppEvent::Message(ChatAppInfoEvent::Received(msg)) => Some(msg),
+            _ => None,
+        })
+    }
+
+    pub fn wait_acked(&self, uuid: &str, timeout: Duration) -> Option<ChatMessage> {
+        self.wait_message(uuid, timeout, |event| match event {
+            ChatAppEvent::Message(ChatAppInfoEvent::AckReceived(msg, _)) => Some(msg),
+            _ => None,
+        })
+    }
+}
+
+impl AppEventObserver for EventRecorder {
+    fn on_event(&mut self, event: ChatAppEvent) {
+        let (lock, cvar) = &*self.events;
+        lock.lock().unwrap().push(event);
+        cvar.notify_all();
+    }
+}
+
+pub struct TestNode {
+    pub model: Arc<Mutex<ChatModel>>,
+    pub recorder: EventRecorder,
+    pub peer_uuid: String,
+    pub endpoint: Endpoint,
+}
+
+impl TestNode {
+    fn start(config_file: &str, peer_uuid: &str) -> Result<Self, Box<dyn Error>> {
+        Self::start_with(config_file, peer_uuid, |model| {
+            let mut engine = Engine::new();
+            engine.add_observer(model.clone());
+            Box::new(engine)
+        })
+    }
+
+    fn start_with<F>(
+        config_file: &str,
+        peer_uuid: &str,
+        transport: F,
+    ) -> Result<Self, Box<dyn Error>>
+    where
+        F: FnOnce(&Arc<Mutex<ChatModel>>) -> Box<dyn Transport>,
+    {
+        let model = Arc::new(Mutex::new(ChatModel::for_peer(config_file, peer_uuid)?));
+        let recorder = EventRecorder::default();
+        let endpoint = model.lock().unwrap().get_localpeer().endpoints[0].clone();
+
+        let transport = transport(&model);
+        {
+            let mut model = model.lock().unwrap();
+            model.add_observer(Arc::new(Mutex::new(recorder.clone())));
+            let endpoints = model.get_localpeer().endpoints;
+            model.start_with_transport(transport, endpoints);
+        }
+        Ok(Self {
+            model,
+            recorder,
+            peer_uuid: peer_uuid.to_string(),
+            endpoint,
+        })
+    }
+
+    // Returns the uuid of the sent message
+    pub fn send_text(&self, to: &TestNode, text: &str) -> Result<String, ChatError> {
+        self.model.lock().unwrap().send_to_peer(
+            &Content::Text(text.to_string()),
+            &TESTKIT_ROOM_UUID.to_string(),
+            to.peer_uuid.clone(),
+            &to.endpoint,
+            false,
+        )
+    }
+}
+
+// Two nodes listening on loopback TCP and UDP ports, base_port and base_port + 1
+pub struct TestPair {
+    pub first: TestNode,
+    pub second: TestNode,
+    dir: PathBuf,
+}
+
+impl TestPair {
+    pub fn start(base_port: u16) -> Result<Self, Box<dyn Error>> {
+        let (dir, config_file) = write_config(base_port, 2, false, "")?;
+        Ok(Self {
+            first: TestNode::start(&config_file, "1")?,
+            second: TestNode::start(&config_file, "2")?,
+            dir,
+        })
+    }
+
+    // Temporary folder of the configuration, removed with the pair
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl Drop for TestPair {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+// Two nodes on a MockNetwork, nothing is delivered until the test steps it, e.g.
+// pair.network.deliver_all() for the message and its ack
+pub struct MockPair {
+    pub first: TestNode,
+    pub second: TestNode,
+    pub network: MockNetwork,
+    dir: PathBuf,
+}
+
+impl MockPair {
+    pub fn start() -> Result<Self, Box<dyn Error>> {
+        Self::start_with("")
+    }
+
+    // extra is appended to the configuration of both nodes, {dir} replaced with the
+    // temporary folder, e.g. "failover_order: [tcp, udp]\n"
+    pub fn start_with(extra: &str) -> Result<Self, Box<dyn Error>> {
+        let (network, mut nodes, dir) = start_mock_nodes(2, extra)?;
+        let second = nodes.remove(1);
+        let first = nodes.remove(0);
+        Ok(Self {
+            first,
+            second,
+            network,
+            dir,
+        })
+    }
+
+    // Temporary folder of the configuration, removed with the pair
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl Drop for MockPair {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+// Three nodes on a MockNetwork, all in the testkit room: what the first sends to the room
+// fans out to the two others
+pub struct MockTrio {
+    pub first: TestNode,
+    pub second: TestNode,
+    pub third: TestNode,
+    pub network: MockNetwork,
+    dir: PathBuf,
+}
+
+impl MockTrio {
+    pub fn start() -> Result<Self, Box<dyn Error>> {
+        let (network, mut nodes, dir) = start_mock_nodes(3, "")?;
+        let third = nodes.remove(2);
+        let second = nodes.remove(1);
+        let first = nodes.remove(0);
+        Ok(Self {
+            first,
+            second,
+            third,
+            network,
+            dir,
+        })
+    }
+}
+
+impl Drop for MockTrio {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+// Peers "1" to nodes on a shared MockNetwork, with the temporary folder of their
+// configuration
+fn start_mock_nodes(
+    nodes: u16,
+    extra: &str,
+) -> Result<(MockNetwork, Vec<TestNode>, PathBuf), Box<dyn Error>> {
+    // The ports are only names on the mock network
+    let (dir, config_file) = write_config(1, nodes, true, extra)?;
+    let network = MockNetwork::new();
+    let started = (1..=nodes)
+        .map(|peer| {
+            TestNode::start_with(&config_file, &peer.to_string(), |model| {
+                Box::new(network.engine(model.clone()))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    network.flush();
+    Ok((network, started, dir))
+}
+
+// Temporary folder holding the configuration, and the configuration file
+fn write_config(
+    base_port: u16,
+    nodes: u16,
+    bp: bool,
+    extra: &str,
+) -> Result<(PathBuf, String), Box<dyn Error>> {
+    let dir = std::env::temp_dir().join(format!("dtchat-testkit-{}", generate_uuid()));
+    fs::create_dir_all(&dir)?;
+    let config_file = dir.join("testkit.yaml");
+    let extra = extra.replace("{dir}", &dir.to_string_lossy());
+    fs::write(&config_file, config(&dir, base_port, nodes, bp) + &extra)?;
+    Ok((dir, config_file.to_string_lossy().into_owned()))
+}
+
+// Peer n listens on TCP port base_port + n - 1, and also on UDP on the same port number
+// for the failover. With bp, on the mock network only, also on the BP endpoint
+// ipn:<port>.1. Every peer is in the testkit room
+fn config(dir: &Path, base_port: u16, nodes: u16, bp: bool) -> String {
+    const COLORS: [&str; 3] = ["BLUE", "GREEN", "ORANGE"];
+    let mut peers = String::new();
+    let mut participants = String::new();
+    for index in 0..nodes {
+        let port = base_port + index;
+        let bp_endpoint = if bp {
+            format!(", \"bp ipn:{}.1\"", port)
+        } else {
+            String::new()
+        };
+        peers += &format!(
+            r#"  - uuid: "{uuid}"
+    name: Testkit {uuid}
+    endpoints: ["tcp 127.0.0.1:{port}", "udp 127.0.0.1:{port}"{bp_endpoint}]
+    color: {color}
+"#,
+            uuid = index + 1,
+            color = COLORS[index as usize % COLORS.len()],
+        );
+        participants += &format!(
+            r#"      - peer_uuid: "{uuid}"
+        endpoint: "tcp 127.0.0.1:{port}"
+"#,
+            uuid = index + 1,
+        );
+    }
+    format!(
+        r#"db_type: YamlVec
+file_reception_dir: '{dir}'
+peer_list:
+{peers}room_list:
+  - uuid: "{room}"
+    name: Testkit
+    participants:
+{participants}"#,
+        dir = dir.join("received").display(),
+        room = TESTKIT_ROOM_UUID,
+    )
+}
@@ -1,4 +1,134 @@
-use chrono::{DateTime, NaiveDate, TimeZone, Timelike, Utc};
+use std::sync::{
+    atomic::{AtomicI64, Ordering as AtomicOrdering},
+    Arc, OnceLock, RwLock,
+};
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, FixedOffset, Local, NaiveDate, TimeZone, Timelike, Utc,
+};
+
+// Where the time is read. Each model has its own, the one of the process is the default
+// and the clock of DTChatTime::now
+pub trait TimeSource: Send + Sync {
+    fn now_millis(&self) -> i64;
+}
+
+pub struct SystemTimeSource;
+
+impl TimeSource for SystemTimeSource {
+    fn now_millis(&self) -> i64 {
+        Utc::now().timestamp_millis()
+    }
+}
+
+// System clock corrected by an offset, kept up to date by a time sync client (NTP, GPS..)
+#[derive(Default)]
+pub struct OffsetTimeSource {
+    offset_ms: AtomicI64,
+}
+
+impl OffsetTimeSource {
+    pub fn new(offset_ms: i64) -> Self {
+        Self {
+            offset_ms: AtomicI64::new(offset_ms),
+        }
+    }
+
+    pub fn set_offset_ms(&self, offset_ms: i64) {
+        self.offset_ms.store(offset_ms, AtomicOrdering::Relaxed);
+    }
+}
+
+impl TimeSource for OffsetTimeSource {
+    fn now_millis(&self) -> i64 {
+        Utc::now().timestamp_millis() + self.offset_ms.load(AtomicOrdering::Relaxed)
+    }
+}
+
+// Only moves when told to, for deterministic simulations
+#[derive(Default)]
+pub struct SimulatedTimeSource {
+    now_ms: AtomicI64,
+}
+
+impl SimulatedTimeSource {
+    pub fn new(start_ms: i64) -> Self {
+        Self {
+            now_ms: AtomicI64::new(start_ms),
+        }
+    }
+
+    pub fn set_millis(&self, now_ms: i64) {
+        self.now_ms.store(now_ms, AtomicOrdering::Relaxed);
+    }
+
+    pub fn advance_millis(&self, ms: i64) {
+        self.now_ms.fetch_add(ms, AtomicOrdering::Relaxed);
+    }
+}
+
+impl TimeSource for SimulatedTimeSource {
+    fn now_millis(&self) -> i64 {
+        self.now_ms.load(AtomicOrdering::Relaxed)
+    }
+}
+
+fn time_source() -> &'static RwLock<Arc<dyn TimeSource>> {
+    static SOURCE: OnceLock<RwLock<Arc<dyn TimeSource>>> = OnceLock::new();
+    SOURCE.get_or_init(|| RwLock::new(Arc::new(SystemTimeSource)))
+}
+
+// Returns the source replaced, to put it back. Models already built keep theirs
+pub fn set_time_source(source: Arc<dyn TimeSource>) -> Arc<dyn TimeSource> {
+    std::mem::replace(&mut *time_source().write().unwrap(), source)
+}
+
+// What a model reads the time from unless given its own
+pub fn default_time_source() -> Arc<dyn TimeSource> {
+    time_source().read().unwrap().clone()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisplayTimezone {
+    Local,
+    Utc,
+    Fixed(FixedOffset),
+}
+
+impl DisplayTimezone {
+    // "local", "utc" or an offset such as "+02:00"
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "local" => Some(Self::Local),
+            "utc" => Some(Self::Utc),
+            offset => offset.parse().ok().map(Self::Fixed),
+        }
+    }
+}
+
+pub fn is_valid_format(format: &str) -> bool {
+    !StrftimeItems::new(format).any(|item| item == Item::Error)
+}
+
+// How the frontends show times, from the configuration
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisplayPrefs {
+    pub timezone: DisplayTimezone,
+    // strftime formats, see chrono::format::strftime
+    pub date_format: String,
+    pub time_format: String,
+}
+
+impl Default for DisplayPrefs {
+    fn default() -> Self {
+        Self {
+            timezone: DisplayTimezone::Local,
+            date_format: "%Y-%m-%d".to_string(),
+            time_format: "%H:%M:%S".to_string(),
+        }
+    }
+}
 
 #[derive(Clone, Debug, Copy, Eq, PartialEq)]
 pub struct DTChatTime {
@@ -7,8 +137,13 @@ pub struct DTChatTime {
 
 impl DTChatTime {
     pub fn now() -> Self {
+        Self::now_from(time_source().read().unwrap().as_ref())
+    }
+
+    pub fn now_from(source: &dyn TimeSource) -> Self {
+        let now_ms = source.now_millis();
         Self {
-            date_time: Utc::now(),
+            date_time: DateTime::from_timestamp_millis(now_ms).unwrap_or_else(Utc::now),
         }
     }
 
@@ -31,11 +166,27 @@ impl DTChatTime {
             None => None,
         }
     }
+    // The protocol uses 0 for messages without expiration
+    pub fn from_expiration_millis(timestamp: i64) -> Option<Self> {
+        if timestamp == 0 {
+            return None;
+        }
+        Self::from_timestamp_millis(timestamp)
+    }
+
     pub fn date_naive(&self) -> NaiveDate {
         return self.date_time.date_naive();
     }
 
-    pub fn mins_hours<Tz: TimeZone>(&self, tz: &Tz) -> (u32, u32) {
+    pub fn mins_hours(&self, prefs: &DisplayPrefs) -> (u32, u32) {
+        match prefs.timezone {
+            DisplayTimezone::Local => self.mins_hours_in(&Local),
+            DisplayTimezone::Utc => self.mins_hours_in(&Utc),
+            DisplayTimezone::Fixed(offset) => self.mins_hours_in(&offset),
+        }
+    }
+
+    fn mins_hours_in<Tz: TimeZone>(&self, tz: &Tz) -> (u32, u32) {
         let with_time_zone: DateTime<Tz> = self.date_time.with_timezone(tz);
         return (
             Timelike::minute(&with_time_zone),
@@ -43,7 +194,30 @@ impl DTChatTime {
         );
     }
 
-    pub fn ts_to_str<Tz>(&self, date: bool, time: bool, separator: Option<&str>, tz: &Tz) -> String
+    pub fn ts_to_str(
+        &self,
+        date: bool,
+        time: bool,
+        separator: Option<&str>,
+        prefs: &DisplayPrefs,
+    ) -> String {
+        match prefs.timezone {
+            DisplayTimezone::Local => self.ts_to_str_in(date, time, separator, prefs, &Local),
+            DisplayTimezone::Utc => self.ts_to_str_in(date, time, separator, prefs, &Utc),
+            DisplayTimezone::Fixed(offset) => {
+                self.ts_to_str_in(date, time, separator, prefs, &offset)
+            }
+        }
+    }
+
+    fn ts_to_str_in<Tz>(
+        &self,
+        date: bool,
+        time: bool,
+        separator: Option<&str>,
+        prefs: &DisplayPrefs,
+        tz: &Tz,
+    ) -> String
     where
         Tz: TimeZone,
         Tz::Offset: std::fmt::Display,
@@ -52,13 +226,13 @@ impl DTChatTime {
         let mut res = String::new();
 
         if date {
-            res += &with_time_zone.format("%Y-%m-%d").to_string();
+            res += &with_time_zone.format(&prefs.date_format).to_string();
         }
         if date && time {
             res += separator.unwrap_or(" ");
         }
         if time {
-            res += &with_time_zone.format("%H:%M:%S").to_string();
+            res += &with_time_zone.format(&prefs.time_format).to_string();
         }
 
         res
@@ -0,0 +1,40 @@
+#[cfg(feature = "native")]
+use socket_engine::engine::Engine;
+
+use crate::{
+    bundle::{BundleMetadata, BundleStatusReport},
+    net::Endpoint,
+};
+
+// What the model needs from the network, received data and send reports come back
+// through EngineObserver::on_engine_event whatever the transport. Without the native
+// feature, e.g. in a wasm32 build, the application gives its own
+pub trait Transport: Send {
+    fn start_listener(&mut self, endpoint: Endpoint);
+    fn send(&mut self, from: Option<Endpoint>, to: Endpoint, data: Vec<u8>, token: String);
+    // Drops a send not started yet, false when it cannot be withdrawn anymore
+    fn cancel(&mut self, _token: &str) -> bool {
+        false
+    }
+    // Of the bundle whose payload was just given by a Received event from a BP endpoint,
+    // asked once per event. None when the transport cannot read the primary blocks
+    fn take_bundle_metadata(&mut self, _from: &Endpoint) -> Option<BundleMetadata> {
+        None
+    }
+    // BP status reports received since the last call, about the bundles sent with send
+    fn take_status_reports(&mut self) -> Vec<BundleStatusReport> {
+        Vec::new()
+    }
+}
+
+// The socket engine starts every send right away, there is nothing to cancel
+#[cfg(feature = "native")]
+impl Transport for Engine {
+    fn start_listener(&mut self, endpoint: Endpoint) {
+        self.start_listener_async(endpoint);
+    }
+
+    fn send(&mut self, from: Option<Endpoint>, to: Endpoint, data: Vec<u8>, token: String) {
+        self.send_async(from, to, data, token);
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    sync::mpsc::{self, Sender},
+    thread,
+    time::Duration,
+};
+
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+use crate::{
+    event::{AppEventObserver, ChatAppEvent, ChatAppInfoEvent, NetworkErrorEvent},
+    message::{ChatMessage, Content},
+    net::ErrorEvent,
+    time::DTChatTime,
+};
+
+const SIGNATURE_HEADER: &str = "X-DTChat-Signature";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum WebhookEvent {
+    MessageFailed,
+    FileReceived,
+    PeerUnreachable,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    // Bodies are signed with HMAC-SHA256 when set
+    pub secret: Option<String>,
+    pub events: Vec<WebhookEvent>,
+    #[serde(default = "WebhookConfig::default_max_retries")]
+    pub max_retries: u32,
+    #[serde(default = "WebhookConfig::default_retry_delay_ms")]
+    pub retry_delay_ms: u64,
+}
+
+impl WebhookConfig {
+    fn default_max_retries() -> u32 {
+        3
+    }
+
+    fn default_retry_delay_ms() -> u64 {
+        500
+    }
+}
+
+fn message_payload(msg: &ChatMessage) -> Value {
+    json!({
+        "uuid": msg.uuid,
+        "sender_uuid": msg.sender_uuid,
+        "room_uuid": msg.room_uuid,
+        "content": msg.content_as_string(),
+        "send_time": msg.send_time.timestamp_millis(),
+    })
+}
+
+fn sign(secret: &str, body: &str) -> String {
+    // HMAC accepts keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+// Any transport error or non 2xx status counts as a failed attempt
+fn post(config: &WebhookConfig, body: &str) -> bool {
+    let mut request = ureq::post(&config.url).set("Content-Type", "application/json");
+    if let Some(secret) = &config.secret {
+        request = request.set(SIGNATURE_HEADER, &sign(secret, body));
+    }
+    request.send_string(body).is_ok()
+}
+
+fn deliver(config: &WebhookConfig, body: &str) {
+    let mut delay = Duration::from_millis(config.retry_delay_ms);
+    for attempt in 0..=config.max_retries {
+        if post(config, body) {
+            return;
+        }
+        if attempt < config.max_retries {
+            thread::sleep(delay);
+            delay *= 2;
+        }
+    }
+    // The observer has no way to report back, the payload is dropped
+}
+
+pub struct WebhookDispatcher {
+    events: Vec<WebhookEvent>,
+    queue: Sender<String>,
+}
+
+impl WebhookDispatcher {
+    pub fn new(config: WebhookConfig) -> Self {
+        let (queue, pending) = mpsc::channel::<String>();
+        let events = config.events.clone();
+        // Posting happens on a dedicated thread so observers are never blocked by the network
+        thread::spawn(move || {
+            for body in pending {
+                deliver(&config, &body);
+            }
+        });
+        Self { events, queue }
+    }
+
+    fn payload(&self, event: &ChatAppEvent) -> Option<(WebhookEvent, Value)> {
+        match event {
+            ChatAppEvent::Message(ChatAppInfoEvent::Failed(msg)) => {
+                Some((WebhookEvent::MessageFailed, message_payload(msg)))
+            }
+            ChatAppEvent::Message(ChatAppInfoEvent::Received(msg)) => match msg.content {
+                Content::File(_) | Content::Audio(..) => {
+                    Some((WebhookEvent::FileReceived, message_payload(msg)))
+                }
+                Content::Text(_) | Content::Blob(..) | Content::Location(_) => None,
+            },
+            ChatAppEvent::SocketEngineError(NetworkErrorEvent::SocketError(
+                ErrorEvent::ConnectionFailed {
+                    endpoint, reason, ..
+                },
+                _,
+            )) => Some((
+                WebhookEvent::PeerUnreachable,
+                json!({
+                    "endpoint": endpoint.to_string(),
+                    "reason": reason.to_string(),
+                }),
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl AppEventObserver for WebhookDispatcher {
+    fn on_event(&mut self, event: ChatAppEvent) {
+        let Some((kind, data)) = self.payload(&event) else {
+            return;
+        };
+        if !self.events.contains(&kind) {
+            return;
+        }
+        let body = json!({
+            "event": format!("{:?}", kind),
+            "timestamp": DTChatTime::now().timestamp_millis(),
+            "data": data,
+        });
+        let _ = self.queue.send(body.to_string());
+    }
+}
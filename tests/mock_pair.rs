@@ -0,0 +1,457 @@
+#![cfg(feature = "testkit")]
+
+use std::{fs, thread, time::Duration};
+
+use dtchat_backend::{
+    error::ChatErrorKind,
+    event::{ChatAppErrorEvent, ChatAppEvent, ChatAppInfoEvent},
+    message::{ChatMessage, Content, MessageStatus},
+    proto::{proto_message::MsgType, ProtoMessage},
+    testkit::{EventRecorder, MockPair, MockTrio, TESTKIT_ROOM_UUID},
+    time::DTChatTime,
+};
+use socket_engine::{
+    endpoint::{Endpoint, EndpointProto},
+    event::{DataEvent, EngineObserver, SocketEngineEvent},
+};
+
+// Every event is given on the thread stepping the network, nothing is awaited for long
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+fn received_count(recorder: &EventRecorder, uuid: &str) -> usize {
+    recorder
+        .events()
+        .iter()
+        .filter(|event| match event {
+            ChatAppEvent::Message(ChatAppInfoEvent::Received(msg)) => msg.uuid == uuid,
+            _ => false,
+        })
+        .count()
+}
+
+// Returns the uuid of the text sent to the BP endpoint of the second node
+fn send_over_bp(pair: &MockPair, text: &str) -> String {
+    pair.first
+        .model
+        .lock()
+        .unwrap()
+        .send_to_peer(
+            &Content::Text(text.to_string()),
+            &TESTKIT_ROOM_UUID.to_string(),
+            pair.second.peer_uuid.clone(),
+            &Endpoint::from_str("bp ipn:2.1").unwrap(),
+            false,
+        )
+        .unwrap()
+}
+
+#[test]
+fn text_is_received_and_acked() {
+    let pair = MockPair::start().unwrap();
+    let uuid = pair.first.send_text(&pair.second, "hello").unwrap();
+    assert_eq!(pair.network.in_flight(), 1);
+
+    // The text, then its ack
+    assert_eq!(pair.network.deliver_all(), 2);
+    let received = pair.second.recorder.wait_received(&uuid, TIMEOUT).unwrap();
+    assert_eq!(received.content_as_string(), "hello");
+    let acked = pair.first.recorder.wait_acked(&uuid, TIMEOUT).unwrap();
+    assert_eq!(acked.status, MessageStatus::ReceivedByPeer);
+}
+
+#[test]
+fn lost_text_is_not_acked() {
+    let pair = MockPair::start().unwrap();
+    let uuid = pair.first.send_text(&pair.second, "lost").unwrap();
+
+    assert!(pair.network.drop_next());
+    assert_eq!(pair.network.in_flight(), 0);
+    assert!(pair.second.recorder.wait_received(&uuid, TIMEOUT).is_none());
+    let sent = pair.first.model.lock().unwrap().get_message(&uuid).unwrap();
+    assert_eq!(sent.status, MessageStatus::Sent);
+}
+
+#[test]
+fn duplicate_is_dropped_and_acked_again() {
+    let pair = MockPair::start().unwrap();
+    let uuid = pair.first.send_text(&pair.second, "twice").unwrap();
+    let frame = pair.network.peek().remove(0);
+    assert_eq!(pair.network.deliver_all(), 2);
+
+    pair.second
+        .model
+        .lock()
+        .unwrap()
+        .on_engine_event(SocketEngineEvent::Data(DataEvent::Received {
+            data: frame.data,
+            from: frame.from,
+        }));
+
+    assert_eq!(received_count(&pair.second.recorder, &uuid), 1);
+    // The ack of the first copy may have been the one lost
+    assert_eq!(pair.network.in_flight(), 1);
+    assert_eq!(pair.network.deliver_all(), 1);
+}
+
+#[test]
+fn failed_send_fails_over_to_udp() {
+    let pair = MockPair::start_with("failover_order: [tcp, udp]\n").unwrap();
+    let uuid = pair.first.send_text(&pair.second, "again").unwrap();
+
+    assert!(pair.network.fail_next());
+    let resent = pair.network.peek();
+    assert_eq!(resent.len(), 1);
+    assert!(resent[0].to.proto == EndpointProto::Udp);
+
+    assert_eq!(pair.network.deliver_all(), 2);
+    assert!(pair.second.recorder.wait_received(&uuid, TIMEOUT).is_some());
+    assert!(pair.first.recorder.wait_acked(&uuid, TIMEOUT).is_some());
+}
+
+#[test]
+fn queue_holds_sends_beyond_max_in_flight() {
+    let pair =
+        MockPair::start_with("send_queues:\n  - proto: tcp\n    max_in_flight: 1\n").unwrap();
+    let first = pair.first.send_text(&pair.second, "first").unwrap();
+    let second = pair.first.send_text(&pair.second, "second").unwrap();
+    assert_eq!(pair.network.in_flight(), 1);
+
+    // Once sent, the first frees the slot of the second, which goes along with its ack
+    assert!(pair.network.deliver_next());
+    assert_eq!(pair.network.in_flight(), 2);
+    assert_eq!(pair.network.deliver_all(), 3);
+    assert!(pair
+        .second
+        .recorder
+        .wait_received(&first, TIMEOUT)
+        .is_some());
+    assert!(pair
+        .second
+        .recorder
+        .wait_received(&second, TIMEOUT)
+        .is_some());
+}
+
+#[test]
+fn drafts_are_encrypted_with_the_passphrase() {
+    let var = "DTCHAT_TESTKIT_PASSPHRASE";
+    std::env::set_var(var, "testkit passphrase");
+    let extra = format!(
+        "encryption_passphrase_env: {}\ndrafts_path: '{{dir}}/drafts.json'\n",
+        var
+    );
+    let pair = MockPair::start_with(&extra).unwrap();
+    let room = TESTKIT_ROOM_UUID.to_string();
+    let mut model = pair.first.model.lock().unwrap();
+    model.save_draft(&room, "secret draft".to_string());
+
+    let stored = fs::read(pair.dir().join("drafts.json")).unwrap();
+    assert!(!String::from_utf8_lossy(&stored).contains("secret draft"));
+    assert_eq!(model.get_draft(&room).as_deref(), Some("secret draft"));
+}
+
+#[test]
+fn bundle_metadata_is_attached_to_bp_messages() {
+    let pair = MockPair::start().unwrap();
+    let uuid = send_over_bp(&pair, "bundled");
+
+    assert!(pair.network.deliver_next());
+    let received = pair.second.recorder.wait_received(&uuid, TIMEOUT).unwrap();
+    let bundle = received.bundle.unwrap();
+    assert_eq!(bundle.source_eid, "ipn:1.1");
+    assert!(bundle.expires_at().unwrap() > bundle.creation_time);
+}
+
+#[test]
+fn delivery_report_marks_the_message_delivered_to_the_node() {
+    let pair = MockPair::start().unwrap();
+    let uuid = send_over_bp(&pair, "reported");
+
+    // The text only, the ack stays in flight
+    assert!(pair.network.deliver_next());
+    let mut model = pair.first.model.lock().unwrap();
+    model.tick(DTChatTime::now());
+    let sent = model.get_message(&uuid).unwrap();
+    assert_eq!(sent.status, MessageStatus::DeliveredToNode);
+}
+
+#[test]
+fn handshake_records_the_signed_key_and_refuses_a_replayed_answer() {
+    // Both nodes read the same seed, the signatures are checked all the same
+    let pair = MockPair::start_with("key_path: '{dir}/node.key'\n").unwrap();
+    pair.first
+        .model
+        .lock()
+        .unwrap()
+        .query_peer_info(pair.second.endpoint.clone());
+    assert!(pair.network.deliver_next());
+    let answer = pair.network.peek().remove(0);
+    pair.network.deliver_all();
+    let recorded = pair.first.recorder.events().iter().any(|event| {
+        matches!(event, ChatAppEvent::Message(ChatAppInfoEvent::PeerKeyRecorded(uuid, _))
+            if *uuid == pair.second.peer_uuid)
+    });
+    assert!(recorded);
+
+    pair.first
+        .model
+        .lock()
+        .unwrap()
+        .on_engine_event(SocketEngineEvent::Data(DataEvent::Received {
+            data: answer.data,
+            from: answer.from,
+        }));
+    let refused = pair.first.recorder.events().iter().any(|event| {
+        matches!(event, ChatAppEvent::Error(ChatAppErrorEvent::TrustViolation(details))
+            if details.contains("answers no challenge"))
+    });
+    assert!(refused);
+}
+
+#[test]
+fn refused_send_names_the_peer_endpoint_and_message() {
+    let pair = MockPair::start().unwrap();
+    let endpoint = Endpoint::from_str("udp 127.0.0.1:9").unwrap();
+    let error = pair
+        .first
+        .model
+        .lock()
+        .unwrap()
+        .send_to_peer(
+            &Content::Text("misrouted".to_string()),
+            &TESTKIT_ROOM_UUID.to_string(),
+            pair.second.peer_uuid.clone(),
+            &endpoint,
+            false,
+        )
+        .unwrap_err();
+
+    assert_eq!(error.kind, ChatErrorKind::EndpointMismatch);
+    let named = matches!(&error.cause, ChatAppErrorEvent::EndpointMismatch(peer_uuid, _)
+        if *peer_uuid == pair.second.peer_uuid);
+    assert!(named);
+    assert_eq!(error.endpoint, Some(endpoint));
+    assert!(error.message_uuid.is_some());
+    assert_eq!(pair.network.in_flight(), 0);
+}
+
+#[test]
+fn rooms_the_sender_is_not_in_are_dropped() {
+    let pair = MockPair::start().unwrap();
+    let mut msg = ChatMessage::new_to_send(
+        &pair.first.peer_uuid,
+        &TESTKIT_ROOM_UUID.to_string(),
+        Content::Text("tagged".to_string()),
+        pair.second.endpoint.clone(),
+        DTChatTime::now(),
+    );
+    msg.also_rooms = vec!["elsewhere".to_string()];
+    let proto_msg = ProtoMessage::new_text(&msg, Some(pair.first.endpoint.clone())).unwrap();
+    pair.second
+        .model
+        .lock()
+        .unwrap()
+        .treat_proto_message(proto_msg);
+
+    let received = pair
+        .second
+        .recorder
+        .wait_received(&msg.uuid, TIMEOUT)
+        .unwrap();
+    assert!(received.also_rooms.is_empty());
+    let refused = pair.second.recorder.events().iter().any(|event| {
+        matches!(event, ChatAppEvent::Error(ChatAppErrorEvent::TrustViolation(details))
+            if details.contains("elsewhere"))
+    });
+    assert!(refused);
+}
+
+#[test]
+fn mention_by_uuid_reaches_the_peer() {
+    let pair = MockPair::start().unwrap();
+    let text = format!("ping @{}", pair.second.peer_uuid);
+    let uuid = pair.first.send_text(&pair.second, &text).unwrap();
+    pair.network.deliver_all();
+
+    let mentioned = pair.second.recorder.events().iter().any(|event| {
+        matches!(event, ChatAppEvent::Message(ChatAppInfoEvent::Mentioned(msg)) if msg.uuid == uuid)
+    });
+    assert!(mentioned);
+}
+
+#[test]
+fn duplicate_blob_is_routed_once() {
+    let pair = MockPair::start().unwrap();
+    let uuid = pair
+        .first
+        .model
+        .lock()
+        .unwrap()
+        .send_to_peer(
+            &Content::Blob("application/x-testkit".to_string(), vec![1, 2, 3]),
+            &TESTKIT_ROOM_UUID.to_string(),
+            pair.second.peer_uuid.clone(),
+            &pair.second.endpoint,
+            false,
+        )
+        .unwrap();
+    let frame = pair.network.peek().remove(0);
+    pair.network.deliver_all();
+    pair.second
+        .model
+        .lock()
+        .unwrap()
+        .on_engine_event(SocketEngineEvent::Data(DataEvent::Received {
+            data: frame.data,
+            from: frame.from,
+        }));
+
+    let routed = pair
+        .second
+        .recorder
+        .events()
+        .iter()
+        .filter(|event| {
+            matches!(event, ChatAppEvent::Message(ChatAppInfoEvent::UnhandledBlob(msg))
+                if msg.uuid == uuid)
+        })
+        .count();
+    assert_eq!(routed, 1);
+}
+
+fn send_blob(pair: &MockPair) -> String {
+    pair.first
+        .model
+        .lock()
+        .unwrap()
+        .send_to_peer(
+            &Content::Blob("application/x-testkit".to_string(), vec![0; 64]),
+            &TESTKIT_ROOM_UUID.to_string(),
+            pair.second.peer_uuid.clone(),
+            &pair.second.endpoint,
+            false,
+        )
+        .unwrap()
+}
+
+#[test]
+fn bandwidth_budget_holds_blobs_until_the_next_second() {
+    let pair =
+        MockPair::start_with("send_queues:\n  - proto: tcp\n    bytes_per_second: 100\n").unwrap();
+    send_blob(&pair);
+    let held = send_blob(&pair);
+    assert_eq!(pair.network.in_flight(), 1);
+
+    // Texts are not held, the blob is reported once however often the queue is flushed
+    pair.first.send_text(&pair.second, "small").unwrap();
+    assert_eq!(pair.network.in_flight(), 2);
+    let throttled = pair
+        .first
+        .recorder
+        .events()
+        .iter()
+        .filter(|event| {
+            matches!(event, ChatAppEvent::Message(ChatAppInfoEvent::BandwidthThrottled(msg, _, 100))
+                if msg.uuid == held)
+        })
+        .count();
+    assert_eq!(throttled, 1);
+
+    let next_second = DTChatTime::now().timestamp_millis() + 1_000;
+    let mut model = pair.first.model.lock().unwrap();
+    model.tick(DTChatTime::from_timestamp_millis(next_second).unwrap());
+    assert_eq!(pair.network.in_flight(), 3);
+}
+
+#[test]
+fn room_send_fans_out_a_replica_per_peer() {
+    let trio = MockTrio::start().unwrap();
+    let room = TESTKIT_ROOM_UUID.to_string();
+    let report = trio
+        .first
+        .model
+        .lock()
+        .unwrap()
+        .send_to_room(&Content::Text("to all".to_string()), &room, false)
+        .unwrap();
+    assert!(report.failed.is_empty());
+    let room_msg = report.room_msg;
+    assert!(!room_msg.bundled);
+    assert_eq!(room_msg.peers, vec!["2".to_string(), "3".to_string()]);
+    assert_eq!(room_msg.messages.len(), 2);
+    assert_ne!(room_msg.messages[0], room_msg.messages[1]);
+
+    // The link to the third node loses its replica
+    let to_third = trio
+        .network
+        .peek()
+        .into_iter()
+        .find(|send| send.to == trio.third.endpoint)
+        .unwrap();
+    assert!(trio.network.drop_send(to_third.id));
+    // The replica to the second node, then its ack
+    assert!(trio.network.deliver_all() >= 2);
+
+    let (to_second, lost) = (&room_msg.messages[0], &room_msg.messages[1]);
+    assert!(trio
+        .second
+        .recorder
+        .wait_received(to_second, TIMEOUT)
+        .is_some());
+    assert!(trio.third.recorder.wait_received(lost, TIMEOUT).is_none());
+    let status = trio
+        .first
+        .model
+        .lock()
+        .unwrap()
+        .get_room_message_status(&room_msg.uuid)
+        .unwrap();
+    assert_eq!(
+        status.replicas,
+        vec![
+            ("2".to_string(), Some(MessageStatus::ReceivedByPeer)),
+            ("3".to_string(), Some(MessageStatus::Sent)),
+        ]
+    );
+    assert_eq!(status.acked_count(), 1);
+}
+
+#[test]
+fn history_digest_lists_the_most_recent_texts_only() {
+    let pair = MockPair::start_with("history_digest_size: 1\n").unwrap();
+    let older = pair.first.send_text(&pair.second, "older").unwrap();
+    // Apart on the millisecond send times
+    thread::sleep(Duration::from_millis(5));
+    let newer = pair.first.send_text(&pair.second, "newer").unwrap();
+    pair.network.deliver_all();
+    assert!(pair
+        .second
+        .recorder
+        .wait_received(&older, TIMEOUT)
+        .is_some());
+    assert!(pair
+        .second
+        .recorder
+        .wait_received(&newer, TIMEOUT)
+        .is_some());
+
+    let mut model = pair.first.model.lock().unwrap();
+    model.sync_history_with(&pair.second.peer_uuid);
+    let newer_sent = model.get_message(&newer).unwrap().send_time;
+    drop(model);
+    let sends = pair.network.peek();
+    assert_eq!(sends.len(), 1);
+    let digest = match ProtoMessage::decode_from_slice(&sends[0].data)
+        .unwrap()
+        .msg_type
+    {
+        Some(MsgType::HistoryDigest(digest)) => digest,
+        other => panic!("expected a history digest, got {:?}", other),
+    };
+    assert_eq!(digest.message_uuids, vec![newer]);
+    assert_eq!(digest.since, newer_sent.timestamp_millis());
+
+    // The older message is out of the window, nothing is pushed back nor requested
+    assert!(pair.network.deliver_next());
+    assert_eq!(pair.network.in_flight(), 0);
+}